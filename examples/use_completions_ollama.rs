@@ -0,0 +1,55 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+
+use allms::{
+    llm::{LLMModel, OllamaModels},
+    Completions,
+};
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+struct TranslationResponse {
+    pub spanish: String,
+    pub french: String,
+    pub german: String,
+    pub polish: String,
+}
+
+// Requires a local Ollama instance (http://localhost:11434 or `OLLAMA_API_URL`) with the model pulled, e.g. `ollama pull llama3.1`
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+
+    // Example context and instructions
+    let instructions =
+        "Translate the following English sentence to all the languages in the response type: Rust is best for working with LLMs";
+
+    // Get answer using the native Ollama API. No API key is required
+    let model = OllamaModels::try_from_str("llama3.1").unwrap(); // Choose the model
+    println!("Ollama model: {:#?}", model.as_str());
+
+    let ollama_completion = Completions::new(model, "", None, None);
+
+    match ollama_completion
+        .get_answer::<TranslationResponse>(instructions)
+        .await
+    {
+        Ok(response) => println!("Ollama response: {:#?}", response),
+        Err(e) => eprintln!("Error: {:?}", e),
+    }
+
+    // Get answer using the OpenAI-compatible Ollama API
+    let model = OllamaModels::OpenAICompatible {
+        name: "llama3.1".to_string(),
+    };
+
+    let ollama_completion = Completions::new(model, "", None, None);
+
+    match ollama_completion
+        .get_answer::<TranslationResponse>(instructions)
+        .await
+    {
+        Ok(response) => println!("Ollama (OpenAI-compatible) response: {:#?}", response),
+        Err(e) => eprintln!("Error: {:?}", e),
+    }
+}
@@ -1,5 +1,6 @@
 use std::ffi::OsStr;
 use std::path::Path;
+use std::time::Duration;
 
 use allms::assistants::{OpenAIAssistant, OpenAIAssistantVersion, OpenAIFile, OpenAIVectorStore};
 use allms::llm::OpenAIModels;
@@ -51,9 +52,16 @@ async fn main() -> Result<()> {
     let openai_vector_store = OpenAIVectorStore::new(None, "Concerts", &api_key)
         .debug()
         .version(OpenAIAssistantVersion::V2)
+        // Delete the store automatically if it is not used for a day
+        .with_expiration(1)
         .upload(&[openai_file.id.clone().unwrap_or_default()])
         .await?;
 
+    // Wait until the file is indexed before running file_search against the store
+    openai_vector_store
+        .wait_until_ready(Duration::from_secs(2), Duration::from_secs(120))
+        .await?;
+
     let status = openai_vector_store.status().await?;
     println!(
         ">>> Vector Store: {:?}; Status: {:?}",
@@ -71,6 +79,8 @@ async fn main() -> Result<()> {
         .debug()
         // Constructor defaults to V1
         .version(OpenAIAssistantVersion::V2)
+        // Delete the assistant and thread once the answer is received
+        .auto_cleanup(true)
         .vector_store(openai_vector_store.clone())
         .await?
         .set_context(
@@ -0,0 +1,204 @@
+use anyhow::{anyhow, Result};
+use log::{error, info};
+use reqwest::{multipart, Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::constants::{
+    ANTHROPIC_DEFAULT_API_VERSION, ANTHROPIC_FILES_API_BETA, ANTHROPIC_FILES_API_URL,
+};
+use crate::domain::AllmsError;
+use crate::utils::{get_file_part, get_file_stream_part};
+
+/// [Anthropic Docs](https://docs.anthropic.com/en/docs/build-with-claude/files)
+///
+/// The Files API allows to upload documents once and reference them by ID in subsequent requests.
+/// The API is in beta so the `anthropic-beta: files-api-2025-04-14` header is sent with each call.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AnthropicFile {
+    pub id: Option<String>,
+    debug: bool,
+    api_key: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AnthropicFileResp {
+    pub id: String,
+    pub filename: String,
+    pub mime_type: String,
+    pub size_bytes: u64,
+    pub created_at: String,
+    pub downloadable: Option<bool>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct AnthropicFileDeleteResp {
+    id: String,
+    #[serde(rename(deserialize = "type", serialize = "type"))]
+    object: String,
+}
+
+impl AnthropicFile {
+    /// Constructor
+    pub fn new(id: Option<String>, api_key: &str) -> Self {
+        AnthropicFile {
+            id,
+            debug: false,
+            api_key: api_key.to_string(),
+        }
+    }
+
+    ///
+    /// This method can be used to turn on debug mode for the AnthropicFile struct
+    ///
+    pub fn debug(mut self) -> Self {
+        self.debug = true;
+        self
+    }
+
+    ///
+    /// This function uploads a file to Anthropic. Once uploaded the `id` can be used to reference the file in messages
+    ///
+    pub async fn upload(self, file_name: &str, file_bytes: Vec<u8>) -> Result<Self> {
+        let file_part = get_file_part(file_name, file_bytes)?;
+        self.upload_part(file_part).await
+    }
+
+    ///
+    /// This function works like `upload` but streams the file from disk instead of loading it into memory
+    /// The file name sent to Anthropic and the MIME type are based on the path
+    ///
+    pub async fn upload_from_path(self, path: &Path) -> Result<Self> {
+        let file_part = get_file_stream_part(path).await?;
+        self.upload_part(file_part).await
+    }
+
+    // Sends the multipart form with the file part to the Files API
+    async fn upload_part(mut self, file_part: multipart::Part) -> Result<Self> {
+        let form = multipart::Form::new().part("file", file_part);
+
+        //Make the API call
+        let client = Client::new();
+
+        // This API sends a form so content type is automatically set by multipart method
+        let response = self
+            .with_headers(client.post(&*ANTHROPIC_FILES_API_URL))
+            .multipart(form)
+            .send()
+            .await?;
+
+        let response_status = response.status();
+        let response_text = response.text().await?;
+
+        if self.debug {
+            info!(
+                "[debug] Anthropic Files API response: [{}] {:#?}",
+                &response_status, &response_text
+            );
+        }
+
+        //Deserialize the string response into the File object to confirm if there were any errors
+        let response_deser: AnthropicFileResp =
+            serde_json::from_str(&response_text).map_err(|error| {
+                let error = AllmsError {
+                    crate_name: "allms".to_string(),
+                    module: "assistants::anthropic_file".to_string(),
+                    error_message: format!("Files API response serialization error: {}", error),
+                    error_detail: response_text,
+                };
+                error!("{:?}", error);
+                anyhow!("{:?}", error)
+            })?;
+
+        self.id = Some(response_deser.id);
+
+        Ok(self)
+    }
+
+    /*
+     * This function deletes a file from Anthropic
+     */
+    pub async fn delete(&self) -> Result<()> {
+        let file_id = if let Some(id) = &self.id {
+            id
+        } else {
+            return Err(anyhow!(
+                "[Anthropic][File API] Unable to delete file without an ID."
+            ));
+        };
+
+        let file_url = format!(
+            "{}/{}",
+            (*ANTHROPIC_FILES_API_URL).trim_end_matches('/'),
+            file_id
+        );
+
+        //Make the API call
+        let client = Client::new();
+
+        let response = self.with_headers(client.delete(file_url)).send().await?;
+
+        let response_status = response.status();
+        let response_text = response.text().await?;
+
+        if self.debug {
+            info!(
+                "[debug] Anthropic Files Delete API response: [{}] {:#?}",
+                &response_status, &response_text
+            );
+        }
+
+        //Check if the file was successfully deleted
+        serde_json::from_str::<AnthropicFileDeleteResp>(&response_text)
+            .map_err(|error| {
+                let error = AllmsError {
+                    crate_name: "allms".to_string(),
+                    module: "assistants::anthropic_file".to_string(),
+                    error_message: format!(
+                        "Files Delete API response serialization error: {}",
+                        error
+                    ),
+                    error_detail: response_text,
+                };
+                error!("{:?}", error);
+                anyhow!("{:?}", error)
+            })
+            .and_then(|response| match response.object.as_str() {
+                "file_deleted" => Ok(()),
+                _ => Err(anyhow!("[Anthropic][File API] Failed to delete the file.")),
+            })
+    }
+
+    // Adds the authentication, version and beta headers required by the Files API
+    fn with_headers(&self, request: RequestBuilder) -> RequestBuilder {
+        request
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_DEFAULT_API_VERSION)
+            .header("anthropic-beta", ANTHROPIC_FILES_API_BETA)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AnthropicFileDeleteResp, AnthropicFileResp};
+
+    #[test]
+    fn test_file_responses_deserialization() {
+        let upload_response = r#"{
+            "id": "file_011CNha8iCJcU1wXNR6q4V8w",
+            "type": "file",
+            "filename": "report.pdf",
+            "mime_type": "application/pdf",
+            "size_bytes": 1024000,
+            "created_at": "2025-01-01T00:00:00Z",
+            "downloadable": false
+        }"#;
+        let file: AnthropicFileResp = serde_json::from_str(upload_response).unwrap();
+        assert_eq!(file.id, "file_011CNha8iCJcU1wXNR6q4V8w");
+        assert_eq!(file.mime_type, "application/pdf");
+
+        let delete_response = r#"{"id": "file_011CNha8iCJcU1wXNR6q4V8w", "type": "file_deleted"}"#;
+        let deleted: AnthropicFileDeleteResp = serde_json::from_str(delete_response).unwrap();
+        assert_eq!(deleted.object, "file_deleted");
+    }
+}
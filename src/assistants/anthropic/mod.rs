@@ -0,0 +1,3 @@
+pub mod anthropic_file;
+
+pub use anthropic_file::{AnthropicFile, AnthropicFileResp};
@@ -1,6 +1,11 @@
+pub mod anthropic;
 pub mod openai;
 
+pub use anthropic::{AnthropicFile, AnthropicFileResp};
+
 pub use openai::{
-    OpenAIAssistant, OpenAIAssistantResource, OpenAIAssistantVersion, OpenAIFile,
-    OpenAIVectorStore, OpenAIVectorStoreFileCounts, OpenAIVectorStoreStatus,
+    OpenAIAssistant, OpenAIAssistantResource, OpenAIAssistantResp, OpenAIAssistantVersion,
+    OpenAIAuth, OpenAIBatch, OpenAIBatchRequestCounts, OpenAIBatchStatus, OpenAIFile,
+    OpenAIFilePurpose, OpenAIFunctionTool, OpenAIVectorStore, OpenAIVectorStoreFileCounts,
+    OpenAIVectorStoreResp, OpenAIVectorStoreStatus,
 };
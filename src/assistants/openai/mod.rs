@@ -1,11 +1,20 @@
 pub mod openai_api_version;
 pub mod openai_assistant;
+pub mod openai_batch;
 pub mod openai_file;
+pub mod openai_function_tool;
 pub mod openai_vector_store;
 
-pub use openai_api_version::{OpenAIAssistantResource, OpenAIAssistantVersion};
+pub use crate::domain::{
+    OpenAIAssistantResp, OpenAIContent, OpenAIContentText, OpenAIMessageAttachment,
+    OpenAIMessageAttachmentTools, OpenAIMessageResp,
+};
+pub use crate::enums::{OpenAIAssistantRole, OpenAIToolTypes};
+pub use openai_api_version::{OpenAIAssistantResource, OpenAIAssistantVersion, OpenAIAuth};
 pub use openai_assistant::OpenAIAssistant;
-pub use openai_file::OpenAIFile;
+pub use openai_batch::{OpenAIBatch, OpenAIBatchRequestCounts, OpenAIBatchStatus};
+pub use openai_file::{OpenAIFile, OpenAIFilePurpose};
+pub use openai_function_tool::{OpenAIFunctionHandler, OpenAIFunctionTool};
 pub use openai_vector_store::{
-    OpenAIVectorStore, OpenAIVectorStoreFileCounts, OpenAIVectorStoreStatus,
+    OpenAIVectorStore, OpenAIVectorStoreFileCounts, OpenAIVectorStoreResp, OpenAIVectorStoreStatus,
 };
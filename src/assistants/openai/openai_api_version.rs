@@ -1,10 +1,16 @@
 use anyhow::{anyhow, Result};
+use log::{error, info};
 use reqwest::header::{self, HeaderMap, HeaderValue};
+use reqwest::Client;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::fmt;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use crate::constants::{DEFAULT_AZURE_VERSION, OPENAI_API_URL};
+use crate::domain::{AllmsError, OpenAIListResp};
 
 #[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
 pub enum OpenAIAssistantVersion {
@@ -14,6 +20,31 @@ pub enum OpenAIAssistantVersion {
     AzureVersion { version: String },
 }
 
+///
+/// Authentication method used when calling OpenAI and Azure OpenAI APIs
+///
+#[derive(Clone, Default)]
+pub enum OpenAIAuth {
+    /// The API key provided in the constructor is used (`Authorization: Bearer` for OpenAI, `api-key` header for Azure)
+    #[default]
+    ApiKey,
+    /// Azure AD (Entra ID) access token sent as `Authorization: Bearer <token>` instead of the `api-key` header
+    AzureAd(String),
+    /// Callback returning an Azure AD (Entra ID) access token. It is called before each request so that tokens can be refreshed
+    AzureAdTokenProvider(Arc<dyn Fn() -> Result<String> + Send + Sync>),
+}
+
+impl fmt::Debug for OpenAIAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Tokens are not printed to avoid leaking them to logs
+        match self {
+            OpenAIAuth::ApiKey => write!(f, "ApiKey"),
+            OpenAIAuth::AzureAd(_) => write!(f, "AzureAd"),
+            OpenAIAuth::AzureAdTokenProvider(_) => write!(f, "AzureAdTokenProvider"),
+        }
+    }
+}
+
 impl OpenAIAssistantVersion {
     pub(crate) fn get_endpoint(&self, resource: &OpenAIAssistantResource) -> String {
         //OpenAI documentation: https://platform.openai.com/docs/models/model-endpoint-compatibility
@@ -33,6 +64,9 @@ impl OpenAIAssistantVersion {
                 format!("{base_url}/assistants/{assistant_id}")
             }
             OpenAIAssistantResource::Threads => format!("{base_url}/threads"),
+            OpenAIAssistantResource::Thread { thread_id } => {
+                format!("{base_url}/threads/{thread_id}")
+            }
             OpenAIAssistantResource::Messages { thread_id } => {
                 format!("{base_url}/threads/{thread_id}/messages")
             }
@@ -42,8 +76,16 @@ impl OpenAIAssistantVersion {
             OpenAIAssistantResource::Run { thread_id, run_id } => {
                 format!("{base_url}/threads/{thread_id}/runs/{run_id}")
             }
+            OpenAIAssistantResource::RunSubmitToolOutputs { thread_id, run_id } => {
+                format!("{base_url}/threads/{thread_id}/runs/{run_id}/submit_tool_outputs")
+            }
             OpenAIAssistantResource::Files => format!("{base_url}/files"),
             OpenAIAssistantResource::File { file_id } => format!("{base_url}/files/{file_id}"),
+            OpenAIAssistantResource::FileContent { file_id } => {
+                format!("{base_url}/files/{file_id}/content")
+            }
+            OpenAIAssistantResource::Batches => format!("{base_url}/batches"),
+            OpenAIAssistantResource::Batch { batch_id } => format!("{base_url}/batches/{batch_id}"),
             OpenAIAssistantResource::VectorStores => format!("{base_url}/vector_stores"),
             OpenAIAssistantResource::VectorStore { vector_store_id } => {
                 format!("{base_url}/vector_stores/{vector_store_id}")
@@ -51,6 +93,10 @@ impl OpenAIAssistantVersion {
             OpenAIAssistantResource::VectorStoreFileBatches { vector_store_id } => {
                 format!("{base_url}/vector_stores/{vector_store_id}/file_batches")
             }
+            OpenAIAssistantResource::VectorStoreFile {
+                vector_store_id,
+                file_id,
+            } => format!("{base_url}/vector_stores/{vector_store_id}/files/{file_id}"),
         };
 
         // Add Azure version suffix if needed
@@ -112,6 +158,88 @@ impl OpenAIAssistantVersion {
         headers
     }
 
+    pub(crate) fn get_auth_headers(&self, api_key: &str, auth: &OpenAIAuth) -> Result<HeaderMap> {
+        let token = match auth {
+            OpenAIAuth::ApiKey => return Ok(self.get_headers(api_key)),
+            OpenAIAuth::AzureAd(token) => token.to_string(),
+            OpenAIAuth::AzureAdTokenProvider(token_provider) => token_provider()?,
+        };
+
+        // Azure AD tokens are always sent as bearer tokens, without the `api-key` header
+        let mut headers = self.get_headers(&token);
+        headers.remove("api-key");
+        let bearer_header = HeaderValue::from_str(&format!("Bearer {token}"))
+            .map_err(|_| anyhow!("[OpenAI] Invalid Azure AD token provided."))?;
+        headers.insert(header::AUTHORIZATION, bearer_header);
+        Ok(headers)
+    }
+
+    /*
+     * This function retrieves all items of a List API, following the `after` cursor until there are no more pages
+     */
+    pub(crate) async fn list_all<R: DeserializeOwned>(
+        &self,
+        resource: &OpenAIAssistantResource,
+        api_key: &str,
+        auth: &OpenAIAuth,
+        debug: bool,
+    ) -> Result<Vec<R>> {
+        //Get version-specific URL
+        let list_url = self.get_endpoint(resource);
+
+        let client = Client::new();
+
+        let mut items = Vec::new();
+        let mut after: Option<String> = None;
+        loop {
+            //Get version-specific headers. Token providers are called for each page
+            let version_headers = self.get_auth_headers(api_key, auth)?;
+
+            let mut request = client
+                .get(&list_url)
+                .headers(version_headers)
+                .query(&[("limit", "100")]);
+            if let Some(after) = &after {
+                request = request.query(&[("after", after)]);
+            }
+
+            let response = request.send().await?;
+
+            let response_status = response.status();
+            let response_text = response.text().await?;
+
+            if debug {
+                info!(
+                    "[debug] OpenAI {:?} List API response: [{}] {:#?}",
+                    resource, &response_status, &response_text
+                );
+            }
+
+            //Deserialize the string response into a page of the requested objects
+            let page: OpenAIListResp<R> =
+                serde_json::from_str(&response_text).map_err(|error| {
+                    let error = AllmsError {
+                        crate_name: "allms".to_string(),
+                        module: "assistants::openai_api_version".to_string(),
+                        error_message: format!(
+                            "{:?} List API response serialization error: {}",
+                            resource, error
+                        ),
+                        error_detail: response_text,
+                    };
+                    error!("{:?}", error);
+                    anyhow!("{:?}", error)
+                })?;
+
+            items.extend(page.data);
+            match (page.has_more, page.last_id) {
+                (Some(true), Some(last_id)) => after = Some(last_id),
+                _ => break,
+            }
+        }
+        Ok(items)
+    }
+
     pub(crate) fn get_tools_payload(&self) -> Value {
         match self {
             OpenAIAssistantVersion::V1 => json!([{
@@ -193,16 +321,49 @@ impl FromStr for OpenAIAssistantVersion {
 #[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
 pub enum OpenAIAssistantResource {
     Assistants,
-    Assistant { assistant_id: String },
+    Assistant {
+        assistant_id: String,
+    },
     Threads,
-    Messages { thread_id: String },
-    Runs { thread_id: String },
-    Run { thread_id: String, run_id: String },
+    Thread {
+        thread_id: String,
+    },
+    Messages {
+        thread_id: String,
+    },
+    Runs {
+        thread_id: String,
+    },
+    Run {
+        thread_id: String,
+        run_id: String,
+    },
+    RunSubmitToolOutputs {
+        thread_id: String,
+        run_id: String,
+    },
     Files,
-    File { file_id: String },
+    File {
+        file_id: String,
+    },
+    FileContent {
+        file_id: String,
+    },
+    Batches,
+    Batch {
+        batch_id: String,
+    },
     VectorStores,
-    VectorStore { vector_store_id: String },
-    VectorStoreFileBatches { vector_store_id: String },
+    VectorStore {
+        vector_store_id: String,
+    },
+    VectorStoreFileBatches {
+        vector_store_id: String,
+    },
+    VectorStoreFile {
+        vector_store_id: String,
+        file_id: String,
+    },
 }
 
 #[cfg(test)]
@@ -248,6 +409,16 @@ mod tests {
         assert_eq!(version.get_endpoint(&resource), expected_url);
     }
 
+    #[test]
+    fn test_v2_thread_endpoint() {
+        let version = OpenAIAssistantVersion::V2;
+        let resource = OpenAIAssistantResource::Thread {
+            thread_id: "thread_123".to_string(),
+        };
+        let expected_url = format!("{}/v1/threads/thread_123", OPENAI_API_URL);
+        assert_eq!(version.get_endpoint(&resource), expected_url);
+    }
+
     #[test]
     fn test_v2_threads_endpoint() {
         let version = OpenAIAssistantVersion::V2;
@@ -271,6 +442,49 @@ mod tests {
         assert_eq!(version.get_endpoint(&resource), expected_url);
     }
 
+    #[test]
+    fn test_v2_vector_store_file_endpoint() {
+        let version = OpenAIAssistantVersion::V2;
+        let resource = OpenAIAssistantResource::VectorStoreFile {
+            vector_store_id: "vs_123".to_string(),
+            file_id: "file_456".to_string(),
+        };
+        let expected_url = format!("{}/v1/vector_stores/vs_123/files/file_456", OPENAI_API_URL);
+        assert_eq!(version.get_endpoint(&resource), expected_url);
+    }
+
+    #[test]
+    fn test_azure_ad_auth_headers() {
+        let version = OpenAIAssistantVersion::Azure;
+
+        let headers = version
+            .get_auth_headers("key", &OpenAIAuth::ApiKey)
+            .unwrap();
+        assert_eq!(headers.get("api-key").unwrap(), "key");
+        assert!(headers.get(header::AUTHORIZATION).is_none());
+
+        let headers = version
+            .get_auth_headers("key", &OpenAIAuth::AzureAd("aad-token".to_string()))
+            .unwrap();
+        assert!(headers.get("api-key").is_none());
+        assert_eq!(
+            headers.get(header::AUTHORIZATION).unwrap(),
+            "Bearer aad-token"
+        );
+
+        let token_provider =
+            OpenAIAuth::AzureAdTokenProvider(Arc::new(|| Ok("refreshed-token".to_string())));
+        let headers = version.get_auth_headers("key", &token_provider).unwrap();
+        assert_eq!(
+            headers.get(header::AUTHORIZATION).unwrap(),
+            "Bearer refreshed-token"
+        );
+
+        let failing_provider =
+            OpenAIAuth::AzureAdTokenProvider(Arc::new(|| Err(anyhow!("Token expired"))));
+        assert!(version.get_auth_headers("key", &failing_provider).is_err());
+    }
+
     #[test]
     fn test_v1_run_endpoint() {
         let version = OpenAIAssistantVersion::V1;
@@ -280,6 +494,36 @@ mod tests {
         };
         let expected_url = format!("{}/v1/threads/xyz/runs/456", OPENAI_API_URL);
         assert_eq!(version.get_endpoint(&resource), expected_url);
+
+        let resource = OpenAIAssistantResource::RunSubmitToolOutputs {
+            thread_id: "xyz".to_string(),
+            run_id: "456".to_string(),
+        };
+        let expected_url = format!(
+            "{}/v1/threads/xyz/runs/456/submit_tool_outputs",
+            OPENAI_API_URL
+        );
+        assert_eq!(version.get_endpoint(&resource), expected_url);
+    }
+
+    #[test]
+    fn test_v1_batch_endpoint() {
+        let version = OpenAIAssistantVersion::V1;
+        let resource = OpenAIAssistantResource::Batch {
+            batch_id: "batch_123".to_string(),
+        };
+        let expected_url = format!("{}/v1/batches/batch_123", OPENAI_API_URL);
+        assert_eq!(version.get_endpoint(&resource), expected_url);
+    }
+
+    #[test]
+    fn test_v1_file_content_endpoint() {
+        let version = OpenAIAssistantVersion::V1;
+        let resource = OpenAIAssistantResource::FileContent {
+            file_id: "file-abc".to_string(),
+        };
+        let expected_url = format!("{}/v1/files/file-abc/content", OPENAI_API_URL);
+        assert_eq!(version.get_endpoint(&resource), expected_url);
     }
 
     #[test]
@@ -1,8 +1,9 @@
 use anyhow::{anyhow, Result};
-use jsonschema::JSONSchema;
+use futures::stream::{self, BoxStream, StreamExt};
 use log::error;
 use log::info;
-use reqwest::Client;
+use log::warn;
+use reqwest::{Client, Response};
 use schemars::JsonSchema;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
@@ -11,15 +12,18 @@ use std::time::Duration;
 use tokio::time;
 use tokio::time::timeout;
 
-use crate::assistants::{OpenAIAssistantResource, OpenAIAssistantVersion, OpenAIVectorStore};
-use crate::constants::OPENAI_ASSISTANT_INSTRUCTIONS;
+use crate::assistants::{
+    OpenAIAssistantResource, OpenAIAssistantVersion, OpenAIAuth, OpenAIFile, OpenAIFunctionTool,
+    OpenAIVectorStore,
+};
+use crate::constants::{OPENAI_ASSISTANT_INSTRUCTIONS, OPENAI_ASSISTANT_MESSAGE_MAX_CHARS};
 use crate::domain::{
-    AllmsError, OpenAIAssistantResp, OpenAIMessageListResp, OpenAIMessageResp, OpenAIRunResp,
-    OpenAIThreadResp,
+    AllmsError, OpenAIAssistantResp, OpenAIDeleteResp, OpenAIMessageDeltaResp,
+    OpenAIMessageListResp, OpenAIMessageResp, OpenAIRunResp, OpenAIThreadResp,
 };
 use crate::enums::{OpenAIAssistantRole, OpenAIRunStatus};
 use crate::llm_models::{LLMModel, OpenAIModels};
-use crate::utils::{get_type_schema, sanitize_json_response};
+use crate::utils::{get_type_schema, get_valid_json, sanitize_json_response};
 
 /// [OpenAI Docs](https://platform.openai.com/docs/assistants/overview)
 ///
@@ -35,11 +39,23 @@ pub struct OpenAIAssistant {
     run_id: Option<String>,
     model: OpenAIModels,
     instructions: String,
+    base_instructions: String,
+    name: Option<String>,
+    description: Option<String>,
     debug: bool,
     api_key: String,
     version: OpenAIAssistantVersion,
     vector_store: Option<OpenAIVectorStore>,
     temperature: f32,
+    auto_cleanup: bool,
+    validate_ids: bool,
+    delete_files_on_cleanup: bool,
+    attached_file_ids: Vec<String>,
+    #[serde(skip)]
+    auth: OpenAIAuth,
+    #[serde(skip)]
+    function_tools: Vec<OpenAIFunctionTool>,
+    parallel_tool_calls: Option<bool>,
 }
 
 impl OpenAIAssistant {
@@ -52,11 +68,38 @@ impl OpenAIAssistant {
             temperature: model.get_default_temperature(),
             model,
             instructions: OPENAI_ASSISTANT_INSTRUCTIONS.to_string(),
+            base_instructions: OPENAI_ASSISTANT_INSTRUCTIONS.to_string(),
+            name: None,
+            description: None,
             debug: false,
             api_key: open_ai_key.to_string(),
             // Defaulting to V1 for now
             version: OpenAIAssistantVersion::V1,
             vector_store: None,
+            auto_cleanup: false,
+            validate_ids: false,
+            delete_files_on_cleanup: false,
+            attached_file_ids: Vec::new(),
+            auth: OpenAIAuth::ApiKey,
+            function_tools: Vec::new(),
+            parallel_tool_calls: None,
+        }
+    }
+
+    ///
+    /// Constructor attaching to an existing Assistant and Thread instead of creating new ones
+    /// It is equivalent to `new` followed by `with_assistant_id` and `with_thread_id`, so the IDs are validated when the Assistant is first used
+    ///
+    pub fn from_ids(
+        model: OpenAIModels,
+        open_ai_key: &str,
+        assistant_id: String,
+        thread_id: Option<String>,
+    ) -> Self {
+        let assistant = OpenAIAssistant::new(model, open_ai_key).with_assistant_id(assistant_id);
+        match thread_id {
+            Some(thread_id) => assistant.with_thread_id(thread_id),
+            None => assistant,
         }
     }
 
@@ -77,6 +120,65 @@ impl OpenAIAssistant {
         self
     }
 
+    ///
+    /// This method can be used to set the authentication method, e.g. Azure AD (Entra ID) tokens instead of API keys
+    /// Default is `OpenAIAuth::ApiKey` which uses the key provided in the constructor
+    ///
+    pub fn auth(mut self, auth: OpenAIAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    ///
+    /// This method can be used to replace the default system instructions the Assistant is created with
+    ///
+    pub fn with_instructions(mut self, instructions: String) -> Self {
+        self.instructions = instructions;
+        self
+    }
+
+    ///
+    /// This method can be used to replace the default instructions added as the first message of a new Thread
+    ///
+    pub fn with_base_instructions(mut self, base_instructions: String) -> Self {
+        self.base_instructions = base_instructions;
+        self
+    }
+
+    ///
+    /// This method can be used to set the name of the Assistant, e.g. to identify it when listing Assistants
+    ///
+    pub fn with_name(mut self, name: String) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    ///
+    /// This method can be used to set the description of the Assistant
+    ///
+    pub fn with_description(mut self, description: String) -> Self {
+        self.description = Some(description);
+        self
+    }
+
+    ///
+    /// This method can be used to register a function the Assistant can call
+    /// When a run requires action the handler is invoked with the parsed arguments and its output is submitted to continue the run
+    ///
+    pub fn add_function(mut self, function_tool: OpenAIFunctionTool) -> Self {
+        self.function_tools.push(function_tool);
+        self
+    }
+
+    ///
+    /// This method can be used to allow or prevent the run from calling multiple functions at once (Assistants API v2)
+    /// All function calls of a run step are handled before the outputs are submitted. If not set the API default is used
+    ///
+    pub fn with_parallel_tool_calls(mut self, parallel_tool_calls: bool) -> Self {
+        self.parallel_tool_calls = Some(parallel_tool_calls);
+        self
+    }
+
     ///
     /// This method can be used to define the model temperature used by the Assistant
     /// This method accepts % target of the acceptable range for the model
@@ -96,32 +198,212 @@ impl OpenAIAssistant {
         self
     }
 
+    ///
+    /// This method can be used to reuse an existing Assistant instead of creating a new one
+    /// The ID is validated when the Assistant is first used
+    ///
+    pub fn with_assistant_id(mut self, assistant_id: String) -> Self {
+        self.id = Some(assistant_id);
+        self.validate_ids = true;
+        self
+    }
+
+    ///
+    /// This method can be used to continue an existing Thread, e.g. after a process restart
+    /// The ID is validated when the Assistant is first used
+    ///
+    pub fn with_thread_id(mut self, thread_id: String) -> Self {
+        self.thread_id = Some(thread_id);
+        self.validate_ids = true;
+        self
+    }
+
+    ///
+    /// Returns the ID of the Assistant, if it was already created
+    ///
+    pub fn assistant_id(&self) -> Option<String> {
+        self.id.clone()
+    }
+
+    ///
+    /// Returns the ID of the Thread, if it was already created. It can be persisted and passed to `with_thread_id` to continue the conversation later
+    ///
+    pub fn thread_id(&self) -> Option<String> {
+        self.thread_id.clone()
+    }
+
+    ///
+    /// This method can be used to delete the Assistant and its Thread once an answer is received
+    /// Useful for one-off prompts so that long-lived processes don't accumulate orphaned assistants
+    /// Any context provided earlier is removed together with the Thread
+    ///
+    pub fn auto_cleanup(mut self, auto_cleanup: bool) -> Self {
+        self.auto_cleanup = auto_cleanup;
+        self
+    }
+
+    ///
+    /// This method can be used to also delete the Files attached to messages when `cleanup` is called
+    /// Useful for one-off retrievals so that uploaded Files don't keep incurring storage costs
+    ///
+    pub fn delete_files_on_cleanup(mut self, delete_files: bool) -> Self {
+        self.delete_files_on_cleanup = delete_files;
+        self
+    }
+
     /*
-     * This function creates an Assistant and updates the ID of the OpenAIAssistant struct
+     * This function makes sure the Assistant and Thread exist, either by validating the provided IDs or by creating new ones
      */
-    async fn create_assistant(&mut self) -> Result<()> {
-        //Get the assistant API url
-        let assistant_url = self
-            .version
-            .get_endpoint(&OpenAIAssistantResource::Assistants);
+    async fn initialize(&mut self) -> Result<()> {
+        if self.validate_ids {
+            self.validate_existing_ids().await?;
+            self.validate_ids = false;
+        }
 
-        //Get the version-specific header
-        let version_headers = self.version.get_headers(&self.api_key);
+        if self.id.is_none() {
+            //Call OpenAI API to get an ID for the assistant
+            self.create_assistant().await?;
+        }
+
+        if self.thread_id.is_none() {
+            //Add first message thus initializing the thread
+            let base_instructions = self.base_instructions.clone();
+            self.add_message(&base_instructions, &Vec::new()).await?;
+        }
+
+        Ok(())
+    }
+
+    /*
+     * This function checks that the Assistant and Thread provided by the user exist
+     */
+    async fn validate_existing_ids(&self) -> Result<()> {
+        if let Some(assistant_id) = self.id.clone() {
+            let assistant_url = self
+                .version
+                .get_endpoint(&OpenAIAssistantResource::Assistant {
+                    assistant_id: assistant_id.clone(),
+                });
+            self.get_existing_resource::<OpenAIAssistantResp>(&assistant_url, "Assistant")
+                .await
+                .map_err(|error| {
+                    anyhow!(
+                        "[OpenAIAssistant] Assistant '{}' could not be found: {}",
+                        assistant_id,
+                        error
+                    )
+                })?;
+        }
+
+        if let Some(thread_id) = self.thread_id.clone() {
+            let thread_url = self.version.get_endpoint(&OpenAIAssistantResource::Thread {
+                thread_id: thread_id.clone(),
+            });
+            self.get_existing_resource::<OpenAIThreadResp>(&thread_url, "Thread")
+                .await
+                .map_err(|error| {
+                    anyhow!(
+                        "[OpenAIAssistant] Thread '{}' could not be found: {}",
+                        thread_id,
+                        error
+                    )
+                })?;
+        }
+
+        Ok(())
+    }
+
+    /*
+     * This function retrieves an existing Assistants API resource
+     */
+    async fn get_existing_resource<R: DeserializeOwned>(
+        &self,
+        url: &str,
+        resource_name: &str,
+    ) -> Result<R> {
+        //Get version-specific headers
+        let version_headers = self.version.get_auth_headers(&self.api_key, &self.auth)?;
+
+        //Make the API call
+        let client = Client::new();
+
+        let response = client.get(url).headers(version_headers).send().await?;
+
+        let response_status = response.status();
+        let response_text = response.text().await?;
+
+        if self.debug {
+            info!(
+                "[debug] OpenAI {} API response: [{}] {:#?}",
+                resource_name, &response_status, &response_text
+            );
+        }
+
+        serde_json::from_str::<R>(&response_text).map_err(|error| {
+            let error = AllmsError {
+                crate_name: "allms".to_string(),
+                module: "assistants::openai_assistant".to_string(),
+                error_message: format!(
+                    "{} API response serialization error: {}",
+                    resource_name, error
+                ),
+                error_detail: response_text,
+            };
+            error!("{:?}", error);
+            anyhow!("{:?}", error)
+        })
+    }
 
+    // This function builds the body used to create the Assistant. Name and description are only sent if provided
+    fn get_assistant_body(&self) -> Value {
         let mut assistant_body = json!({
             "instructions": self.instructions.clone(),
             "model": self.model.as_str(),
             "temperature": self.temperature,
         });
 
-        //Get the retrieval / file_search part of the payload (if supported)
+        //Get the retrieval / file_search part of the payload (if supported) and registered functions
+        let mut tools_payload: Vec<Value> = Vec::new();
         if self.model.tools_support() {
+            if let Value::Array(version_tools) = self.version.get_tools_payload() {
+                tools_payload.extend(version_tools);
+            }
+        }
+        tools_payload.extend(
+            self.function_tools
+                .iter()
+                .map(|function_tool| function_tool.get_tool_payload()),
+        );
+        if !tools_payload.is_empty() {
             if let Some(assistant_body_object) = assistant_body.as_object_mut() {
-                let tools_payload = self.version.get_tools_payload();
-                assistant_body_object.insert("tools".to_string(), tools_payload);
+                assistant_body_object.insert("tools".to_string(), Value::Array(tools_payload));
             }
         }
 
+        if let Some(name) = &self.name {
+            assistant_body["name"] = json!(name);
+        }
+        if let Some(description) = &self.description {
+            assistant_body["description"] = json!(description);
+        }
+
+        assistant_body
+    }
+
+    /*
+     * This function creates an Assistant and updates the ID of the OpenAIAssistant struct
+     */
+    async fn create_assistant(&mut self) -> Result<()> {
+        //Get the assistant API url
+        let assistant_url = self
+            .version
+            .get_endpoint(&OpenAIAssistantResource::Assistants);
+
+        //Get the version-specific header
+        let version_headers = self.version.get_auth_headers(&self.api_key, &self.auth)?;
+
+        let assistant_body = self.get_assistant_body();
+
         //Make the API call
         let client = Client::new();
 
@@ -174,9 +456,11 @@ impl OpenAIAssistant {
         let schema_string = get_type_schema::<T>()?;
 
         // Call assistant
-        let assistant_response = self
-            .call_assistant(&schema_string, message, file_ids)
-            .await?;
+        let assistant_response = self.call_assistant(&schema_string, message, file_ids).await;
+
+        // Remove the assistant and thread if requested, regardless of the outcome
+        self.cleanup_if_requested().await;
+        let assistant_response = assistant_response?;
 
         // Deserialize assistant message
         serde_json::from_str::<T>(&assistant_response).map_err(|e| {
@@ -200,28 +484,95 @@ impl OpenAIAssistant {
         file_ids: &[String],
     ) -> Result<Value> {
         // Call assistant
-        let assistant_response = self.call_assistant(json_schema, message, file_ids).await?;
+        let assistant_response = self.call_assistant(json_schema, message, file_ids).await;
+
+        // Remove the assistant and thread if requested, regardless of the outcome
+        self.cleanup_if_requested().await;
+        let assistant_response = assistant_response?;
 
         // Deserialize assistant message
-        self.get_valid_json(json_schema, &assistant_response)
+        get_valid_json(
+            "assistants::openai_assistant",
+            json_schema,
+            &assistant_response,
+        )
     }
 
-    // This function performs orchestration with Assistants API to get a message with response
-    async fn call_assistant(
+    ///
+    /// This function is similar to _get_answer_ however the Assistant response is streamed as it is being generated
+    /// The stream yields text deltas which, once concatenated, form the Json answer matching the schema of the type parameter
+    /// The Assistant and Thread are not removed automatically when streaming, even if `auto_cleanup` is set
+    /// Function tools are not supported when streaming. Use `get_answer` if functions were added with `add_function`
+    ///
+    pub async fn stream_answer<T: JsonSchema + DeserializeOwned>(
+        &mut self,
+        message: &str,
+        file_ids: &[String],
+    ) -> Result<BoxStream<'static, Result<String>>> {
+        if !self.function_tools.is_empty() {
+            return Err(anyhow!(
+                "[OpenAIAssistant] Streaming does not support function tools. Use get_answer instead."
+            ));
+        }
+
+        // Instruct the Assistant to answer with the right Json format
+        // Output schema is extracted from the type parameter
+        let schema_string = get_type_schema::<T>()?;
+
+        self.prepare_run(&schema_string, message, file_ids).await?;
+
+        let response = self.start_run_stream().await?;
+
+        let debug = self.debug;
+        let stream = stream::unfold(
+            (response.bytes_stream().boxed(), Vec::new(), false),
+            move |(mut bytes, mut buffer, finished)| async move {
+                if finished {
+                    return None;
+                }
+                loop {
+                    // Process complete events (separated by a blank line) available in the buffer
+                    if let Some(position) = buffer.windows(2).position(|window| window == b"\n\n") {
+                        let event_bytes: Vec<u8> = buffer.drain(..position + 2).collect();
+                        let event = String::from_utf8_lossy(&event_bytes);
+                        if debug {
+                            info!("[debug] OpenAI Run stream event: {:#?}", event);
+                        }
+                        match parse_run_stream_event(&event) {
+                            OpenAIRunStreamEvent::Delta(text) => {
+                                return Some((Ok(text), (bytes, buffer, false)))
+                            }
+                            OpenAIRunStreamEvent::Error(error) => {
+                                return Some((Err(error), (bytes, buffer, true)))
+                            }
+                            OpenAIRunStreamEvent::Done => return None,
+                            OpenAIRunStreamEvent::Other => continue,
+                        }
+                    }
+
+                    match bytes.next().await {
+                        Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                        Some(Err(error)) => {
+                            return Some((Err(anyhow!(error)), (bytes, buffer, true)))
+                        }
+                        None => return None,
+                    }
+                }
+            },
+        );
+
+        Ok(stream.boxed())
+    }
+
+    // This function adds the schema instructions and the user message to the thread, creating the assistant and thread if needed
+    async fn prepare_run(
         &mut self,
         json_schema: &str,
         message: &str,
         file_ids: &[String],
-    ) -> Result<String> {
+    ) -> Result<()> {
         // If the assistant and thread are not initialized we do that first
-        if self.id.is_none() {
-            //Call OpenAI API to get an ID for the assistant
-            self.create_assistant().await?;
-
-            //Add first message thus initializing the thread
-            self.add_message(OPENAI_ASSISTANT_INSTRUCTIONS, &Vec::new())
-                .await?;
-        }
+        self.initialize().await?;
 
         // Instruct Assistant to answer with that schema
         let schema_message = format!(
@@ -233,8 +584,19 @@ impl OpenAIAssistant {
         );
         self.add_message(&schema_message, &Vec::new()).await?;
 
-        //Step 2: Add user message and files to thread
-        self.add_message(message, file_ids).await?;
+        //Add user message and files to thread
+        self.add_message(message, file_ids).await
+    }
+
+    // This function performs orchestration with Assistants API to get a message with response
+    async fn call_assistant(
+        &mut self,
+        json_schema: &str,
+        message: &str,
+        file_ids: &[String],
+    ) -> Result<String> {
+        //Step 1-2: Add schema instructions, user message and files to thread
+        self.prepare_run(json_schema, message, file_ids).await?;
 
         //Step 3: Kick off processing (aka Run)
         self.start_run().await?;
@@ -243,7 +605,7 @@ impl OpenAIAssistant {
         let operation_timeout = Duration::from_secs(600); // Timeout for the whole operation
         let poll_interval = Duration::from_secs(10);
 
-        let _result = timeout(operation_timeout, async {
+        timeout(operation_timeout, async {
             let mut interval = time::interval(poll_interval);
             loop {
                 interval.tick().await; // Wait for the next interval tick
@@ -253,24 +615,27 @@ impl OpenAIAssistant {
                         OpenAIRunStatus::Completed => {
                             break Ok(());
                         }
-                        //TODO: We will need better handling of requires_action
-                        OpenAIRunStatus::RequiresAction
-                        | OpenAIRunStatus::Cancelling
-                        | OpenAIRunStatus::Cancelled
-                        | OpenAIRunStatus::Failed
-                        | OpenAIRunStatus::Expired => {
-                            return Err(anyhow!("Failed to validate status of the run"));
+                        //Function calls are handled by the registered handlers and the run continues
+                        OpenAIRunStatus::RequiresAction => {
+                            self.submit_tool_outputs(&resp).await?;
+                            interval.reset();
+                            continue;
+                        }
+                        //Runs that ended without completing have no answer, and older messages of the thread must not be returned instead
+                        status => {
+                            check_run_status(&status)?;
+                            continue; // Keep polling if in_progress or queued
                         }
-                        _ => continue, // Keep polling if in_progress or queued
                     },
                     Err(e) => return Err(e), // Break on error
                 }
             }
         })
-        .await?;
+        .await
+        .map_err(|_| anyhow!("[OpenAIAssistant] Timeout waiting for the run to complete."))??;
 
         //Step 5: Get all messages posted on the thread. This should now include response from the Assistant
-        let messages = self.get_message_thread().await?;
+        let messages = self.list_messages().await?.data;
 
         messages
             .iter()
@@ -295,51 +660,6 @@ impl OpenAIAssistant {
             })
     }
 
-    // This function checks if a Json object matches the schema
-    fn get_valid_json(&self, schema: &str, value: &str) -> Result<Value> {
-        let schema_value = serde_json::from_str(schema).map_err(|e| {
-            let error = AllmsError {
-                crate_name: "alms".to_string(),
-                module: "assistants::openai_assistant".to_string(),
-                error_message: format!("Json Schema parsing error: {:?}", e),
-                error_detail: format!("Schema: {:?}", schema),
-            };
-            anyhow!("{:?}", error)
-        })?;
-
-        let compiled_schema = JSONSchema::compile(&schema_value).map_err(|e| {
-            let error = AllmsError {
-                crate_name: "alms".to_string(),
-                module: "assistants::openai_assistant".to_string(),
-                error_message: format!("Json Schema compilation error: {:?}", e),
-                error_detail: format!("Schema: {:?}", schema_value),
-            };
-            anyhow!("{:?}", error)
-        })?;
-
-        let data_value = serde_json::from_str(value).map_err(|e| {
-            let error = AllmsError {
-                crate_name: "alms".to_string(),
-                module: "assistants::openai_assistant".to_string(),
-                error_message: format!("Json data parsing error: {:?}", e),
-                error_detail: format!("Data: {:?}", value),
-            };
-            anyhow!("{:?}", error)
-        })?;
-
-        compiled_schema.validate(&data_value).map_err(|_| {
-            let error = AllmsError {
-                crate_name: "alms".to_string(),
-                module: "assistants::openai_assistant".to_string(),
-                error_message: "Json Schema validation error".to_string(),
-                error_detail: format!("Data: {:?}\nSchema: {:?}", &data_value, &schema_value),
-            };
-            anyhow!("{:?}", error)
-        })?;
-
-        Ok(data_value)
-    }
-
     ///
     /// This method can be used to provide data that will be used as context for the prompt.
     /// Using this function you can provide multiple sets of context data by calling it multiple times. New values will be as messages to the thread
@@ -347,26 +667,29 @@ impl OpenAIAssistant {
     ///
     pub async fn set_context<T: Serialize>(mut self, dataset_name: &str, data: &T) -> Result<Self> {
         // If the assistant and thread are not initialized we do that first
-        if self.id.is_none() {
-            //Call OpenAI API to get an ID for the assistant
-            self.create_assistant().await?;
-
-            //Add first message thus initializing the thread
-            self.add_message(OPENAI_ASSISTANT_INSTRUCTIONS, &Vec::new())
-                .await?;
-        }
+        self.initialize().await?;
 
-        let serialized_data = if let Ok(json) = serde_json::to_string(&data) {
-            json
-        } else {
-            return Err(anyhow!("Unable serialize provided input data."));
-        };
-        let message = format!("'{dataset_name}'= {serialized_data}");
+        let message = get_context_message(dataset_name, data)?;
         let file_ids = Vec::new();
         self.add_message(&message, &file_ids).await?;
         Ok(self)
     }
 
+    ///
+    /// This method works like `set_context` but adds multiple datasets at once.
+    /// The labeled datasets are combined into as few messages as possible, so that loading many datasets doesn't require an API call for each of them.
+    ///
+    pub async fn set_contexts<T: Serialize>(mut self, datasets: &[(&str, &T)]) -> Result<Self> {
+        // If the assistant and thread are not initialized we do that first
+        self.initialize().await?;
+
+        let file_ids = Vec::new();
+        for message in get_contexts_messages(datasets, OPENAI_ASSISTANT_MESSAGE_MAX_CHARS)? {
+            self.add_message(&message, &file_ids).await?;
+        }
+        Ok(self)
+    }
+
     /*
      * This function creates a Thread and updates the thread_id of the OpenAIAssistant struct
      */
@@ -379,6 +702,12 @@ impl OpenAIAssistant {
 
         if !file_ids.is_empty() {
             message = self.version.add_message_attachments(&message, file_ids);
+            //Keep track of the attached files so that they can be removed on cleanup
+            for file_id in file_ids {
+                if !self.attached_file_ids.contains(file_id) {
+                    self.attached_file_ids.push(file_id.to_string());
+                }
+            }
         }
 
         //If there is no thread_id we need to create one
@@ -402,7 +731,7 @@ impl OpenAIAssistant {
         let thread_url = self.version.get_endpoint(&OpenAIAssistantResource::Threads);
 
         //Get version-specific headers
-        let version_headers = self.version.get_headers(&self.api_key);
+        let version_headers = self.version.get_auth_headers(&self.api_key, &self.auth)?;
 
         //Make the API call
         let client = Client::new();
@@ -458,7 +787,7 @@ impl OpenAIAssistant {
         let message_url = self.version.get_endpoint(&messages_resource);
 
         //Get version-specific headers
-        let version_headers = self.version.get_headers(&self.api_key);
+        let version_headers = self.version.get_auth_headers(&self.api_key, &self.auth)?;
 
         //Make the API call
         let client = Client::new();
@@ -496,10 +825,27 @@ impl OpenAIAssistant {
         Ok(())
     }
 
+    ///
+    /// This method returns all messages posted to the Thread, newest first
+    /// When continuing an existing Thread this includes the full prior history
+    ///
+    pub async fn get_message_thread(&self) -> Result<Vec<OpenAIMessageResp>> {
+        let thread_id = if let Some(id) = self.thread_id.clone() {
+            id
+        } else {
+            return Err(anyhow!("No active thread detected."));
+        };
+
+        let message_resource = OpenAIAssistantResource::Messages { thread_id };
+        self.version
+            .list_all(&message_resource, &self.api_key, &self.auth, self.debug)
+            .await
+    }
+
     /*
-     * This function gets all message posted to an existing thread
+     * This function gets a single page of messages posted to an existing thread
      */
-    async fn get_message_thread(&self) -> Result<Vec<OpenAIMessageResp>> {
+    async fn list_messages(&self) -> Result<OpenAIMessageListResp> {
         if self.thread_id.is_none() {
             return Err(anyhow!("No active thread detected."));
         }
@@ -511,7 +857,7 @@ impl OpenAIAssistant {
         let message_url = self.version.get_endpoint(&message_resource);
 
         //Get version-specific headers
-        let version_headers = self.version.get_headers(&self.api_key);
+        let version_headers = self.version.get_auth_headers(&self.api_key, &self.auth)?;
 
         //Make the API call
         let client = Client::new();
@@ -519,6 +865,7 @@ impl OpenAIAssistant {
         let response = client
             .get(message_url)
             .headers(version_headers)
+            .query(&[("limit", "100")])
             .send()
             .await?;
 
@@ -532,20 +879,72 @@ impl OpenAIAssistant {
             );
         }
 
-        //Deserialize the string response into a vector of OpenAIMessageResp objects
-        let response_deser: OpenAIMessageListResp =
-            serde_json::from_str(&response_text).map_err(|error| {
-                let error = AllmsError {
-                    crate_name: "allms".to_string(),
-                    module: "assistants::openai_assistant".to_string(),
-                    error_message: format!("Messages API response serialization error: {}", error),
-                    error_detail: response_text,
-                };
-                error!("{:?}", error);
-                anyhow!("{:?}", error)
-            })?;
+        //Deserialize the string response into a list of OpenAIMessageResp objects
+        serde_json::from_str(&response_text).map_err(|error| {
+            let error = AllmsError {
+                crate_name: "allms".to_string(),
+                module: "assistants::openai_assistant".to_string(),
+                error_message: format!("Messages API response serialization error: {}", error),
+                error_detail: response_text,
+            };
+            error!("{:?}", error);
+            anyhow!("{:?}", error)
+        })
+    }
 
-        Ok(response_deser.data)
+    /*
+     * This function starts an assistant run that streams its events
+     */
+    async fn start_run_stream(&self) -> Result<Response> {
+        let assistant_id = if let Some(id) = self.id.clone() {
+            id
+        } else {
+            return Err(anyhow!("No active assistant detected."));
+        };
+
+        let thread_id = if let Some(id) = self.thread_id.clone() {
+            id
+        } else {
+            return Err(anyhow!("No active thread detected."));
+        };
+
+        //Get version-specific URL
+        let run_resource = OpenAIAssistantResource::Runs { thread_id };
+        let run_url = self.version.get_endpoint(&run_resource);
+
+        //Get version-specific headers
+        let version_headers = self.version.get_auth_headers(&self.api_key, &self.auth)?;
+
+        let body = json!({
+            "assistant_id": assistant_id,
+            "stream": true,
+        });
+
+        //Make the API call
+        let client = Client::new();
+
+        let response = client
+            .post(run_url)
+            .headers(version_headers)
+            .json(&body)
+            .send()
+            .await?;
+
+        //Errors are returned as a regular Json response
+        if !response.status().is_success() {
+            let response_status = response.status();
+            let response_text = response.text().await?;
+            let error = AllmsError {
+                crate_name: "allms".to_string(),
+                module: "assistants::openai_assistant".to_string(),
+                error_message: format!("Run API stream request failed: {}", response_status),
+                error_detail: response_text,
+            };
+            error!("{:?}", error);
+            return Err(anyhow!("{:?}", error));
+        }
+
+        Ok(response)
     }
 
     /*
@@ -569,11 +968,14 @@ impl OpenAIAssistant {
         let run_url = self.version.get_endpoint(&run_resource);
 
         //Get version-specific headers
-        let version_headers = self.version.get_headers(&self.api_key);
+        let version_headers = self.version.get_auth_headers(&self.api_key, &self.auth)?;
 
-        let body = json!({
+        let mut body = json!({
             "assistant_id": assistant_id,
         });
+        if let Some(parallel_tool_calls) = self.parallel_tool_calls {
+            body["parallel_tool_calls"] = json!(parallel_tool_calls);
+        }
 
         //Make the API call
         let client = Client::new();
@@ -635,7 +1037,7 @@ impl OpenAIAssistant {
         let run_url = self.version.get_endpoint(&run_resource);
 
         //Get version-specific headers
-        let version_headers = self.version.get_headers(&self.api_key);
+        let version_headers = self.version.get_auth_headers(&self.api_key, &self.auth)?;
 
         //Make the API call
         let client = Client::new();
@@ -668,6 +1070,253 @@ impl OpenAIAssistant {
         Ok(response_deser)
     }
 
+    /*
+     * This function invokes the handlers of the functions called by the run and submits their outputs
+     */
+    async fn submit_tool_outputs(&self, run: &OpenAIRunResp) -> Result<()> {
+        let thread_id = if let Some(id) = self.thread_id.clone() {
+            id
+        } else {
+            return Err(anyhow!("No active thread detected."));
+        };
+
+        let tool_calls = run
+            .required_action
+            .as_ref()
+            .and_then(|action| action.submit_tool_outputs.as_ref())
+            .map(|submit_tool_outputs| submit_tool_outputs.tool_calls.as_slice())
+            .ok_or_else(|| {
+                anyhow!("[OpenAIAssistant] Run requires action but no tool calls were provided.")
+            })?;
+
+        let body = OpenAIFunctionTool::get_tool_outputs(&self.function_tools, tool_calls)?;
+
+        //Get version-specific URL
+        let submit_resource = OpenAIAssistantResource::RunSubmitToolOutputs {
+            thread_id,
+            run_id: run.id.clone(),
+        };
+        let submit_url = self.version.get_endpoint(&submit_resource);
+
+        //Get version-specific headers
+        let version_headers = self.version.get_auth_headers(&self.api_key, &self.auth)?;
+
+        //Make the API call
+        let client = Client::new();
+
+        let response = client
+            .post(submit_url)
+            .headers(version_headers)
+            .json(&body)
+            .send()
+            .await?;
+
+        let response_status = response.status();
+        let response_text = response.text().await?;
+
+        if self.debug {
+            info!(
+                "[debug] OpenAI Submit tool outputs API response: [{}] {:#?}",
+                &response_status, &response_text
+            );
+        }
+
+        //Deserialize the string response into the Run object to confirm if there were any errors
+        serde_json::from_str::<OpenAIRunResp>(&response_text).map_err(|error| {
+            let error = AllmsError {
+                crate_name: "allms".to_string(),
+                module: "assistants::openai_assistant".to_string(),
+                error_message: format!("Run API response serialization error: {}", error),
+                error_detail: response_text,
+            };
+            error!("{:?}", error);
+            anyhow!("{:?}", error)
+        })?;
+
+        Ok(())
+    }
+
+    ///
+    /// This method lists all Assistants available for the provided API key, following pagination until all are retrieved
+    /// Useful to audit and clean up Assistants that were not deleted, e.g. using `with_assistant_id` and `delete_assistant`
+    ///
+    pub async fn list(
+        api_key: &str,
+        version: OpenAIAssistantVersion,
+        auth: &OpenAIAuth,
+    ) -> Result<Vec<OpenAIAssistantResp>> {
+        version
+            .list_all(&OpenAIAssistantResource::Assistants, api_key, auth, false)
+            .await
+    }
+
+    ///
+    /// This method deletes the Assistant from OpenAI
+    ///
+    pub async fn delete_assistant(&mut self) -> Result<()> {
+        let assistant_id = if let Some(id) = self.id.clone() {
+            id
+        } else {
+            return Err(anyhow!("No active assistant detected."));
+        };
+
+        //Get version-specific URL
+        let assistant_resource = OpenAIAssistantResource::Assistant { assistant_id };
+        let assistant_url = self.version.get_endpoint(&assistant_resource);
+
+        self.delete_resource(&assistant_url, "Assistant").await?;
+
+        self.id = None;
+
+        Ok(())
+    }
+
+    ///
+    /// This method deletes the Thread (including all of its messages) from OpenAI
+    ///
+    pub async fn delete_thread(&mut self) -> Result<()> {
+        let thread_id = if let Some(id) = self.thread_id.clone() {
+            id
+        } else {
+            return Err(anyhow!("No active thread detected."));
+        };
+
+        //Get version-specific URL
+        let thread_resource = OpenAIAssistantResource::Thread { thread_id };
+        let thread_url = self.version.get_endpoint(&thread_resource);
+
+        self.delete_resource(&thread_url, "Thread").await?;
+
+        self.thread_id = None;
+        self.run_id = None;
+
+        Ok(())
+    }
+
+    ///
+    /// This method detaches Files from the Vector Store attached to the Assistant so that they are no longer used for retrieval
+    /// The Files themselves are not deleted and stop being tracked for `delete_files_on_cleanup`
+    ///
+    pub async fn detach_files(&mut self, file_ids: &[String]) -> Result<()> {
+        let vector_store_id = if let Some(id) = self
+            .vector_store
+            .as_ref()
+            .and_then(|vector_store| vector_store.id.clone())
+        {
+            id
+        } else {
+            return Err(anyhow!(
+                "[OpenAI][Assistants] Unable to detach Files. No Vector Store attached."
+            ));
+        };
+
+        for file_id in file_ids {
+            //Get version-specific URL
+            let vector_store_file_resource = OpenAIAssistantResource::VectorStoreFile {
+                vector_store_id: vector_store_id.clone(),
+                file_id: file_id.to_string(),
+            };
+            let vector_store_file_url = self.version.get_endpoint(&vector_store_file_resource);
+
+            self.delete_resource(&vector_store_file_url, "Vector Store File")
+                .await?;
+
+            self.attached_file_ids.retain(|id| id != file_id);
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// This method deletes both the Thread and the Assistant from OpenAI
+    /// Files attached to messages are deleted as well if `delete_files_on_cleanup` is set
+    /// Attached Vector Stores and Files uploaded to them are not removed and need to be deleted separately
+    ///
+    pub async fn cleanup(&mut self) -> Result<()> {
+        if self.thread_id.is_some() {
+            self.delete_thread().await?;
+        }
+        if self.id.is_some() {
+            self.delete_assistant().await?;
+        }
+        if self.delete_files_on_cleanup {
+            self.delete_attached_files().await?;
+        }
+        Ok(())
+    }
+
+    /*
+     * This function deletes all Files that were attached to messages of the Thread
+     */
+    async fn delete_attached_files(&mut self) -> Result<()> {
+        while let Some(file_id) = self.attached_file_ids.first().cloned() {
+            let mut openai_file = OpenAIFile::new(Some(file_id), &self.api_key)
+                .version(self.version.clone())
+                .auth(self.auth.clone());
+            if self.debug {
+                openai_file = openai_file.debug();
+            }
+            openai_file.delete().await?;
+            self.attached_file_ids.remove(0);
+        }
+        Ok(())
+    }
+
+    // This function runs cleanup if auto cleanup was requested. Failures are only logged so that they don't hide the answer
+    async fn cleanup_if_requested(&mut self) {
+        if self.auto_cleanup {
+            if let Err(error) = self.cleanup().await {
+                warn!("[OpenAIAssistant] Auto cleanup failed: {:?}", error);
+            }
+        }
+    }
+
+    /*
+     * This function sends a DELETE request for an Assistants API resource
+     */
+    async fn delete_resource(&self, url: &str, resource_name: &str) -> Result<()> {
+        //Get version-specific headers
+        let version_headers = self.version.get_auth_headers(&self.api_key, &self.auth)?;
+
+        //Make the API call
+        let client = Client::new();
+
+        let response = client.delete(url).headers(version_headers).send().await?;
+
+        let response_status = response.status();
+        let response_text = response.text().await?;
+
+        if self.debug {
+            info!(
+                "[debug] OpenAI {} Delete API response: [{}] {:#?}",
+                resource_name, &response_status, &response_text
+            );
+        }
+
+        //Check if the resource was successfully deleted
+        serde_json::from_str::<OpenAIDeleteResp>(&response_text)
+            .map_err(|error| {
+                let error = AllmsError {
+                    crate_name: "allms".to_string(),
+                    module: "assistants::openai_assistant".to_string(),
+                    error_message: format!(
+                        "{} Delete API response serialization error: {}",
+                        resource_name, error
+                    ),
+                    error_detail: response_text,
+                };
+                error!("{:?}", error);
+                anyhow!("{:?}", error)
+            })
+            .and_then(|response| match response.deleted {
+                true => Ok(()),
+                false => Err(anyhow!(
+                    "[OpenAIAssistant] Failed to delete the {}.",
+                    resource_name
+                )),
+            })
+    }
+
     ///
     /// This method can be used to attach a Vector Store object to an Assistant
     ///
@@ -692,14 +1341,7 @@ impl OpenAIAssistant {
      */
     async fn attach_vector_store(&mut self, vector_store: &OpenAIVectorStore) -> Result<()> {
         // If the assistant and thread are not initialized we do that first
-        if self.id.is_none() {
-            //Call OpenAI API to get an ID for the assistant
-            self.create_assistant().await?;
-
-            //Add first message thus initializing the thread
-            self.add_message(OPENAI_ASSISTANT_INSTRUCTIONS, &Vec::new())
-                .await?;
-        }
+        self.initialize().await?;
 
         // Extract Vector Store ID
         let vector_store_id = if let Some(id) = &vector_store.id {
@@ -717,7 +1359,7 @@ impl OpenAIAssistant {
         let assistant_url = self.version.get_endpoint(&assistant_resource);
 
         //Get version-specific headers
-        let version_headers = self.version.get_headers(&self.api_key);
+        let version_headers = self.version.get_auth_headers(&self.api_key, &self.auth)?;
 
         let body = json!({
             "tool_resources": {
@@ -765,3 +1407,260 @@ impl OpenAIAssistant {
             .map(|_| Ok(()))?
     }
 }
+
+// Events of a streamed run that are relevant for the answer
+enum OpenAIRunStreamEvent {
+    Delta(String),
+    Done,
+    Error(anyhow::Error),
+    Other,
+}
+
+// Serializes the dataset into a message labeled with the dataset name
+fn get_context_message<T: Serialize>(dataset_name: &str, data: &T) -> Result<String> {
+    let serialized_data = serde_json::to_string(&data)
+        .map_err(|_| anyhow!("Unable serialize provided input data."))?;
+    Ok(format!("'{dataset_name}'= {serialized_data}"))
+}
+
+// Combines the labeled datasets into messages, starting a new message when the next dataset would exceed the maximum message length
+// Datasets longer than the limit are sent in a message of their own
+fn get_contexts_messages<T: Serialize>(
+    datasets: &[(&str, &T)],
+    max_chars: usize,
+) -> Result<Vec<String>> {
+    let mut messages: Vec<String> = Vec::new();
+    for (dataset_name, data) in datasets {
+        let context_message = get_context_message(dataset_name, data)?;
+        match messages.last_mut() {
+            Some(message) if message.len() + 1 + context_message.len() <= max_chars => {
+                message.push('\n');
+                message.push_str(&context_message);
+            }
+            _ => messages.push(context_message),
+        }
+    }
+    Ok(messages)
+}
+
+// Returns an error if the run ended without completing, e.g. it failed, expired or was cancelled
+fn check_run_status(status: &OpenAIRunStatus) -> Result<()> {
+    match status {
+        OpenAIRunStatus::Cancelling
+        | OpenAIRunStatus::Cancelled
+        | OpenAIRunStatus::Failed
+        | OpenAIRunStatus::Expired
+        | OpenAIRunStatus::Incomplete => {
+            let error = AllmsError {
+                crate_name: "allms".to_string(),
+                module: "assistants::openai_assistant".to_string(),
+                error_message: "Run did not complete".to_string(),
+                error_detail: format!("Status: {:?}", status),
+            };
+            error!("{:?}", error);
+            Err(anyhow!("{:?}", error))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Parses a single Server-Sent Event of a streamed run
+fn parse_run_stream_event(event: &str) -> OpenAIRunStreamEvent {
+    let mut event_name = "";
+    let mut data = String::new();
+    for line in event.lines() {
+        if let Some(name) = line.strip_prefix("event:") {
+            event_name = name.trim();
+        } else if let Some(line_data) = line.strip_prefix("data:") {
+            data.push_str(line_data.trim());
+        }
+    }
+
+    match event_name {
+        "thread.message.delta" => match serde_json::from_str::<OpenAIMessageDeltaResp>(&data) {
+            Ok(message_delta) => {
+                let text: String = message_delta
+                    .delta
+                    .content
+                    .into_iter()
+                    .filter_map(|content| content.text.and_then(|text| text.value))
+                    .collect();
+                match text.is_empty() {
+                    true => OpenAIRunStreamEvent::Other,
+                    false => OpenAIRunStreamEvent::Delta(text),
+                }
+            }
+            Err(error) => OpenAIRunStreamEvent::Error(anyhow!(
+                "[OpenAIAssistant] Unable to parse message delta: {}. Data: {}",
+                error,
+                data
+            )),
+        },
+        //Function calls are only handled when polling the run (non-streaming calls), so `stream_answer` rejects assistants with function tools
+        "thread.run.requires_action"
+        | "thread.run.failed"
+        | "thread.run.cancelling"
+        | "thread.run.cancelled"
+        | "thread.run.expired"
+        | "thread.run.incomplete"
+        | "error" => {
+            let error = AllmsError {
+                crate_name: "allms".to_string(),
+                module: "assistants::openai_assistant".to_string(),
+                error_message: format!("Run stream ended with event: {}", event_name),
+                error_detail: data,
+            };
+            error!("{:?}", error);
+            OpenAIRunStreamEvent::Error(anyhow!("{:?}", error))
+        }
+        "done" => OpenAIRunStreamEvent::Done,
+        _ => OpenAIRunStreamEvent::Other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use schemars::JsonSchema;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::{
+        check_run_status, get_contexts_messages, parse_run_stream_event, OpenAIAssistant,
+        OpenAIRunStreamEvent,
+    };
+    use crate::assistants::OpenAIFunctionTool;
+    use crate::constants::OPENAI_ASSISTANT_INSTRUCTIONS;
+    use crate::enums::OpenAIRunStatus;
+    use crate::llm_models::OpenAIModels;
+
+    #[derive(Deserialize, JsonSchema, Debug)]
+    struct Answer {
+        _value: String,
+    }
+
+    #[test]
+    fn test_check_run_status() {
+        assert!(check_run_status(&OpenAIRunStatus::Queued).is_ok());
+        assert!(check_run_status(&OpenAIRunStatus::InProgress).is_ok());
+        for status in [
+            OpenAIRunStatus::Failed,
+            OpenAIRunStatus::Cancelled,
+            OpenAIRunStatus::Expired,
+            OpenAIRunStatus::Incomplete,
+        ] {
+            let error = check_run_status(&status).unwrap_err();
+            assert!(error.to_string().contains(&format!("{:?}", status)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_answer_rejects_function_tools() {
+        let mut assistant =
+            OpenAIAssistant::new(OpenAIModels::Gpt4o, "key").add_function(OpenAIFunctionTool::new(
+                "get_weather",
+                "Returns the weather in a city",
+                json!({"type": "object", "properties": {"city": {"type": "string"}}}),
+                |_| Ok("sunny".to_string()),
+            ));
+        let error = assistant
+            .stream_answer::<Answer>("What is the weather in Paris?", &[])
+            .await
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("function tools"));
+        //The check is made before any API call
+        assert!(assistant.assistant_id().is_none());
+    }
+
+    #[test]
+    fn test_get_contexts_messages() {
+        let sales = vec![120, 95];
+        let returns = vec![3];
+        let messages =
+            get_contexts_messages(&[("sales", &sales), ("returns", &returns)], 1000).unwrap();
+        assert_eq!(
+            messages,
+            vec!["'sales'= [120,95]\n'returns'= [3]".to_string()]
+        );
+
+        // Datasets that don't fit in the current message start a new one
+        let messages =
+            get_contexts_messages(&[("sales", &sales), ("returns", &returns)], 20).unwrap();
+        assert_eq!(messages, vec!["'sales'= [120,95]", "'returns'= [3]"]);
+
+        let empty: Vec<(&str, &Vec<u32>)> = Vec::new();
+        assert!(get_contexts_messages(&empty, 1000).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_from_ids() {
+        let assistant = OpenAIAssistant::from_ids(
+            OpenAIModels::Gpt4o,
+            "key",
+            "asst_123".to_string(),
+            Some("thread_456".to_string()),
+        );
+        assert_eq!(assistant.assistant_id(), Some("asst_123".to_string()));
+        assert_eq!(assistant.thread_id(), Some("thread_456".to_string()));
+        assert!(assistant.validate_ids);
+
+        let assistant =
+            OpenAIAssistant::from_ids(OpenAIModels::Gpt4o, "key", "asst_123".to_string(), None);
+        assert!(assistant.thread_id().is_none());
+    }
+
+    #[test]
+    fn test_assistant_body() {
+        let default_body = OpenAIAssistant::new(OpenAIModels::Gpt4o, "key").get_assistant_body();
+        assert_eq!(default_body["instructions"], OPENAI_ASSISTANT_INSTRUCTIONS);
+        assert!(default_body.get("name").is_none());
+        assert!(default_body.get("description").is_none());
+
+        let body = OpenAIAssistant::new(OpenAIModels::Gpt4o, "key")
+            .with_instructions("You are a concise analyst.".to_string())
+            .with_name("Report analyst".to_string())
+            .with_description("Extracts data from reports".to_string())
+            .get_assistant_body();
+        assert_eq!(body["instructions"], "You are a concise analyst.");
+        assert_eq!(body["name"], "Report analyst");
+        assert_eq!(body["description"], "Extracts data from reports");
+    }
+
+    #[test]
+    fn test_parse_run_stream_message_delta() {
+        let event = "event: thread.message.delta\ndata: {\"id\":\"msg_123\",\"object\":\"thread.message.delta\",\"delta\":{\"content\":[{\"index\":0,\"type\":\"text\",\"text\":{\"value\":\"{\\\"name\\\"\",\"annotations\":[]}}]}}\n\n";
+        match parse_run_stream_event(event) {
+            OpenAIRunStreamEvent::Delta(text) => assert_eq!(text, "{\"name\""),
+            _ => panic!("Expected a text delta"),
+        }
+    }
+
+    #[test]
+    fn test_parse_run_stream_terminal_events() {
+        let requires_action = "event: thread.run.requires_action\ndata: {\"id\":\"run_123\",\"status\":\"requires_action\"}\n\n";
+        assert!(matches!(
+            parse_run_stream_event(requires_action),
+            OpenAIRunStreamEvent::Error(_)
+        ));
+
+        let failed =
+            "event: thread.run.failed\ndata: {\"id\":\"run_123\",\"status\":\"failed\"}\n\n";
+        assert!(matches!(
+            parse_run_stream_event(failed),
+            OpenAIRunStreamEvent::Error(_)
+        ));
+
+        let done = "event: done\ndata: [DONE]\n\n";
+        assert!(matches!(
+            parse_run_stream_event(done),
+            OpenAIRunStreamEvent::Done
+        ));
+
+        let created =
+            "event: thread.run.created\ndata: {\"id\":\"run_123\",\"status\":\"queued\"}\n\n";
+        assert!(matches!(
+            parse_run_stream_event(created),
+            OpenAIRunStreamEvent::Other
+        ));
+    }
+}
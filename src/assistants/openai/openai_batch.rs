@@ -0,0 +1,651 @@
+use anyhow::{anyhow, Result};
+use log::{error, info};
+use reqwest::{Client, StatusCode, Url};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::time;
+use tokio::time::timeout;
+
+use crate::assistants::{
+    OpenAIAssistantResource, OpenAIAssistantVersion, OpenAIAuth, OpenAIFile, OpenAIFilePurpose,
+};
+use crate::domain::AllmsError;
+use crate::llm_models::{LLMModel, OpenAIModels};
+use crate::Completions;
+
+/// [OpenAI Docs](https://platform.openai.com/docs/guides/batch)
+///
+/// The Batch API allows to send asynchronous groups of requests with 50% lower costs and a 24h turnaround time.
+/// Each request is prepared using the `Completions` API so the prompt, schema and sanitization pipeline are the same as for a single call.
+pub struct OpenAIBatch {
+    pub id: Option<String>,
+    api_key: String,
+    debug: bool,
+    version: OpenAIAssistantVersion,
+    requests: Vec<OpenAIBatchRequest>,
+    status: Option<OpenAIBatchStatus>,
+    output_file_id: Option<String>,
+    error_file_id: Option<String>,
+    poll_interval: Duration,
+    timeout: Duration,
+    auth: OpenAIAuth,
+}
+
+// A single request of the batch together with the Completions instance used to parse its response
+struct OpenAIBatchRequest {
+    custom_id: String,
+    endpoint: String,
+    body: Value,
+    completions: Completions<OpenAIModels>,
+}
+
+impl OpenAIBatch {
+    /// Constructor
+    pub fn new(api_key: &str) -> Self {
+        OpenAIBatch {
+            id: None,
+            api_key: api_key.to_string(),
+            debug: false,
+            version: OpenAIAssistantVersion::V1,
+            requests: Vec::new(),
+            status: None,
+            output_file_id: None,
+            error_file_id: None,
+            poll_interval: Duration::from_secs(60),
+            timeout: Duration::from_secs(24 * 60 * 60),
+            auth: OpenAIAuth::ApiKey,
+        }
+    }
+
+    ///
+    /// This method can be used to turn on debug mode for the OpenAIBatch struct
+    ///
+    pub fn debug(mut self) -> Self {
+        self.debug = true;
+        self
+    }
+
+    ///
+    /// This method can be used to set the API version used to call Files and Batches endpoints
+    /// Current default is V1
+    ///
+    pub fn version(mut self, version: OpenAIAssistantVersion) -> Self {
+        // Files endpoint currently requires v1 so if v2 is selected we overwrite
+        let version = match version {
+            OpenAIAssistantVersion::V2 => OpenAIAssistantVersion::V1,
+            _ => version,
+        };
+        self.version = version;
+        self
+    }
+
+    ///
+    /// This method can be used to set the authentication method, e.g. Azure AD (Entra ID) tokens instead of API keys
+    /// Default is `OpenAIAuth::ApiKey` which uses the key provided in the constructor
+    ///
+    pub fn auth(mut self, auth: OpenAIAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    ///
+    /// This method can be used to set how often the status of the batch is checked
+    /// Current default is 60 seconds
+    ///
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    ///
+    /// This method can be used to set how long to wait for the batch to complete
+    /// Current default is 24 hours, which matches the batch completion window
+    ///
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    ///
+    /// This method adds a request to the batch. The body is built by the provided `Completions` instance for the given instructions and output type.
+    /// All requests in the batch need to target the same endpoint and should use the same output type.
+    ///
+    pub fn add_request<U: JsonSchema + DeserializeOwned>(
+        mut self,
+        custom_id: &str,
+        completions: Completions<OpenAIModels>,
+        instructions: &str,
+    ) -> Result<Self> {
+        if self
+            .requests
+            .iter()
+            .any(|request| request.custom_id == custom_id)
+        {
+            return Err(anyhow!(
+                "[OpenAI][Batch API] Duplicate custom_id: {}",
+                custom_id
+            ));
+        }
+
+        let body = completions.build_request_body::<U>(instructions)?;
+
+        // Batch API expects the relative path of the endpoint, e.g. /v1/chat/completions
+        let endpoint = Url::parse(&completions.model().get_endpoint())?
+            .path()
+            .to_string();
+
+        if let Some(request) = self.requests.first() {
+            if request.endpoint != endpoint {
+                return Err(anyhow!(
+                    "[OpenAI][Batch API] All requests need to use the same endpoint. Expected {}, got {}.",
+                    request.endpoint,
+                    endpoint
+                ));
+            }
+        }
+
+        self.requests.push(OpenAIBatchRequest {
+            custom_id: custom_id.to_string(),
+            endpoint,
+            body,
+            completions,
+        });
+        Ok(self)
+    }
+
+    ///
+    /// This method performs all the orchestration needed to get batch results: submits the batch, waits for it to complete and parses the output
+    ///
+    pub async fn run<U: JsonSchema + DeserializeOwned>(
+        mut self,
+    ) -> Result<Vec<(String, Result<U>)>> {
+        self.submit().await?;
+        self.wait().await?;
+        self.get_results::<U>().await
+    }
+
+    ///
+    /// This method uploads the batch input file and creates the batch
+    ///
+    pub async fn submit(&mut self) -> Result<()> {
+        let endpoint = if let Some(request) = self.requests.first() {
+            request.endpoint.clone()
+        } else {
+            return Err(anyhow!(
+                "[OpenAI][Batch API] Unable to submit a batch without requests."
+            ));
+        };
+
+        // Each line of the input file is a separate request
+        let input_jsonl = self
+            .requests
+            .iter()
+            .map(|request| {
+                serde_json::to_string(&json!({
+                    "custom_id": request.custom_id,
+                    "method": "POST",
+                    "url": request.endpoint,
+                    "body": request.body,
+                }))
+            })
+            .collect::<Result<Vec<String>, _>>()?
+            .join("\n");
+
+        let input_file = OpenAIFile::new(None, &self.api_key)
+            .version(self.version.clone())
+            .auth(self.auth.clone())
+            .purpose(OpenAIFilePurpose::Batch)
+            .upload("batch_input.jsonl", input_jsonl.into_bytes())
+            .await?;
+
+        let batches_url = self.version.get_endpoint(&OpenAIAssistantResource::Batches);
+        let version_headers = self.version.get_auth_headers(&self.api_key, &self.auth)?;
+
+        let body = json!({
+            "input_file_id": input_file.id,
+            "endpoint": endpoint,
+            "completion_window": "24h",
+        });
+
+        //Make the API call
+        let client = Client::new();
+
+        let response = client
+            .post(batches_url)
+            .headers(version_headers)
+            .json(&body)
+            .send()
+            .await?;
+
+        let response_status = response.status();
+        let response_text = response.text().await?;
+
+        if self.debug {
+            info!(
+                "[debug] OpenAI Batch Create API response: [{}] {:#?}",
+                &response_status, &response_text
+            );
+        }
+
+        let response_deser = self.deserialize_batch(response_status, response_text, "Create")?;
+
+        self.id = Some(response_deser.id);
+        self.status = Some(response_deser.status);
+        self.output_file_id = response_deser.output_file_id;
+        self.error_file_id = response_deser.error_file_id;
+
+        Ok(())
+    }
+
+    ///
+    /// This method checks the status of the batch
+    ///
+    pub async fn status(&mut self) -> Result<OpenAIBatchStatus> {
+        let batch_id = if let Some(id) = &self.id {
+            id.to_string()
+        } else {
+            return Err(anyhow!(
+                "[OpenAI][Batch API] Unable to check status. No ID provided."
+            ));
+        };
+
+        let batch_url = self
+            .version
+            .get_endpoint(&OpenAIAssistantResource::Batch { batch_id });
+        let version_headers = self.version.get_auth_headers(&self.api_key, &self.auth)?;
+
+        //Make the API call
+        let client = Client::new();
+
+        let response = client
+            .get(batch_url)
+            .headers(version_headers)
+            .send()
+            .await?;
+
+        let response_status = response.status();
+        let response_text = response.text().await?;
+
+        if self.debug {
+            info!(
+                "[debug] OpenAI Batch Status API response: [{}] {:#?}",
+                &response_status, &response_text
+            );
+        }
+
+        let response_deser = self.deserialize_batch(response_status, response_text, "Status")?;
+
+        self.status = Some(response_deser.status.clone());
+        self.output_file_id = response_deser.output_file_id;
+        self.error_file_id = response_deser.error_file_id;
+
+        Ok(response_deser.status)
+    }
+
+    ///
+    /// This method polls the status of the batch until it is completed or the timeout is reached
+    ///
+    pub async fn wait(&mut self) -> Result<()> {
+        let operation_timeout = self.timeout;
+        let poll_interval = self.poll_interval;
+
+        timeout(operation_timeout, async {
+            let mut interval = time::interval(poll_interval);
+            loop {
+                interval.tick().await; // Wait for the next interval tick
+                match self.status().await? {
+                    OpenAIBatchStatus::Completed => break Ok(()),
+                    OpenAIBatchStatus::Failed
+                    | OpenAIBatchStatus::Expired
+                    | OpenAIBatchStatus::Cancelling
+                    | OpenAIBatchStatus::Cancelled => {
+                        break Err(anyhow!(
+                            "[OpenAI][Batch API] Batch did not complete. Status: {:?}",
+                            self.status
+                        ));
+                    }
+                    // Keep polling if validating, in_progress or finalizing
+                    _ => continue,
+                }
+            }
+        })
+        .await
+        .map_err(|_| anyhow!("[OpenAI][Batch API] Timeout waiting for the batch."))?
+    }
+
+    ///
+    /// This method downloads the output and error files of a completed batch and parses the response of each request into the expected type
+    /// Requests that failed are returned with their error. If all requests failed an error is returned for the whole batch.
+    ///
+    pub async fn get_results<U: JsonSchema + DeserializeOwned>(
+        &self,
+    ) -> Result<Vec<(String, Result<U>)>> {
+        // Failed requests are written to a separate error file
+        let error_lines = match &self.error_file_id {
+            Some(error_file_id) => {
+                parse_batch_output(&self.download_file(error_file_id, "Error File").await?)?
+            }
+            None => Vec::new(),
+        };
+
+        // The output file is not created if all requests failed
+        let mut output_lines = match &self.output_file_id {
+            Some(output_file_id) => {
+                parse_batch_output(&self.download_file(output_file_id, "Output File").await?)?
+            }
+            None if !error_lines.is_empty() => {
+                let error = AllmsError {
+                    crate_name: "allms".to_string(),
+                    module: "assistants::openai_batch".to_string(),
+                    error_message: format!(
+                        "All {} requests of the batch failed. Status: {:?}",
+                        error_lines.len(),
+                        self.status
+                    ),
+                    error_detail: serde_json::to_string(&error_lines)?,
+                };
+                error!("{:?}", error);
+                return Err(anyhow!("{:?}", error));
+            }
+            None => {
+                return Err(anyhow!(
+                    "[OpenAI][Batch API] No output file found. Status: {:?}",
+                    self.status
+                ));
+            }
+        };
+        output_lines.extend(error_lines);
+
+        // Results are returned in the order the requests were added
+        Ok(self
+            .requests
+            .iter()
+            .map(|request| {
+                let result = output_lines
+                    .iter()
+                    .find(|line| line.custom_id == request.custom_id)
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "[OpenAI][Batch API] No output found for request: {}",
+                            request.custom_id
+                        )
+                    })
+                    .and_then(|line| parse_batch_output_line::<U>(line, &request.completions));
+                (request.custom_id.clone(), result)
+            })
+            .collect())
+    }
+
+    // Downloads the content of a batch output or error file
+    async fn download_file(&self, file_id: &str, operation: &str) -> Result<String> {
+        let file_url = self
+            .version
+            .get_endpoint(&OpenAIAssistantResource::FileContent {
+                file_id: file_id.to_string(),
+            });
+        let version_headers = self.version.get_auth_headers(&self.api_key, &self.auth)?;
+
+        //Make the API call
+        let client = Client::new();
+
+        let response = client.get(file_url).headers(version_headers).send().await?;
+
+        let response_status = response.status();
+        let response_text = response.text().await?;
+
+        if self.debug {
+            info!(
+                "[debug] OpenAI Batch {} API response: [{}] {:#?}",
+                operation, &response_status, &response_text
+            );
+        }
+
+        check_response_status(response_status, &response_text, operation)?;
+
+        Ok(response_text)
+    }
+
+    // Deserializes the Batch object returned by the API
+    fn deserialize_batch(
+        &self,
+        response_status: StatusCode,
+        response_text: String,
+        operation: &str,
+    ) -> Result<OpenAIBatchResp> {
+        check_response_status(response_status, &response_text, operation)?;
+
+        serde_json::from_str::<OpenAIBatchResp>(&response_text).map_err(|error| {
+            let error = AllmsError {
+                crate_name: "allms".to_string(),
+                module: "assistants::openai_batch".to_string(),
+                error_message: format!(
+                    "Batch {} API response serialization error: {}",
+                    operation, error
+                ),
+                error_detail: response_text,
+            };
+            error!("{:?}", error);
+            anyhow!("{:?}", error)
+        })
+    }
+}
+
+// Errors are returned as a regular Json response
+fn check_response_status(
+    response_status: StatusCode,
+    response_text: &str,
+    operation: &str,
+) -> Result<()> {
+    if response_status.is_success() {
+        return Ok(());
+    }
+    let error = AllmsError {
+        crate_name: "allms".to_string(),
+        module: "assistants::openai_batch".to_string(),
+        error_message: format!(
+            "Batch {} API request failed: {}",
+            operation, response_status
+        ),
+        error_detail: response_text.to_string(),
+    };
+    error!("{:?}", error);
+    Err(anyhow!("{:?}", error))
+}
+
+// Parses the response of a single request, returning an error if the request failed
+fn parse_batch_output_line<U: JsonSchema + DeserializeOwned>(
+    line: &OpenAIBatchOutputLine,
+    completions: &Completions<OpenAIModels>,
+) -> Result<U> {
+    match (&line.response, &line.error) {
+        (_, Some(error)) => Err(anyhow!(
+            "[OpenAI][Batch API] Request {} failed: {:?}",
+            line.custom_id,
+            error
+        )),
+        (Some(response), None) if !(200..300).contains(&response.status_code) => {
+            let error = AllmsError {
+                crate_name: "allms".to_string(),
+                module: "assistants::openai_batch".to_string(),
+                error_message: format!(
+                    "Request {} failed with status code {}",
+                    line.custom_id, response.status_code
+                ),
+                error_detail: response.body.to_string(),
+            };
+            error!("{:?}", error);
+            Err(anyhow!("{:?}", error))
+        }
+        (Some(response), None) => {
+            let response_text = serde_json::to_string(&response.body)?;
+            completions.parse_response::<U>(&response_text)
+        }
+        (None, None) => Err(anyhow!(
+            "[OpenAI][Batch API] Empty output for request: {}",
+            line.custom_id
+        )),
+    }
+}
+
+// Parses the JSONL output file of a batch
+fn parse_batch_output(output: &str) -> Result<Vec<OpenAIBatchOutputLine>> {
+    output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str::<OpenAIBatchOutputLine>(line).map_err(|error| {
+                let error = AllmsError {
+                    crate_name: "allms".to_string(),
+                    module: "assistants::openai_batch".to_string(),
+                    error_message: format!("Batch output serialization error: {}", error),
+                    error_detail: line.to_string(),
+                };
+                error!("{:?}", error);
+                anyhow!("{:?}", error)
+            })
+        })
+        .collect()
+}
+
+/******************************************************************************************
+*
+* API Response serialization / deserialization structs
+*
+******************************************************************************************/
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct OpenAIBatchResp {
+    id: String,
+    status: OpenAIBatchStatus,
+    input_file_id: Option<String>,
+    output_file_id: Option<String>,
+    error_file_id: Option<String>,
+    request_counts: Option<OpenAIBatchRequestCounts>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OpenAIBatchRequestCounts {
+    pub total: i32,
+    pub completed: i32,
+    pub failed: i32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub enum OpenAIBatchStatus {
+    #[serde(rename(deserialize = "validating", serialize = "validating"))]
+    Validating,
+    #[serde(rename(deserialize = "failed", serialize = "failed"))]
+    Failed,
+    #[serde(rename(deserialize = "in_progress", serialize = "in_progress"))]
+    InProgress,
+    #[serde(rename(deserialize = "finalizing", serialize = "finalizing"))]
+    Finalizing,
+    #[serde(rename(deserialize = "completed", serialize = "completed"))]
+    Completed,
+    #[serde(rename(deserialize = "expired", serialize = "expired"))]
+    Expired,
+    #[serde(rename(deserialize = "cancelling", serialize = "cancelling"))]
+    Cancelling,
+    #[serde(rename(deserialize = "cancelled", serialize = "cancelled"))]
+    Cancelled,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct OpenAIBatchOutputLine {
+    custom_id: String,
+    response: Option<OpenAIBatchOutputResponse>,
+    error: Option<Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct OpenAIBatchOutputResponse {
+    status_code: u16,
+    body: Value,
+}
+
+#[cfg(test)]
+mod tests {
+    use schemars::JsonSchema;
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Deserialize, JsonSchema, Debug)]
+    struct Translation {
+        spanish: String,
+    }
+
+    #[test]
+    fn test_add_request_rejects_duplicate_custom_id() {
+        let batch = OpenAIBatch::new("key")
+            .add_request::<Translation>(
+                "request-1",
+                Completions::new(OpenAIModels::Gpt4oMini, "key", None, None),
+                "Translate 'hello'",
+            )
+            .unwrap();
+        assert_eq!(batch.requests[0].endpoint, "/v1/chat/completions");
+
+        let result = batch.add_request::<Translation>(
+            "request-1",
+            Completions::new(OpenAIModels::Gpt4oMini, "key", None, None),
+            "Translate 'bye'",
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_batch_output() {
+        let output = r#"{"id": "batch_req_1", "custom_id": "request-1", "response": {"status_code": 200, "request_id": "req_1", "body": {"id": "chatcmpl-1", "object": "chat.completion", "created": 1711652795, "model": "gpt-4o-mini", "choices": [{"index": 0, "message": {"role": "assistant", "content": "{\"spanish\": \"hola\"}"}, "finish_reason": "stop"}]}}, "error": null}
+{"id": "batch_req_2", "custom_id": "request-2", "response": null, "error": {"code": "server_error", "message": "Internal error"}}
+"#;
+        let lines = parse_batch_output(output).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].custom_id, "request-1");
+        assert_eq!(lines[0].response.as_ref().unwrap().status_code, 200);
+        assert!(lines[1].response.is_none());
+        assert!(lines[1].error.is_some());
+
+        // The response body is parsed the same way as a regular Completions response
+        let completions =
+            Completions::new(OpenAIModels::Gpt4oMini, "key", None, None).function_calling(false);
+        let body = serde_json::to_string(&lines[0].response.as_ref().unwrap().body).unwrap();
+        let translation = completions.parse_response::<Translation>(&body).unwrap();
+        assert_eq!(translation.spanish, "hola");
+    }
+
+    #[test]
+    fn test_parse_batch_output_line_errors() {
+        let completions =
+            Completions::new(OpenAIModels::Gpt4oMini, "key", None, None).function_calling(false);
+        let output = r#"{"id": "batch_req_1", "custom_id": "request-1", "response": {"status_code": 400, "request_id": "req_1", "body": {"error": {"message": "Invalid model", "type": "invalid_request_error"}}}, "error": null}
+{"id": "batch_req_2", "custom_id": "request-2", "response": null, "error": {"code": "server_error", "message": "Internal error"}}
+{"id": "batch_req_3", "custom_id": "request-3", "response": null, "error": null}
+"#;
+        let lines = parse_batch_output(output).unwrap();
+
+        let error = parse_batch_output_line::<Translation>(&lines[0], &completions).unwrap_err();
+        assert!(error.to_string().contains("status code 400"));
+        assert!(error.to_string().contains("Invalid model"));
+
+        let error = parse_batch_output_line::<Translation>(&lines[1], &completions).unwrap_err();
+        assert!(error.to_string().contains("server_error"));
+
+        assert!(parse_batch_output_line::<Translation>(&lines[2], &completions).is_err());
+    }
+
+    #[test]
+    fn test_check_response_status() {
+        assert!(check_response_status(StatusCode::OK, "{}", "Create").is_ok());
+        let error = check_response_status(
+            StatusCode::UNAUTHORIZED,
+            r#"{"error": {"message": "Invalid API key"}}"#,
+            "Create",
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("401"));
+        assert!(error.to_string().contains("Invalid API key"));
+    }
+}
@@ -1,11 +1,12 @@
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, Result};
 use log::{error, info};
 use reqwest::{header, multipart, Client};
 use serde::{Deserialize, Serialize};
 use std::path::Path;
 
-use crate::assistants::{OpenAIAssistantResource, OpenAIAssistantVersion};
+use crate::assistants::{OpenAIAssistantResource, OpenAIAssistantVersion, OpenAIAuth};
 use crate::domain::AllmsError;
+use crate::utils::{get_file_part, get_file_stream_part};
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct OpenAIFile {
@@ -13,6 +14,26 @@ pub struct OpenAIFile {
     debug: bool,
     api_key: String,
     version: OpenAIAssistantVersion,
+    purpose: OpenAIFilePurpose,
+    #[serde(skip)]
+    auth: OpenAIAuth,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub enum OpenAIFilePurpose {
+    #[serde(rename(deserialize = "assistants", serialize = "assistants"))]
+    Assistants,
+    #[serde(rename(deserialize = "batch", serialize = "batch"))]
+    Batch,
+}
+
+impl OpenAIFilePurpose {
+    pub(crate) fn as_str(&self) -> &str {
+        match self {
+            OpenAIFilePurpose::Assistants => "assistants",
+            OpenAIFilePurpose::Batch => "batch",
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -35,6 +56,8 @@ impl OpenAIFile {
             debug: false,
             api_key: open_ai_key.to_string(),
             version: OpenAIAssistantVersion::V1, // Default to V1
+            purpose: OpenAIFilePurpose::Assistants,
+            auth: OpenAIAuth::ApiKey,
         }
     }
 
@@ -61,60 +84,51 @@ impl OpenAIFile {
     }
 
     ///
-    /// This function uploads a file to OpenAI and assigns it for use with Assistant API
+    /// This method can be used to set the authentication method, e.g. Azure AD (Entra ID) tokens instead of API keys
+    /// Default is `OpenAIAuth::ApiKey` which uses the key provided in the constructor
+    ///
+    pub fn auth(mut self, auth: OpenAIAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    ///
+    /// This method can be used to set the intended purpose of the uploaded file
+    /// Current default is Assistants
+    ///
+    pub fn purpose(mut self, purpose: OpenAIFilePurpose) -> Self {
+        self.purpose = purpose;
+        self
+    }
+
+    ///
+    /// This function uploads a file to OpenAI and assigns it for use with Assistant API (or other API as per the file purpose)
+    ///
+    pub async fn upload(self, file_name: &str, file_bytes: Vec<u8>) -> Result<Self> {
+        let file_part = get_file_part(file_name, file_bytes)?;
+        self.upload_part(file_part).await
+    }
+
+    ///
+    /// This function works like `upload` but streams the file from disk instead of loading it into memory, e.g. for large datasets
+    /// The file name sent to OpenAI and the MIME type are based on the path
     ///
-    pub async fn upload(mut self, file_name: &str, file_bytes: Vec<u8>) -> Result<Self> {
+    pub async fn upload_from_path(self, path: &Path) -> Result<Self> {
+        let file_part = get_file_stream_part(path).await?;
+        self.upload_part(file_part).await
+    }
+
+    // Sends the multipart form with the file part to the Files API
+    async fn upload_part(mut self, file_part: multipart::Part) -> Result<Self> {
         let files_url = self.version.get_endpoint(&OpenAIAssistantResource::Files);
 
         // This API sends a form so content type is automatically set by multipart method
-        let mut version_headers = self.version.get_headers(&self.api_key);
+        let mut version_headers = self.version.get_auth_headers(&self.api_key, &self.auth)?;
         version_headers.remove(header::CONTENT_TYPE);
 
-        // Determine MIME type based on file extension
-        // OpenAI documentation: https://platform.openai.com/docs/assistants/tools/supported-files
-        let mime_type = match Path::new(file_name)
-            .extension()
-            .and_then(std::ffi::OsStr::to_str)
-        {
-            Some("pdf") => "application/pdf",
-            Some("json") => "application/json",
-            Some("txt") => "text/plain",
-            Some("html") => "text/html",
-            Some("c") => "text/x-c",
-            Some("cpp") => "text/x-c++",
-            Some("docx") => {
-                "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
-            }
-            Some("java") => "text/x-java",
-            Some("md") => "text/markdown",
-            Some("php") => "text/x-php",
-            Some("pptx") => {
-                "application/vnd.openxmlformats-officedocument.presentationml.presentation"
-            }
-            Some("py") => "text/x-python",
-            Some("rb") => "text/x-ruby",
-            Some("tex") => "text/x-tex",
-            //The below are currently only supported for Code Interpreter but NOT Retrieval
-            Some("css") => "text/css",
-            Some("jpeg") | Some("jpg") => "image/jpeg",
-            Some("js") => "text/javascript",
-            Some("gif") => "image/gif",
-            Some("png") => "image/png",
-            Some("tar") => "application/x-tar",
-            Some("ts") => "application/typescript",
-            Some("xlsx") => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
-            Some("xml") => "application/xml",
-            Some("zip") => "application/zip",
-            _ => anyhow::bail!("Unsupported file type"),
-        };
-
-        let form = multipart::Form::new().text("purpose", "assistants").part(
-            "file",
-            multipart::Part::bytes(file_bytes)
-                .file_name(file_name.to_string())
-                .mime_str(mime_type)
-                .context("Failed to set MIME type")?,
-        );
+        let form = multipart::Form::new()
+            .text("purpose", self.purpose.as_str().to_string())
+            .part("file", file_part);
 
         //Make the API call
         let client = Client::new();
@@ -170,7 +184,7 @@ impl OpenAIFile {
             file_id: file_id.to_string(),
         };
         let files_url = self.version.get_endpoint(&files_resource);
-        let version_headers = self.version.get_headers(&self.api_key);
+        let version_headers = self.version.get_auth_headers(&self.api_key, &self.auth)?;
 
         //Make the API call
         let client = Client::new();
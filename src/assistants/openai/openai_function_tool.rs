@@ -0,0 +1,165 @@
+use anyhow::{anyhow, Result};
+use log::error;
+use serde_json::{json, Value};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::domain::{AllmsError, OpenAIRunToolCall};
+
+/// Callback invoked with the parsed arguments of a function call. The returned string is submitted as the output of the call
+pub type OpenAIFunctionHandler = Arc<dyn Fn(Value) -> Result<String> + Send + Sync>;
+
+///
+/// Function tool that can be registered with `OpenAIAssistant::add_function`
+/// When a run requires action the Assistant invokes the handler matching the name of the called function
+///
+#[derive(Clone)]
+pub struct OpenAIFunctionTool {
+    pub name: String,
+    pub description: String,
+    pub parameters: Value,
+    handler: OpenAIFunctionHandler,
+}
+
+impl fmt::Debug for OpenAIFunctionTool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpenAIFunctionTool")
+            .field("name", &self.name)
+            .field("description", &self.description)
+            .field("parameters", &self.parameters)
+            .finish()
+    }
+}
+
+impl OpenAIFunctionTool {
+    /// Constructor. `parameters` is the JSON schema of the function arguments
+    pub fn new<F>(name: &str, description: &str, parameters: Value, handler: F) -> Self
+    where
+        F: Fn(Value) -> Result<String> + Send + Sync + 'static,
+    {
+        OpenAIFunctionTool {
+            name: name.to_string(),
+            description: description.to_string(),
+            parameters,
+            handler: Arc::new(handler),
+        }
+    }
+
+    // Definition of the tool sent when creating the Assistant
+    pub(crate) fn get_tool_payload(&self) -> Value {
+        json!({
+            "type": "function",
+            "function": {
+                "name": self.name,
+                "description": self.description,
+                "parameters": self.parameters,
+            }
+        })
+    }
+
+    // This function finds the handler for each tool call, invokes it and returns the payload for the `submit_tool_outputs` endpoint
+    pub(crate) fn get_tool_outputs(
+        tools: &[OpenAIFunctionTool],
+        tool_calls: &[OpenAIRunToolCall],
+    ) -> Result<Value> {
+        let tool_outputs = tool_calls
+            .iter()
+            .map(|tool_call| {
+                let tool = tools
+                    .iter()
+                    .find(|tool| tool.name == tool_call.function.name)
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "[OpenAIAssistant] No handler registered for function: {}",
+                            tool_call.function.name
+                        )
+                    })?;
+
+                let arguments: Value = serde_json::from_str(&tool_call.function.arguments)
+                    .map_err(|error| {
+                        let error = AllmsError {
+                            crate_name: "allms".to_string(),
+                            module: "assistants::openai_function_tool".to_string(),
+                            error_message: format!(
+                                "Function call arguments deserialization error: {}",
+                                error
+                            ),
+                            error_detail: tool_call.function.arguments.clone(),
+                        };
+                        error!("{:?}", error);
+                        anyhow!("{:?}", error)
+                    })?;
+
+                let output = (tool.handler)(arguments)?;
+
+                Ok(json!({
+                    "tool_call_id": tool_call.id,
+                    "output": output,
+                }))
+            })
+            .collect::<Result<Vec<Value>>>()?;
+
+        Ok(json!({ "tool_outputs": tool_outputs }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use serde_json::json;
+
+    use super::OpenAIFunctionTool;
+    use crate::domain::OpenAIRunResp;
+
+    #[test]
+    fn test_get_tool_outputs() {
+        let run_response = r#"{
+            "id": "run_abc123",
+            "object": "thread.run",
+            "created_at": 1699075072,
+            "status": "requires_action",
+            "required_action": {
+                "type": "submit_tool_outputs",
+                "submit_tool_outputs": {
+                    "tool_calls": [
+                        {
+                            "id": "call_001",
+                            "type": "function",
+                            "function": {"name": "get_weather", "arguments": "{\"city\": \"Paris\"}"}
+                        }
+                    ]
+                }
+            }
+        }"#;
+        let run: OpenAIRunResp = serde_json::from_str(run_response).unwrap();
+        let tool_calls = run
+            .required_action
+            .and_then(|action| action.submit_tool_outputs)
+            .unwrap()
+            .tool_calls;
+
+        let weather_tool = OpenAIFunctionTool::new(
+            "get_weather",
+            "Get the current weather in a city",
+            json!({"type": "object", "properties": {"city": {"type": "string"}}}),
+            |arguments| Ok(format!("Sunny in {}", arguments["city"].as_str().unwrap())),
+        );
+        assert_eq!(
+            weather_tool.get_tool_payload()["function"]["name"],
+            "get_weather"
+        );
+
+        let outputs = OpenAIFunctionTool::get_tool_outputs(&[weather_tool], &tool_calls).unwrap();
+        assert_eq!(
+            outputs,
+            json!({"tool_outputs": [{"tool_call_id": "call_001", "output": "Sunny in Paris"}]})
+        );
+
+        // Unknown functions and failing handlers are surfaced as errors
+        assert!(OpenAIFunctionTool::get_tool_outputs(&[], &tool_calls).is_err());
+        let failing_tool = OpenAIFunctionTool::new("get_weather", "", json!({}), |_| {
+            Err(anyhow!("Weather service unavailable"))
+        });
+        assert!(OpenAIFunctionTool::get_tool_outputs(&[failing_tool], &tool_calls).is_err());
+    }
+}
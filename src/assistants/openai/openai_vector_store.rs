@@ -2,9 +2,11 @@ use anyhow::{anyhow, Result};
 use log::{error, info};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::time;
 
-use crate::assistants::{OpenAIAssistantResource, OpenAIAssistantVersion};
+use crate::assistants::{OpenAIAssistantResource, OpenAIAssistantVersion, OpenAIAuth};
 use crate::domain::AllmsError;
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -15,6 +17,9 @@ pub struct OpenAIVectorStore {
     status: OpenAIVectorStoreStatus,
     debug: bool,
     version: OpenAIAssistantVersion,
+    expires_after_days: Option<u32>,
+    #[serde(skip)]
+    auth: OpenAIAuth,
 }
 
 impl OpenAIVectorStore {
@@ -27,6 +32,8 @@ impl OpenAIVectorStore {
             status: OpenAIVectorStoreStatus::InProgress,
             debug: false,
             version: OpenAIAssistantVersion::V2,
+            expires_after_days: None,
+            auth: OpenAIAuth::ApiKey,
         }
     }
 
@@ -52,6 +59,24 @@ impl OpenAIVectorStore {
         self
     }
 
+    ///
+    /// This method can be used to set the authentication method, e.g. Azure AD (Entra ID) tokens instead of API keys
+    /// Default is `OpenAIAuth::ApiKey` which uses the key provided in the constructor
+    ///
+    pub fn auth(mut self, auth: OpenAIAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    ///
+    /// This method can be used to set an expiration policy so that the Vector Store is deleted automatically
+    /// after the given number of days (1-365) since it was last active. The policy is applied when the store is created
+    ///
+    pub fn with_expiration(mut self, days_since_last_active: u32) -> Self {
+        self.expires_after_days = Some(days_since_last_active);
+        self
+    }
+
     /*
      * This function creates a new Vector Store and updates the ID of the struct
      */
@@ -64,14 +89,9 @@ impl OpenAIVectorStore {
         let client = Client::new();
 
         //Get the version-specific header
-        let version_headers = self.version.get_headers(&self.api_key);
+        let version_headers = self.version.get_auth_headers(&self.api_key, &self.auth)?;
 
-        let mut body = json!({
-            "name": self.name.clone(),
-        });
-        if let Some(ids) = file_ids {
-            body["file_ids"] = json!(ids.to_vec());
-        }
+        let body = self.get_create_body(file_ids)?;
 
         let response = client
             .post(vector_store_url)
@@ -113,6 +133,47 @@ impl OpenAIVectorStore {
         Ok(())
     }
 
+    // This function prepares the body of the Vector Store Create API call
+    fn get_create_body(&self, file_ids: Option<Vec<String>>) -> Result<Value> {
+        let mut body = json!({
+            "name": self.name.clone(),
+        });
+        if let Some(ids) = file_ids {
+            body["file_ids"] = json!(ids.to_vec());
+        }
+        if let Some(days) = self.expires_after_days {
+            if !(1..=365).contains(&days) {
+                return Err(anyhow!(
+                    "[allms][OpenAI][VectorStore] Expiration must be between 1 and 365 days. Got: {}",
+                    days
+                ));
+            }
+            body["expires_after"] = json!({
+                "anchor": "last_active_at",
+                "days": days,
+            });
+        }
+        Ok(body)
+    }
+
+    ///
+    /// This method lists all Vector Stores available for the provided API key, following pagination until all are retrieved
+    ///
+    pub async fn list(
+        api_key: &str,
+        version: OpenAIAssistantVersion,
+        auth: &OpenAIAuth,
+    ) -> Result<Vec<OpenAIVectorStoreResp>> {
+        // VectorStores endpoint is only available for v2 so if v1 is selected we overwrite
+        let version = match version {
+            OpenAIAssistantVersion::V1 => OpenAIAssistantVersion::V2,
+            _ => version,
+        };
+        version
+            .list_all(&OpenAIAssistantResource::VectorStores, api_key, auth, false)
+            .await
+    }
+
     ///
     /// This method uploads files to a Vector Store. If no ID was provided the method first creates the Vector Store
     ///
@@ -147,7 +208,7 @@ impl OpenAIVectorStore {
         let url = self.version.get_endpoint(&vector_store_resource);
 
         //Get the version-specific header
-        let version_headers = self.version.get_headers(&self.api_key);
+        let version_headers = self.version.get_auth_headers(&self.api_key, &self.auth)?;
 
         //Make the API call
         let client = Client::new();
@@ -195,61 +256,61 @@ impl OpenAIVectorStore {
     /// This method checks the status of a Vector Store
     ///
     pub async fn status(&self) -> Result<OpenAIVectorStoreStatus> {
-        // Requires an ID of an existing vector store
-        let vs_id = if let Some(id) = &self.id {
-            id
-        } else {
-            return Err(anyhow!(
-                "[allms][OpenAI][VectorStore][debug] Unable to check status. No ID provided."
-            ));
-        };
-
-        // Construct the API url
-        let vector_store_resource = OpenAIAssistantResource::VectorStore {
-            vector_store_id: vs_id.to_string(),
-        };
-        let url = self.version.get_endpoint(&vector_store_resource);
-
-        //Get the version-specific header
-        let version_headers = self.version.get_headers(&self.api_key);
-
-        //Make the API call
-        let client = Client::new();
-
-        let response = client.get(&url).headers(version_headers).send().await?;
-
-        let response_status = response.status();
-        let response_text = response.text().await?;
-
-        if self.debug {
-            info!(
-                "[allms][OpenAI][VectorStore][debug] VectorStore Status API response: [{}] {:#?}",
-                &response_status, &response_text
-            );
-        }
-
-        //Deserialize & validate the string response
-        let response_deser: OpenAIVectorStoreResp =
-            serde_json::from_str(&response_text).map_err(|error| {
-                let error = AllmsError {
-                    crate_name: "allms".to_string(),
-                    module: "assistants::openai_vector_store".to_string(),
-                    error_message: format!(
-                        "VectorStore Status API response serialization error: {}",
-                        error
-                    ),
-                    error_detail: response_text,
-                };
-                error!("{:?}", error);
-                anyhow!("{:?}", error)
-            })?;
-        Ok(response_deser.status)
+        self.retrieve().await.map(|response| response.status)
     }
 
     ///
     /// This method checks the counts of files added to a Vector Store and their statuses
     ///
     pub async fn file_count(&self) -> Result<OpenAIVectorStoreFileCounts> {
+        self.retrieve().await.map(|response| response.file_counts)
+    }
+
+    ///
+    /// This method polls the Vector Store until all files are processed so that file_search does not run against a partially indexed store
+    /// Returns an error if any of the files failed to process or if the timeout is reached
+    ///
+    pub async fn wait_until_ready(&self, poll_interval: Duration, timeout: Duration) -> Result<()> {
+        time::timeout(timeout, async {
+            let mut interval = time::interval(poll_interval);
+            loop {
+                interval.tick().await; // Wait for the next interval tick
+                let response = self.retrieve().await?;
+
+                if response.file_counts.failed > 0 {
+                    break Err(anyhow!(
+                        "[allms][OpenAI][VectorStore] {} of {} files failed to process.",
+                        response.file_counts.failed,
+                        response.file_counts.total
+                    ));
+                }
+                match response.status {
+                    OpenAIVectorStoreStatus::Expired => {
+                        break Err(anyhow!(
+                            "[allms][OpenAI][VectorStore] Vector Store expired."
+                        ));
+                    }
+                    OpenAIVectorStoreStatus::Completed => break Ok(()),
+                    // Keep polling while files are being processed
+                    OpenAIVectorStoreStatus::InProgress if response.file_counts.in_progress > 0 => {
+                        continue
+                    }
+                    OpenAIVectorStoreStatus::InProgress => break Ok(()),
+                }
+            }
+        })
+        .await
+        .map_err(|_| {
+            anyhow!(
+                "[allms][OpenAI][VectorStore] Timeout waiting for the Vector Store to be ready."
+            )
+        })?
+    }
+
+    /*
+     * This function retrieves the Vector Store object including its status and file counts
+     */
+    async fn retrieve(&self) -> Result<OpenAIVectorStoreResp> {
         // Requires an ID of an existing vector store
         let vs_id = if let Some(id) = &self.id {
             id
@@ -266,7 +327,7 @@ impl OpenAIVectorStore {
         let url = self.version.get_endpoint(&vector_store_resource);
 
         //Get the version-specific header
-        let version_headers = self.version.get_headers(&self.api_key);
+        let version_headers = self.version.get_auth_headers(&self.api_key, &self.auth)?;
 
         //Make the API call
         let client = Client::new();
@@ -284,21 +345,19 @@ impl OpenAIVectorStore {
         }
 
         //Deserialize & validate the string response
-        let response_deser: OpenAIVectorStoreResp =
-            serde_json::from_str(&response_text).map_err(|error| {
-                let error = AllmsError {
-                    crate_name: "allms".to_string(),
-                    module: "assistants::openai_vector_store".to_string(),
-                    error_message: format!(
-                        "VectorStore Status API response serialization error: {}",
-                        error
-                    ),
-                    error_detail: response_text,
-                };
-                error!("{:?}", error);
-                anyhow!("{:?}", error)
-            })?;
-        Ok(response_deser.file_counts)
+        serde_json::from_str(&response_text).map_err(|error| {
+            let error = AllmsError {
+                crate_name: "allms".to_string(),
+                module: "assistants::openai_vector_store".to_string(),
+                error_message: format!(
+                    "VectorStore Status API response serialization error: {}",
+                    error
+                ),
+                error_detail: response_text,
+            };
+            error!("{:?}", error);
+            anyhow!("{:?}", error)
+        })
     }
 
     ///
@@ -321,7 +380,7 @@ impl OpenAIVectorStore {
         let url = self.version.get_endpoint(&vector_store_resource);
 
         //Get the version-specific header
-        let version_headers = self.version.get_headers(&self.api_key);
+        let version_headers = self.version.get_auth_headers(&self.api_key, &self.auth)?;
 
         //Make the API call
         let client = Client::new();
@@ -368,14 +427,14 @@ impl OpenAIVectorStore {
 *
 ******************************************************************************************/
 #[derive(Deserialize, Serialize, Debug, Clone)]
-struct OpenAIVectorStoreResp {
-    id: String,
-    name: String,
-    status: OpenAIVectorStoreStatus,
-    created_at: i64,
-    expires_at: Option<i64>,
-    last_active_at: Option<i64>,
-    file_counts: OpenAIVectorStoreFileCounts,
+pub struct OpenAIVectorStoreResp {
+    pub id: String,
+    pub name: Option<String>,
+    pub status: OpenAIVectorStoreStatus,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_active_at: Option<i64>,
+    pub file_counts: OpenAIVectorStoreFileCounts,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -423,3 +482,81 @@ struct OpenAIVectorStoreDeleteResp {
     id: String,
     deleted: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{OpenAIVectorStore, OpenAIVectorStoreResp};
+    use crate::domain::OpenAIListResp;
+
+    #[test]
+    fn test_create_body_with_expiration() {
+        let vector_store = OpenAIVectorStore::new(None, "Concerts", "key").with_expiration(7);
+        let body = vector_store
+            .get_create_body(Some(vec!["file-123".to_string()]))
+            .unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "name": "Concerts",
+                "file_ids": ["file-123"],
+                "expires_after": {
+                    "anchor": "last_active_at",
+                    "days": 7,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_create_body_without_expiration() {
+        let vector_store = OpenAIVectorStore::new(None, "Concerts", "key");
+        let body = vector_store.get_create_body(None).unwrap();
+        assert_eq!(body, json!({ "name": "Concerts" }));
+    }
+
+    #[test]
+    fn test_create_body_invalid_expiration() {
+        let vector_store = OpenAIVectorStore::new(None, "Concerts", "key").with_expiration(0);
+        assert!(vector_store.get_create_body(None).is_err());
+    }
+
+    #[test]
+    fn test_list_response_deserialization() {
+        let response = r#"{
+            "object": "list",
+            "data": [
+                {
+                    "id": "vs_abc123",
+                    "object": "vector_store",
+                    "created_at": 1699061776,
+                    "name": "Concerts",
+                    "usage_bytes": 139920,
+                    "status": "completed",
+                    "expires_at": null,
+                    "last_active_at": 1699061776,
+                    "file_counts": {"in_progress": 0, "completed": 3, "failed": 0, "cancelled": 0, "total": 3}
+                },
+                {
+                    "id": "vs_abc456",
+                    "object": "vector_store",
+                    "created_at": 1699061776,
+                    "name": null,
+                    "usage_bytes": 0,
+                    "status": "in_progress",
+                    "file_counts": {"in_progress": 1, "completed": 0, "failed": 0, "cancelled": 0, "total": 1}
+                }
+            ],
+            "first_id": "vs_abc123",
+            "last_id": "vs_abc456",
+            "has_more": false
+        }"#;
+        let page: OpenAIListResp<OpenAIVectorStoreResp> = serde_json::from_str(response).unwrap();
+        assert_eq!(page.data.len(), 2);
+        assert_eq!(page.data[0].name, Some("Concerts".to_string()));
+        assert_eq!(page.data[1].name, None);
+        assert_eq!(page.last_id, Some("vs_abc456".to_string()));
+        assert_eq!(page.has_more, Some(false));
+    }
+}
@@ -1,11 +1,29 @@
 use anyhow::{anyhow, Result};
-use log::{error, info, warn};
+use log::{debug, error, info, trace, warn};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    StatusCode,
+};
 use schemars::JsonSchema;
 use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
 
-use crate::domain::{AllmsError, OpenAIDataResponse};
-use crate::llm_models::LLMModel;
-use crate::utils::{get_tokenizer, get_type_schema};
+use crate::domain::{
+    AllmsCancelledError, AllmsError, AllmsRefusalError, AllmsTruncatedOutputError, AnswerMetadata,
+    AudioInput, AudioOutput, CodeExecution, LLMFileRef, LLMFunction, LLMMessage, LLMMessageRole,
+    LiveRateLimit, OpenAIDataResponse, PromptCacheTtl, RequestContext, ServiceTier, TokenLogprob,
+    TokenUsage, ToolCall, ToolChoice, WebSource,
+};
+use crate::llm_models::{AnthropicModels, GoogleModels, GoogleTokenProvider, LLMModel};
+use crate::moderation::Moderation;
+use crate::utils::{
+    check_enum_values, get_api_error, get_api_error_message, get_tokenizer, get_type_schema,
+    get_valid_json, inline_schema_definitions, sanitize_json_response, unwrap_array_response,
+    wrap_array_schema, PartialJsonParser,
+};
 
 /// Completions APIs take a list of messages as input and return a model-generated message as output.
 /// Although the Completions format is designed to make multi-turn conversations easy,
@@ -14,11 +32,40 @@ pub struct Completions<T: LLMModel> {
     model: T,
     //For prompt & response
     max_tokens: usize,
+    //If provided, the tokens remaining after the prompt are sent as the output limit to models that otherwise omit it
+    max_tokens_provided: bool,
     temperature: f32,
     input_json: Option<String>,
+    system_prompt: Option<String>,
+    base_instructions: Option<String>,
     debug: bool,
     function_call: bool,
     api_key: String,
+    extra_headers: HeaderMap,
+    seed: Option<u64>,
+    files: Vec<LLMFileRef>,
+    audio: Vec<AudioInput>,
+    audio_output: Option<(String, String)>,
+    moderation_api_key: Option<String>,
+    logprobs: bool,
+    top_logprobs: u8,
+    web_search: bool,
+    code_execution: bool,
+    functions: Vec<LLMFunction>,
+    inline_schema: bool,
+    strict_tools: bool,
+    tool_choice: Option<ToolChoice>,
+    parallel_tool_calls: Option<bool>,
+    service_tier: Option<ServiceTier>,
+    store: Option<bool>,
+    metadata: HashMap<String, String>,
+    logit_bias: HashMap<u32, i32>,
+    message_history: Vec<LLMMessage>,
+    auto_expand_retries: usize,
+    fuzzy_enum_matching: bool,
+    prompt_cache: Option<PromptCacheTtl>,
+    //If set, an access token is requested for each call and used instead of `api_key`
+    google_token_provider: Option<Arc<dyn GoogleTokenProvider>>,
 }
 
 impl<T: LLMModel> Completions<T> {
@@ -35,12 +82,39 @@ impl<T: LLMModel> Completions<T> {
         Completions {
             //If no max tokens limit is provided we default to max allowed for the model
             max_tokens: max_tokens.unwrap_or_else(|| model.default_max_tokens()),
+            max_tokens_provided: max_tokens.is_some(),
             function_call: model.function_call_default(),
             model,
             temperature,
             input_json: None,
+            system_prompt: None,
+            base_instructions: None,
             debug: false,
             api_key: api_key.to_string(),
+            extra_headers: HeaderMap::new(),
+            seed: None,
+            files: Vec::new(),
+            audio: Vec::new(),
+            audio_output: None,
+            moderation_api_key: None,
+            logprobs: false,
+            top_logprobs: 0,
+            web_search: false,
+            code_execution: false,
+            functions: Vec::new(),
+            inline_schema: true,
+            strict_tools: false,
+            tool_choice: None,
+            parallel_tool_calls: None,
+            service_tier: None,
+            store: None,
+            metadata: HashMap::new(),
+            logit_bias: HashMap::new(),
+            message_history: Vec::new(),
+            auto_expand_retries: 0,
+            fuzzy_enum_matching: false,
+            prompt_cache: None,
+            google_token_provider: None,
         }
     }
 
@@ -65,20 +139,291 @@ impl<T: LLMModel> Completions<T> {
     /// This method accepts % target of the acceptable range for the model
     ///
     pub fn temperature(mut self, temp_target: u32) -> Self {
+        self.warn_if_temperature_ignored();
         self.temperature = self.model.get_normalized_temperature(temp_target);
         self
     }
 
+    ///
+    /// This method can be used to set the exact temperature sent to the model (e.g. 0.2) instead of a percentage of its range
+    /// The value is clamped to the range accepted by the model (see `LLMModel::temperature_range`)
+    ///
+    pub fn with_temperature_absolute(mut self, temp: f32) -> Self {
+        self.warn_if_temperature_ignored();
+        let (min, max) = self.model.temperature_range();
+        let clamped_temp = temp.clamp(min, max);
+        if clamped_temp != temp {
+            warn!(
+                "Temperature {} is outside of the {}-{} range accepted by {}. Using {} instead.",
+                temp,
+                min,
+                max,
+                self.model.as_str(),
+                clamped_temp
+            );
+        }
+        self.temperature = clamped_temp;
+        self
+    }
+
     ///
     /// This method can be used to define the model temperature used by the Assistant
     /// Using this method the temperature can be set directly without any validation of the range accepted by the model
     /// For a range-safe implementation please consider using `OpenAIAssistant::temperature` method
     ///
     pub fn temperature_unchecked(mut self, temp: f32) -> Self {
+        self.warn_if_temperature_ignored();
         self.temperature = temp;
         self
     }
 
+    // Warns that the temperature set by the user will be dropped from the request
+    fn warn_if_temperature_ignored(&self) {
+        if !self.model.supports_temperature() {
+            warn!(
+                "{} does not support setting the temperature. The value will be ignored.",
+                self.model.as_str()
+            );
+        }
+    }
+
+    // Adds a header that will be sent with the API request, replacing any previous value
+    fn with_header(mut self, name: &str, value: &str) -> Result<Self> {
+        let header_name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|error| anyhow!("Invalid header name '{}': {}", name, error))?;
+        let header_value = HeaderValue::from_str(value)
+            .map_err(|error| anyhow!("Invalid value for header '{}': {}", name, error))?;
+        self.extra_headers.insert(header_name, header_value);
+        Ok(self)
+    }
+
+    ///
+    /// This method can be used to retry requests whose output was truncated because it reached the token limit
+    /// Each retry doubles `max_tokens`, bounded by the default max tokens of the model. It requires `max_tokens` to be provided in the constructor
+    /// If the output is still truncated an `AllmsTruncatedOutputError` is returned
+    ///
+    pub fn with_auto_expand(mut self, max_retries: usize) -> Self {
+        self.auto_expand_retries = max_retries;
+        self
+    }
+
+    ///
+    /// This method can be used to recover categorical outputs where the model returns an enum variant with different casing or whitespace
+    /// Values that match a single variant after trimming and ignoring case are replaced with the variant before deserialization
+    ///
+    pub fn with_fuzzy_enum_matching(mut self, fuzzy_enum_matching: bool) -> Self {
+        self.fuzzy_enum_matching = fuzzy_enum_matching;
+        self
+    }
+
+    ///
+    /// This method can be used to replace the default base instructions added to each prompt, e.g. to use another language or tune them to a domain.
+    /// The output Json schema is still added to the prompt.
+    ///
+    pub fn with_base_instructions(mut self, base_instructions: &str) -> Self {
+        self.base_instructions = Some(base_instructions.to_string());
+        self
+    }
+
+    ///
+    /// This method can be used to provide a system prompt that is sent separately from the user instructions.
+    /// Depending on the provider it is placed in the `system` message, top-level `system` field or `systemInstruction`.
+    ///
+    pub fn with_system_prompt(mut self, system_prompt: &str) -> Self {
+        self.system_prompt = Some(system_prompt.to_string());
+        self
+    }
+
+    ///
+    /// This method can be used to set a seed so that repeated requests with the same parameters return (mostly) deterministic results.
+    /// Supported by OpenAI, Mistral and Ollama models. Other providers ignore it.
+    ///
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    ///
+    /// This method can be used to request the log probabilities of the output tokens, e.g. for confidence scoring.
+    /// Supported by OpenAI models except o1. Use `get_answer_with_logprobs` to retrieve them.
+    ///
+    pub fn with_logprobs(mut self, logprobs: bool) -> Self {
+        self.logprobs = logprobs;
+        self
+    }
+
+    ///
+    /// This method works like `with_logprobs(true)` but also requests the `top_n` most likely alternatives of each output token.
+    /// OpenAI Chat models return up to 20 alternatives and the legacy Completions model up to 5; larger values are capped.
+    ///
+    pub fn with_top_logprobs(mut self, top_n: u8) -> Self {
+        self.logprobs = true;
+        self.top_logprobs = top_n;
+        self
+    }
+
+    ///
+    /// This method can be used to ground the answer with the provider's built-in web search (e.g. Google Search for Gemini 1.5 models)
+    /// Use `get_answer_with_sources` to retrieve the web pages used by the model
+    ///
+    pub fn with_web_search(mut self) -> Self {
+        self.web_search = true;
+        self
+    }
+
+    ///
+    /// This method can be used to let the model generate and run code with the provider's built-in code execution (e.g. Python for Gemini 1.5 models)
+    /// Use `get_answer_with_code_execution` to retrieve the executed code and its output
+    ///
+    pub fn with_code_execution(mut self) -> Self {
+        self.code_execution = true;
+        self
+    }
+
+    ///
+    /// This method can be used to declare functions the model can call instead of answering directly (currently OpenAI Chat, Gemini 1.5, Anthropic Messages API and Mistral models)
+    /// Use `get_tool_calls` to retrieve the calls requested by the model
+    ///
+    pub fn with_functions(mut self, functions: Vec<LLMFunction>) -> Self {
+        self.functions.extend(functions);
+        self
+    }
+
+    ///
+    /// This method can be used to return an error when the model does not support a tool attached to the request
+    /// By default unsupported tools are ignored with a warning. See also `validate_tools`
+    ///
+    pub fn with_strict_tools(mut self, strict_tools: bool) -> Self {
+        self.strict_tools = strict_tools;
+        self
+    }
+
+    ///
+    /// This method can be used to suppress or favor tokens by their token ID. Bias values range from -100 (ban) to 100 (exclusive selection)
+    /// It only applies to OpenAI Chat and Completions models and is ignored with a warning by reasoning models and other providers
+    ///
+    pub fn with_logit_bias(mut self, logit_bias: HashMap<u32, i32>) -> Self {
+        self.logit_bias.extend(logit_bias);
+        self
+    }
+
+    ///
+    /// This method works like `with_logit_bias` but takes words that are tokenized with the model's tokenizer
+    /// The bias is applied to every token of each word. Note that words preceded by a space (e.g. `" yes"`) are usually different tokens
+    ///
+    pub fn with_logit_bias_for_words(mut self, words: &[&str], bias: i32) -> Result<Self> {
+        let tokenizer = get_tokenizer(&self.model)?;
+        for word in words {
+            for token in tokenizer.encode_ordinary(word) {
+                self.logit_bias.insert(token, bias);
+            }
+        }
+        Ok(self)
+    }
+
+    ///
+    /// This method can be used to control whether and which tool (function) the model calls, e.g. to always call the output function
+    /// It is mapped to the provider's `tool_choice` and ignored with a warning if the request does not use tools or the forced function is not defined
+    ///
+    pub fn with_tool_choice(mut self, tool_choice: ToolChoice) -> Self {
+        self.tool_choice = Some(tool_choice);
+        self
+    }
+
+    ///
+    /// This method can be used to allow or prevent multiple tool calls in a single response, e.g. to handle them one at a time
+    /// It applies to OpenAI Chat and Mistral models when the request uses tools. If not set the provider default is used
+    ///
+    pub fn with_parallel_tool_calls(mut self, parallel_tool_calls: bool) -> Self {
+        self.parallel_tool_calls = Some(parallel_tool_calls);
+        self
+    }
+
+    ///
+    /// This method can be used to select the processing tier, e.g. `ServiceTier::Flex` for cheaper but slower processing of latency-tolerant jobs
+    /// Requests return an error if the model does not support the selected tier
+    ///
+    pub fn with_service_tier(mut self, service_tier: ServiceTier) -> Self {
+        self.service_tier = Some(service_tier);
+        self
+    }
+
+    ///
+    /// This method can be used to control whether the provider stores the response, e.g. to show it in the OpenAI dashboard for evals
+    /// It is ignored with a warning by models that don't support it
+    ///
+    pub fn with_store(mut self, store: bool) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    ///
+    /// This method can be used to tag the request with key-value pairs, e.g. to search stored responses in the OpenAI dashboard
+    /// It is ignored with a warning by models that don't support it
+    ///
+    pub fn with_metadata(mut self, metadata: HashMap<String, String>) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    ///
+    /// This method can be used to skip the output schema in the prompt when the model constrains the output to the schema natively (e.g. Gemini 1.5 `responseSchema`, Mistral Json schema output or Ollama `format`), which saves prompt tokens for large schemas
+    /// Models without native structured output keep the schema in the prompt. Current default is `true`
+    ///
+    pub fn with_inline_schema(mut self, inline_schema: bool) -> Self {
+        self.inline_schema = inline_schema;
+        self
+    }
+
+    ///
+    /// This method can be used to add a previous turn of the conversation, e.g. for multi-turn chat
+    /// Messages are sent in the order they are added, before the instructions of the request. Anthropic expects user and assistant turns to alternate
+    ///
+    pub fn add_message(mut self, role: LLMMessageRole, content: &str) -> Self {
+        self.message_history.push(LLMMessage {
+            role,
+            content: content.to_string(),
+        });
+        self
+    }
+
+    ///
+    /// This method can be used to attach a file uploaded via the provider's Files API (e.g. `OpenAIFile`, `AnthropicFile`) to the prompt.
+    /// Supported by OpenAI GPT-4o, Anthropic Claude 3 and Google Gemini models. Other models return an error when building the request.
+    ///
+    pub fn add_file(mut self, file: LLMFileRef) -> Self {
+        self.files.push(file);
+        self
+    }
+
+    ///
+    /// This method can be used to attach an audio clip to the prompt.
+    /// Supported by OpenAI audio models (e.g. `gpt-4o-audio-preview`). Other models return an error when building the request.
+    ///
+    pub fn add_audio(mut self, audio: AudioInput) -> Self {
+        self.audio.push(audio);
+        self
+    }
+
+    ///
+    /// This method can be used to request the answer to also be spoken with the provided `voice` (e.g. `alloy`) and audio `format` (e.g. `wav` or `mp3`)
+    /// Use `get_answer_with_audio` to retrieve the audio. It is ignored with a warning by models that don't support audio outputs
+    ///
+    pub fn with_audio_output(mut self, voice: &str, format: &str) -> Self {
+        self.audio_output = Some((voice.to_string(), format.to_string()));
+        self
+    }
+
+    ///
+    /// This method can be used to check the instructions and context with the OpenAI Moderation API before the request is sent.
+    /// If the input is flagged the request is not sent and an error listing the flagged categories is returned.
+    /// An OpenAI API key is required regardless of the provider used for the completion.
+    ///
+    pub fn with_moderation(mut self, openai_api_key: &str) -> Self {
+        self.moderation_api_key = Some(openai_api_key.to_string());
+        self
+    }
+
     ///
     /// This method can be used to provide values that will be used as context for the prompt.
     /// Using this function you can provide multiple input values by calling it multiple times. New values will be appended with the category name
@@ -115,7 +460,11 @@ impl<T: LLMModel> Completions<T> {
     ) -> Result<usize> {
         //Output schema is extracted from the type parameter
         let schema = get_type_schema::<U>()?;
+        self.check_prompt_tokens_for_schema(instructions, &schema)
+    }
 
+    // This method works like `check_prompt_tokens` but takes the output schema as a Json string
+    fn check_prompt_tokens_for_schema(&self, instructions: &str, schema: &str) -> Result<usize> {
         let prompt = format!(
             "Instructions:
             {instructions}
@@ -128,36 +477,630 @@ impl<T: LLMModel> Completions<T> {
             input_json = self.input_json.clone().unwrap_or_default(),
         );
 
+        let message_history: String = self
+            .message_history
+            .iter()
+            .map(|message| message.content.as_str())
+            .collect();
+
         let full_prompt = format!(
-            "{}{}{}",
+            "{}{}{}{}{}",
             //Base (system) instructions
-            self.model.get_base_instructions(Some(self.function_call)),
+            self.base_instructions
+                .clone()
+                .unwrap_or_else(|| self.model.get_base_instructions(Some(self.function_call))),
+            //User-provided system prompt
+            self.system_prompt.clone().unwrap_or_default(),
+            //Previous turns of the conversation
+            message_history,
             //Instructions & context data
             prompt,
             //Output schema
             schema
         );
 
-        //Check how many tokens are required for prompt
-        let bpe = get_tokenizer(&self.model)?;
-        let prompt_tokens = bpe.encode_with_special_tokens(&full_prompt).len();
+        //Check how many tokens are required for prompt
+        let bpe = get_tokenizer(&self.model)?;
+        let prompt_tokens = bpe.encode_with_special_tokens(&full_prompt).len();
+
+        //Assuming another 5% overhead for json formatting
+        Ok((prompt_tokens as f64 * 1.05) as usize)
+    }
+
+    ///
+    /// This method is used to submit a prompt to OpenAI and process the response.
+    /// When calling the function you need to specify the type parameter as the response will match the schema of that type.
+    /// The prompt in this function is written in a way to instruct OpenAI to behave like a computer function that calculates an output based on provided input and its language model.
+    ///
+    pub async fn get_answer<U: JsonSchema + DeserializeOwned>(
+        self,
+        instructions: &str,
+    ) -> Result<U> {
+        let response_text = self.send_request::<U>(instructions).await?;
+
+        self.parse_response::<U>(&response_text)
+    }
+
+    ///
+    /// This method works like `get_answer` but stops waiting for the response when the `cancellation` future resolves, e.g. `CancellationToken::cancelled()`.
+    /// The in-flight request is dropped and an `AllmsCancelledError` is returned. It can be used in interactive apps when the result is no longer needed.
+    ///
+    pub async fn get_answer_with_cancellation<U, F>(
+        self,
+        instructions: &str,
+        cancellation: F,
+    ) -> Result<U>
+    where
+        U: JsonSchema + DeserializeOwned,
+        F: Future<Output = ()>,
+    {
+        tokio::select! {
+            //Cancellation is checked first so that an already cancelled request is never sent
+            biased;
+            _ = cancellation => {
+                let error = AllmsCancelledError {
+                    crate_name: "allms".to_string(),
+                    module: format!("completions::{}", self.model.as_str()),
+                };
+                warn!("{}", error);
+                Err(anyhow!(error))
+            }
+            response_text = self.send_request::<U>(instructions) => {
+                self.parse_response::<U>(&response_text?)
+            }
+        }
+    }
+
+    ///
+    /// This method works like `get_answer` but takes the output Json schema as a string and returns a `serde_json::Value`.
+    /// It can be used when the output type is not known at compile time. The response is validated against the schema.
+    ///
+    pub async fn get_answer_value(self, instructions: &str, json_schema: &str) -> Result<Value> {
+        let (answer, _usage) = self
+            .get_answer_value_with_usage(instructions, json_schema)
+            .await?;
+        Ok(answer)
+    }
+
+    // This method works like `get_answer_value` without consuming the instance and also returns the token usage reported by the API
+    pub(crate) async fn get_answer_value_with_usage(
+        &self,
+        instructions: &str,
+        json_schema: &str,
+    ) -> Result<(Value, Option<TokenUsage>)> {
+        let response_text = self
+            .send_for_schema(instructions, json_schema, &RequestContext::new())
+            .await?;
+
+        let usage = self.model.get_usage(&response_text);
+        let response_string = self.extract_data(&response_text)?;
+        let response_string = self.check_response_enum_values(json_schema, response_string)?;
+        let answer = self.get_valid_response_value(json_schema, &response_string)?;
+        Ok((answer, usage))
+    }
+
+    ///
+    /// This method works like `get_answer` but also returns the metadata reported with the response, e.g. token usage, system fingerprint, live rate limit, log probabilities or web sources.
+    /// Metadata that is not reported by the provider, or whose feature was not enabled, is returned as `None`.
+    ///
+    pub async fn get_answer_with_metadata<U: JsonSchema + DeserializeOwned>(
+        self,
+        instructions: &str,
+    ) -> Result<(U, AnswerMetadata)> {
+        let context = RequestContext::new();
+        let schema = get_type_schema::<U>()?;
+        let response_text = self
+            .send_for_schema(instructions, &schema, &context)
+            .await?;
+
+        let answer = self.parse_response::<U>(&response_text)?;
+        Ok((answer, self.get_metadata(&response_text, &context)))
+    }
+
+    ///
+    /// This method works like `get_answer` but also returns the `system_fingerprint` reported by the API, if any.
+    /// Combined with `with_seed` it can be used to detect backend changes that may affect the determinism of the results.
+    ///
+    pub async fn get_answer_with_fingerprint<U: JsonSchema + DeserializeOwned>(
+        self,
+        instructions: &str,
+    ) -> Result<(U, Option<String>)> {
+        let (answer, metadata) = self.get_answer_with_metadata::<U>(instructions).await?;
+        Ok((answer, metadata.system_fingerprint))
+    }
+
+    ///
+    /// This method works like `get_answer` but also returns the processing tier that served the request, if reported by the API.
+    /// It can be used for cost accounting as the tier used may differ from the requested one (e.g. `auto`).
+    ///
+    pub async fn get_answer_with_service_tier<U: JsonSchema + DeserializeOwned>(
+        self,
+        instructions: &str,
+    ) -> Result<(U, Option<String>)> {
+        let (answer, metadata) = self.get_answer_with_metadata::<U>(instructions).await?;
+        Ok((answer, metadata.service_tier))
+    }
+
+    ///
+    /// This method works like `get_answer` but also returns the live rate limit reported in the response headers (OpenAI and Anthropic).
+    /// The remaining requests and tokens can be used for adaptive throttling, as static limits differ by account tier.
+    ///
+    pub async fn get_answer_with_rate_limit<U: JsonSchema + DeserializeOwned>(
+        self,
+        instructions: &str,
+    ) -> Result<(U, Option<LiveRateLimit>)> {
+        let (answer, metadata) = self.get_answer_with_metadata::<U>(instructions).await?;
+        Ok((answer, metadata.live_rate_limit))
+    }
+
+    ///
+    /// This method works like `get_answer` but also returns the log probabilities of the output tokens.
+    /// The logprobs are only available if requested with `with_logprobs(true)` and supported by the model, otherwise `None` is returned.
+    ///
+    pub async fn get_answer_with_logprobs<U: JsonSchema + DeserializeOwned>(
+        self,
+        instructions: &str,
+    ) -> Result<(U, Option<Vec<TokenLogprob>>)> {
+        let (answer, metadata) = self.get_answer_with_metadata::<U>(instructions).await?;
+        Ok((answer, metadata.logprobs))
+    }
+
+    ///
+    /// This method works like `get_answer` but also returns the web pages used to ground the answer when `with_web_search` is enabled.
+    /// If the answer was not grounded an empty list is returned.
+    ///
+    pub async fn get_answer_with_sources<U: JsonSchema + DeserializeOwned>(
+        self,
+        instructions: &str,
+    ) -> Result<(U, Vec<WebSource>)> {
+        let (answer, metadata) = self.get_answer_with_metadata::<U>(instructions).await?;
+        Ok((answer, metadata.sources.unwrap_or_default()))
+    }
+
+    ///
+    /// This method works like `get_answer` but also returns the spoken answer when `with_audio_output` is enabled.
+    ///
+    pub async fn get_answer_with_audio<U: JsonSchema + DeserializeOwned>(
+        self,
+        instructions: &str,
+    ) -> Result<(U, Option<AudioOutput>)> {
+        let (answer, metadata) = self.get_answer_with_metadata::<U>(instructions).await?;
+        Ok((answer, metadata.audio))
+    }
+
+    ///
+    /// This method works like `get_answer` but also returns the code executed by the model and its output when `with_code_execution` is enabled.
+    /// If no code was executed an empty list is returned.
+    ///
+    pub async fn get_answer_with_code_execution<U: JsonSchema + DeserializeOwned>(
+        self,
+        instructions: &str,
+    ) -> Result<(U, Vec<CodeExecution>)> {
+        let (answer, metadata) = self.get_answer_with_metadata::<U>(instructions).await?;
+        Ok((answer, metadata.code_executions.unwrap_or_default()))
+    }
+
+    ///
+    /// This method sends the request with the functions provided with `with_functions` and returns the function calls requested by the model.
+    /// If the model answered directly an empty list is returned. The type parameter defines the output schema sent with the prompt.
+    ///
+    pub async fn get_tool_calls<U: JsonSchema + DeserializeOwned>(
+        self,
+        instructions: &str,
+    ) -> Result<Vec<ToolCall>> {
+        let response_text = self.send_request::<U>(instructions).await?;
+
+        Ok(self
+            .model
+            .get_tool_calls(&response_text)
+            .unwrap_or_default())
+    }
+
+    ///
+    /// This method works like `get_answer` but returns the data portion of the response without deserializing it.
+    /// The text is extracted from the provider response and sanitized (e.g. markdown fences removed). The type parameter defines the output schema sent with the prompt.
+    ///
+    pub async fn get_answer_raw<U: JsonSchema + DeserializeOwned>(
+        self,
+        instructions: &str,
+    ) -> Result<String> {
+        let response_text = self.send_request::<U>(instructions).await?;
+
+        self.extract_data(&response_text)
+    }
+
+    ///
+    /// This method works like `get_answer` but also returns the untouched response body received from the provider, e.g. for an audit trail.
+    ///
+    pub async fn get_answer_with_raw<U: JsonSchema + DeserializeOwned>(
+        self,
+        instructions: &str,
+    ) -> Result<(U, String)> {
+        let response_text = self.send_request::<U>(instructions).await?;
+
+        let answer = self.parse_response::<U>(&response_text)?;
+        Ok((answer, response_text))
+    }
+
+    ///
+    /// This method works like `get_answer` but streams the response and calls `on_partial` with the members of the output object completed so far, each time a top-level member is completed.
+    /// It can be used to render large outputs progressively. Models that don't support streaming call `on_partial` once with the complete output.
+    ///
+    pub async fn stream_json<U, F>(self, instructions: &str, mut on_partial: F) -> Result<U>
+    where
+        U: JsonSchema + DeserializeOwned,
+        F: FnMut(Value),
+    {
+        let schema = get_type_schema::<U>()?;
+        let mut model_body = self.build_request_body_for_schema(instructions, &schema)?;
+        let mut parser = PartialJsonParser::default();
+
+        if !self.model.apply_stream(&mut model_body) {
+            warn!(
+                "{} does not support streaming. The output will be returned once complete.",
+                self.model.as_str()
+            );
+            //Moderation is checked by `send_for_schema`
+            let response_text = self
+                .send_for_schema(instructions, &schema, &RequestContext::new())
+                .await?;
+            let response_string = self.extract_data(&response_text)?;
+            if let Some(partial) = parser.push(&response_string) {
+                on_partial(partial);
+            }
+            return self.parse_data::<U>(Some(&response_text), response_string);
+        }
+
+        if let Some(moderation_api_key) = &self.moderation_api_key {
+            self.check_moderation(moderation_api_key, instructions)
+                .await?;
+        }
+
+        let context = RequestContext::new();
+        trace!(
+            "[allms][{}] Request body: {}",
+            context.request_id,
+            model_body
+        );
+        let api_key = self.get_api_key().await?;
+        let mut streamed_response = String::new();
+        let mut truncated = false;
+        self.model
+            .call_api_stream(
+                &api_key,
+                &model_body,
+                &self.extra_headers,
+                &context,
+                &mut |event_data| {
+                    if self.model.is_stream_truncated(event_data) {
+                        truncated = true;
+                    }
+                    if let Some(delta) = self.model.get_stream_delta(event_data, self.function_call)
+                    {
+                        streamed_response.push_str(&delta);
+                        if let Some(partial) = parser.push(&delta) {
+                            on_partial(partial);
+                        }
+                    }
+                },
+            )
+            .await?;
+        debug!(
+            "[allms][{}] Streamed response completed in {:?}",
+            context.request_id,
+            context.elapsed()
+        );
+
+        if truncated {
+            let error = AllmsTruncatedOutputError {
+                crate_name: "allms".to_string(),
+                module: format!("completions::{}", self.model.as_str()),
+                max_tokens: self.max_tokens,
+            };
+            error!("{}", error);
+            return Err(anyhow!(error));
+        }
+
+        //There is no complete API response when streaming so only the streamed data is parsed
+        let response_string = sanitize_json_response(&streamed_response);
+        if self.debug {
+            info!("[debug] Completions response data: {}", response_string);
+        }
+        self.parse_data::<U>(None, response_string)
+    }
+
+    // This method builds the request body, calls the API and returns the response text
+    async fn send_request<U: JsonSchema + DeserializeOwned>(
+        &self,
+        instructions: &str,
+    ) -> Result<String> {
+        //Output schema is extracted from the type parameter
+        let schema = get_type_schema::<U>()?;
+
+        self.send_for_schema(instructions, &schema, &RequestContext::new())
+            .await
+    }
+
+    // This method builds the request body for the schema and calls the API
+    // Truncated outputs are retried with an expanded token limit if `with_auto_expand` is used, otherwise an error is returned
+    async fn send_for_schema(
+        &self,
+        instructions: &str,
+        schema: &str,
+        context: &RequestContext,
+    ) -> Result<String> {
+        //Moderation is checked once as retries send the same instructions
+        if let Some(moderation_api_key) = &self.moderation_api_key {
+            self.check_moderation(moderation_api_key, instructions)
+                .await?;
+        }
+
+        let mut max_tokens = self.max_tokens;
+        let mut retries = 0;
+        loop {
+            let model_body =
+                self.build_request_body_with_max_tokens(instructions, schema, max_tokens)?;
+            let response_text = self.send_body(model_body, context).await?;
+
+            if !self.model.is_truncated(&response_text) {
+                return Ok(response_text);
+            }
+
+            let expanded_max_tokens = (max_tokens * 2).min(self.model.default_max_tokens());
+            if !self.max_tokens_provided
+                || retries >= self.auto_expand_retries
+                || expanded_max_tokens <= max_tokens
+            {
+                let error = AllmsTruncatedOutputError {
+                    crate_name: "allms".to_string(),
+                    module: format!("completions::{}", self.model.as_str()),
+                    max_tokens,
+                };
+                error!("{}", error);
+                return Err(anyhow!(error));
+            }
+
+            warn!(
+                "[allms][{}] Output truncated at {} max tokens. Retrying with {}.",
+                context.request_id, max_tokens, expanded_max_tokens
+            );
+            retries += 1;
+            max_tokens = expanded_max_tokens;
+        }
+    }
+
+    // This method calls the API with the provided body using the context, e.g. to read the live rate limit after the call
+    async fn send_body(&self, model_body: Value, context: &RequestContext) -> Result<String> {
+        debug!(
+            "[allms][{}] Sending request: provider={} model={} endpoint={}",
+            context.request_id,
+            self.provider_name(),
+            self.model.as_str(),
+            self.model.get_endpoint()
+        );
+        trace!(
+            "[allms][{}] Request body: {}",
+            context.request_id,
+            model_body
+        );
+
+        let api_key = self.get_api_key().await?;
+        let response_text = self
+            .model
+            .call_api(
+                &api_key,
+                &model_body,
+                &self.extra_headers,
+                context,
+                self.debug,
+            )
+            .await?;
+
+        trace!(
+            "[allms][{}] Response body: {}",
+            context.request_id,
+            response_text
+        );
+        match self.model.get_usage(&response_text) {
+            Some(usage) => debug!(
+                "[allms][{}] Request completed in {:?}: input_tokens={} cached_input_tokens={} output_tokens={} total_tokens={}",
+                context.request_id,
+                context.elapsed(),
+                usage.input_tokens,
+                usage.cached_input_tokens,
+                usage.output_tokens,
+                usage.total_tokens
+            ),
+            None => debug!(
+                "[allms][{}] Request completed in {:?}",
+                context.request_id,
+                context.elapsed()
+            ),
+        }
+        if let Some(system_fingerprint) = self.model.get_system_fingerprint(&response_text) {
+            debug!(
+                "[allms][{}] System fingerprint: {}",
+                context.request_id, system_fingerprint
+            );
+        }
+        if let Some(service_tier) = self.model.get_service_tier(&response_text) {
+            debug!(
+                "[allms][{}] Service tier: {}",
+                context.request_id, service_tier
+            );
+        }
+        if let Some(live_rate_limit) = context.live_rate_limit() {
+            debug!(
+                "[allms][{}] Live rate limit: {:?}",
+                context.request_id, live_rate_limit
+            );
+        }
+
+        Ok(response_text)
+    }
+
+    // This method collects the metadata reported in the API response text and the response headers read into the context
+    fn get_metadata(&self, response_text: &str, context: &RequestContext) -> AnswerMetadata {
+        AnswerMetadata {
+            usage: self.model.get_usage(response_text),
+            system_fingerprint: self.model.get_system_fingerprint(response_text),
+            service_tier: self.model.get_service_tier(response_text),
+            live_rate_limit: context.live_rate_limit(),
+            logprobs: self.model.get_logprobs(response_text),
+            sources: self.model.get_web_sources(response_text),
+            code_executions: self.model.get_code_execution(response_text),
+            audio: self.model.get_audio_output(response_text),
+        }
+    }
+
+    // Returns the key used to authenticate the call. Access tokens of the Google token provider are requested for each call as they expire
+    async fn get_api_key(&self) -> Result<String> {
+        match &self.google_token_provider {
+            Some(token_provider) => token_provider.token().await,
+            None => Ok(self.api_key.clone()),
+        }
+    }
+
+    // This method checks the instructions and context with the Moderation API and returns an error if they are flagged
+    async fn check_moderation(&self, moderation_api_key: &str, instructions: &str) -> Result<()> {
+        let input = match &self.input_json {
+            Some(input_json) => format!("{instructions}\n\n{input_json}"),
+            None => instructions.to_string(),
+        };
+
+        let moderation = Moderation::check(moderation_api_key, &input).await?;
+        if moderation.flagged {
+            let error = AllmsError {
+                crate_name: "allms".to_string(),
+                module: "completions".to_string(),
+                error_message: format!(
+                    "Input flagged by moderation: {}",
+                    moderation.flagged_categories().join(", ")
+                ),
+                error_detail: input,
+            };
+            error!("{:?}", error);
+            return Err(anyhow!("{:?}", error));
+        }
+        Ok(())
+    }
+
+    // Returns the short name of the model enum (e.g. `OpenAIModels`) used to identify the provider in logs
+    fn provider_name(&self) -> &'static str {
+        std::any::type_name::<T>()
+            .rsplit("::")
+            .next()
+            .unwrap_or_default()
+    }
+
+    // Returns the model used by this Completions instance
+    pub(crate) fn model(&self) -> &T {
+        &self.model
+    }
+
+    ///
+    /// This method returns the exact JSON body that would be sent to the provider for the instructions and output type
+    /// No API calls are made so it can be used to inspect prompt construction, e.g. in golden tests
+    ///
+    pub fn build_request_body<U: JsonSchema + DeserializeOwned>(
+        &self,
+        instructions: &str,
+    ) -> Result<Value> {
+        //Output schema is extracted from the type parameter
+        let schema = get_type_schema::<U>()?;
+        self.build_request_body_for_schema(instructions, &schema)
+    }
+
+    ///
+    /// This method checks that the model supports the tools attached to the request (web search, code execution, functions, tool choice and parallel tool calls)
+    /// It returns an error naming the first unsupported tool, regardless of `with_strict_tools`
+    ///
+    pub fn validate_tools(&self) -> Result<()> {
+        let mut model_body = self.model.get_body(
+            "",
+            &serde_json::json!({}),
+            self.function_call,
+            &self.max_tokens,
+            &self.temperature,
+            self.system_prompt.as_deref(),
+            self.base_instructions.as_deref(),
+        );
+        self.apply_tools(&mut model_body, true)
+    }
+
+    // This method adds the attached tools to the body. Unsupported tools are ignored with a warning unless `strict` is set, in which case an error is returned
+    fn apply_tools(&self, model_body: &mut Value, strict: bool) -> Result<()> {
+        if self.web_search && !self.model.apply_web_search(model_body) {
+            self.reject_tool("web search", strict)?;
+        }
+
+        if self.code_execution && !self.model.apply_code_execution(model_body) {
+            self.reject_tool("code execution", strict)?;
+        }
+
+        if !self.functions.is_empty() && !self.model.apply_functions(model_body, &self.functions) {
+            self.reject_tool("function calling", strict)?;
+        }
 
-        //Assuming another 5% overhead for json formatting
-        Ok((prompt_tokens as f64 * 1.05) as usize)
+        if let Some(tool_choice) = &self.tool_choice {
+            if !self.model.apply_tool_choice(model_body, tool_choice) {
+                self.reject_tool("tool choice", strict)?;
+            }
+        }
+
+        if let Some(parallel_tool_calls) = self.parallel_tool_calls {
+            if !self
+                .model
+                .apply_parallel_tool_calls(model_body, parallel_tool_calls)
+            {
+                self.reject_tool("parallel tool calls", strict)?;
+            }
+        }
+        Ok(())
     }
 
-    ///
-    /// This method is used to submit a prompt to OpenAI and process the response.
-    /// When calling the function you need to specify the type parameter as the response will match the schema of that type.
-    /// The prompt in this function is written in a way to instruct OpenAI to behave like a computer function that calculates an output based on provided input and its language model.
-    ///
-    pub async fn get_answer<U: JsonSchema + DeserializeOwned>(
-        self,
+    // This method returns an error for an unsupported tool if `strict` is set, otherwise it logs a warning
+    fn reject_tool(&self, tool: &str, strict: bool) -> Result<()> {
+        if !strict {
+            warn!(
+                "{} does not support {} for this request. Ignoring it.",
+                self.model.as_str(),
+                tool
+            );
+            return Ok(());
+        }
+        let error = AllmsError {
+            crate_name: "allms".to_string(),
+            module: format!("completions::{}", self.model.as_str()),
+            error_message: format!(
+                "Model {} does not support tool {}",
+                self.model.as_str(),
+                tool
+            ),
+            error_detail: "Tools the model can't use are only ignored if strict tools are disabled"
+                .to_string(),
+        };
+        error!("{:?}", error);
+        Err(anyhow!("{:?}", error))
+    }
+
+    // This method works like `build_request_body` but takes the output schema as a Json string
+    fn build_request_body_for_schema(&self, instructions: &str, schema: &str) -> Result<Value> {
+        self.build_request_body_with_max_tokens(instructions, schema, self.max_tokens)
+    }
+
+    // This method builds the request body using the provided token limit, e.g. when the limit is expanded after a truncated response
+    fn build_request_body_with_max_tokens(
+        &self,
         instructions: &str,
-    ) -> Result<U> {
-        //Output schema is extracted from the type parameter
-        let schema = get_type_schema::<U>()?;
-        let json_schema = serde_json::from_str(&schema)?;
+        schema: &str,
+        max_tokens: usize,
+    ) -> Result<Value> {
+        //Array outputs (e.g. `Vec<T>`) are requested as an object wrapping the array, which is removed when parsing the response
+        let mut json_schema = wrap_array_schema(serde_json::from_str(schema)?);
+        if self.model.requires_inlined_schema() {
+            json_schema = inline_schema_definitions(&json_schema);
+        }
 
         let prompt = format!(
             "Instructions:
@@ -173,56 +1116,190 @@ impl<T: LLMModel> Completions<T> {
 
         //Validate how many tokens remain for the response (and how many are used for prompt)
         let prompt_tokens = self
-            .check_prompt_tokens::<U>(instructions)
+            .check_prompt_tokens_for_schema(instructions, schema)
             .unwrap_or_default();
 
-        if prompt_tokens >= self.max_tokens {
-            return Err(anyhow!(
-                "The provided prompt requires more tokens than allocated."
-            ));
+        if prompt_tokens >= max_tokens {
+            let error = AllmsError {
+                crate_name: "allms".to_string(),
+                module: format!("completions::{}", self.model.as_str()),
+                error_message: "The provided prompt requires more tokens than allocated."
+                    .to_string(),
+                error_detail: format!(
+                    "{} tokens allocated, approx {} used for prompt",
+                    max_tokens, prompt_tokens
+                ),
+            };
+            error!("{:?}", error);
+            return Err(anyhow!("{:?}", error));
         }
-        let response_tokens = self.max_tokens - prompt_tokens;
+        let response_tokens = max_tokens - prompt_tokens;
 
-        //Throw a warning if after processing the prompt there might be not enough tokens for response
-        //This assumes response will be similar size as input. Because this is not always correct this is a warning and not an error
-        if prompt_tokens * 2 >= self.max_tokens {
+        //Throw a warning if after processing the prompt less than 25% of the allocated tokens remain for the response
+        //The response may fit in fewer tokens so this is a warning and not an error. Truncated responses are detected after the call
+        if response_tokens * 4 < max_tokens {
             warn!(
                 "{} tokens remaining for response: {} allocated, {} used for prompt",
-                response_tokens.to_string(),
-                self.max_tokens.to_string(),
-                prompt_tokens.to_string(),
+                response_tokens, max_tokens, prompt_tokens,
             );
         };
+        debug!(
+            "Prompt accounts for approx {} tokens, leaving {} tokens for the response.",
+            prompt_tokens, response_tokens,
+        );
 
         //Build the API body depending on the used model
-        let model_body = self.model.get_body(
+        let mut model_body = self.model.get_body(
             &prompt,
             &json_schema,
             self.function_call,
             &response_tokens,
             &self.temperature,
+            self.system_prompt.as_deref(),
+            self.base_instructions.as_deref(),
         );
 
+        if self.max_tokens_provided {
+            self.model
+                .apply_max_output_tokens(&mut model_body, response_tokens);
+        }
+
+        if let Some(seed) = self.seed {
+            if !self.model.apply_seed(&mut model_body, seed) {
+                warn!(
+                    "{} does not support the seed parameter. Ignoring it.",
+                    self.model.as_str()
+                );
+            }
+        }
+
+        if self.logprobs
+            && !self
+                .model
+                .apply_logprobs(&mut model_body, self.top_logprobs)
+        {
+            warn!(
+                "{} does not support logprobs. Ignoring it.",
+                self.model.as_str()
+            );
+        }
+
+        self.apply_tools(&mut model_body, self.strict_tools)?;
+
+        //Tools may disable native structured output so the schema is only removed after they are applied
+        if !self.inline_schema && !self.model.remove_inline_schema(&mut model_body, &prompt) {
+            debug!(
+                "{} does not use native structured output for this request. Keeping the schema in the prompt.",
+                self.model.as_str()
+            );
+        }
+
+        if !self.logit_bias.is_empty()
+            && !self
+                .model
+                .apply_logit_bias(&mut model_body, &self.logit_bias)
+        {
+            warn!(
+                "{} does not support logit bias. Ignoring it.",
+                self.model.as_str()
+            );
+        }
+
+        if let Some(service_tier) = &self.service_tier {
+            self.model
+                .apply_service_tier(&mut model_body, service_tier)?;
+        }
+
+        if let Some(store) = self.store {
+            if !self.model.apply_store(&mut model_body, store) {
+                warn!(
+                    "{} does not support storing responses. Ignoring it.",
+                    self.model.as_str()
+                );
+            }
+        }
+
+        if !self.metadata.is_empty() && !self.model.apply_metadata(&mut model_body, &self.metadata)
+        {
+            warn!(
+                "{} does not support request metadata. Ignoring it.",
+                self.model.as_str()
+            );
+        }
+
+        if !self.files.is_empty() {
+            self.model.apply_files(&mut model_body, &self.files)?;
+        }
+
+        if !self.audio.is_empty() {
+            self.model.apply_audio(&mut model_body, &self.audio)?;
+        }
+
+        if let Some((voice, format)) = &self.audio_output {
+            if !self
+                .model
+                .apply_audio_output(&mut model_body, voice, format)
+            {
+                warn!(
+                    "{} does not support audio outputs. Ignoring it.",
+                    self.model.as_str()
+                );
+            }
+        }
+
+        if let Some(ttl) = &self.prompt_cache {
+            if !self.model.apply_prompt_cache(&mut model_body, ttl) {
+                warn!(
+                    "{} does not support prompt caching. Ignoring it.",
+                    self.model.as_str()
+                );
+            }
+        }
+
+        //History is added last so that files are attached to the current instructions
+        if !self.message_history.is_empty()
+            && !self
+                .model
+                .apply_message_history(&mut model_body, &self.message_history)
+        {
+            warn!(
+                "{} does not support multi-turn conversations. Ignoring message history.",
+                self.model.as_str()
+            );
+        }
+
         //Display debug info if requested
         if self.debug {
             info!("[debug] Model body: {:#?}", model_body);
             info!(
                 "[debug] Prompt accounts for approx {} tokens, leaving {} tokens for answer.",
-                prompt_tokens.to_string(),
-                response_tokens.to_string(),
+                prompt_tokens, response_tokens,
             );
         }
 
-        let response_text = self
-            .model
-            .call_api(&self.api_key, &model_body, self.debug)
-            .await?;
+        Ok(model_body)
+    }
 
+    // This method extracts the data portion from the API response text and sanitizes it
+    fn extract_data(&self, response_text: &str) -> Result<String> {
         //Extract data from the returned response text based on the used model
         let response_string = self
             .model
-            .get_data(&response_text, self.function_call)
+            .get_data(response_text, self.function_call)
             .map_err(|error| {
+                //Refusals are passed through so that they can be handled separately from other failures
+                if error.downcast_ref::<AllmsRefusalError>().is_some() {
+                    return error;
+                }
+                //Error bodies returned with a success status are surfaced with the message of the API
+                if get_api_error_message(response_text).is_some() {
+                    return get_api_error(
+                        &format!("completions::{}", self.model.as_str()),
+                        StatusCode::OK,
+                        &HeaderMap::new(),
+                        response_text.to_string(),
+                    );
+                }
                 let error = AllmsError {
                     crate_name: "allms".to_string(),
                     module: format!("assistants::completions::{}", self.model.as_str()),
@@ -239,6 +1316,72 @@ impl<T: LLMModel> Completions<T> {
         if self.debug {
             info!("[debug] Completions response data: {}", response_string);
         }
+        Ok(response_string)
+    }
+
+    // This method reports values outside of the enum variants allowed by the schema, fixing near matches if fuzzy matching is enabled
+    // Responses that are not valid Json are returned as is so that they are handled by the deserialization
+    fn check_response_enum_values(
+        &self,
+        json_schema: &str,
+        response_string: String,
+    ) -> Result<String> {
+        let (Ok(schema), Ok(mut value)) = (
+            serde_json::from_str::<Value>(json_schema),
+            serde_json::from_str::<Value>(&response_string),
+        ) else {
+            return Ok(response_string);
+        };
+        //Array outputs are usually returned wrapped in an object
+        let schema = match value.is_object() {
+            true => wrap_array_schema(schema),
+            false => schema,
+        };
+
+        let violations = check_enum_values(&schema, &mut value, self.fuzzy_enum_matching);
+        if !violations.is_empty() {
+            let error = AllmsError {
+                crate_name: "allms".to_string(),
+                module: format!("completions::{}", self.model.as_str()),
+                error_message: "Response contains values outside of the allowed enum variants"
+                    .to_string(),
+                error_detail: violations.join("; "),
+            };
+            error!("{:?}", error);
+            return Err(anyhow!("{:?}", error));
+        }
+        Ok(value.to_string())
+    }
+
+    // This method validates the response data against the schema, accepting array outputs both wrapped in an object and as is
+    fn get_valid_response_value(&self, json_schema: &str, response_string: &str) -> Result<Value> {
+        let module = format!("completions::{}", self.model.as_str());
+        get_valid_json(&module, json_schema, response_string).or_else(|error| {
+            unwrap_array_response(response_string)
+                .and_then(|array| get_valid_json(&module, json_schema, &array).ok())
+                .ok_or(error)
+        })
+    }
+
+    // This method extracts the data from the API response text and deserializes it into the expected output type
+    pub(crate) fn parse_response<U: JsonSchema + DeserializeOwned>(
+        &self,
+        response_text: &str,
+    ) -> Result<U> {
+        let response_string = self.extract_data(response_text)?;
+        self.parse_data::<U>(Some(response_text), response_string)
+    }
+
+    // This method deserializes the data extracted from the API response text into the expected output type
+    // The API response text is only used as a fallback for OpenAI responses with a `data` property, and is not available when streaming
+    fn parse_data<U: JsonSchema + DeserializeOwned>(
+        &self,
+        response_text: Option<&str>,
+        response_string: String,
+    ) -> Result<U> {
+        let schema = get_type_schema::<U>()?;
+        let response_string = self.check_response_enum_values(&schema, response_string)?;
+
         //Deserialize the string response into the expected output type
         let response_deser: anyhow::Result<U, anyhow::Error> =
             serde_json::from_str(&response_string).map_err(|error| {
@@ -249,15 +1392,23 @@ impl<T: LLMModel> Completions<T> {
                         "Completions API response serialization error: {}",
                         error
                     ),
-                    error_detail: response_string,
+                    error_detail: response_string.clone(),
                 };
                 error!("{:?}", error);
                 anyhow!("{:?}", error)
             });
+        // Array outputs are requested wrapped in an object, but models may respond with either form
+        if response_deser.is_err() {
+            if let Some(array) = unwrap_array_response(&response_string) {
+                if let Ok(response_deser) = serde_json::from_str(&array) {
+                    return Ok(response_deser);
+                }
+            }
+        }
         // Sometimes openai responds with a json object that has a data property. If that's the case, we need to extract the data property and deserialize that.
         // TODO: This is OpenAI specific and should be implemented within the model.
-        if let Err(_e) = response_deser {
-            let response_deser: OpenAIDataResponse<U> = serde_json::from_str(&response_text)
+        if let (Err(_e), Some(response_text)) = (&response_deser, response_text) {
+            let response_deser: OpenAIDataResponse<U> = serde_json::from_str(response_text)
                 .map_err(|error| {
                     let error = AllmsError {
                         crate_name: "allms".to_string(),
@@ -266,14 +1417,866 @@ impl<T: LLMModel> Completions<T> {
                             "Completions API response serialization error: {}",
                             error
                         ),
-                        error_detail: response_text,
+                        error_detail: response_text.to_string(),
                     };
                     error!("{:?}", error);
                     anyhow!("{:?}", error)
                 })?;
             Ok(response_deser.data)
         } else {
-            Ok(response_deser.unwrap())
+            response_deser
+        }
+    }
+}
+
+impl Completions<AnthropicModels> {
+    ///
+    /// This method can be used to set the `anthropic-version` header sent with each request.
+    /// If not set the default version supported by the crate is used.
+    ///
+    pub fn with_anthropic_version(self, version: &str) -> Result<Self> {
+        self.with_header("anthropic-version", version)
+    }
+
+    ///
+    /// This method can be used to enable Anthropic beta features (e.g. `output-128k-2025-02-19`) by setting the `anthropic-beta` header.
+    ///
+    pub fn with_anthropic_beta(self, betas: Vec<String>) -> Result<Self> {
+        self.with_header("anthropic-beta", &betas.join(","))
+    }
+
+    ///
+    /// This method can be used to cache the system prompt, base instructions and attached documents between requests.
+    /// It reduces the cost and latency of repeated calls sharing a large context. Cache reads and writes are reported in the token usage.
+    ///
+    pub fn with_anthropic_cache(mut self, ttl: PromptCacheTtl) -> Self {
+        self.prompt_cache = Some(ttl);
+        self
+    }
+}
+
+impl Completions<GoogleModels> {
+    ///
+    /// This method can be used to authenticate Vertex AI calls with a token provider, e.g. `GoogleVertexAuth`, instead of the API key.
+    /// A token is requested for each call so long-lived instances keep working after the access token expires.
+    ///
+    pub fn with_google_vertex_auth<P: GoogleTokenProvider + 'static>(
+        mut self,
+        token_provider: P,
+    ) -> Self {
+        self.google_token_provider = Some(Arc::new(token_provider));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use reqwest::header::{HeaderMap, AUTHORIZATION};
+    use schemars::JsonSchema;
+    use serde::Deserialize;
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::domain::{
+        AllmsApiError, AllmsCancelledError, AllmsTruncatedOutputError, OpenAIDataResponse,
+        RequestContext,
+    };
+    use crate::llm_models::{
+        AnthropicModels, GoogleModels, GoogleTokenProvider, LLMFileRef, LLMFunction,
+        LLMMessageRole, LLMModel, MistralModels, OpenAIModels, ToolChoice,
+    };
+    use crate::utils::get_type_schema;
+    use crate::Completions;
+
+    #[test]
+    fn test_anthropic_headers() {
+        let completions = Completions::new(AnthropicModels::Claude3_5Sonnet, "key", None, None)
+            .with_anthropic_version("2024-01-01")
+            .unwrap()
+            .with_anthropic_beta(vec![
+                "output-128k-2025-02-19".to_string(),
+                "files-api-2025-04-14".to_string(),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            completions.extra_headers.get("anthropic-version").unwrap(),
+            "2024-01-01"
+        );
+        assert_eq!(
+            completions.extra_headers.get("anthropic-beta").unwrap(),
+            "output-128k-2025-02-19,files-api-2025-04-14"
+        );
+    }
+
+    #[test]
+    fn test_anthropic_invalid_header_value() {
+        let completions = Completions::new(AnthropicModels::Claude3_5Sonnet, "key", None, None)
+            .with_anthropic_version("2024-01-01\n");
+        assert!(completions.is_err());
+    }
+
+    #[test]
+    fn test_temperature_absolute() {
+        let openai_body = Completions::new(OpenAIModels::Gpt4o, "key", None, None)
+            .with_temperature_absolute(0.2)
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert_eq!(openai_body["temperature"], 0.2f32);
+
+        let openai_body = Completions::new(OpenAIModels::Gpt4o, "key", None, None)
+            .with_temperature_absolute(1.5)
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert_eq!(openai_body["temperature"], 1.5f32);
+
+        //Values outside of the model range are clamped
+        let anthropic_body = Completions::new(AnthropicModels::Claude3_5Sonnet, "key", None, None)
+            .with_temperature_absolute(1.5)
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert_eq!(anthropic_body["temperature"], 1.0f32);
+    }
+
+    #[test]
+    fn test_seed_in_body() {
+        let openai_body = Completions::new(OpenAIModels::Gpt4o, "key", None, None)
+            .with_seed(42)
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert_eq!(openai_body["seed"], 42);
+
+        let mistral_body = Completions::new(MistralModels::MistralSmall, "key", None, None)
+            .with_seed(42)
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert_eq!(mistral_body["random_seed"], 42);
+
+        //Providers without seed support ignore it
+        let anthropic_body = Completions::new(AnthropicModels::Claude3_5Sonnet, "key", None, None)
+            .with_seed(42)
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert!(anthropic_body.get("seed").is_none());
+    }
+
+    #[test]
+    fn test_get_valid_response_value() {
+        let completions = Completions::new(OpenAIModels::Gpt4o, "key", None, None);
+        let schema = r#"{"type": "array", "items": {"type": "string"}}"#;
+
+        //Array outputs are accepted both wrapped in an object and as is
+        let value = completions
+            .get_valid_response_value(schema, r#"{"items": ["France", "Spain"]}"#)
+            .unwrap();
+        assert_eq!(value, serde_json::json!(["France", "Spain"]));
+        let value = completions
+            .get_valid_response_value(schema, r#"["France"]"#)
+            .unwrap();
+        assert_eq!(value, serde_json::json!(["France"]));
+
+        assert!(completions
+            .get_valid_response_value(schema, r#"{"items": [1, 2]}"#)
+            .is_err());
+    }
+
+    #[test]
+    fn test_store_and_metadata_in_body() {
+        let metadata = HashMap::from([("eval".to_string(), "translation-v2".to_string())]);
+
+        let openai_body = Completions::new(OpenAIModels::Gpt4oMini, "key", None, None)
+            .with_store(true)
+            .with_metadata(metadata.clone())
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert_eq!(openai_body["store"], true);
+        assert_eq!(openai_body["metadata"]["eval"], "translation-v2");
+
+        //Providers without stored responses ignore both parameters
+        let anthropic_body = Completions::new(AnthropicModels::Claude3Haiku, "key", None, None)
+            .with_store(true)
+            .with_metadata(metadata)
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert!(anthropic_body.get("store").is_none());
+        assert!(anthropic_body.get("metadata").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_get_answer_with_cancellation() {
+        let completions = Completions::new(OpenAIModels::Gpt4o, "key", None, None);
+        let error = completions
+            .get_answer_with_cancellation::<OpenAIDataResponse<String>, _>(
+                "Say hi",
+                std::future::ready(()),
+            )
+            .await
+            .unwrap_err();
+        assert!(error.downcast_ref::<AllmsCancelledError>().is_some());
+    }
+
+    #[test]
+    fn test_base_instructions_override() {
+        let completions = Completions::new(OpenAIModels::Gpt4o, "key", None, None)
+            .with_base_instructions("Tu es une fonction informatique.");
+        let body = completions
+            .build_request_body::<OpenAIDataResponse<String>>("Dis bonjour")
+            .unwrap();
+        assert_eq!(
+            body["messages"][0]["content"],
+            "Tu es une fonction informatique."
+        );
+
+        let anthropic_body = Completions::new(AnthropicModels::Claude3Haiku, "key", None, None)
+            .with_base_instructions("Tu es une fonction informatique.")
+            .build_request_body::<OpenAIDataResponse<String>>("Dis bonjour")
+            .unwrap();
+        let content = anthropic_body["messages"][0]["content"][0]["text"]
+            .as_str()
+            .unwrap();
+        assert!(content.starts_with("Tu es une fonction informatique."));
+        assert!(!content.contains("You are a computer function"));
+    }
+
+    #[test]
+    fn test_message_history_in_body() {
+        let openai_body = Completions::new(OpenAIModels::Gpt4o, "key", None, None)
+            .add_message(LLMMessageRole::User, "My name is Ada.")
+            .add_message(LLMMessageRole::Assistant, "Nice to meet you, Ada.")
+            .build_request_body::<OpenAIDataResponse<String>>("What is my name?")
+            .unwrap();
+        let messages = openai_body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[0]["role"], "system");
+        assert_eq!(messages[1]["content"], "My name is Ada.");
+        assert_eq!(messages[2]["role"], "assistant");
+        assert!(messages[3]["content"]
+            .as_str()
+            .unwrap()
+            .contains("What is my name?"));
+
+        let anthropic_body = Completions::new(AnthropicModels::Claude3_5Sonnet, "key", None, None)
+            .add_message(LLMMessageRole::User, "My name is Ada.")
+            .add_message(LLMMessageRole::Assistant, "Nice to meet you, Ada.")
+            .build_request_body::<OpenAIDataResponse<String>>("What is my name?")
+            .unwrap();
+        let roles: Vec<&str> = anthropic_body["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|message| message["role"].as_str().unwrap())
+            .collect();
+        assert_eq!(roles, vec!["user", "assistant", "user"]);
+
+        let google_body = Completions::new(GoogleModels::Gemini1_5Flash, "key", None, None)
+            .add_message(LLMMessageRole::Assistant, "Nice to meet you, Ada.")
+            .build_request_body::<OpenAIDataResponse<String>>("What is my name?")
+            .unwrap();
+        let contents = google_body["contents"].as_array().unwrap();
+        assert_eq!(contents[0]["role"], "model");
+        assert!(contents[1]["parts"][0]["text"]
+            .as_str()
+            .unwrap()
+            .contains("What is my name?"));
+    }
+
+    #[test]
+    fn test_logit_bias_for_words() {
+        let openai_body = Completions::new(OpenAIModels::Gpt4o, "key", None, None)
+            .with_logit_bias_for_words(&["Yes", " yes"], -100)
+            .unwrap()
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        let logit_bias = openai_body["logit_bias"].as_object().unwrap();
+        assert_eq!(logit_bias.len(), 2);
+        assert!(logit_bias.values().all(|bias| bias == -100));
+    }
+
+    #[test]
+    fn test_tool_choice_in_body() {
+        //OpenAI legacy function calling is converted to tools to apply the tool choice
+        let openai_body = Completions::new(OpenAIModels::Gpt4o, "key", None, None)
+            .function_calling(true)
+            .with_tool_choice(ToolChoice::Auto)
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert_eq!(openai_body["tool_choice"], "auto");
+        assert_eq!(openai_body["tools"][0]["function"]["name"], "analyze_data");
+        assert!(openai_body.get("functions").is_none());
+        assert!(openai_body.get("function_call").is_none());
+
+        let openai_body = Completions::new(OpenAIModels::Gpt4o, "key", None, None)
+            .function_calling(true)
+            .with_tool_choice(ToolChoice::Any)
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert_eq!(openai_body["tool_choice"], "required");
+
+        //Functions that are not defined in the body can't be forced
+        let openai_body = Completions::new(OpenAIModels::Gpt4o, "key", None, None)
+            .function_calling(true)
+            .with_tool_choice(ToolChoice::Tool("get_weather".to_string()))
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert_eq!(openai_body["function_call"]["name"], "analyze_data");
+        assert!(openai_body.get("tool_choice").is_none());
+        assert!(Completions::new(OpenAIModels::Gpt4o, "key", None, None)
+            .function_calling(true)
+            .with_tool_choice(ToolChoice::Tool("get_weather".to_string()))
+            .with_strict_tools(true)
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .is_err());
+
+        //Requests without tools are left unchanged
+        let openai_body = Completions::new(OpenAIModels::Gpt4o, "key", None, None)
+            .function_calling(false)
+            .with_tool_choice(ToolChoice::None)
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert!(openai_body.get("function_call").is_none());
+        assert!(openai_body.get("tool_choice").is_none());
+
+        //OpenAI, Anthropic and Mistral receive the tool choice with the declared functions
+        let functions = vec![LLMFunction::new(
+            "get_weather",
+            "Get the current weather in a city",
+            json!({"type": "object", "properties": {"city": {"type": "string"}}}),
+        )];
+        let openai_body = Completions::new(OpenAIModels::Gpt4o, "key", None, None)
+            .function_calling(true)
+            .with_functions(functions.clone())
+            .with_tool_choice(ToolChoice::Tool("get_weather".to_string()))
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert_eq!(openai_body["tools"][0]["function"]["name"], "analyze_data");
+        assert_eq!(openai_body["tools"][1]["function"]["name"], "get_weather");
+        assert_eq!(
+            openai_body["tool_choice"],
+            json!({"type": "function", "function": {"name": "get_weather"}})
+        );
+
+        let openai_body = Completions::new(OpenAIModels::Gpt4o, "key", None, None)
+            .function_calling(false)
+            .with_functions(functions.clone())
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert_eq!(openai_body["tools"].as_array().unwrap().len(), 1);
+        assert!(openai_body.get("tool_choice").is_none());
+
+        let anthropic_body = Completions::new(AnthropicModels::Claude3_5Sonnet, "key", None, None)
+            .with_functions(functions.clone())
+            .with_tool_choice(ToolChoice::Tool("get_weather".to_string()))
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert_eq!(anthropic_body["tools"][0]["name"], "get_weather");
+        assert_eq!(
+            anthropic_body["tool_choice"],
+            json!({"type": "tool", "name": "get_weather"})
+        );
+
+        let mistral_body = Completions::new(MistralModels::MistralLarge, "key", None, None)
+            .with_functions(functions)
+            .with_tool_choice(ToolChoice::Any)
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert_eq!(mistral_body["tools"][0]["function"]["name"], "get_weather");
+        assert_eq!(mistral_body["tool_choice"], "any");
+        assert!(mistral_body.get("response_format").is_none());
+
+        let anthropic_body = Completions::new(AnthropicModels::Claude3_5Sonnet, "key", None, None)
+            .with_tool_choice(ToolChoice::Any)
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert!(anthropic_body.get("tool_choice").is_none());
+    }
+
+    #[test]
+    fn test_parallel_tool_calls_in_body() {
+        let functions = vec![LLMFunction::new(
+            "get_weather",
+            "Get the current weather in a city",
+            json!({"type": "object", "properties": {"city": {"type": "string"}}}),
+        )];
+        let openai_body = Completions::new(OpenAIModels::Gpt4o, "key", None, None)
+            .function_calling(false)
+            .with_functions(functions)
+            .with_parallel_tool_calls(false)
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert_eq!(openai_body["parallel_tool_calls"], false);
+
+        //The output function of legacy function calling is converted to a tool
+        let openai_body = Completions::new(OpenAIModels::Gpt4o, "key", None, None)
+            .function_calling(true)
+            .with_parallel_tool_calls(false)
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert_eq!(openai_body["parallel_tool_calls"], false);
+        assert_eq!(
+            openai_body["tool_choice"],
+            json!({"type": "function", "function": {"name": "analyze_data"}})
+        );
+
+        //Requests without tools are left unchanged
+        let openai_body = Completions::new(OpenAIModels::Gpt4o, "key", None, None)
+            .function_calling(false)
+            .with_parallel_tool_calls(false)
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert!(openai_body.get("parallel_tool_calls").is_none());
+    }
+
+    #[test]
+    fn test_auto_expand_raises_google_max_output_tokens() {
+        let completions = Completions::new(GoogleModels::Gemini1_5Flash, "key", Some(1024), None)
+            .with_auto_expand(1);
+        let schema = get_type_schema::<OpenAIDataResponse<String>>().unwrap();
+
+        //Each retry sends the expanded limit, minus the tokens used by the prompt
+        let body = completions
+            .build_request_body_with_max_tokens("Say hi", &schema, 1024)
+            .unwrap();
+        let expanded_body = completions
+            .build_request_body_with_max_tokens("Say hi", &schema, 2048)
+            .unwrap();
+        let max_output_tokens = body["generationConfig"]["maxOutputTokens"]
+            .as_u64()
+            .unwrap();
+        assert_eq!(
+            expanded_body["generationConfig"]["maxOutputTokens"],
+            max_output_tokens + 1024
+        );
+
+        //The model default is used if no limit is provided
+        let body = Completions::new(GoogleModels::Gemini1_5Flash, "key", None, None)
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert!(body["generationConfig"].get("maxOutputTokens").is_none());
+    }
+
+    #[test]
+    fn test_prompt_tokens_check() {
+        let completions = Completions::new(OpenAIModels::Gpt4o, "key", Some(50), None);
+        let error = completions
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("The provided prompt requires more tokens than allocated."));
+
+        //Previous turns of the conversation are part of the prompt
+        let prompt_tokens = Completions::new(OpenAIModels::Gpt4o, "key", None, None)
+            .check_prompt_tokens::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        let prompt_tokens_with_history = Completions::new(OpenAIModels::Gpt4o, "key", None, None)
+            .add_message(LLMMessageRole::User, "Tell me about the history of Rome")
+            .check_prompt_tokens::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert!(prompt_tokens_with_history > prompt_tokens);
+    }
+
+    #[test]
+    fn test_inlined_schema_refs() {
+        let gemini_body = Completions::new(GoogleModels::Gemini1_0Pro, "key", None, None)
+            .build_request_body::<OpenAIDataResponse<Country>>("Name a country")
+            .unwrap();
+        assert!(!gemini_body.to_string().contains("$ref"));
+
+        let openai_body = Completions::new(OpenAIModels::Gpt4o, "key", None, None)
+            .build_request_body::<OpenAIDataResponse<Country>>("Name a country")
+            .unwrap();
+        assert!(openai_body.to_string().contains("#/definitions/Country"));
+    }
+
+    #[test]
+    fn test_inline_schema() {
+        let mistral_body = Completions::new(MistralModels::MistralLarge, "key", None, None)
+            .with_inline_schema(false)
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        let user_message = mistral_body["messages"][1]["content"].as_str().unwrap();
+        assert!(user_message.contains("Say hi"));
+        assert!(!user_message.contains("Output Json schema"));
+
+        let gemini_body = Completions::new(GoogleModels::Gemini1_5Pro, "key", None, None)
+            .with_inline_schema(false)
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert_eq!(
+            gemini_body["systemInstruction"]["parts"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+
+        //Models without native structured output keep the schema in the prompt
+        let mistral_body = Completions::new(MistralModels::Mistral7B, "key", None, None)
+            .with_inline_schema(false)
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap();
+        assert!(mistral_body["messages"][1]["content"]
+            .as_str()
+            .unwrap()
+            .contains("Output Json schema"));
+    }
+
+    #[test]
+    fn test_strict_tools() {
+        let completions =
+            Completions::new(MistralModels::MistralLarge, "key", None, None).with_web_search();
+        assert!(completions.validate_tools().is_err());
+
+        //Unsupported tools are ignored unless strict tools are enabled
+        assert!(completions
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .is_ok());
+        let error = Completions::new(MistralModels::MistralLarge, "key", None, None)
+            .with_web_search()
+            .with_strict_tools(true)
+            .build_request_body::<OpenAIDataResponse<String>>("Say hi")
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("does not support tool web search"));
+
+        let completions = Completions::new(GoogleModels::Gemini1_5Flash, "key", None, None)
+            .with_web_search()
+            .with_strict_tools(true);
+        assert!(completions.validate_tools().is_ok());
+    }
+
+    #[test]
+    fn test_files_in_body() {
+        let openai_body = Completions::new(OpenAIModels::Gpt4o, "key", None, None)
+            .add_file(LLMFileRef::new("file-abc"))
+            .build_request_body::<OpenAIDataResponse<String>>("Summarize the document")
+            .unwrap();
+        let content = openai_body["messages"][1]["content"].as_array().unwrap();
+        assert_eq!(content[0]["type"], "text");
+        assert_eq!(content[1]["file"]["file_id"], "file-abc");
+
+        //Models without file inputs return an error instead of silently dropping the file
+        let mistral_body = Completions::new(MistralModels::MistralSmall, "key", None, None)
+            .add_file(LLMFileRef::new("file-abc"))
+            .build_request_body::<OpenAIDataResponse<String>>("Summarize the document");
+        assert!(mistral_body.is_err());
+    }
+
+    #[test]
+    fn test_extract_and_parse_response() {
+        let completions =
+            Completions::new(OpenAIModels::Gpt4o, "key", None, None).function_calling(false);
+        let response_text = r#"{
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1727776800,
+            "model": "gpt-4o",
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "```json\n{\"data\": \"hola\"}```"}, "finish_reason": "stop"}]
+        }"#;
+
+        assert_eq!(
+            completions.extract_data(response_text).unwrap(),
+            r#"{"data": "hola"}"#
+        );
+        let answer = completions
+            .parse_response::<OpenAIDataResponse<String>>(response_text)
+            .unwrap();
+        assert_eq!(answer.data, "hola");
+
+        //Error envelopes are surfaced with the message of the API instead of a deserialization error
+        let error_text = r#"{"error": {"message": "Incorrect API key provided", "type": "invalid_request_error"}}"#;
+        let error = completions.extract_data(error_text).unwrap_err();
+        let api_error = error.downcast_ref::<AllmsApiError>().unwrap();
+        assert_eq!(
+            api_error.message,
+            Some("Incorrect API key provided".to_string())
+        );
+    }
+
+    #[derive(Deserialize, JsonSchema, Debug, PartialEq)]
+    enum Sentiment {
+        Positive,
+        Negative,
+        Neutral,
+    }
+
+    #[derive(Deserialize, JsonSchema, Debug, PartialEq)]
+    struct Review {
+        summary: String,
+        sentiment: Sentiment,
+        aspects: Vec<Option<Sentiment>>,
+    }
+
+    #[test]
+    fn test_enum_outputs() {
+        let response_text = r#"{
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1727776800,
+            "model": "gpt-4o",
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "{\"summary\": \"Great food\", \"sentiment\": \" positive\", \"aspects\": [\"NEUTRAL\", null]}"}, "finish_reason": "stop"}]
+        }"#;
+
+        //Without fuzzy matching the error reports the offending value and the allowed variants
+        let completions =
+            Completions::new(OpenAIModels::Gpt4o, "key", None, None).function_calling(false);
+        let error = completions
+            .parse_response::<Review>(response_text)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains(
+            r#"/sentiment: \" positive\" is not one of [\"Positive\",\"Negative\",\"Neutral\"]"#
+        ));
+        assert!(error.contains("/aspects/0"));
+
+        let completions = Completions::new(OpenAIModels::Gpt4o, "key", None, None)
+            .function_calling(false)
+            .with_fuzzy_enum_matching(true);
+        let review = completions.parse_response::<Review>(response_text).unwrap();
+        assert_eq!(review.sentiment, Sentiment::Positive);
+        assert_eq!(review.aspects, vec![Some(Sentiment::Neutral), None]);
+    }
+
+    #[derive(Deserialize, JsonSchema, Debug, PartialEq)]
+    struct Country {
+        name: String,
+        capital: String,
+    }
+
+    #[test]
+    fn test_array_output() {
+        let completions = Completions::new(OpenAIModels::Gpt4o, "key", None, None);
+        let body = completions
+            .build_request_body::<Vec<Country>>("List two countries")
+            .unwrap();
+        let parameters = &body["functions"][0]["parameters"];
+        assert_eq!(parameters["type"], "object");
+        assert_eq!(parameters["properties"]["items"]["type"], "array");
+        assert!(parameters["definitions"]["Country"].is_object());
+
+        let expected = vec![
+            Country {
+                name: "France".to_string(),
+                capital: "Paris".to_string(),
+            },
+            Country {
+                name: "Japan".to_string(),
+                capital: "Tokyo".to_string(),
+            },
+        ];
+
+        //Function calling returns the array wrapped in an object
+        let wrapped_response = r#"{
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1727776800,
+            "model": "gpt-4o",
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": null, "function_call": {"name": "analyze_data", "arguments": "{\"items\": [{\"name\": \"France\", \"capital\": \"Paris\"}, {\"name\": \"Japan\", \"capital\": \"Tokyo\"}]}"}}, "finish_reason": "stop"}]
+        }"#;
+        let answer = completions
+            .parse_response::<Vec<Country>>(wrapped_response)
+            .unwrap();
+        assert_eq!(answer, expected);
+
+        //Without function calling the model may respond with a bare array
+        let completions =
+            Completions::new(OpenAIModels::Gpt4o, "key", None, None).function_calling(false);
+        let bare_response = r#"{
+            "id": "chatcmpl-2",
+            "object": "chat.completion",
+            "created": 1727776800,
+            "model": "gpt-4o",
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "```json\n[{\"name\": \"France\", \"capital\": \"Paris\"}, {\"name\": \"Japan\", \"capital\": \"Tokyo\"}]\n```"}, "finish_reason": "stop"}]
+        }"#;
+        let answer = completions
+            .parse_response::<Vec<Country>>(bare_response)
+            .unwrap();
+        assert_eq!(answer, expected);
+    }
+
+    //Token provider returning a new token for each call
+    struct MockTokenProvider {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait(?Send)]
+    impl GoogleTokenProvider for MockTokenProvider {
+        async fn token(&self) -> Result<String> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(format!("token-{call}"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_google_vertex_auth() {
+        let model = GoogleModels::Gemini1_5ProVertex;
+        let completions = Completions::new(model.clone(), "expired-token", None, None)
+            .with_google_vertex_auth(MockTokenProvider {
+                calls: AtomicUsize::new(0),
+            });
+
+        //A token is requested for each call and used in the authorization header
+        for expected_token in ["token-1", "token-2"] {
+            let api_key = completions.get_api_key().await.unwrap();
+            let headers = model.auth_headers(&api_key).unwrap();
+            assert_eq!(
+                headers[AUTHORIZATION],
+                format!("Bearer {expected_token}").as_str()
+            );
+        }
+
+        let completions = Completions::new(model, "token", None, None);
+        assert_eq!(completions.get_api_key().await.unwrap(), "token");
+    }
+
+    #[test]
+    fn test_get_metadata() {
+        let response_text = r#"{
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1727776800,
+            "model": "gpt-4o",
+            "system_fingerprint": "fp_1",
+            "service_tier": "default",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "{\"name\": \"Dune\"}"},
+                "logprobs": {"content": [{"token": "Dune", "logprob": -0.01, "bytes": null, "top_logprobs": []}]},
+                "finish_reason": "stop"
+            }],
+            "usage": {"prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15}
+        }"#;
+        let completions = Completions::new(OpenAIModels::Gpt4o, "key", None, None);
+        let metadata = completions.get_metadata(response_text, &RequestContext::new());
+        assert_eq!(metadata.system_fingerprint, Some("fp_1".to_string()));
+        assert_eq!(metadata.service_tier, Some("default".to_string()));
+        assert_eq!(metadata.usage.unwrap().total_tokens, 15);
+        assert_eq!(metadata.logprobs.unwrap()[0].token, "Dune");
+        assert_eq!(metadata.live_rate_limit, None);
+        assert_eq!(metadata.sources, None);
+        assert_eq!(metadata.code_executions, None);
+        assert_eq!(metadata.audio, None);
+    }
+
+    // Model replaying predefined stream events
+    struct MockStreamModel {
+        events: Vec<&'static str>,
+    }
+
+    #[async_trait(?Send)]
+    impl LLMModel for MockStreamModel {
+        fn as_str(&self) -> &str {
+            "mock-stream"
+        }
+
+        fn try_from_str(_name: &str) -> Option<Self> {
+            None
+        }
+
+        fn default_max_tokens(&self) -> usize {
+            4096
+        }
+
+        fn get_endpoint(&self) -> String {
+            "http://localhost".to_string()
+        }
+
+        fn get_body(
+            &self,
+            _instructions: &str,
+            _json_schema: &Value,
+            _function_call: bool,
+            _max_tokens: &usize,
+            _temperature: &f32,
+            _system_prompt: Option<&str>,
+            _base_instructions: Option<&str>,
+        ) -> Value {
+            json!({})
+        }
+
+        fn apply_stream(&self, _body: &mut Value) -> bool {
+            true
         }
+
+        async fn call_api_stream(
+            &self,
+            _api_key: &str,
+            _body: &Value,
+            _extra_headers: &HeaderMap,
+            _context: &RequestContext,
+            on_event: &mut dyn for<'a> FnMut(&'a str),
+        ) -> Result<()> {
+            for event in &self.events {
+                on_event(event);
+            }
+            Ok(())
+        }
+
+        fn get_stream_delta(&self, event_data: &str, _function_call: bool) -> Option<String> {
+            let event: Value = serde_json::from_str(event_data).ok()?;
+            event["delta"].as_str().map(|delta| delta.to_string())
+        }
+
+        fn is_stream_truncated(&self, event_data: &str) -> bool {
+            event_data.contains("max_tokens")
+        }
+
+        fn get_data(&self, response_text: &str, _function_call: bool) -> Result<String> {
+            Ok(response_text.to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_json() {
+        let model = MockStreamModel {
+            events: vec![
+                r#"{"delta": "{\"summary\": \"Great food\", "}"#,
+                r#"{"delta": "\"sentiment\": \" positive\", \"aspects\": []}"}"#,
+                r#"{"stop": "end_turn"}"#,
+            ],
+        };
+        let mut partials = Vec::new();
+        let review = Completions::new(model, "key", None, None)
+            .with_fuzzy_enum_matching(true)
+            .stream_json::<Review, _>("Review", |partial| partials.push(partial))
+            .await
+            .unwrap();
+        assert_eq!(review.sentiment, Sentiment::Positive);
+        assert_eq!(partials[0], json!({"summary": "Great food"}));
+
+        //Enum values are checked the same way as in `get_answer`
+        let model = MockStreamModel {
+            events: vec![
+                r#"{"delta": "{\"summary\": \"Great food\", \"sentiment\": \" positive\", \"aspects\": []}"}"#,
+            ],
+        };
+        let error = Completions::new(model, "key", None, None)
+            .stream_json::<Review, _>("Review", |_| {})
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("/sentiment"));
+
+        //Truncated outputs are reported instead of failing to deserialize
+        let model = MockStreamModel {
+            events: vec![
+                r#"{"delta": "{\"summary\": \"Great"}"#,
+                r#"{"stop": "max_tokens"}"#,
+            ],
+        };
+        let error = Completions::new(model, "key", Some(1024), None)
+            .stream_json::<Review, _>("Review", |_| {})
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error
+                .downcast_ref::<AllmsTruncatedOutputError>()
+                .unwrap()
+                .max_tokens,
+            1024
+        );
     }
 }
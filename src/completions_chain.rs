@@ -0,0 +1,275 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use log::{error, warn};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::completions::Completions;
+use crate::domain::{AllmsApiError, AllmsError, TokenUsage};
+use crate::llm_models::LLMModel;
+use crate::utils::get_type_schema;
+
+/// Ordered list of `Completions` tried one after another until one of them answers, e.g. GPT-4o, then Claude, then Gemini.
+///
+/// Only transient failures (timeouts, connection errors, rate limits and server errors) fall back to the next model.
+/// Deterministic failures such as invalid requests or responses that don't match the schema are returned immediately, as other models would likely fail the same way.
+#[derive(Default)]
+pub struct CompletionsChain {
+    links: Vec<Box<dyn ChainLink>>,
+}
+
+/// Answer returned by `CompletionsChain` with the model that produced it and the token usage reported by the API
+#[derive(Debug, Clone)]
+pub struct CompletionsChainAnswer<U> {
+    pub answer: U,
+    pub model: String,
+    pub usage: Option<TokenUsage>,
+}
+
+// Type-erased `Completions` so that models from different providers can be chained
+#[async_trait(?Send)]
+trait ChainLink {
+    fn model_name(&self) -> String;
+    async fn get_answer_value(
+        &self,
+        instructions: &str,
+        json_schema: &str,
+    ) -> Result<(Value, Option<TokenUsage>)>;
+}
+
+#[async_trait(?Send)]
+impl<T: LLMModel> ChainLink for Completions<T> {
+    fn model_name(&self) -> String {
+        self.model().as_str().to_string()
+    }
+
+    async fn get_answer_value(
+        &self,
+        instructions: &str,
+        json_schema: &str,
+    ) -> Result<(Value, Option<TokenUsage>)> {
+        self.get_answer_value_with_usage(instructions, json_schema)
+            .await
+    }
+}
+
+impl CompletionsChain {
+    /// Constructor
+    pub fn new() -> Self {
+        CompletionsChain { links: Vec::new() }
+    }
+
+    ///
+    /// This method adds a model with its API key to the end of the chain using the default Completions settings
+    ///
+    pub fn add_model<T: LLMModel + 'static>(self, model: T, api_key: &str) -> Self {
+        self.add_completions(Completions::new(model, api_key, None, None))
+    }
+
+    ///
+    /// This method adds a configured Completions instance to the end of the chain, e.g. to set the temperature or system prompt of a model
+    ///
+    pub fn add_completions<T: LLMModel + 'static>(mut self, completions: Completions<T>) -> Self {
+        self.links.push(Box::new(completions));
+        self
+    }
+
+    ///
+    /// This method submits the prompt to each model of the chain in order and returns the first answer together with the model that produced it
+    /// If all models fail with transient errors the error of the last model is returned
+    ///
+    pub async fn get_answer<U: JsonSchema + DeserializeOwned>(
+        &self,
+        instructions: &str,
+    ) -> Result<CompletionsChainAnswer<U>> {
+        let schema = get_type_schema::<U>()?;
+        let mut last_error = None;
+
+        for (index, link) in self.links.iter().enumerate() {
+            let model = link.model_name();
+            match link.get_answer_value(instructions, &schema).await {
+                Ok((value, usage)) => {
+                    let answer = serde_json::from_value::<U>(value.clone()).map_err(|error| {
+                        let error = AllmsError {
+                            crate_name: "allms".to_string(),
+                            module: format!("completions_chain::{}", model),
+                            error_message: format!(
+                                "Completions API response serialization error: {}",
+                                error
+                            ),
+                            error_detail: value.to_string(),
+                        };
+                        error!("{:?}", error);
+                        anyhow!("{:?}", error)
+                    })?;
+                    return Ok(CompletionsChainAnswer {
+                        answer,
+                        model,
+                        usage,
+                    });
+                }
+                Err(error) if is_retryable(&error) => {
+                    if index + 1 < self.links.len() {
+                        warn!(
+                            "[allms][CompletionsChain] {} failed with a transient error. Falling back to the next model. Error: {}",
+                            model, error
+                        );
+                    }
+                    last_error = Some(error);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            let error = AllmsError {
+                crate_name: "allms".to_string(),
+                module: "completions_chain".to_string(),
+                error_message: "No models were added to the chain".to_string(),
+                error_detail: String::new(),
+            };
+            error!("{:?}", error);
+            anyhow!("{:?}", error)
+        }))
+    }
+}
+
+// Transient API errors and network failures fall back to the next model, everything else is deterministic
+fn is_retryable(error: &anyhow::Error) -> bool {
+    if let Some(api_error) = error.downcast_ref::<AllmsApiError>() {
+        return api_error.is_retryable();
+    }
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(|error| error.is_timeout() || error.is_connect() || error.is_request())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Result};
+    use async_trait::async_trait;
+    use serde::Deserialize;
+    use serde_json::{json, Value};
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::{is_retryable, ChainLink, CompletionsChain};
+    use crate::domain::{AllmsApiError, TokenUsage};
+    use schemars::JsonSchema;
+
+    #[derive(Deserialize, JsonSchema, Debug)]
+    struct Answer {
+        value: String,
+    }
+
+    // Link returning a predefined result and counting the calls
+    struct MockLink {
+        name: &'static str,
+        status_code: Option<u16>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait(?Send)]
+    impl ChainLink for MockLink {
+        fn model_name(&self) -> String {
+            self.name.to_string()
+        }
+
+        async fn get_answer_value(
+            &self,
+            _instructions: &str,
+            _json_schema: &str,
+        ) -> Result<(Value, Option<TokenUsage>)> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            match self.status_code {
+                Some(status_code) => Err(api_error(status_code)),
+                None => Ok((
+                    json!({"value": self.name}),
+                    Some(TokenUsage {
+                        input_tokens: 10,
+                        output_tokens: 5,
+                        total_tokens: 15,
+                        cached_input_tokens: 0,
+                        cache_creation_input_tokens: 0,
+                    }),
+                )),
+            }
+        }
+    }
+
+    fn api_error(status_code: u16) -> anyhow::Error {
+        anyhow!(AllmsApiError {
+            crate_name: "allms".to_string(),
+            module: "test".to_string(),
+            status_code,
+            headers: BTreeMap::new(),
+            message: None,
+            error_detail: String::new(),
+        })
+    }
+
+    fn mock_chain(links: &[(&'static str, Option<u16>)]) -> (CompletionsChain, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut chain = CompletionsChain::new();
+        for (name, status_code) in links {
+            chain.links.push(Box::new(MockLink {
+                name,
+                status_code: *status_code,
+                calls: calls.clone(),
+            }));
+        }
+        (chain, calls)
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(is_retryable(&api_error(429)));
+        assert!(is_retryable(&api_error(503)));
+        assert!(is_retryable(&api_error(529)));
+        assert!(!is_retryable(&api_error(400)));
+        assert!(!is_retryable(&api_error(401)));
+        assert!(!is_retryable(&anyhow!("Response does not match schema")));
+    }
+
+    #[tokio::test]
+    async fn test_chain_falls_back_on_retryable_errors() {
+        let (chain, calls) = mock_chain(&[
+            ("gpt-4o", Some(429)),
+            ("claude", Some(500)),
+            ("gemini", None),
+        ]);
+        let answer = chain.get_answer::<Answer>("Test").await.unwrap();
+        assert_eq!(answer.model, "gemini");
+        assert_eq!(answer.answer.value, "gemini");
+        assert_eq!(answer.usage.unwrap().total_tokens, 15);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_chain_stops_on_deterministic_errors() {
+        let (chain, calls) = mock_chain(&[("gpt-4o", Some(400)), ("claude", None)]);
+        let error = chain.get_answer::<Answer>("Test").await.unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<AllmsApiError>().unwrap().status_code,
+            400
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // The last error is returned if all models fail
+        let (chain, _) = mock_chain(&[("gpt-4o", Some(429)), ("claude", Some(503))]);
+        let error = chain.get_answer::<Answer>("Test").await.unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<AllmsApiError>().unwrap().status_code,
+            503
+        );
+
+        assert!(CompletionsChain::new()
+            .get_answer::<Answer>("Test")
+            .await
+            .is_err());
+    }
+}
@@ -5,17 +5,56 @@ lazy_static! {
         std::env::var("OPENAI_API_URL").unwrap_or("https://api.openai.com".to_string());
 }
 
+pub(crate) const OPENAI_MODERATION_MODEL: &str = "omni-moderation-latest";
+//Name of the function used to return the answer when function calling is used
+pub(crate) const OPENAI_OUTPUT_FUNCTION: &str = "analyze_data";
+//Live rate limit headers in the order: requests limit, remaining, reset and tokens limit, remaining, reset
+pub(crate) const OPENAI_RATE_LIMIT_HEADERS: [&str; 6] = [
+    "x-ratelimit-limit-requests",
+    "x-ratelimit-remaining-requests",
+    "x-ratelimit-reset-requests",
+    "x-ratelimit-limit-tokens",
+    "x-ratelimit-remaining-tokens",
+    "x-ratelimit-reset-tokens",
+];
+
 lazy_static! {
     pub(crate) static ref ANTHROPIC_API_URL: String = std::env::var("ANTHROPIC_API_URL")
         .unwrap_or("https://api.anthropic.com/v1/complete".to_string());
     pub(crate) static ref ANTHROPIC_MESSAGES_API_URL: String =
         std::env::var("ANTHROPIC_MESSAGES_API_URL")
             .unwrap_or("https://api.anthropic.com/v1/messages".to_string());
+    pub(crate) static ref ANTHROPIC_MODELS_API_URL: String =
+        std::env::var("ANTHROPIC_MODELS_API_URL")
+            .unwrap_or("https://api.anthropic.com/v1/models".to_string());
+    pub(crate) static ref ANTHROPIC_FILES_API_URL: String =
+        std::env::var("ANTHROPIC_FILES_API_URL")
+            .unwrap_or("https://api.anthropic.com/v1/files".to_string());
 }
 
+pub(crate) const ANTHROPIC_DEFAULT_API_VERSION: &str = "2023-06-01";
+pub(crate) const ANTHROPIC_FILES_API_BETA: &str = "files-api-2025-04-14";
+pub(crate) const ANTHROPIC_EXTENDED_CACHE_TTL_BETA: &str = "extended-cache-ttl-2025-04-11";
+//Live rate limit headers in the order: requests limit, remaining, reset and tokens limit, remaining, reset
+pub(crate) const ANTHROPIC_RATE_LIMIT_HEADERS: [&str; 6] = [
+    "anthropic-ratelimit-requests-limit",
+    "anthropic-ratelimit-requests-remaining",
+    "anthropic-ratelimit-requests-reset",
+    "anthropic-ratelimit-tokens-limit",
+    "anthropic-ratelimit-tokens-remaining",
+    "anthropic-ratelimit-tokens-reset",
+];
+
 lazy_static! {
     pub(crate) static ref MISTRAL_API_URL: String = std::env::var("MISTRAL_API_URL")
         .unwrap_or("https://api.mistral.ai/v1/chat/completions".to_string());
+    pub(crate) static ref MISTRAL_MODELS_API_URL: String = std::env::var("MISTRAL_MODELS_API_URL")
+        .unwrap_or("https://api.mistral.ai/v1/models".to_string());
+}
+
+lazy_static! {
+    pub(crate) static ref OLLAMA_API_URL: String =
+        std::env::var("OLLAMA_API_URL").unwrap_or("http://localhost:11434".to_string());
 }
 
 lazy_static! {
@@ -23,16 +62,17 @@ lazy_static! {
         let region = std::env::var("GOOGLE_REGION").unwrap_or("us-central1".to_string());
         let project_id = std::env::var("GOOGLE_PROJECT_ID").expect("PROJECT_ID not set");
 
-        format!("https://{}-aiplatform.googleapis.com/v1/projects/{}/locations/{}/publishers/google/models/gemini-pro:streamGenerateContent?alt=sse",
+        format!("https://{}-aiplatform.googleapis.com/v1/projects/{}/locations/{}/publishers/google/models",
                 region, project_id, region)
     };
+    //Base URL of the Gemini API. The API version and model are added by `GoogleModels::get_endpoint`
     pub(crate) static ref GOOGLE_GEMINI_API_URL: String = std::env::var("GOOGLE_GEMINI_API_URL")
-        .unwrap_or(
-            "https://generativelanguage.googleapis.com/v1/models/gemini-pro:generateContent"
-                .to_string()
-        );
+        .unwrap_or("https://generativelanguage.googleapis.com".to_string());
 }
 
+pub(crate) const GOOGLE_CLOUD_PLATFORM_SCOPE: &str =
+    "https://www.googleapis.com/auth/cloud-platform";
+
 //Generic OpenAI instructions
 pub(crate) const OPENAI_BASE_INSTRUCTIONS: &str = r#"You are a computer function. You are expected to perform the following tasks:
 Step 1: Review and understand the 'instructions' from the *Instructions* section.
@@ -59,4 +99,6 @@ pub(crate) const OPENAI_ASSISTANT_INSTRUCTIONS: &str = r#"You are a computer fun
 4: Respond ONLY with properly formatted data portion of a Json. No other words or text, only valid Json in your answers. 
 "#;
 
+//Maximum length of the content of an Assistants API message
+pub(crate) const OPENAI_ASSISTANT_MESSAGE_MAX_CHARS: usize = 256_000;
 pub(crate) const DEFAULT_AZURE_VERSION: &str = "2024-06-01";
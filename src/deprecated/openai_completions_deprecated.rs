@@ -376,7 +376,7 @@ pub struct OpenAI {
 }
 
 impl OpenAI {
-    ///
+    /// Constructor
     pub fn new(
         open_ai_key: &str,
         model: OpenAIModels,
@@ -521,9 +521,7 @@ impl OpenAI {
         if prompt_tokens * 2 >= self.max_tokens {
             warn!(
                 "{} tokens remaining for response: {} allocated, {} used for prompt",
-                response_tokens.to_string(),
-                self.max_tokens.to_string(),
-                prompt_tokens.to_string(),
+                response_tokens, self.max_tokens, prompt_tokens,
             );
         };
 
@@ -541,8 +539,7 @@ impl OpenAI {
             info!("[debug] Model body: {:#?}", model_body);
             info!(
                 "[debug] Prompt accounts for approx {} tokens, leaving {} tokens for answer.",
-                prompt_tokens.to_string(),
-                response_tokens.to_string(),
+                prompt_tokens, response_tokens,
             );
         }
 
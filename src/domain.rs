@@ -1,5 +1,10 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use crate::enums::{OpenAIAssistantRole, OpenAIRunStatus, OpenAIToolTypes};
 
@@ -12,16 +17,26 @@ pub struct OpenAPICompletionsResponse {
     pub model: Option<String>,
     pub choices: Option<Vec<OpenAPICompletionsChoices>>,
     pub usage: Option<OpenAPIUsage>,
+    pub system_fingerprint: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct OpenAPICompletionsChoices {
     pub text: Option<String>,
     pub index: Option<u32>,
-    pub logprobs: Option<u32>,
+    pub logprobs: Option<OpenAPILogprobs>,
     pub finish_reason: Option<String>,
 }
 
+//Legacy Completions API returns the logprobs as parallel arrays
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OpenAPILogprobs {
+    pub tokens: Option<Vec<String>>,
+    pub token_logprobs: Option<Vec<Option<f64>>>,
+    pub top_logprobs: Option<Vec<Option<BTreeMap<String, f64>>>>,
+    pub text_offset: Option<Vec<u32>>,
+}
+
 //OpenAI API response type format for Chat API
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct OpenAPIChatResponse {
@@ -31,6 +46,8 @@ pub struct OpenAPIChatResponse {
     pub model: Option<String>,
     pub choices: Option<Vec<OpenAPIChatChoices>>,
     pub usage: Option<OpenAPIUsage>,
+    pub system_fingerprint: Option<String>,
+    pub service_tier: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -38,6 +55,12 @@ pub struct OpenAPIChatChoices {
     pub message: OpenAPIChatMessage,
     pub index: Option<u32>,
     pub finish_reason: Option<String>,
+    pub logprobs: Option<OpenAPIChatLogprobs>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OpenAPIChatLogprobs {
+    pub content: Option<Vec<TokenLogprob>>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -45,6 +68,9 @@ pub struct OpenAPIChatMessage {
     pub role: String,
     pub content: Option<String>,
     pub function_call: Option<OpenAPIChatFunctionCall>,
+    pub tool_calls: Option<Vec<OpenAPIChatToolCall>>,
+    pub refusal: Option<String>,
+    pub audio: Option<AudioOutput>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -53,11 +79,420 @@ pub struct OpenAPIChatFunctionCall {
     pub(crate) arguments: String,
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OpenAPIChatToolCall {
+    pub id: Option<String>,
+    pub function: OpenAPIChatFunctionCall,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct OpenAPIUsage {
-    prompt_tokens: Option<u32>,
-    completion_tokens: Option<u32>,
-    total_tokens: Option<u32>,
+    pub prompt_tokens: Option<u32>,
+    pub completion_tokens: Option<u32>,
+    pub total_tokens: Option<u32>,
+    pub prompt_tokens_details: Option<OpenAPIPromptTokensDetails>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OpenAPIPromptTokensDetails {
+    pub cached_tokens: Option<u32>,
+}
+
+//Response type format of the Models List APIs. Only the model ID is used
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ModelListResp {
+    pub data: Vec<ModelListItem>,
+    pub has_more: Option<bool>,
+    pub last_id: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ModelListItem {
+    pub id: String,
+}
+
+///Reference to a file uploaded via the provider's Files API that can be attached to a `Completions` request
+///For OpenAI and Anthropic `id` is the file ID. For Gemini it is the file URI and `mime_type` is required
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct LLMFileRef {
+    pub id: String,
+    pub mime_type: Option<String>,
+}
+
+impl LLMFileRef {
+    pub fn new(id: &str) -> Self {
+        LLMFileRef {
+            id: id.to_string(),
+            mime_type: None,
+        }
+    }
+
+    pub fn mime_type(mut self, mime_type: &str) -> Self {
+        self.mime_type = Some(mime_type.to_string());
+        self
+    }
+}
+
+///Audio clip attached to a `Completions` request. `data` is the base64 encoded audio in the provided `format` (e.g. `wav` or `mp3`)
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct AudioInput {
+    pub data: String,
+    pub format: String,
+}
+
+impl AudioInput {
+    pub fn new(data: &str, format: &str) -> Self {
+        AudioInput {
+            data: data.to_string(),
+            format: format.to_string(),
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8], format: &str) -> Self {
+        AudioInput {
+            data: base64::encode(bytes),
+            format: format.to_string(),
+        }
+    }
+}
+
+///Spoken answer returned by audio models. `data` is the base64 encoded audio and `transcript` the text that was spoken
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct AudioOutput {
+    pub id: String,
+    pub data: String,
+    #[serde(default)]
+    pub transcript: String,
+    pub expires_at: Option<u64>,
+}
+
+impl AudioOutput {
+    ///Decodes the base64 encoded audio
+    pub fn bytes(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(base64::decode(&self.data)?)
+    }
+}
+
+///Function the model can call, described by its name and the Json schema of its arguments
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct LLMFunction {
+    pub name: String,
+    pub description: String,
+    pub parameters: Value,
+}
+
+impl LLMFunction {
+    pub fn new(name: &str, description: &str, parameters: Value) -> Self {
+        LLMFunction {
+            name: name.to_string(),
+            description: description.to_string(),
+            parameters,
+        }
+    }
+}
+
+///Function call requested by the model, normalized across providers. The function is executed by the caller
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ToolCall {
+    pub name: String,
+    pub arguments: Value,
+}
+
+///Role of a previous turn of the conversation
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub enum LLMMessageRole {
+    User,
+    Assistant,
+}
+
+impl LLMMessageRole {
+    pub fn as_str(&self) -> &str {
+        match self {
+            LLMMessageRole::User => "user",
+            LLMMessageRole::Assistant => "assistant",
+        }
+    }
+}
+
+///Previous turn of the conversation sent before the instructions of a `Completions` request
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct LLMMessage {
+    pub role: LLMMessageRole,
+    pub content: String,
+}
+
+///Token usage reported by the API, normalized across providers
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct TokenUsage {
+    pub input_tokens: usize,
+    pub output_tokens: usize,
+    pub total_tokens: usize,
+    //Input tokens served from the provider's prompt cache, billed at a discounted rate. Included in `input_tokens`
+    #[serde(default)]
+    pub cached_input_tokens: usize,
+    //Input tokens written to the provider's prompt cache, billed at a premium rate. Included in `input_tokens`
+    #[serde(default)]
+    pub cache_creation_input_tokens: usize,
+}
+
+impl TokenUsage {
+    ///
+    /// Estimates the cost of the request in USD based on the price per million tokens of the model
+    /// Cached input tokens are priced at the discounted rate and prompt cache writes at the cache write rate
+    ///
+    pub fn estimated_cost(&self, pricing: &TokenPricing) -> f64 {
+        let uncached_input_tokens = self
+            .input_tokens
+            .saturating_sub(self.cached_input_tokens)
+            .saturating_sub(self.cache_creation_input_tokens);
+
+        (uncached_input_tokens as f64 * pricing.input
+            + self.cached_input_tokens as f64 * pricing.cached_input
+            + self.cache_creation_input_tokens as f64 * pricing.cache_creation_input
+            + self.output_tokens as f64 * pricing.output)
+            / 1_000_000.0
+    }
+}
+
+///Price of a model in USD per million tokens, used to estimate the cost of a request
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct TokenPricing {
+    pub input: f64,
+    //Price of input tokens served from the prompt cache
+    pub cached_input: f64,
+    //Price of input tokens written to the prompt cache
+    pub cache_creation_input: f64,
+    pub output: f64,
+}
+
+impl TokenPricing {
+    ///
+    /// Creates a pricing where prompt cache writes are billed at the regular input rate
+    ///
+    pub fn new(input: f64, cached_input: f64, output: f64) -> Self {
+        TokenPricing {
+            input,
+            cached_input,
+            cache_creation_input: input,
+            output,
+        }
+    }
+
+    ///
+    /// Sets the price of input tokens written to the prompt cache
+    ///
+    pub fn with_cache_creation_input(mut self, cache_creation_input: f64) -> Self {
+        self.cache_creation_input = cache_creation_input;
+        self
+    }
+}
+
+///Log probability of a generated token, normalized across providers
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+    pub bytes: Option<Vec<u8>>,
+    #[serde(default)]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+///One of the most likely alternatives considered at the position of a generated token
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f64,
+    pub bytes: Option<Vec<u8>>,
+}
+
+///Web page used by the model to ground its answer, normalized across providers
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct WebSource {
+    pub uri: String,
+    pub title: Option<String>,
+}
+
+///Code generated and executed by the model while answering, with the output of the execution, normalized across providers
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct CodeExecution {
+    pub language: String,
+    pub code: String,
+    pub output: Option<String>,
+}
+
+///Processing tier used to serve the request. `Flex` trades higher latency for lower cost and `Priority` the opposite
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceTier {
+    Auto,
+    Default,
+    Flex,
+    Priority,
+}
+
+impl ServiceTier {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ServiceTier::Auto => "auto",
+            ServiceTier::Default => "default",
+            ServiceTier::Flex => "flex",
+            ServiceTier::Priority => "priority",
+        }
+    }
+}
+
+///Time a cached prompt prefix is kept by the provider. Longer retention is billed at a higher cache write rate
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub enum PromptCacheTtl {
+    FiveMinutes,
+    OneHour,
+}
+
+///Controls whether and which tool (function) the model calls, mapped to each provider's `tool_choice` equivalent
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub enum ToolChoice {
+    ///The model decides whether to call a tool
+    Auto,
+    ///The model must call one of the provided tools
+    Any,
+    ///The model must not call any tool
+    None,
+    ///The model must call the tool with the provided name
+    Tool(String),
+}
+
+///Live rate limit reported by the provider in the response headers of a request
+///Unlike the static `RateLimit` it reflects the account tier and the usage of other clients sharing the key
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct LiveRateLimit {
+    pub limit_requests: Option<u64>,
+    pub limit_tokens: Option<u64>,
+    pub remaining_requests: Option<u64>,
+    pub remaining_tokens: Option<u64>,
+    ///Time until the limit is reset (OpenAI, e.g. `6m0s`) or the RFC 3339 timestamp of the reset (Anthropic)
+    pub reset_requests: Option<String>,
+    pub reset_tokens: Option<String>,
+}
+
+///Metadata of a Completions response returned with the answer by `get_answer_with_metadata`
+///Fields are `None` if the provider did not report them or the related feature was not enabled
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+pub struct AnswerMetadata {
+    pub usage: Option<TokenUsage>,
+    ///Backend configuration identifier. Combined with `with_seed` it can be used to detect changes that may affect determinism
+    pub system_fingerprint: Option<String>,
+    ///Processing tier that served the request, which may differ from the requested one (e.g. `auto`)
+    pub service_tier: Option<String>,
+    ///Live rate limit reported in the response headers (OpenAI and Anthropic)
+    pub live_rate_limit: Option<LiveRateLimit>,
+    ///Log probabilities of the output tokens when requested with `with_logprobs(true)`
+    pub logprobs: Option<Vec<TokenLogprob>>,
+    ///Web pages used to ground the answer when `with_web_search` is enabled
+    pub sources: Option<Vec<WebSource>>,
+    ///Code executed by the model and its output when `with_code_execution` is enabled
+    pub code_executions: Option<Vec<CodeExecution>>,
+    ///Spoken answer when `with_audio_output` is enabled
+    pub audio: Option<AudioOutput>,
+}
+
+///Per-request metadata used to correlate log lines of a single API call
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    pub request_id: String,
+    started_at: Instant,
+    live_rate_limit: Arc<Mutex<Option<LiveRateLimit>>>,
+}
+
+impl RequestContext {
+    pub fn new() -> Self {
+        //Unique enough for log correlation without adding a dependency: time-based prefix and a process-wide counter
+        static REQUEST_COUNTER: AtomicU64 = AtomicU64::new(0);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or_default();
+        let counter = REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed);
+        RequestContext {
+            request_id: format!("{:x}-{:04x}", timestamp, counter & 0xffff),
+            started_at: Instant::now(),
+            live_rate_limit: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    ///Time elapsed since the request was started
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    ///Live rate limit parsed from the response headers, if the provider returned them
+    pub fn live_rate_limit(&self) -> Option<LiveRateLimit> {
+        self.live_rate_limit
+            .lock()
+            .ok()
+            .and_then(|live_rate_limit| live_rate_limit.clone())
+    }
+
+    pub(crate) fn set_live_rate_limit(&self, live_rate_limit: Option<LiveRateLimit>) {
+        if let Ok(mut current) = self.live_rate_limit.lock() {
+            *current = live_rate_limit;
+        }
+    }
+}
+
+impl Default for RequestContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<OpenAPIUsage> for TokenUsage {
+    fn from(usage: OpenAPIUsage) -> Self {
+        TokenUsage {
+            input_tokens: usage.prompt_tokens.unwrap_or_default() as usize,
+            output_tokens: usage.completion_tokens.unwrap_or_default() as usize,
+            total_tokens: usage.total_tokens.unwrap_or_default() as usize,
+            cached_input_tokens: usage
+                .prompt_tokens_details
+                .and_then(|details| details.cached_tokens)
+                .unwrap_or_default() as usize,
+            cache_creation_input_tokens: 0,
+        }
+    }
+}
+
+//Zips the parallel arrays into per-token entries. Alternatives are sorted from the most likely
+impl From<OpenAPILogprobs> for Vec<TokenLogprob> {
+    fn from(logprobs: OpenAPILogprobs) -> Self {
+        let token_logprobs = logprobs.token_logprobs.unwrap_or_default();
+        let mut top_logprobs = logprobs.top_logprobs.unwrap_or_default().into_iter();
+        logprobs
+            .tokens
+            .unwrap_or_default()
+            .into_iter()
+            .zip(token_logprobs)
+            .map(|(token, logprob)| {
+                let mut alternatives: Vec<TopLogprob> = top_logprobs
+                    .next()
+                    .flatten()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(token, logprob)| TopLogprob {
+                        token,
+                        logprob,
+                        bytes: None,
+                    })
+                    .collect();
+                alternatives.sort_by(|a, b| b.logprob.total_cmp(&a.logprob));
+                TokenLogprob {
+                    token,
+                    //The first token of the prompt has no logprob when `echo` is used
+                    logprob: logprob.unwrap_or(f64::NEG_INFINITY),
+                    bytes: None,
+                    top_logprobs: alternatives,
+                }
+            })
+            .collect()
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -97,10 +532,30 @@ pub struct OpenAIThreadResp {
     pub created_at: u32,
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OpenAIDeleteResp {
+    pub id: String,
+    pub object: String,
+    pub deleted: bool,
+}
+
+//OpenAI API response type format for paginated List APIs
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OpenAIListResp<T> {
+    pub object: String,
+    pub data: Vec<T>,
+    pub first_id: Option<String>,
+    pub last_id: Option<String>,
+    pub has_more: Option<bool>,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct OpenAIMessageListResp {
     pub object: String,
     pub data: Vec<OpenAIMessageResp>,
+    pub first_id: Option<String>,
+    pub last_id: Option<String>,
+    pub has_more: Option<bool>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -127,6 +582,32 @@ pub struct OpenAIContentText {
     pub value: String,
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OpenAIMessageDeltaResp {
+    pub id: String,
+    pub object: String,
+    pub delta: OpenAIMessageDelta,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OpenAIMessageDelta {
+    #[serde(default)]
+    pub content: Vec<OpenAIDeltaContent>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OpenAIDeltaContent {
+    pub index: u32,
+    #[serde(rename(deserialize = "type", serialize = "type"))]
+    pub content_type: String,
+    pub text: Option<OpenAIDeltaContentText>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OpenAIDeltaContentText {
+    pub value: Option<String>,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct OpenAIMessageAttachment {
     pub file_id: String,
@@ -145,6 +626,31 @@ pub struct OpenAIRunResp {
     pub object: String,
     pub created_at: u32,
     pub status: OpenAIRunStatus,
+    pub required_action: Option<OpenAIRunRequiredAction>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OpenAIRunRequiredAction {
+    #[serde(rename(deserialize = "type", serialize = "type"))]
+    pub action_type: String,
+    pub submit_tool_outputs: Option<OpenAIRunSubmitToolOutputs>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OpenAIRunSubmitToolOutputs {
+    pub tool_calls: Vec<OpenAIRunToolCall>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OpenAIRunToolCall {
+    pub id: String,
+    pub function: OpenAIRunToolCallFunction,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OpenAIRunToolCallFunction {
+    pub name: String,
+    pub arguments: String,
 }
 
 //Anthropic API response type format for Text Completions API
@@ -176,13 +682,20 @@ pub struct AnthropicAPIMessagesResponse {
 pub struct AnthropicAPIMessagesContent {
     #[serde(rename(deserialize = "type", serialize = "type"))]
     pub content_type: String,
+    //Only `text` blocks have text
+    #[serde(default)]
     pub text: String,
+    //Name and input of `tool_use` blocks
+    pub name: Option<String>,
+    pub input: Option<Value>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct AnthropicAPIMessagesUsage {
     pub input_tokens: i32,
     pub output_tokens: i32,
+    pub cache_creation_input_tokens: Option<i32>,
+    pub cache_read_input_tokens: Option<i32>,
 }
 
 //Mistral API response type format for Chat Completions API
@@ -209,6 +722,22 @@ pub struct MistralAPICompletionsChoices {
 pub struct MistralAPICompletionsMessage {
     pub role: Option<String>,
     pub content: Option<String>,
+    pub tool_calls: Option<Vec<MistralAPIToolCall>>,
+}
+
+//Mistral API response type format for Chat Completions API
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct MistralAPIToolCall {
+    pub id: Option<String>,
+    pub function: MistralAPIFunctionCall,
+}
+
+//Mistral API response type format for Chat Completions API
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct MistralAPIFunctionCall {
+    pub name: String,
+    //Arguments are returned as a Json string
+    pub arguments: String,
 }
 
 //Mistral API response type format for Chat Completions API
@@ -219,7 +748,25 @@ pub struct MistralAPICompletionsUsage {
     pub total_tokens: usize,
 }
 
-///Google GeminiPro API response deserialization structs
+///Ollama API response type format for native Chat API
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OllamaChatResponse {
+    pub model: Option<String>,
+    pub created_at: Option<String>,
+    pub message: Option<OllamaChatMessage>,
+    pub done: Option<bool>,
+    pub done_reason: Option<String>,
+    pub prompt_eval_count: Option<u32>,
+    pub eval_count: Option<u32>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OllamaChatMessage {
+    pub role: String,
+    pub content: String,
+}
+
+//Google GeminiPro API response deserialization structs
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GoogleGeminiProApiResp {
     pub candidates: Vec<GoogleGeminiProCandidate>,
@@ -236,6 +783,8 @@ pub struct GoogleGeminiProCandidate {
     pub safety_ratings: Option<Vec<GoogleGeminiProSafetyRating>>,
     #[serde(rename = "citationMetadata")]
     pub citation_metadata: Option<GoogleGeminiProCitationMetadata>,
+    #[serde(rename = "groundingMetadata")]
+    pub grounding_metadata: Option<GoogleGeminiGroundingMetadata>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -246,7 +795,33 @@ pub struct GoogleGeminiProContent {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GoogleGeminiProPart {
-    pub text: String,
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(rename = "functionCall")]
+    pub function_call: Option<GoogleGeminiFunctionCall>,
+    #[serde(rename = "executableCode")]
+    pub executable_code: Option<GoogleGeminiExecutableCode>,
+    #[serde(rename = "codeExecutionResult")]
+    pub code_execution_result: Option<GoogleGeminiCodeExecutionResult>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GoogleGeminiExecutableCode {
+    pub language: String,
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GoogleGeminiCodeExecutionResult {
+    pub outcome: String,
+    pub output: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GoogleGeminiFunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub args: Value,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -281,6 +856,26 @@ pub struct GoogleGeminiProDate {
     pub day: i32,
 }
 
+//Returned when the answer is grounded with Google Search
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GoogleGeminiGroundingMetadata {
+    #[serde(rename = "webSearchQueries")]
+    pub web_search_queries: Option<Vec<String>>,
+    #[serde(rename = "groundingChunks")]
+    pub grounding_chunks: Option<Vec<GoogleGeminiGroundingChunk>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GoogleGeminiGroundingChunk {
+    pub web: Option<GoogleGeminiGroundingWeb>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GoogleGeminiGroundingWeb {
+    pub uri: String,
+    pub title: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GoogleGeminiProUsageMetadata {
     #[serde(rename = "promptTokenCount")]
@@ -289,6 +884,8 @@ pub struct GoogleGeminiProUsageMetadata {
     pub candidates_token_count: i32,
     #[serde(rename = "totalTokenCount")]
     pub total_token_count: i32,
+    #[serde(rename = "cachedContentTokenCount")]
+    pub cached_content_token_count: Option<i32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -299,3 +896,131 @@ pub struct AllmsError {
     pub error_message: String,
     pub error_detail: String,
 }
+
+///Error returned when an LLM API responds with a non-success HTTP status or with an error body
+///It carries the status code and rate-limit headers so that callers can implement their own backoff, e.g. `error.downcast_ref::<AllmsApiError>()`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AllmsApiError {
+    #[serde(rename = "crate")]
+    pub crate_name: String,
+    pub module: String,
+    pub status_code: u16,
+    //Only `retry-after` and rate-limit related headers are retained
+    pub headers: BTreeMap<String, String>,
+    //Error message extracted from the provider's error envelope, if any
+    pub message: Option<String>,
+    pub error_detail: String,
+}
+
+impl AllmsApiError {
+    ///Returns the delay requested by the API via `retry-after-ms` or `retry-after` (in seconds) headers
+    pub fn retry_after(&self) -> Option<Duration> {
+        if let Some(millis) = self
+            .headers
+            .get("retry-after-ms")
+            .and_then(|value| value.parse::<f64>().ok())
+        {
+            return Some(Duration::from_millis(millis.max(0.0) as u64));
+        }
+        self.headers
+            .get("retry-after")
+            .and_then(|value| value.parse::<f64>().ok())
+            .map(|seconds| Duration::from_millis((seconds.max(0.0) * 1000.0) as u64))
+    }
+
+    ///Returns true if the request was rejected due to rate limits
+    pub fn is_rate_limited(&self) -> bool {
+        self.status_code == 429
+    }
+
+    ///Returns true if the failure is transient (timeouts, rate limits, overloaded or unavailable servers) and the request can be retried as is
+    pub fn is_retryable(&self) -> bool {
+        matches!(self.status_code, 408 | 429) || self.status_code >= 500
+    }
+}
+
+impl std::fmt::Display for AllmsApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.message {
+            Some(message) => write!(
+                f,
+                "[{}][{}] API request failed with status {}: {}. Headers: {:?}",
+                self.crate_name, self.module, self.status_code, message, self.headers
+            ),
+            None => write!(
+                f,
+                "[{}][{}] API request failed with status {}. Headers: {:?}. Response: {}",
+                self.crate_name, self.module, self.status_code, self.headers, self.error_detail
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AllmsApiError {}
+
+///Error returned when the model refuses to respond, e.g. due to content policies
+///It can be told apart from other failures with `error.downcast_ref::<AllmsRefusalError>()`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AllmsRefusalError {
+    #[serde(rename = "crate")]
+    pub crate_name: String,
+    pub module: String,
+    //Refusal message provided by the model, if any
+    pub message: String,
+}
+
+impl std::fmt::Display for AllmsRefusalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{}][{}] Model refused to respond: {}",
+            self.crate_name, self.module, self.message
+        )
+    }
+}
+
+impl std::error::Error for AllmsRefusalError {}
+
+///Error returned when the model output is cut off because it reached the output token limit
+///It can be told apart from malformed responses with `error.downcast_ref::<AllmsTruncatedOutputError>()`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AllmsTruncatedOutputError {
+    #[serde(rename = "crate")]
+    pub crate_name: String,
+    pub module: String,
+    //Token limit (prompt and response) used for the last attempt
+    pub max_tokens: usize,
+}
+
+impl std::fmt::Display for AllmsTruncatedOutputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{}][{}] Model output was truncated after reaching the token limit of {}",
+            self.crate_name, self.module, self.max_tokens
+        )
+    }
+}
+
+impl std::error::Error for AllmsTruncatedOutputError {}
+
+///Error returned when an in-flight request is cancelled by the caller
+///It can be told apart from other failures with `error.downcast_ref::<AllmsCancelledError>()`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AllmsCancelledError {
+    #[serde(rename = "crate")]
+    pub crate_name: String,
+    pub module: String,
+}
+
+impl std::fmt::Display for AllmsCancelledError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{}][{}] Request was cancelled",
+            self.crate_name, self.module
+        )
+    }
+}
+
+impl std::error::Error for AllmsCancelledError {}
@@ -8,6 +8,8 @@ pub enum OpenAIToolTypes {
     Retrieval,
     #[serde(rename(deserialize = "file_search", serialize = "file_search"))]
     FileSearch,
+    #[serde(rename(deserialize = "function", serialize = "function"))]
+    Function,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
@@ -36,4 +38,6 @@ pub enum OpenAIRunStatus {
     Completed,
     #[serde(rename(deserialize = "expired", serialize = "expired"))]
     Expired,
+    #[serde(rename(deserialize = "incomplete", serialize = "incomplete"))]
+    Incomplete,
 }
@@ -0,0 +1,278 @@
+use anyhow::{anyhow, Result};
+use log::{error, info};
+use reqwest::{header, Client};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::constants::OPENAI_API_URL;
+use crate::domain::AllmsError;
+use crate::utils::get_api_error;
+
+/// [OpenAI Docs](https://platform.openai.com/docs/guides/images)
+///
+/// The Images API generates images from a text prompt.
+/// DALL·E models return URLs valid for 60 minutes by default, while `gpt-image-1` always returns base64 encoded images.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ImageGeneration {
+    model: ImageModels,
+    debug: bool,
+    api_key: String,
+    quality: Option<String>,
+    style: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub enum ImageModels {
+    DallE2,
+    DallE3,
+    GptImage1,
+}
+
+impl ImageModels {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ImageModels::DallE2 => "dall-e-2",
+            ImageModels::DallE3 => "dall-e-3",
+            ImageModels::GptImage1 => "gpt-image-1",
+        }
+    }
+}
+
+/// Generated image returned either as a URL or as the decoded image bytes
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub enum ImageOutput {
+    Url(String),
+    Bytes(Vec<u8>),
+}
+
+/// Generated image with the prompt that was actually used. DALL·E 3 rewrites the prompt to add detail
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct GeneratedImage {
+    pub output: ImageOutput,
+    pub revised_prompt: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct ImageGenerationResp {
+    created: Option<u64>,
+    data: Vec<ImageGenerationData>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct ImageGenerationData {
+    url: Option<String>,
+    b64_json: Option<String>,
+    revised_prompt: Option<String>,
+}
+
+impl ImageGeneration {
+    /// Constructor
+    pub fn new(model: ImageModels, api_key: &str) -> Self {
+        ImageGeneration {
+            model,
+            debug: false,
+            api_key: api_key.to_string(),
+            quality: None,
+            style: None,
+        }
+    }
+
+    ///
+    /// This method can be used to turn on debug mode for the ImageGeneration struct
+    ///
+    pub fn debug(mut self) -> Self {
+        self.debug = true;
+        self
+    }
+
+    ///
+    /// This method can be used to set the quality of the generated images, e.g. `standard` or `hd` for `dall-e-3` and `low`, `medium` or `high` for `gpt-image-1`
+    /// If not set the API default is used
+    ///
+    pub fn quality(mut self, quality: &str) -> Self {
+        self.quality = Some(quality.to_string());
+        self
+    }
+
+    ///
+    /// This method can be used to set the style of the generated images (`vivid` or `natural`). Only supported by `dall-e-3`
+    ///
+    pub fn style(mut self, style: &str) -> Self {
+        self.style = Some(style.to_string());
+        self
+    }
+
+    ///
+    /// This method generates `n` images of the provided `size` (e.g. `1024x1024`) based on the prompt
+    /// Note that `dall-e-3` only supports generating one image per request
+    ///
+    pub async fn generate(&self, prompt: &str, size: &str, n: u32) -> Result<Vec<ImageOutput>> {
+        let images = self.generate_with_revised_prompt(prompt, size, n).await?;
+        Ok(images.into_iter().map(|image| image.output).collect())
+    }
+
+    ///
+    /// This method works like `generate` but also returns the revised prompt used by the API for each image
+    ///
+    pub async fn generate_with_revised_prompt(
+        &self,
+        prompt: &str,
+        size: &str,
+        n: u32,
+    ) -> Result<Vec<GeneratedImage>> {
+        let images_url = format!(
+            "{}/v1/images/generations",
+            (*OPENAI_API_URL).trim_end_matches('/')
+        );
+
+        let body = self.get_body(prompt, size, n);
+
+        //Make the API call
+        let client = Client::new();
+
+        let response = client
+            .post(images_url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .bearer_auth(&self.api_key)
+            .json(&body)
+            .send()
+            .await?;
+
+        let response_status = response.status();
+        let response_headers = response.headers().clone();
+        let response_text = response.text().await?;
+
+        if self.debug {
+            // Base64 images can be megabytes long so only the size of the response is logged
+            info!(
+                "[debug] OpenAI Images API response: [{}] {} bytes",
+                &response_status,
+                response_text.len()
+            );
+        }
+
+        if !response_status.is_success() {
+            return Err(get_api_error(
+                "image_generation",
+                response_status,
+                &response_headers,
+                response_text,
+            ));
+        }
+
+        Self::parse_response(response_text)
+    }
+
+    // Builds the body of the Images API request
+    fn get_body(&self, prompt: &str, size: &str, n: u32) -> serde_json::Value {
+        let mut body = json!({
+            "model": self.model.as_str(),
+            "prompt": prompt,
+            "size": size,
+            "n": n,
+        });
+        if let Some(quality) = &self.quality {
+            body["quality"] = json!(quality);
+        }
+        if let Some(style) = &self.style {
+            body["style"] = json!(style);
+        }
+        body
+    }
+
+    // This function converts the API response into the list of generated images
+    fn parse_response(response_text: String) -> Result<Vec<GeneratedImage>> {
+        let response_deser: ImageGenerationResp =
+            serde_json::from_str(&response_text).map_err(|error| {
+                let error = AllmsError {
+                    crate_name: "allms".to_string(),
+                    module: "image_generation".to_string(),
+                    error_message: format!("Images API response serialization error: {}", error),
+                    error_detail: response_text.clone(),
+                };
+                error!("{:?}", error);
+                anyhow!("{:?}", error)
+            })?;
+
+        response_deser
+            .data
+            .into_iter()
+            .map(|image| {
+                let output = match (image.url, image.b64_json) {
+                    (_, Some(b64_json)) => base64::decode(b64_json)
+                        .map(ImageOutput::Bytes)
+                        .map_err(|error| {
+                            anyhow!("[allms][ImageGeneration] Invalid base64 image: {}", error)
+                        })?,
+                    (Some(url), None) => ImageOutput::Url(url),
+                    (None, None) => {
+                        return Err(anyhow!(
+                            "[allms][ImageGeneration] Image returned without URL or data."
+                        ))
+                    }
+                };
+                Ok(GeneratedImage {
+                    output,
+                    revised_prompt: image.revised_prompt,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImageGeneration, ImageModels, ImageOutput};
+
+    #[test]
+    fn test_get_body() {
+        let body = ImageGeneration::new(ImageModels::DallE3, "key")
+            .quality("hd")
+            .style("natural")
+            .get_body("A crab", "1024x1024", 1);
+        assert_eq!(body["model"], "dall-e-3");
+        assert_eq!(body["quality"], "hd");
+        assert_eq!(body["style"], "natural");
+
+        let default_body =
+            ImageGeneration::new(ImageModels::GptImage1, "key").get_body("A crab", "1024x1024", 2);
+        assert!(default_body.get("quality").is_none());
+        assert!(default_body.get("style").is_none());
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let url_response = r#"{
+            "created": 1713833628,
+            "data": [
+                {
+                    "url": "https://oaidalleapiprodscus.blob.core.windows.net/private/img-1.png",
+                    "revised_prompt": "A watercolor painting of a crab"
+                }
+            ]
+        }"#;
+        let images = ImageGeneration::parse_response(url_response.to_string()).unwrap();
+        assert_eq!(
+            images[0].output,
+            ImageOutput::Url(
+                "https://oaidalleapiprodscus.blob.core.windows.net/private/img-1.png".to_string()
+            )
+        );
+        assert_eq!(
+            images[0].revised_prompt,
+            Some("A watercolor painting of a crab".to_string())
+        );
+
+        let b64_response = r#"{
+            "created": 1713833628,
+            "data": [{"b64_json": "iVBORw0KGgo="}, {"b64_json": "aGVsbG8="}],
+            "usage": {"total_tokens": 100, "input_tokens": 50, "output_tokens": 50}
+        }"#;
+        let images = ImageGeneration::parse_response(b64_response.to_string()).unwrap();
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[1].output, ImageOutput::Bytes(b"hello".to_vec()));
+        assert!(images[1].revised_prompt.is_none());
+
+        assert!(ImageGeneration::parse_response(r#"{"data": [{}]}"#.to_string()).is_err());
+    }
+}
@@ -1,17 +1,36 @@
 pub mod assistants;
 mod completions;
+mod completions_chain;
 mod constants;
 mod domain;
 mod enums;
+mod image_generation;
 pub mod llm_models;
 pub use llm_models as llm;
+mod moderation;
+mod rate_limiter;
+mod speech;
+mod transcription;
 mod utils;
 
 #[allow(deprecated)]
 mod deprecated;
 
 pub use crate::completions::Completions;
+pub use crate::completions_chain::{CompletionsChain, CompletionsChainAnswer};
 #[allow(deprecated)]
 pub use crate::deprecated::{
     OpenAI, OpenAIAssistant, OpenAIAssistantVersion, OpenAIFile, OpenAIModels,
 };
+pub use crate::domain::{
+    AllmsApiError, AllmsCancelledError, AllmsRefusalError, AllmsTruncatedOutputError,
+};
+pub use crate::image_generation::{GeneratedImage, ImageGeneration, ImageModels, ImageOutput};
+pub use crate::moderation::{Moderation, ModerationResult};
+pub use crate::rate_limiter::RateLimiter;
+pub use crate::speech::{Speech, SpeechFormat, SpeechModels};
+pub use crate::transcription::{
+    Transcription, TranscriptionFormat, TranscriptionModels, TranscriptionResult,
+    TranscriptionSegment, TranscriptionWord,
+};
+pub use crate::utils::{chunk_by_tokens, schema_for_type, strict_schema_for};
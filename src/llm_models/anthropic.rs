@@ -1,13 +1,28 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use log::info;
-use reqwest::{header, Client};
+use log::{debug, error, info};
+use reqwest::{
+    header::{self, HeaderMap},
+    Client,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
-use crate::constants::{ANTHROPIC_API_URL, ANTHROPIC_MESSAGES_API_URL};
-use crate::domain::{AnthropicAPICompletionsResponse, AnthropicAPIMessagesResponse};
+use crate::constants::{
+    ANTHROPIC_API_URL, ANTHROPIC_DEFAULT_API_VERSION, ANTHROPIC_EXTENDED_CACHE_TTL_BETA,
+    ANTHROPIC_FILES_API_BETA, ANTHROPIC_MESSAGES_API_URL, ANTHROPIC_MODELS_API_URL,
+    ANTHROPIC_RATE_LIMIT_HEADERS,
+};
+use crate::domain::{
+    AllmsRefusalError, AnthropicAPICompletionsResponse, AnthropicAPIMessagesResponse, LLMFileRef,
+    LLMFunction, LLMMessage, PromptCacheTtl, RateLimit, RequestContext, TokenPricing, TokenUsage,
+    ToolCall, ToolChoice,
+};
 use crate::llm_models::LLMModel;
+use crate::utils::{
+    add_user_content_parts, get_api_error, get_event_stream, get_live_rate_limit, get_model_list,
+    insert_message_history,
+};
 
 #[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
 pub enum AnthropicModels {
@@ -20,6 +35,38 @@ pub enum AnthropicModels {
     ClaudeInstant1_2,
 }
 
+impl AnthropicModels {
+    ///
+    /// Lists the IDs of models available for the provided API key, following pagination until all are retrieved
+    /// The IDs can be used with `try_from_str`
+    ///
+    pub async fn list_available(api_key: &str) -> Result<Vec<String>> {
+        //Anthropic documentation: https://docs.anthropic.com/en/api/models-list
+        let client = Client::new();
+
+        let mut model_ids = Vec::new();
+        let mut after_id: Option<String> = None;
+        loop {
+            let mut request = client
+                .get(&*ANTHROPIC_MODELS_API_URL)
+                .header("x-api-key", api_key)
+                .header("anthropic-version", ANTHROPIC_DEFAULT_API_VERSION)
+                .query(&[("limit", "1000")]);
+            if let Some(after_id) = &after_id {
+                request = request.query(&[("after_id", after_id)]);
+            }
+
+            let models = get_model_list("llm_models::anthropic", request).await?;
+            model_ids.extend(models.data.into_iter().map(|model| model.id));
+            match (models.has_more, models.last_id) {
+                (Some(true), Some(last_id)) => after_id = Some(last_id),
+                _ => break,
+            }
+        }
+        Ok(model_ids)
+    }
+}
+
 #[async_trait(?Send)]
 impl LLMModel for AnthropicModels {
     fn as_str(&self) -> &str {
@@ -81,46 +128,68 @@ impl LLMModel for AnthropicModels {
         function_call: bool,
         max_tokens: &usize,
         temperature: &f32,
+        system_prompt: Option<&str>,
+        base_instructions: Option<&str>,
     ) -> serde_json::Value {
         let schema_string = serde_json::to_string(json_schema).unwrap_or_default();
-        let base_instructions = self.get_base_instructions(Some(function_call));
-
-        let completions_body = json!({
-            "model": self.as_str(),
-            "max_tokens_to_sample": max_tokens,
-            "temperature": temperature,
-            "prompt": format!(
-                "\n\nHuman:
-                {base_instructions}\n\n
-                Output Json schema:\n
-                {schema_string}\n\n
-                {instructions}
-                \n\nAssistant:",
-            ),
-        });
-
-        let message_body = json!({
-            "model": self.as_str(),
-            "max_tokens": max_tokens,
-            "temperature": temperature,
-            "messages": [{
-                "role": "user",
-                "content": format!(
-                    "{base_instructions}\n\n
-                    Output Json schema:\n
-                    {schema_string}\n\n
-                    {instructions}"
-                )
-            }],
-        });
+        let base_instructions = base_instructions
+            .map(str::to_string)
+            .unwrap_or_else(|| self.get_base_instructions(Some(function_call)));
 
         match self {
+            //Messages API expects `max_tokens` and the prompt as a list of messages with roles
             AnthropicModels::Claude3_5Sonnet
             | AnthropicModels::Claude3Opus
             | AnthropicModels::Claude3Sonnet
-            | AnthropicModels::Claude3Haiku => message_body,
-            // Legacy
-            AnthropicModels::Claude2 | AnthropicModels::ClaudeInstant1_2 => completions_body,
+            | AnthropicModels::Claude3Haiku => {
+                //Base instructions and output schema are kept in a separate block so that they can be cached
+                let mut message_body = json!({
+                    "model": self.as_str(),
+                    "max_tokens": max_tokens,
+                    "temperature": temperature,
+                    "messages": [{
+                        "role": "user",
+                        "content": [
+                            {
+                                "type": "text",
+                                "text": format!(
+                                    "{base_instructions}\n\nOutput Json schema:\n{schema_string}"
+                                ),
+                            },
+                            {
+                                "type": "text",
+                                "text": instructions,
+                            },
+                        ],
+                    }],
+                });
+
+                //Messages API accepts the system prompt as a top-level field
+                if let Some(system_prompt) = system_prompt {
+                    message_body["system"] = json!(system_prompt);
+                }
+                message_body
+            }
+            // Legacy Text Completions API expects `max_tokens_to_sample` and a single prompt
+            AnthropicModels::Claude2 | AnthropicModels::ClaudeInstant1_2 => {
+                //Text Completions API has no system field so the system prompt is prepended to the prompt
+                let completions_system_prompt = system_prompt
+                    .map(|system_prompt| format!("{system_prompt}\n\n"))
+                    .unwrap_or_default();
+                json!({
+                    "model": self.as_str(),
+                    "max_tokens_to_sample": max_tokens,
+                    "temperature": temperature,
+                    "prompt": format!(
+                        "\n\nHuman:
+                        {completions_system_prompt}{base_instructions}\n\n
+                        Output Json schema:\n
+                        {schema_string}\n\n
+                        {instructions}
+                        \n\nAssistant:",
+                    ),
+                })
+            }
         }
     }
     /*
@@ -132,6 +201,8 @@ impl LLMModel for AnthropicModels {
         &self,
         api_key: &str,
         body: &serde_json::Value,
+        extra_headers: &HeaderMap,
+        context: &RequestContext,
         debug: bool,
     ) -> Result<String> {
         //Get the API url
@@ -140,19 +211,34 @@ impl LLMModel for AnthropicModels {
         //Make the API call
         let client = Client::new();
 
+        //Referencing uploaded files requires the Files API beta
+        let request_headers = get_request_headers(body, extra_headers);
+
         //Send request
         let response = client
             .post(model_url)
             .header(header::CONTENT_TYPE, "application/json")
             //Anthropic-specific way of passing API key
             .header("x-api-key", api_key)
-            //Required as per documentation
-            .header("anthropic-version", "2023-06-01")
+            //Required as per documentation. Can be overwritten via extra headers
+            .header("anthropic-version", ANTHROPIC_DEFAULT_API_VERSION)
+            .headers(request_headers)
             .json(&body)
             .send()
             .await?;
 
         let response_status = response.status();
+        debug!(
+            "[allms][{}] Anthropic API response status: {} after {:?}",
+            context.request_id,
+            response_status,
+            context.elapsed()
+        );
+        let response_headers = response.headers().clone();
+        context.set_live_rate_limit(get_live_rate_limit(
+            &response_headers,
+            &ANTHROPIC_RATE_LIMIT_HEADERS,
+        ));
         let response_text = response.text().await?;
 
         if debug {
@@ -162,9 +248,206 @@ impl LLMModel for AnthropicModels {
             );
         }
 
+        if !response_status.is_success() {
+            return Err(get_api_error(
+                "llm_models::anthropic",
+                response_status,
+                &response_headers,
+                response_text,
+            ));
+        }
+
         Ok(response_text)
     }
 
+    //Only the Messages API is streamed
+    fn apply_stream(&self, body: &mut Value) -> bool {
+        match self {
+            AnthropicModels::Claude2 | AnthropicModels::ClaudeInstant1_2 => false,
+            _ => {
+                body["stream"] = json!(true);
+                true
+            }
+        }
+    }
+
+    async fn call_api_stream(
+        &self,
+        api_key: &str,
+        body: &serde_json::Value,
+        extra_headers: &HeaderMap,
+        context: &RequestContext,
+        on_event: &mut dyn for<'a> FnMut(&'a str),
+    ) -> Result<()> {
+        let request = Client::new()
+            .post(self.get_endpoint())
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("x-api-key", api_key)
+            .header("anthropic-version", ANTHROPIC_DEFAULT_API_VERSION)
+            .headers(get_request_headers(body, extra_headers))
+            .json(&body);
+
+        debug!(
+            "[allms][{}] Streaming Anthropic API response",
+            context.request_id
+        );
+        get_event_stream("llm_models::anthropic", request, on_event).await
+    }
+
+    //https://docs.anthropic.com/en/api/messages-streaming
+    fn get_stream_delta(&self, event_data: &str, _function_call: bool) -> Option<String> {
+        let event: Value = serde_json::from_str(event_data).ok()?;
+        match event["type"].as_str()? {
+            "content_block_delta" => event["delta"]["text"].as_str().map(|text| text.to_string()),
+            _ => None,
+        }
+    }
+
+    //The stop reason is reported in the `message_delta` event at the end of the stream
+    fn is_stream_truncated(&self, event_data: &str) -> bool {
+        serde_json::from_str::<Value>(event_data)
+            .map(|event| {
+                event["type"] == "message_delta" && event["delta"]["stop_reason"] == "max_tokens"
+            })
+            .unwrap_or(false)
+    }
+
+    //Files are attached to the user message as document blocks
+    //https://docs.anthropic.com/en/docs/build-with-claude/files
+    fn apply_files(&self, body: &mut Value, files: &[LLMFileRef]) -> Result<()> {
+        match self {
+            AnthropicModels::Claude3_5Sonnet
+            | AnthropicModels::Claude3Opus
+            | AnthropicModels::Claude3Sonnet
+            | AnthropicModels::Claude3Haiku => {
+                let document_blocks = files
+                    .iter()
+                    .map(|file| {
+                        json!({
+                            "type": "document",
+                            "source": { "type": "file", "file_id": file.id },
+                        })
+                    })
+                    .collect();
+                add_user_content_parts(body, document_blocks)
+            }
+            // Legacy
+            AnthropicModels::Claude2 | AnthropicModels::ClaudeInstant1_2 => {
+                Err(anyhow!("{} does not support file inputs.", self.as_str()))
+            }
+        }
+    }
+
+    //Cache breakpoints are set on the system prompt, the base instructions block and the last attached document
+    //Documents are moved before the instructions so that they are part of the cached prefix
+    //https://docs.anthropic.com/en/docs/build-with-claude/prompt-caching
+    fn apply_prompt_cache(&self, body: &mut Value, ttl: &PromptCacheTtl) -> bool {
+        if let AnthropicModels::Claude2 | AnthropicModels::ClaudeInstant1_2 = self {
+            return false;
+        }
+        let cache_control = match ttl {
+            PromptCacheTtl::FiveMinutes => json!({ "type": "ephemeral" }),
+            PromptCacheTtl::OneHour => json!({ "type": "ephemeral", "ttl": "1h" }),
+        };
+
+        if let Some(system_prompt) = body["system"].as_str().map(str::to_string) {
+            body["system"] = json!([{
+                "type": "text",
+                "text": system_prompt,
+                "cache_control": cache_control,
+            }]);
+        }
+
+        let Some(content) = body["messages"]
+            .as_array_mut()
+            .and_then(|messages| {
+                messages
+                    .iter_mut()
+                    .rev()
+                    .find(|message| message["role"] == "user")
+            })
+            .and_then(|message| message["content"].as_array_mut())
+        else {
+            return false;
+        };
+        //Content is built as [base instructions, instructions, documents...]
+        if content.len() < 2 {
+            return false;
+        }
+        let instructions = content.remove(1);
+        content.push(instructions);
+        content[0]["cache_control"] = cache_control.clone();
+        if content.len() > 2 {
+            let last_document = content.len() - 2;
+            content[last_document]["cache_control"] = cache_control;
+        }
+        true
+    }
+
+    //Previous turns are added to the Messages API messages. The conversation should alternate user and assistant turns
+    fn apply_message_history(&self, body: &mut Value, history: &[LLMMessage]) -> bool {
+        match self {
+            AnthropicModels::Claude2 | AnthropicModels::ClaudeInstant1_2 => false,
+            _ => insert_message_history(body, history),
+        }
+    }
+
+    //Functions are declared as tools of the Messages API
+    //https://docs.anthropic.com/en/docs/build-with-claude/tool-use
+    fn apply_functions(&self, body: &mut Value, functions: &[LLMFunction]) -> bool {
+        if let AnthropicModels::Claude2 | AnthropicModels::ClaudeInstant1_2 = self {
+            return false;
+        }
+        let tools: Vec<Value> = functions
+            .iter()
+            .map(|function| {
+                json!({
+                    "name": function.name,
+                    "description": function.description,
+                    "input_schema": function.parameters,
+                })
+            })
+            .collect();
+        body["tools"] = json!(tools);
+        true
+    }
+
+    //This method extracts the `tool_use` content blocks from the API response
+    fn get_tool_calls(&self, response_text: &str) -> Option<Vec<ToolCall>> {
+        if let AnthropicModels::Claude2 | AnthropicModels::ClaudeInstant1_2 = self {
+            return None;
+        }
+        let messages_response: AnthropicAPIMessagesResponse =
+            serde_json::from_str(response_text).ok()?;
+        let tool_calls = messages_response
+            .content
+            .into_iter()
+            .filter(|item| item.content_type == "tool_use")
+            .filter_map(|item| {
+                Some(ToolCall {
+                    name: item.name?,
+                    arguments: item.input.unwrap_or_default(),
+                })
+            })
+            .collect();
+        Some(tool_calls)
+    }
+
+    //Tool choice is only applicable when the body defines `tools`
+    //https://docs.anthropic.com/en/docs/build-with-claude/tool-use#forcing-tool-use
+    fn apply_tool_choice(&self, body: &mut Value, tool_choice: &ToolChoice) -> bool {
+        if body.get("tools").is_none() {
+            return false;
+        }
+        body["tool_choice"] = match tool_choice {
+            ToolChoice::Auto => json!({ "type": "auto" }),
+            ToolChoice::Any => json!({ "type": "any" }),
+            ToolChoice::None => json!({ "type": "none" }),
+            ToolChoice::Tool(name) => json!({ "type": "tool", "name": name }),
+        };
+        true
+    }
+
     //This method attempts to convert the provided API response text into the expected struct and extracts the data from the response
     fn get_data(&self, response_text: &str, _function_call: bool) -> Result<String> {
         //Convert API response to struct representing expected response format
@@ -185,6 +468,17 @@ impl LLMModel for AnthropicModels {
                         acc
                     });
 
+                //Refusals are returned as a distinct error instead of a partial or empty answer
+                if messages_response.stop_reason.as_deref() == Some("refusal") {
+                    let error = AllmsRefusalError {
+                        crate_name: "allms".to_string(),
+                        module: format!("llm_models::anthropic::{}", self.as_str()),
+                        message: assistant_response,
+                    };
+                    error!("{}", error);
+                    return Err(anyhow!(error));
+                }
+
                 //Return completions text
                 Ok(assistant_response)
             }
@@ -198,4 +492,466 @@ impl LLMModel for AnthropicModels {
             }
         }
     }
+
+    //This method extracts the token usage from the API response. Only Messages API reports usage
+    //Both Messages and Text Completions APIs report `max_tokens` as the stop reason of truncated outputs
+    fn is_truncated(&self, response_text: &str) -> bool {
+        let stop_reason = match self {
+            AnthropicModels::Claude3_5Sonnet
+            | AnthropicModels::Claude3Opus
+            | AnthropicModels::Claude3Sonnet
+            | AnthropicModels::Claude3Haiku => {
+                serde_json::from_str::<AnthropicAPIMessagesResponse>(response_text)
+                    .ok()
+                    .and_then(|response| response.stop_reason)
+            }
+            AnthropicModels::Claude2 | AnthropicModels::ClaudeInstant1_2 => {
+                serde_json::from_str::<AnthropicAPICompletionsResponse>(response_text)
+                    .ok()
+                    .map(|response| response.stop_reason)
+            }
+        };
+        stop_reason.as_deref() == Some("max_tokens")
+    }
+
+    fn get_usage(&self, response_text: &str) -> Option<TokenUsage> {
+        match self {
+            AnthropicModels::Claude3_5Sonnet
+            | AnthropicModels::Claude3Opus
+            | AnthropicModels::Claude3Sonnet
+            | AnthropicModels::Claude3Haiku => {
+                let usage = serde_json::from_str::<AnthropicAPIMessagesResponse>(response_text)
+                    .ok()?
+                    .usage;
+                //Input tokens reported by the API exclude the tokens written to and read from the cache
+                let cache_creation_input_tokens =
+                    usage.cache_creation_input_tokens.unwrap_or_default().max(0) as usize;
+                let cached_input_tokens =
+                    usage.cache_read_input_tokens.unwrap_or_default().max(0) as usize;
+                let input_tokens = usage.input_tokens.max(0) as usize
+                    + cache_creation_input_tokens
+                    + cached_input_tokens;
+                let output_tokens = usage.output_tokens.max(0) as usize;
+                Some(TokenUsage {
+                    input_tokens,
+                    output_tokens,
+                    total_tokens: input_tokens + output_tokens,
+                    cached_input_tokens,
+                    cache_creation_input_tokens,
+                })
+            }
+            // Legacy
+            AnthropicModels::Claude2 | AnthropicModels::ClaudeInstant1_2 => None,
+        }
+    }
+
+    //This function allows to check the rate limits for different models
+    fn get_rate_limit(&self) -> RateLimit {
+        //Anthropic documentation (Tier 1): https://docs.anthropic.com/en/api/rate-limits
+        let tpm = match self {
+            AnthropicModels::Claude3_5Sonnet => 40_000,
+            AnthropicModels::Claude3Opus => 20_000,
+            AnthropicModels::Claude3Sonnet => 40_000,
+            AnthropicModels::Claude3Haiku => 50_000,
+            // Legacy
+            AnthropicModels::Claude2 => 40_000,
+            AnthropicModels::ClaudeInstant1_2 => 50_000,
+        };
+        RateLimit { tpm, rpm: 50 }
+    }
+
+    //Anthropic pricing (USD per 1M tokens): https://www.anthropic.com/pricing#anthropic-api
+    //Cache reads are billed at 10% of the input price and cache writes at 125% of it
+    fn get_token_pricing(&self) -> Option<TokenPricing> {
+        let pricing = match self {
+            AnthropicModels::Claude3_5Sonnet | AnthropicModels::Claude3Sonnet => {
+                TokenPricing::new(3.0, 0.3, 15.0).with_cache_creation_input(3.75)
+            }
+            AnthropicModels::Claude3Opus => {
+                TokenPricing::new(15.0, 1.5, 75.0).with_cache_creation_input(18.75)
+            }
+            AnthropicModels::Claude3Haiku => {
+                TokenPricing::new(0.25, 0.03, 1.25).with_cache_creation_input(0.3)
+            }
+            // Legacy
+            AnthropicModels::Claude2 => TokenPricing::new(8.0, 8.0, 24.0),
+            AnthropicModels::ClaudeInstant1_2 => TokenPricing::new(0.8, 0.8, 2.4),
+        };
+        Some(pricing)
+    }
+}
+
+// Adds the betas required by the body to the `anthropic-beta` header: Files API if it references uploaded files and extended cache TTL if it uses 1h cache breakpoints
+// Any betas already provided via extra headers are kept
+fn get_request_headers(body: &Value, extra_headers: &HeaderMap) -> HeaderMap {
+    let mut request_headers = extra_headers.clone();
+
+    let content_blocks: Vec<&Value> = body["messages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|message| message["content"].as_array())
+        .flatten()
+        .chain(body["system"].as_array().into_iter().flatten())
+        .collect();
+    let uses_files = content_blocks
+        .iter()
+        .any(|block| block["source"]["type"] == "file");
+    let uses_extended_cache_ttl = content_blocks
+        .iter()
+        .any(|block| block["cache_control"]["ttl"] == "1h");
+
+    let required_betas = [
+        (uses_files, ANTHROPIC_FILES_API_BETA),
+        (uses_extended_cache_ttl, ANTHROPIC_EXTENDED_CACHE_TTL_BETA),
+    ];
+    for beta in required_betas
+        .iter()
+        .filter(|(required, _)| *required)
+        .map(|(_, beta)| *beta)
+    {
+        let betas = request_headers
+            .get("anthropic-beta")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let betas = match betas {
+            Some(betas) if betas.contains(beta) => betas,
+            Some(betas) => format!("{betas},{beta}"),
+            None => beta.to_string(),
+        };
+        if let Ok(betas) = header::HeaderValue::from_str(&betas) {
+            request_headers.insert("anthropic-beta", betas);
+        }
+    }
+    request_headers
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::{HeaderMap, HeaderValue};
+    use serde_json::json;
+
+    use super::get_request_headers;
+    use crate::domain::{AllmsRefusalError, ModelListResp};
+    use crate::llm_models::{AnthropicModels, LLMFileRef, LLMModel, PromptCacheTtl, ToolChoice};
+
+    #[test]
+    fn test_get_tool_calls() {
+        let model = AnthropicModels::Claude3_5Sonnet;
+        let response = r#"{
+            "id": "msg_01",
+            "type": "message",
+            "role": "assistant",
+            "content": [
+                {"type": "text", "text": "Checking the weather."},
+                {"type": "tool_use", "id": "toolu_01", "name": "get_weather", "input": {"city": "Paris"}}
+            ],
+            "model": "claude-3-5-sonnet-20240620",
+            "stop_reason": "tool_use",
+            "stop_sequence": null,
+            "usage": {"input_tokens": 10, "output_tokens": 5}
+        }"#;
+        let tool_calls = model.get_tool_calls(response).unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].name, "get_weather");
+        assert_eq!(tool_calls[0].arguments, json!({"city": "Paris"}));
+        assert_eq!(
+            model.get_data(response, false).unwrap(),
+            "Checking the weather."
+        );
+    }
+
+    #[test]
+    fn test_stream_delta() {
+        let model = AnthropicModels::Claude3_5Sonnet;
+        let mut body = json!({});
+        assert!(model.apply_stream(&mut body));
+        assert_eq!(body["stream"], true);
+        assert!(!AnthropicModels::Claude2.apply_stream(&mut json!({})));
+
+        let delta = r#"{"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "{\"name\""}}"#;
+        assert_eq!(
+            model.get_stream_delta(delta, false),
+            Some("{\"name\"".to_string())
+        );
+        let ping = r#"{"type": "ping"}"#;
+        assert_eq!(model.get_stream_delta(ping, false), None);
+        assert!(!model.is_stream_truncated(ping));
+
+        let end_turn = r#"{"type": "message_delta", "delta": {"stop_reason": "end_turn", "stop_sequence": null}, "usage": {"output_tokens": 15}}"#;
+        assert!(!model.is_stream_truncated(end_turn));
+        let max_tokens = r#"{"type": "message_delta", "delta": {"stop_reason": "max_tokens", "stop_sequence": null}, "usage": {"output_tokens": 1024}}"#;
+        assert!(model.is_stream_truncated(max_tokens));
+    }
+
+    #[test]
+    fn test_try_from_str_round_trip() {
+        let models = vec![
+            AnthropicModels::Claude3_5Sonnet,
+            AnthropicModels::Claude3Opus,
+            AnthropicModels::Claude3Sonnet,
+            AnthropicModels::Claude3Haiku,
+            AnthropicModels::Claude2,
+            AnthropicModels::ClaudeInstant1_2,
+        ];
+        for model in models {
+            assert_eq!(model.canonical_name(), model.as_str());
+            assert_eq!(
+                AnthropicModels::try_from_str(model.canonical_name()),
+                Some(model.clone())
+            );
+        }
+    }
+
+    #[test]
+    fn test_max_requests() {
+        let model = AnthropicModels::Claude3_5Sonnet;
+        let expected_max = std::cmp::min(50, 40_000 / ((4_096_f64 * 0.5).ceil() as usize));
+        assert_eq!(model.get_max_requests(), expected_max);
+
+        let model = AnthropicModels::Claude3Opus;
+        let expected_max = std::cmp::min(50, 20_000 / ((4_096_f64 * 0.5).ceil() as usize));
+        assert_eq!(model.get_max_requests(), expected_max);
+    }
+
+    #[test]
+    fn test_get_data_refusal() {
+        let response = r#"{
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "content": [],
+            "model": "claude-3-5-sonnet-20240620",
+            "stop_reason": "refusal",
+            "stop_sequence": null,
+            "usage": {"input_tokens": 10, "output_tokens": 0}
+        }"#;
+        let error = AnthropicModels::Claude3_5Sonnet
+            .get_data(response, false)
+            .unwrap_err();
+        assert!(error.downcast_ref::<AllmsRefusalError>().is_some());
+    }
+
+    #[test]
+    fn test_is_truncated() {
+        let response = r#"{
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "content": [{"type": "text", "text": "{\"items\": [1, 2"}],
+            "model": "claude-3-haiku-20240307",
+            "stop_reason": "max_tokens",
+            "stop_sequence": null,
+            "usage": {"input_tokens": 10, "output_tokens": 5}
+        }"#;
+        assert!(AnthropicModels::Claude3Haiku.is_truncated(response));
+        assert!(!AnthropicModels::Claude3Haiku
+            .is_truncated(&response.replace("max_tokens", "end_turn")));
+    }
+
+    #[test]
+    fn test_max_tokens_field() {
+        let body = AnthropicModels::Claude3_5Sonnet.get_body(
+            "Say hi",
+            &json!({}),
+            false,
+            &1000,
+            &0.0,
+            Some("Be brief"),
+            None,
+        );
+        assert_eq!(body["max_tokens"], 1000);
+        assert!(body.get("max_tokens_to_sample").is_none());
+        assert_eq!(body["messages"][0]["role"], "user");
+        assert_eq!(body["system"], "Be brief");
+
+        let legacy_body =
+            AnthropicModels::Claude2.get_body("Say hi", &json!({}), false, &1000, &0.0, None, None);
+        assert_eq!(legacy_body["max_tokens_to_sample"], 1000);
+        assert!(legacy_body.get("max_tokens").is_none());
+        assert!(legacy_body.get("messages").is_none());
+    }
+
+    #[test]
+    fn test_apply_tool_choice() {
+        let model = AnthropicModels::Claude3_5Sonnet;
+        let mut body = json!({"tools": [{"name": "get_weather"}]});
+        assert!(model.apply_tool_choice(&mut body, &ToolChoice::Tool("get_weather".to_string())));
+        assert_eq!(
+            body["tool_choice"],
+            json!({"type": "tool", "name": "get_weather"})
+        );
+
+        let mut body = json!({});
+        assert!(!model.apply_tool_choice(&mut body, &ToolChoice::Any));
+        assert!(body.get("tool_choice").is_none());
+    }
+
+    #[test]
+    fn test_apply_prompt_cache() {
+        let model = AnthropicModels::Claude3_5Sonnet;
+        let mut body = model.get_body(
+            "Summarize",
+            &json!({}),
+            false,
+            &1000,
+            &0.0,
+            Some("You are a legal analyst."),
+            None,
+        );
+        model
+            .apply_files(&mut body, &[LLMFileRef::new("file_123")])
+            .unwrap();
+        assert!(model.apply_prompt_cache(&mut body, &PromptCacheTtl::OneHour));
+
+        let cache_control = json!({"type": "ephemeral", "ttl": "1h"});
+        assert_eq!(body["system"][0]["text"], "You are a legal analyst.");
+        assert_eq!(body["system"][0]["cache_control"], cache_control);
+
+        // Documents are moved before the instructions, which are not cached
+        let content = body["messages"][0]["content"].as_array().unwrap();
+        assert_eq!(content[0]["cache_control"], cache_control);
+        assert_eq!(content[1]["type"], "document");
+        assert_eq!(content[1]["cache_control"], cache_control);
+        assert_eq!(content[2]["text"], "Summarize");
+        assert!(content[2].get("cache_control").is_none());
+
+        let headers = get_request_headers(&body, &HeaderMap::new());
+        assert_eq!(
+            headers.get("anthropic-beta").unwrap(),
+            "files-api-2025-04-14,extended-cache-ttl-2025-04-11"
+        );
+
+        let mut legacy_body = AnthropicModels::Claude2.get_body(
+            "Summarize",
+            &json!({}),
+            false,
+            &1000,
+            &0.0,
+            None,
+            None,
+        );
+        assert!(!AnthropicModels::Claude2
+            .apply_prompt_cache(&mut legacy_body, &PromptCacheTtl::FiveMinutes));
+    }
+
+    #[test]
+    fn test_get_usage_with_cache() {
+        let response = r#"{
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "content": [{"type": "text", "text": "{}"}],
+            "model": "claude-3-5-sonnet-20240620",
+            "stop_reason": "end_turn",
+            "stop_sequence": null,
+            "usage": {"input_tokens": 50, "cache_creation_input_tokens": 0, "cache_read_input_tokens": 10000, "output_tokens": 20}
+        }"#;
+        let usage = AnthropicModels::Claude3_5Sonnet
+            .get_usage(response)
+            .unwrap();
+        assert_eq!(usage.input_tokens, 10050);
+        assert_eq!(usage.cached_input_tokens, 10000);
+        assert_eq!(usage.cache_creation_input_tokens, 0);
+        assert_eq!(usage.total_tokens, 10070);
+    }
+
+    #[test]
+    fn test_estimated_cost_with_cache() {
+        let model = AnthropicModels::Claude3_5Sonnet;
+        let pricing = model.get_token_pricing().unwrap();
+        let response = r#"{
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "content": [{"type": "text", "text": "{}"}],
+            "model": "claude-3-5-sonnet-20240620",
+            "stop_reason": "end_turn",
+            "stop_sequence": null,
+            "usage": {"input_tokens": 50, "cache_creation_input_tokens": 2000, "cache_read_input_tokens": 10000, "output_tokens": 20}
+        }"#;
+        let usage = model.get_usage(response).unwrap();
+        //Cache reads at $0.30, cache writes at $3.75 and the remaining input at $3 per 1M
+        let expected = (50.0 * 3.0 + 10000.0 * 0.3 + 2000.0 * 3.75 + 20.0 * 15.0) / 1_000_000.0;
+        assert!((usage.estimated_cost(&pricing) - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_apply_files() {
+        let model = AnthropicModels::Claude3_5Sonnet;
+        let mut body = model.get_body("Summarize", &json!({}), false, &1000, &0.0, None, None);
+        model
+            .apply_files(&mut body, &[LLMFileRef::new("file_123")])
+            .unwrap();
+
+        let content = body["messages"][0]["content"].as_array().unwrap();
+        assert_eq!(content.len(), 3);
+        assert_eq!(content[0]["type"], "text");
+        assert_eq!(content[1]["text"], "Summarize");
+        assert_eq!(
+            content[2],
+            json!({"type": "document", "source": {"type": "file", "file_id": "file_123"}})
+        );
+
+        // The Files API beta is added to any betas already requested
+        let mut extra_headers = HeaderMap::new();
+        extra_headers.insert(
+            "anthropic-beta",
+            HeaderValue::from_static("output-128k-2025-02-19"),
+        );
+        let headers = get_request_headers(&body, &extra_headers);
+        assert_eq!(
+            headers.get("anthropic-beta").unwrap(),
+            "output-128k-2025-02-19,files-api-2025-04-14"
+        );
+
+        let mut legacy_body = AnthropicModels::Claude2.get_body(
+            "Summarize",
+            &json!({}),
+            false,
+            &1000,
+            &0.0,
+            None,
+            None,
+        );
+        assert!(AnthropicModels::Claude2
+            .apply_files(&mut legacy_body, &[LLMFileRef::new("file_123")])
+            .is_err());
+    }
+
+    #[test]
+    fn test_request_headers_without_files() {
+        let body = AnthropicModels::Claude3Haiku.get_body(
+            "Summarize",
+            &json!({}),
+            false,
+            &1000,
+            &0.0,
+            None,
+            None,
+        );
+        let headers = get_request_headers(&body, &HeaderMap::new());
+        assert!(headers.get("anthropic-beta").is_none());
+    }
+
+    #[test]
+    fn test_model_list_deserialization() {
+        let response = r#"{
+            "data": [
+                {"type": "model", "id": "claude-3-5-sonnet-20240620", "display_name": "Claude 3.5 Sonnet", "created_at": "2024-06-20T00:00:00Z"},
+                {"type": "model", "id": "claude-3-haiku-20240307", "display_name": "Claude 3 Haiku", "created_at": "2024-03-07T00:00:00Z"}
+            ],
+            "has_more": true,
+            "first_id": "claude-3-5-sonnet-20240620",
+            "last_id": "claude-3-haiku-20240307"
+        }"#;
+        let models: ModelListResp = serde_json::from_str(response).unwrap();
+        assert_eq!(models.data[0].id, "claude-3-5-sonnet-20240620");
+        assert_eq!(
+            AnthropicModels::try_from_str(&models.data[0].id),
+            Some(AnthropicModels::Claude3_5Sonnet)
+        );
+        assert_eq!(models.has_more, Some(true));
+        assert_eq!(models.last_id, Some("claude-3-haiku-20240307".to_string()));
+    }
 }
@@ -1,15 +1,25 @@
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use futures::stream::StreamExt;
-use log::info;
-use reqwest::{header, Client};
+use log::{debug, info};
+use reqwest::{
+    header::{self, HeaderMap},
+    Client,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use yup_oauth2::{
+    authenticator::DefaultAuthenticator, parse_service_account_key, read_service_account_key,
+    ServiceAccountAuthenticator,
+};
 
-use crate::constants::{GOOGLE_GEMINI_API_URL, GOOGLE_VERTEX_API_URL};
-use crate::domain::{GoogleGeminiProApiResp, RateLimit};
+use crate::constants::{GOOGLE_CLOUD_PLATFORM_SCOPE, GOOGLE_GEMINI_API_URL, GOOGLE_VERTEX_API_URL};
+use crate::domain::{
+    CodeExecution, GoogleGeminiProApiResp, LLMFileRef, LLMFunction, LLMMessage, LLMMessageRole,
+    RateLimit, RequestContext, TokenUsage, ToolCall, ToolChoice, WebSource,
+};
 use crate::llm_models::LLMModel;
-use crate::utils::sanitize_json_response;
+use crate::utils::{gemini_response_schema, get_api_error, sanitize_json_response};
 
 #[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
 //Google docs: https://cloud.google.com/vertex-ai/docs/generative-ai/model-reference/gemini
@@ -25,6 +35,62 @@ pub enum GoogleModels {
     Gemini1_0ProVertex,
 }
 
+impl GoogleModels {
+    ///
+    /// Returns `true` if the model supports Json output via `responseMimeType` and `responseSchema`
+    ///
+    pub fn supports_response_schema(&self) -> bool {
+        matches!(
+            self,
+            GoogleModels::Gemini1_5Pro
+                | GoogleModels::Gemini1_5Flash
+                | GoogleModels::Gemini1_5ProVertex
+                | GoogleModels::Gemini1_5FlashVertex
+        )
+    }
+
+    //Structured output, system instructions and tools are only available in the `v1beta` version of the Gemini API
+    //Vertex AI supports them in `v1`
+    fn get_model_url(&self, api_url: &str) -> String {
+        match self {
+            GoogleModels::GeminiPro
+            | GoogleModels::Gemini1_5Pro
+            | GoogleModels::Gemini1_5Flash
+            | GoogleModels::Gemini1_0Pro => {
+                let api_version = match self.supports_response_schema() {
+                    true => "v1beta",
+                    false => "v1",
+                };
+                format!(
+                    "{}/{}/models/{}:generateContent",
+                    api_url.trim_end_matches('/'),
+                    api_version,
+                    self.as_str()
+                )
+            }
+            GoogleModels::GeminiProVertex
+            | GoogleModels::Gemini1_5ProVertex
+            | GoogleModels::Gemini1_5FlashVertex
+            | GoogleModels::Gemini1_0ProVertex => format!(
+                "{}/{}:streamGenerateContent?alt=sse",
+                api_url.trim_end_matches('/'),
+                self.as_str()
+            ),
+        }
+    }
+
+    //Gemini 1.0 models reject `systemInstruction` ("Developer instruction is not enabled")
+    fn supports_system_instruction(&self) -> bool {
+        matches!(
+            self,
+            GoogleModels::Gemini1_5Pro
+                | GoogleModels::Gemini1_5Flash
+                | GoogleModels::Gemini1_5ProVertex
+                | GoogleModels::Gemini1_5FlashVertex
+        )
+    }
+}
+
 #[async_trait(?Send)]
 impl LLMModel for GoogleModels {
     fn as_str(&self) -> &str {
@@ -50,6 +116,17 @@ impl LLMModel for GoogleModels {
         }
     }
 
+    //Vertex AI variants call the same models as the Gemini API so they are told apart with a `-vertex` suffix
+    fn canonical_name(&self) -> &str {
+        match self {
+            GoogleModels::GeminiProVertex => "gemini-pro-vertex",
+            GoogleModels::Gemini1_5ProVertex => "gemini-1.5-pro-vertex",
+            GoogleModels::Gemini1_5FlashVertex => "gemini-1.5-flash-vertex",
+            GoogleModels::Gemini1_0ProVertex => "gemini-1.0-pro-vertex",
+            _ => self.as_str(),
+        }
+    }
+
     fn default_max_tokens(&self) -> usize {
         //https://cloud.google.com/vertex-ai/docs/generative-ai/learn/models
         match self {
@@ -60,6 +137,11 @@ impl LLMModel for GoogleModels {
         }
     }
 
+    //Gemini rejects `$ref` in `responseSchema` and the schema in the system instruction should match it
+    fn requires_inlined_schema(&self) -> bool {
+        true
+    }
+
     fn get_endpoint(&self) -> String {
         //The URL requires GOOGLE_REGION and GOOGLE_PROJECT_ID env variables defined to work.
         //If not set GOOGLE_REGION will default to 'us-central1' but GOOGLE_PROJECT_ID needs to be defined.
@@ -67,11 +149,11 @@ impl LLMModel for GoogleModels {
             GoogleModels::GeminiPro
             | GoogleModels::Gemini1_5Pro
             | GoogleModels::Gemini1_5Flash
-            | GoogleModels::Gemini1_0Pro => GOOGLE_GEMINI_API_URL.to_string(),
+            | GoogleModels::Gemini1_0Pro => self.get_model_url(&GOOGLE_GEMINI_API_URL),
             GoogleModels::GeminiProVertex
             | GoogleModels::Gemini1_5ProVertex
             | GoogleModels::Gemini1_5FlashVertex
-            | GoogleModels::Gemini1_0ProVertex => GOOGLE_VERTEX_API_URL.to_string(),
+            | GoogleModels::Gemini1_0ProVertex => self.get_model_url(&GOOGLE_VERTEX_API_URL),
         }
     }
 
@@ -83,37 +165,94 @@ impl LLMModel for GoogleModels {
         function_call: bool,
         _max_tokens: &usize,
         temperature: &f32,
+        system_prompt: Option<&str>,
+        base_instructions: Option<&str>,
     ) -> serde_json::Value {
-        //Prepare the 'messages' part of the body
+        //Base instructions and output schema are passed via the dedicated system instruction field if supported
         let base_instructions_json = json!({
-            "text": self.get_base_instructions(Some(function_call))
+            "text": base_instructions
+                .map(str::to_string)
+                .unwrap_or_else(|| self.get_base_instructions(Some(function_call)))
         });
 
         let schema_string = serde_json::to_string(json_schema).unwrap_or_default();
         let output_instructions_json =
             json!({ "text": format!("'Output Json schema': {schema_string}") });
 
+        let mut system_parts = vec![base_instructions_json, output_instructions_json];
+        if let Some(system_prompt) = system_prompt {
+            system_parts.push(json!({ "text": system_prompt }));
+        }
+
         let user_instructions_json = json!({
             "text": instructions,
         });
 
+        //Models without system instruction support receive all instructions as user parts
+        let (system_instruction, user_parts) = match self.supports_system_instruction() {
+            true => (
+                Some(json!({ "parts": system_parts })),
+                vec![user_instructions_json],
+            ),
+            false => {
+                system_parts.push(user_instructions_json);
+                (None, system_parts)
+            }
+        };
+
         let contents = json!({
             "role": "user",
-            "parts": vec![
-                base_instructions_json,
-                output_instructions_json,
-                user_instructions_json,
-            ],
+            "parts": user_parts,
         });
 
-        let generation_config = json!({
+        let mut generation_config = json!({
             "temperature": temperature,
         });
 
-        json!({
+        //Gemini 1.5 models support native Json output constrained to a schema
+        //Schemas that can't be expressed in the Gemini subset rely on the prompt only
+        if self.supports_response_schema() {
+            generation_config["responseMimeType"] = json!("application/json");
+            if let Some(response_schema) = gemini_response_schema(json_schema) {
+                generation_config["responseSchema"] = response_schema;
+            }
+        }
+
+        let mut body = json!({
             "contents": contents,
             "generationConfig": generation_config,
-        })
+        });
+        if let Some(system_instruction) = system_instruction {
+            body["systemInstruction"] = system_instruction;
+        }
+        body
+    }
+    //Files uploaded via the Gemini Files API are referenced by their URI
+    //https://ai.google.dev/gemini-api/docs/document-processing
+    fn apply_files(&self, body: &mut Value, files: &[LLMFileRef]) -> Result<()> {
+        let file_parts = files
+            .iter()
+            .map(|file| {
+                let mime_type = file.mime_type.as_ref().ok_or_else(|| {
+                    anyhow!(
+                        "[allms][Google] MIME type is required to attach file {}.",
+                        file.id
+                    )
+                })?;
+                Ok(json!({
+                    "fileData": {
+                        "mimeType": mime_type,
+                        "fileUri": file.id,
+                    }
+                }))
+            })
+            .collect::<Result<Vec<Value>>>()?;
+
+        match body["contents"]["parts"].as_array_mut() {
+            Some(parts) => parts.extend(file_parts),
+            None => return Err(anyhow!("No user contents found in the request body.")),
+        }
+        Ok(())
     }
     /*
      * This function leverages Mistral API to perform any query as per the provided body.
@@ -124,6 +263,8 @@ impl LLMModel for GoogleModels {
         &self,
         api_key: &str,
         body: &serde_json::Value,
+        extra_headers: &HeaderMap,
+        context: &RequestContext,
         debug: bool,
     ) -> Result<String> {
         //Get the API url
@@ -141,11 +282,19 @@ impl LLMModel for GoogleModels {
                 let response = client
                     .post(model_url)
                     .header(header::CONTENT_TYPE, "application/json")
-                    .bearer_auth(api_key)
+                    .headers(self.auth_headers(api_key)?)
+                    .headers(extra_headers.clone())
                     .json(&body)
                     .send()
                     .await?;
 
+                debug!(
+                    "[allms][{}] Google Vertex AI API response status: {} after {:?}",
+                    context.request_id,
+                    response.status(),
+                    context.elapsed()
+                );
+
                 //For Vertex we are streaming that data spo we need to deserialize each chunk separately
                 // Check if the API uses streaming
                 if response.status().is_success() {
@@ -175,7 +324,7 @@ impl LLMModel for GoogleModels {
                             .iter()
                             .filter(|candidate| candidate.content.role.as_deref() == Some("model"))
                             .flat_map(|candidate| &candidate.content.parts)
-                            .map(|part| &part.text)
+                            .filter_map(|part| part.text.as_deref())
                             .fold(String::new(), |mut acc, text| {
                                 acc.push_str(text);
                                 acc
@@ -195,11 +344,13 @@ impl LLMModel for GoogleModels {
                     Ok(sanitize_json_response(&streamed_response))
                 } else {
                     let response_status = response.status();
-                    let response_txt = response.text().await?;
-                    Err(anyhow!(
-                        "[allms][Google][{}] Response body: {:#?}",
+                    let response_headers = response.headers().clone();
+                    let response_text = response.text().await?;
+                    Err(get_api_error(
+                        "llm_models::google",
                         response_status,
-                        response_txt
+                        &response_headers,
+                        response_text,
                     ))
                 }
             }
@@ -211,11 +362,19 @@ impl LLMModel for GoogleModels {
                 let response = client
                     .post(url_with_key)
                     .header(header::CONTENT_TYPE, "application/json")
+                    .headers(extra_headers.clone())
                     .json(&body)
                     .send()
                     .await?;
 
                 let response_status = response.status();
+                debug!(
+                    "[allms][{}] Google AI Studio API response status: {} after {:?}",
+                    context.request_id,
+                    response_status,
+                    context.elapsed()
+                );
+                let response_headers = response.headers().clone();
                 let response_text = response.text().await?;
 
                 if debug {
@@ -225,6 +384,15 @@ impl LLMModel for GoogleModels {
                     );
                 }
 
+                if !response_status.is_success() {
+                    return Err(get_api_error(
+                        "llm_models::google",
+                        response_status,
+                        &response_headers,
+                        response_text,
+                    ));
+                }
+
                 Ok(response_text)
             }
         }
@@ -245,16 +413,298 @@ impl LLMModel for GoogleModels {
                 let gemini_response: GoogleGeminiProApiResp = serde_json::from_str(response_text)?;
 
                 //Extract the data part from the response
-                Ok(gemini_response
+                //Grounded answers are not constrained to Json so they may be wrapped in fences
+                let response_text = gemini_response
                     .candidates
                     .iter()
                     .filter(|candidate| candidate.content.role.as_deref() == Some("model"))
                     .flat_map(|candidate| &candidate.content.parts)
-                    .map(|part| &part.text)
+                    .filter_map(|part| part.text.as_deref())
                     .fold(String::new(), |mut acc, text| {
                         acc.push_str(text);
                         acc
-                    }))
+                    });
+                Ok(sanitize_json_response(&response_text))
+            }
+        }
+    }
+
+    //This method extracts the token usage from the API response
+    //For Vertex the response is streamed and only the text is passed forward so usage is not available
+    fn get_usage(&self, response_text: &str) -> Option<TokenUsage> {
+        match self {
+            GoogleModels::GeminiProVertex
+            | GoogleModels::Gemini1_5ProVertex
+            | GoogleModels::Gemini1_5FlashVertex
+            | GoogleModels::Gemini1_0ProVertex => None,
+            GoogleModels::GeminiPro
+            | GoogleModels::Gemini1_5Pro
+            | GoogleModels::Gemini1_5Flash
+            | GoogleModels::Gemini1_0Pro => {
+                serde_json::from_str::<GoogleGeminiProApiResp>(response_text)
+                    .ok()?
+                    .usage_metadata
+                    .map(|usage| TokenUsage {
+                        input_tokens: usage.prompt_token_count.max(0) as usize,
+                        output_tokens: usage.candidates_token_count.max(0) as usize,
+                        total_tokens: usage.total_token_count.max(0) as usize,
+                        cached_input_tokens: usage
+                            .cached_content_token_count
+                            .unwrap_or_default()
+                            .max(0) as usize,
+                        cache_creation_input_tokens: 0,
+                    })
+            }
+        }
+    }
+
+    //The body omits the limit so the model default is used. If the user provided one it is sent so that `with_auto_expand` retries can raise it
+    fn apply_max_output_tokens(&self, body: &mut Value, max_tokens: usize) {
+        body["generationConfig"]["maxOutputTokens"] = json!(max_tokens);
+    }
+
+    //Vertex AI responses are streamed in chunks so truncation is only detected for Gemini API models
+    fn is_truncated(&self, response_text: &str) -> bool {
+        match self {
+            GoogleModels::GeminiProVertex
+            | GoogleModels::Gemini1_5ProVertex
+            | GoogleModels::Gemini1_5FlashVertex
+            | GoogleModels::Gemini1_0ProVertex => false,
+            GoogleModels::GeminiPro
+            | GoogleModels::Gemini1_5Pro
+            | GoogleModels::Gemini1_5Flash
+            | GoogleModels::Gemini1_0Pro => {
+                serde_json::from_str::<GoogleGeminiProApiResp>(response_text)
+                    .map(|response| {
+                        response.candidates.iter().any(|candidate| {
+                            candidate.finish_reason.as_deref() == Some("MAX_TOKENS")
+                        })
+                    })
+                    .unwrap_or(false)
+            }
+        }
+    }
+
+    //The schema is only redundant if it's passed as `responseSchema`, i.e. it is expressible in the Gemini subset and not removed by tools
+    fn remove_inline_schema(&self, body: &mut Value, _instructions: &str) -> bool {
+        if body["generationConfig"].get("responseSchema").is_none() {
+            return false;
+        }
+        if let Some(system_parts) = body["systemInstruction"]["parts"].as_array_mut() {
+            system_parts.retain(|part| {
+                !part["text"]
+                    .as_str()
+                    .is_some_and(|text| text.starts_with("'Output Json schema':"))
+            });
+        }
+        true
+    }
+
+    //Gemini takes the conversation as a list of contents with `user` and `model` roles
+    //https://ai.google.dev/gemini-api/docs/text-generation#chat
+    fn apply_message_history(&self, body: &mut Value, history: &[LLMMessage]) -> bool {
+        if !body["contents"].is_object() {
+            return false;
+        }
+        let mut contents: Vec<Value> = history
+            .iter()
+            .map(|message| {
+                let role = match message.role {
+                    LLMMessageRole::User => "user",
+                    LLMMessageRole::Assistant => "model",
+                };
+                json!({
+                    "role": role,
+                    "parts": [{ "text": message.content }],
+                })
+            })
+            .collect();
+        contents.push(body["contents"].take());
+        body["contents"] = json!(contents);
+        true
+    }
+
+    //Gemini 1.5 models can ground answers with Google Search
+    //Search grounding can't be combined with Json mode so the output schema is only passed in the instructions
+    //https://ai.google.dev/gemini-api/docs/grounding
+    fn apply_web_search(&self, body: &mut Value) -> bool {
+        if !self.supports_response_schema() {
+            return false;
+        }
+        if let Some(generation_config) = body["generationConfig"].as_object_mut() {
+            generation_config.remove("responseMimeType");
+            generation_config.remove("responseSchema");
+        }
+        body["tools"] = json!([{ "google_search_retrieval": {} }]);
+        true
+    }
+
+    //Gemini 1.5 models can generate and run Python code. Executed code is returned in separate parts so it isn't included in the answer text
+    //https://ai.google.dev/gemini-api/docs/code-execution
+    fn apply_code_execution(&self, body: &mut Value) -> bool {
+        if !matches!(
+            self,
+            GoogleModels::Gemini1_5Pro | GoogleModels::Gemini1_5Flash
+        ) {
+            return false;
+        }
+        if let Some(generation_config) = body["generationConfig"].as_object_mut() {
+            generation_config.remove("responseMimeType");
+            generation_config.remove("responseSchema");
+        }
+        let tool = json!({ "code_execution": {} });
+        match body["tools"].as_array_mut() {
+            Some(tools) => tools.push(tool),
+            None => body["tools"] = json!([tool]),
+        }
+        true
+    }
+
+    //This method pairs each executed code part with the result part that follows it
+    fn get_code_execution(&self, response_text: &str) -> Option<Vec<CodeExecution>> {
+        match self {
+            GoogleModels::GeminiProVertex
+            | GoogleModels::Gemini1_5ProVertex
+            | GoogleModels::Gemini1_5FlashVertex
+            | GoogleModels::Gemini1_0ProVertex => None,
+            GoogleModels::GeminiPro
+            | GoogleModels::Gemini1_5Pro
+            | GoogleModels::Gemini1_5Flash
+            | GoogleModels::Gemini1_0Pro => {
+                let gemini_response: GoogleGeminiProApiResp =
+                    serde_json::from_str(response_text).ok()?;
+                let mut code_executions: Vec<CodeExecution> = Vec::new();
+                for part in gemini_response
+                    .candidates
+                    .into_iter()
+                    .flat_map(|candidate| candidate.content.parts)
+                {
+                    if let Some(executable_code) = part.executable_code {
+                        code_executions.push(CodeExecution {
+                            language: executable_code.language,
+                            code: executable_code.code,
+                            output: None,
+                        });
+                    }
+                    if let Some(result) = part.code_execution_result {
+                        if let Some(code_execution) = code_executions.last_mut() {
+                            code_execution.output = result.output;
+                        }
+                    }
+                }
+                Some(code_executions)
+            }
+        }
+    }
+
+    //Functions are passed as declarations of a single tool. Like search grounding they can't be combined with Json mode
+    //Vertex AI responses are streamed and only the text is passed forward so function calls are only supported for Gemini API models
+    //https://ai.google.dev/gemini-api/docs/function-calling
+    fn apply_functions(&self, body: &mut Value, functions: &[LLMFunction]) -> bool {
+        if !matches!(
+            self,
+            GoogleModels::Gemini1_5Pro | GoogleModels::Gemini1_5Flash
+        ) {
+            return false;
+        }
+        if let Some(generation_config) = body["generationConfig"].as_object_mut() {
+            generation_config.remove("responseMimeType");
+            generation_config.remove("responseSchema");
+        }
+        let function_declarations: Vec<Value> = functions
+            .iter()
+            .map(|function| {
+                json!({
+                    "name": function.name,
+                    "description": function.description,
+                    "parameters": function.parameters,
+                })
+            })
+            .collect();
+        let tool = json!({ "functionDeclarations": function_declarations });
+        match body["tools"].as_array_mut() {
+            Some(tools) => tools.push(tool),
+            None => body["tools"] = json!([tool]),
+        }
+        true
+    }
+
+    //This method extracts the function calls from the API response
+    fn get_tool_calls(&self, response_text: &str) -> Option<Vec<ToolCall>> {
+        match self {
+            GoogleModels::GeminiProVertex
+            | GoogleModels::Gemini1_5ProVertex
+            | GoogleModels::Gemini1_5FlashVertex
+            | GoogleModels::Gemini1_0ProVertex => None,
+            GoogleModels::GeminiPro
+            | GoogleModels::Gemini1_5Pro
+            | GoogleModels::Gemini1_5Flash
+            | GoogleModels::Gemini1_0Pro => {
+                let gemini_response: GoogleGeminiProApiResp =
+                    serde_json::from_str(response_text).ok()?;
+                let tool_calls = gemini_response
+                    .candidates
+                    .into_iter()
+                    .flat_map(|candidate| candidate.content.parts)
+                    .filter_map(|part| part.function_call)
+                    .map(|function_call| ToolCall {
+                        name: function_call.name,
+                        arguments: function_call.args,
+                    })
+                    .collect();
+                Some(tool_calls)
+            }
+        }
+    }
+
+    //Function calling mode only applies when the body declares functions
+    //https://ai.google.dev/gemini-api/docs/function-calling#function_calling_modes
+    fn apply_tool_choice(&self, body: &mut Value, tool_choice: &ToolChoice) -> bool {
+        let has_functions = body["tools"].as_array().is_some_and(|tools| {
+            tools
+                .iter()
+                .any(|tool| tool.get("functionDeclarations").is_some())
+        });
+        if !has_functions {
+            return false;
+        }
+        body["toolConfig"] = match tool_choice {
+            ToolChoice::Auto => json!({ "functionCallingConfig": { "mode": "AUTO" } }),
+            ToolChoice::Any => json!({ "functionCallingConfig": { "mode": "ANY" } }),
+            ToolChoice::None => json!({ "functionCallingConfig": { "mode": "NONE" } }),
+            ToolChoice::Tool(name) => json!({
+                "functionCallingConfig": { "mode": "ANY", "allowedFunctionNames": [name] }
+            }),
+        };
+        true
+    }
+
+    //This method extracts the grounding sources from the API response
+    //For Vertex the response is streamed and only the text is passed forward so sources are not available
+    fn get_web_sources(&self, response_text: &str) -> Option<Vec<WebSource>> {
+        match self {
+            GoogleModels::GeminiProVertex
+            | GoogleModels::Gemini1_5ProVertex
+            | GoogleModels::Gemini1_5FlashVertex
+            | GoogleModels::Gemini1_0ProVertex => None,
+            GoogleModels::GeminiPro
+            | GoogleModels::Gemini1_5Pro
+            | GoogleModels::Gemini1_5Flash
+            | GoogleModels::Gemini1_0Pro => {
+                let gemini_response: GoogleGeminiProApiResp =
+                    serde_json::from_str(response_text).ok()?;
+                let sources = gemini_response
+                    .candidates
+                    .into_iter()
+                    .filter_map(|candidate| candidate.grounding_metadata?.grounding_chunks)
+                    .flatten()
+                    .filter_map(|chunk| chunk.web)
+                    .map(|web| WebSource {
+                        uri: web.uri,
+                        title: web.title,
+                    })
+                    .collect();
+                Some(sources)
             }
         }
     }
@@ -267,4 +717,497 @@ impl LLMModel for GoogleModels {
             rpm: 60,
         }
     }
+
+    //Gemini 1.5 models accept temperatures up to 2.0, while Gemini 1.0 models are limited to 1.0
+    //https://ai.google.dev/gemini-api/docs/models/generative-models#model-parameters
+    fn temperature_range(&self) -> (f32, f32) {
+        match self {
+            GoogleModels::Gemini1_5Flash
+            | GoogleModels::Gemini1_5Pro
+            | GoogleModels::Gemini1_5FlashVertex
+            | GoogleModels::Gemini1_5ProVertex => (0.0, 2.0),
+            GoogleModels::GeminiPro
+            | GoogleModels::Gemini1_0Pro
+            | GoogleModels::GeminiProVertex
+            | GoogleModels::Gemini1_0ProVertex => (0.0, 1.0),
+        }
+    }
+}
+
+/// Source of the OAuth access tokens used to authenticate Vertex AI requests, e.g. `GoogleVertexAuth`.
+///
+/// It can be set with `Completions::with_google_vertex_auth` so that a token is requested for each call instead of passing a token that expires as the API key.
+#[async_trait(?Send)]
+pub trait GoogleTokenProvider {
+    ///Returns a valid access token
+    async fn token(&self) -> Result<String>;
+}
+
+/// Helper that mints and refreshes OAuth access tokens for Google Vertex AI using a GCP service account key.
+///
+/// Tokens are cached by the underlying authenticator and refreshed automatically once they expire,
+/// so a single instance can be kept for the lifetime of a long-running service.
+#[derive(Clone)]
+pub struct GoogleVertexAuth {
+    authenticator: DefaultAuthenticator,
+}
+
+impl GoogleVertexAuth {
+    ///
+    /// Creates the authenticator from a service account key. Accepts either the path to the key file or the JSON content of the key.
+    ///
+    pub async fn from_service_account(path_or_json: &str) -> Result<Self> {
+        let service_account_key = if path_or_json.trim_start().starts_with('{') {
+            parse_service_account_key(path_or_json)?
+        } else {
+            read_service_account_key(path_or_json).await?
+        };
+
+        let authenticator = ServiceAccountAuthenticator::builder(service_account_key)
+            .build()
+            .await?;
+
+        Ok(GoogleVertexAuth { authenticator })
+    }
+
+    ///
+    /// Returns a valid access token. Pass the authenticator to `Completions::with_google_vertex_auth` to refresh the token on each call
+    ///
+    pub async fn token(&self) -> Result<String> {
+        let access_token = self
+            .authenticator
+            .token(&[GOOGLE_CLOUD_PLATFORM_SCOPE])
+            .await?;
+
+        access_token
+            .token()
+            .map(|token| token.to_string())
+            .ok_or_else(|| anyhow!("[allms][Google Vertex AI] No access token returned."))
+    }
+}
+
+#[async_trait(?Send)]
+impl GoogleTokenProvider for GoogleVertexAuth {
+    async fn token(&self) -> Result<String> {
+        GoogleVertexAuth::token(self).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::llm_models::{GoogleModels, LLMFileRef, LLMFunction, LLMModel, ToolChoice};
+
+    #[test]
+    fn test_web_search_grounding() {
+        let model = GoogleModels::Gemini1_5Flash;
+        let mut body = model.get_body(
+            "Who won?",
+            &json!({"type": "object"}),
+            false,
+            &100,
+            &0.0,
+            None,
+            None,
+        );
+        assert!(model.apply_web_search(&mut body));
+        assert_eq!(body["tools"], json!([{ "google_search_retrieval": {} }]));
+        assert!(body["generationConfig"].get("responseMimeType").is_none());
+        //Grounding is sent to the selected model on the v1beta Gemini API
+        assert_eq!(
+            model.get_model_url("https://generativelanguage.googleapis.com"),
+            "https://generativelanguage.googleapis.com/v1beta/models/gemini-1.5-flash:generateContent"
+        );
+
+        let mut gemini1_0_body = GoogleModels::Gemini1_0Pro.get_body(
+            "Who won?",
+            &json!({}),
+            false,
+            &100,
+            &0.0,
+            None,
+            None,
+        );
+        assert!(!GoogleModels::Gemini1_0Pro.apply_web_search(&mut gemini1_0_body));
+
+        let response = r#"{
+            "candidates": [{
+                "content": {"parts": [{"text": "```json\n{\"winner\": \"Spain\"}\n```"}], "role": "model"},
+                "finishReason": "STOP",
+                "groundingMetadata": {
+                    "searchEntryPoint": {"renderedContent": "<style></style>"},
+                    "groundingChunks": [
+                        {"web": {"uri": "https://vertexaisearch.cloud.google.com/grounding-api-redirect/1", "title": "uefa.com"}},
+                        {"web": {"uri": "https://vertexaisearch.cloud.google.com/grounding-api-redirect/2", "title": "bbc.com"}}
+                    ],
+                    "groundingSupports": [],
+                    "webSearchQueries": ["euro 2024 winner"]
+                }
+            }]
+        }"#;
+        assert_eq!(
+            model.get_data(response, false).unwrap(),
+            r#"{"winner": "Spain"}"#
+        );
+        let sources = model.get_web_sources(response).unwrap();
+        assert_eq!(sources.len(), 2);
+        assert_eq!(sources[1].title, Some("bbc.com".to_string()));
+
+        //Responses without grounding return no sources
+        let ungrounded_response =
+            r#"{"candidates": [{"content": {"parts": [{"text": "{}"}], "role": "model"}}]}"#;
+        assert!(model
+            .get_web_sources(ungrounded_response)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_function_calling() {
+        let model = GoogleModels::Gemini1_5Pro;
+        let mut body = model.get_body(
+            "What is the weather in Paris?",
+            &json!({"type": "object"}),
+            false,
+            &100,
+            &0.0,
+            None,
+            None,
+        );
+        let functions = vec![LLMFunction::new(
+            "get_weather",
+            "Get the current weather in a city",
+            json!({"type": "object", "properties": {"city": {"type": "string"}}}),
+        )];
+        assert!(model.apply_functions(&mut body, &functions));
+        assert_eq!(
+            body["tools"][0]["functionDeclarations"][0]["name"],
+            "get_weather"
+        );
+        assert!(body["generationConfig"].get("responseSchema").is_none());
+        assert!(model.apply_tool_choice(&mut body, &ToolChoice::Tool("get_weather".to_string())));
+        assert_eq!(
+            body["toolConfig"]["functionCallingConfig"]["allowedFunctionNames"],
+            json!(["get_weather"])
+        );
+        assert!(!GoogleModels::Gemini1_5ProVertex.apply_functions(&mut json!({}), &functions));
+        //Function declarations are sent to the selected model on the v1beta Gemini API
+        assert_eq!(
+            model.get_model_url("https://generativelanguage.googleapis.com"),
+            "https://generativelanguage.googleapis.com/v1beta/models/gemini-1.5-pro:generateContent"
+        );
+
+        let response = r#"{
+            "candidates": [{
+                "content": {
+                    "parts": [{"functionCall": {"name": "get_weather", "args": {"city": "Paris"}}}],
+                    "role": "model"
+                },
+                "finishReason": "STOP"
+            }]
+        }"#;
+        let tool_calls = model.get_tool_calls(response).unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].name, "get_weather");
+        assert_eq!(tool_calls[0].arguments, json!({"city": "Paris"}));
+        assert_eq!(model.get_data(response, false).unwrap(), "");
+    }
+
+    #[test]
+    fn test_code_execution() {
+        let model = GoogleModels::Gemini1_5Flash;
+        let mut body = model.get_body(
+            "What is the sum of the first 50 prime numbers?",
+            &json!({"type": "object"}),
+            false,
+            &100,
+            &0.0,
+            None,
+            None,
+        );
+        assert!(model.apply_code_execution(&mut body));
+        assert_eq!(body["tools"], json!([{ "code_execution": {} }]));
+        assert!(!GoogleModels::Gemini1_0Pro.apply_code_execution(&mut json!({})));
+        //Code execution is sent to the selected model on the v1beta Gemini API
+        assert_eq!(
+            model.get_model_url("https://generativelanguage.googleapis.com"),
+            "https://generativelanguage.googleapis.com/v1beta/models/gemini-1.5-flash:generateContent"
+        );
+
+        let response = r#"{
+            "candidates": [{
+                "content": {
+                    "parts": [
+                        {"executableCode": {"language": "PYTHON", "code": "print(sum(primes[:50]))"}},
+                        {"codeExecutionResult": {"outcome": "OUTCOME_OK", "output": "5117\n"}},
+                        {"text": "{\"sum\": 5117}"}
+                    ],
+                    "role": "model"
+                },
+                "finishReason": "STOP"
+            }]
+        }"#;
+        let code_executions = model.get_code_execution(response).unwrap();
+        assert_eq!(code_executions.len(), 1);
+        assert_eq!(code_executions[0].language, "PYTHON");
+        assert_eq!(code_executions[0].output, Some("5117\n".to_string()));
+        //Executed code is not included in the answer text
+        assert_eq!(model.get_data(response, false).unwrap(), r#"{"sum": 5117}"#);
+    }
+
+    #[test]
+    fn test_try_from_str_round_trip() {
+        let models = vec![
+            GoogleModels::GeminiPro,
+            GoogleModels::GeminiProVertex,
+            GoogleModels::Gemini1_5Pro,
+            GoogleModels::Gemini1_5ProVertex,
+            GoogleModels::Gemini1_5Flash,
+            GoogleModels::Gemini1_5FlashVertex,
+            GoogleModels::Gemini1_0Pro,
+            GoogleModels::Gemini1_0ProVertex,
+        ];
+        for model in models {
+            assert_eq!(
+                GoogleModels::try_from_str(model.canonical_name()),
+                Some(model.clone())
+            );
+        }
+        //Vertex AI variants call the same API model
+        assert_eq!(
+            GoogleModels::Gemini1_5FlashVertex.as_str(),
+            "gemini-1.5-flash"
+        );
+    }
+
+    #[test]
+    fn test_max_requests() {
+        let model = GoogleModels::Gemini1_0Pro;
+        let expected_max = std::cmp::min(60, 60 * 32_000 / ((32_000_f64 * 0.5).ceil() as usize));
+        assert_eq!(model.get_max_requests(), expected_max);
+
+        let model = GoogleModels::Gemini1_5ProVertex;
+        let expected_max = std::cmp::min(60, 60 * 32_000 / ((1_048_576_f64 * 0.5).ceil() as usize));
+        assert_eq!(model.get_max_requests(), expected_max);
+    }
+
+    #[test]
+    fn test_normalized_temperature_within_model_range() {
+        assert_eq!(
+            GoogleModels::Gemini1_5Pro.get_normalized_temperature(100),
+            2.0
+        );
+        assert_eq!(
+            GoogleModels::Gemini1_5Flash.get_normalized_temperature(50),
+            1.0
+        );
+        //Gemini 1.0 models reject temperatures above 1.0
+        assert_eq!(
+            GoogleModels::Gemini1_0Pro.get_normalized_temperature(100),
+            1.0
+        );
+        assert_eq!(
+            GoogleModels::GeminiProVertex.get_normalized_temperature(50),
+            0.5
+        );
+    }
+
+    #[test]
+    fn test_get_model_url() {
+        let gemini_api_url = "https://generativelanguage.googleapis.com";
+        let vertex_api_url = "https://us-central1-aiplatform.googleapis.com/v1/projects/project/locations/us-central1/publishers/google/models";
+        let expected_urls = [
+            (
+                GoogleModels::GeminiPro,
+                "https://generativelanguage.googleapis.com/v1/models/gemini-pro:generateContent",
+            ),
+            (
+                GoogleModels::Gemini1_0Pro,
+                "https://generativelanguage.googleapis.com/v1/models/gemini-1.0-pro:generateContent",
+            ),
+            (
+                GoogleModels::Gemini1_5Pro,
+                "https://generativelanguage.googleapis.com/v1beta/models/gemini-1.5-pro:generateContent",
+            ),
+            (
+                GoogleModels::Gemini1_5Flash,
+                "https://generativelanguage.googleapis.com/v1beta/models/gemini-1.5-flash:generateContent",
+            ),
+            (
+                GoogleModels::GeminiProVertex,
+                "https://us-central1-aiplatform.googleapis.com/v1/projects/project/locations/us-central1/publishers/google/models/gemini-pro:streamGenerateContent?alt=sse",
+            ),
+            (
+                GoogleModels::Gemini1_0ProVertex,
+                "https://us-central1-aiplatform.googleapis.com/v1/projects/project/locations/us-central1/publishers/google/models/gemini-1.0-pro:streamGenerateContent?alt=sse",
+            ),
+            (
+                GoogleModels::Gemini1_5ProVertex,
+                "https://us-central1-aiplatform.googleapis.com/v1/projects/project/locations/us-central1/publishers/google/models/gemini-1.5-pro:streamGenerateContent?alt=sse",
+            ),
+            (
+                GoogleModels::Gemini1_5FlashVertex,
+                "https://us-central1-aiplatform.googleapis.com/v1/projects/project/locations/us-central1/publishers/google/models/gemini-1.5-flash:streamGenerateContent?alt=sse",
+            ),
+        ];
+        for (model, expected_url) in expected_urls {
+            let api_url = match model.canonical_name().ends_with("-vertex") {
+                true => vertex_api_url,
+                false => gemini_api_url,
+            };
+            assert_eq!(model.get_model_url(api_url), expected_url);
+        }
+    }
+
+    #[test]
+    fn test_get_body_system_instruction() {
+        let model = GoogleModels::Gemini1_5Flash;
+        let schema = json!({ "type": "object" });
+        let body = model.get_body("Instructions", &schema, false, &1000, &0.0, None, None);
+
+        let system_parts = body["systemInstruction"]["parts"].as_array().unwrap();
+        assert_eq!(system_parts.len(), 2);
+        assert_eq!(
+            system_parts[0]["text"].as_str().unwrap(),
+            model.get_base_instructions(Some(false))
+        );
+        assert!(system_parts[1]["text"]
+            .as_str()
+            .unwrap()
+            .contains("Output Json schema"));
+
+        let user_parts = body["contents"]["parts"].as_array().unwrap();
+        assert_eq!(user_parts.len(), 1);
+        assert_eq!(user_parts[0]["text"], "Instructions");
+    }
+
+    #[test]
+    fn test_get_body_system_instruction_with_system_prompt() {
+        let model = GoogleModels::Gemini1_5Pro;
+        let body = model.get_body(
+            "Instructions",
+            &json!({}),
+            false,
+            &1000,
+            &0.0,
+            Some("You are a translator."),
+            None,
+        );
+
+        let system_parts = body["systemInstruction"]["parts"].as_array().unwrap();
+        assert_eq!(system_parts.len(), 3);
+        assert_eq!(system_parts[2]["text"], "You are a translator.");
+        assert_eq!(body["contents"]["parts"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_apply_max_output_tokens() {
+        let model = GoogleModels::Gemini1_5Flash;
+        let mut body = model.get_body("Say hi", &json!({}), false, &100, &0.2, None, None);
+        assert!(body["generationConfig"].get("maxOutputTokens").is_none());
+
+        model.apply_max_output_tokens(&mut body, 2048);
+        assert_eq!(body["generationConfig"]["maxOutputTokens"], 2048);
+        assert_eq!(body["generationConfig"]["temperature"], json!(0.2_f32));
+    }
+
+    #[test]
+    fn test_get_body_without_system_instruction() {
+        for model in [GoogleModels::Gemini1_0Pro, GoogleModels::GeminiProVertex] {
+            let body = model.get_body(
+                "Instructions",
+                &json!({ "type": "object" }),
+                false,
+                &1000,
+                &0.0,
+                Some("You are a translator."),
+                None,
+            );
+
+            assert!(body.get("systemInstruction").is_none());
+            let user_parts = body["contents"]["parts"].as_array().unwrap();
+            assert_eq!(user_parts.len(), 4);
+            assert_eq!(
+                user_parts[0]["text"].as_str().unwrap(),
+                model.get_base_instructions(Some(false))
+            );
+            assert!(user_parts[1]["text"]
+                .as_str()
+                .unwrap()
+                .contains("Output Json schema"));
+            assert_eq!(user_parts[2]["text"], "You are a translator.");
+            assert_eq!(user_parts[3]["text"], "Instructions");
+        }
+    }
+
+    #[test]
+    fn test_get_body_response_schema() {
+        let schema = json!({
+            "type": "object",
+            "properties": {"spanish": {"type": "string"}},
+            "required": ["spanish"],
+            "additionalProperties": false,
+        });
+
+        let body = GoogleModels::Gemini1_5Flash
+            .get_body("Say hi", &schema, false, &1000, &0.0, None, None);
+        assert_eq!(
+            body["generationConfig"]["responseMimeType"],
+            "application/json"
+        );
+        assert_eq!(
+            body["generationConfig"]["responseSchema"],
+            json!({
+                "type": "object",
+                "properties": {"spanish": {"type": "string"}},
+                "required": ["spanish"],
+            })
+        );
+
+        //Free-form objects can't be expressed in Gemini schema so only Json output is requested
+        let body = GoogleModels::Gemini1_5Pro.get_body(
+            "Say hi",
+            &json!({"type": "object"}),
+            false,
+            &1000,
+            &0.0,
+            None,
+            None,
+        );
+        assert_eq!(
+            body["generationConfig"]["responseMimeType"],
+            "application/json"
+        );
+        assert!(body["generationConfig"].get("responseSchema").is_none());
+
+        let body =
+            GoogleModels::Gemini1_0Pro.get_body("Say hi", &schema, false, &1000, &0.0, None, None);
+        assert!(body["generationConfig"].get("responseMimeType").is_none());
+    }
+
+    #[test]
+    fn test_apply_files() {
+        let model = GoogleModels::Gemini1_5Pro;
+        let mut body = model.get_body("Summarize", &json!({}), false, &1000, &0.0, None, None);
+        let file = LLMFileRef::new("https://generativelanguage.googleapis.com/v1beta/files/abc")
+            .mime_type("application/pdf");
+        model.apply_files(&mut body, &[file]).unwrap();
+
+        let user_parts = body["contents"]["parts"].as_array().unwrap();
+        assert_eq!(user_parts.len(), 2);
+        assert_eq!(
+            user_parts[1]["fileData"],
+            json!({
+                "mimeType": "application/pdf",
+                "fileUri": "https://generativelanguage.googleapis.com/v1beta/files/abc",
+            })
+        );
+
+        // MIME type is required by Gemini
+        let mut body = model.get_body("Summarize", &json!({}), false, &1000, &0.0, None, None);
+        assert!(model
+            .apply_files(&mut body, &[LLMFileRef::new("files/abc")])
+            .is_err());
+    }
 }
@@ -1,10 +1,20 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use log::{debug, info};
+use reqwest::{
+    header::{self, HeaderMap, HeaderValue},
+    Client,
+};
 use serde_json::Value;
+use std::collections::HashMap;
 
 use crate::constants::OPENAI_BASE_INSTRUCTIONS;
-use crate::domain::RateLimit;
-use crate::utils::map_to_range;
+use crate::domain::{
+    AudioInput, AudioOutput, CodeExecution, LLMFileRef, LLMFunction, LLMMessage, PromptCacheTtl,
+    RateLimit, RequestContext, ServiceTier, TokenLogprob, TokenPricing, TokenUsage, ToolCall,
+    ToolChoice, WebSource,
+};
+use crate::utils::{get_api_error, get_event_stream, map_to_range_f32};
 
 ///This trait defines functions that need to be implemented for an enum that represents an LLM Model from any of the API providers
 #[async_trait(?Send)]
@@ -15,6 +25,11 @@ pub trait LLMModel {
     fn try_from_str(name: &str) -> Option<Self>
     where
         Self: Sized;
+    ///Returns the name that `try_from_str` converts back to the same variant
+    ///It is the same as `as_str` unless several variants call the same model of the API, e.g. via different platforms
+    fn canonical_name(&self) -> &str {
+        self.as_str()
+    }
     ///Returns max supported number of tokens for each of the variants of the enum
     fn default_max_tokens(&self) -> usize;
     ///Returns the url of the endpoint that should be called for each variant of the LLM Model enum
@@ -28,6 +43,9 @@ pub trait LLMModel {
         false
     }
     ///Constructs the body that should be attached to the API call for each of the LLM Models
+    ///The optional `system_prompt` should be placed in the provider-specific system field, separately from the user instructions
+    ///The optional `base_instructions` replace the ones returned by `get_base_instructions`
+    #[allow(clippy::too_many_arguments)]
     fn get_body(
         &self,
         instructions: &str,
@@ -35,16 +53,252 @@ pub trait LLMModel {
         function_call: bool,
         max_tokens: &usize,
         temperature: &f32,
+        system_prompt: Option<&str>,
+        base_instructions: Option<&str>,
     ) -> serde_json::Value;
+    ///Returns the url the request body is posted to by the default `call_api`
+    fn request_url(&self) -> String {
+        self.get_endpoint()
+    }
+    ///Returns the authentication headers added to the request by the default `call_api`
+    ///Defaults to bearer authentication with the provided API key
+    fn auth_headers(&self, api_key: &str) -> Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {api_key}"))?,
+        );
+        Ok(headers)
+    }
     ///Makes the call to the correct API for the selected model
+    ///Any `extra_headers` are added to the request and take precedence over the defaults set by the model
+    ///The `context` carries the request id and start time used to correlate log lines of the call
+    ///The default implementation posts the body as Json to `request_url` with `auth_headers`
+    ///Models that need custom request handling should override it
     async fn call_api(
         &self,
         api_key: &str,
         body: &serde_json::Value,
+        extra_headers: &HeaderMap,
+        context: &RequestContext,
         debug: bool,
-    ) -> Result<String>;
+    ) -> Result<String> {
+        //Make the API call
+        let client = Client::new();
+
+        //Send request
+        let response = client
+            .post(self.request_url())
+            .header(header::CONTENT_TYPE, "application/json")
+            .headers(self.auth_headers(api_key)?)
+            .headers(extra_headers.clone())
+            .json(&body)
+            .send()
+            .await?;
+
+        let response_status = response.status();
+        debug!(
+            "[allms][{}] {} API response status: {} after {:?}",
+            context.request_id,
+            self.as_str(),
+            response_status,
+            context.elapsed()
+        );
+        let response_headers = response.headers().clone();
+        let response_text = response.text().await?;
+
+        if debug {
+            info!(
+                "[debug] {} API response: [{}] {:#?}",
+                self.as_str(),
+                &response_status,
+                &response_text
+            );
+        }
+
+        if !response_status.is_success() {
+            return Err(get_api_error(
+                "llm_models::llm_model",
+                response_status,
+                &response_headers,
+                response_text,
+            ));
+        }
+
+        Ok(response_text)
+    }
+    ///Requests the response to be streamed as server-sent events
+    ///Returns `false` if the model does not support streaming, in which case the body is left unchanged
+    fn apply_stream(&self, _body: &mut Value) -> bool {
+        false
+    }
+    ///Makes a streaming call to the API and invokes `on_event` with the data of each server-sent event as it arrives
+    ///The default implementation posts the body as Json to `request_url` with `auth_headers`
+    async fn call_api_stream(
+        &self,
+        api_key: &str,
+        body: &serde_json::Value,
+        extra_headers: &HeaderMap,
+        context: &RequestContext,
+        on_event: &mut dyn for<'a> FnMut(&'a str),
+    ) -> Result<()> {
+        let request = Client::new()
+            .post(self.request_url())
+            .header(header::CONTENT_TYPE, "application/json")
+            .headers(self.auth_headers(api_key)?)
+            .headers(extra_headers.clone())
+            .json(&body);
+
+        debug!(
+            "[allms][{}] Streaming {} API response",
+            context.request_id,
+            self.as_str()
+        );
+        get_event_stream("llm_models::llm_model", request, on_event).await
+    }
+    ///Extracts the chunk of the answer text from the data of a streamed event, if any
+    ///The chunks concatenated together match the data returned by `get_data` for a non-streamed response
+    fn get_stream_delta(&self, _event_data: &str, _function_call: bool) -> Option<String> {
+        None
+    }
+    ///Returns `true` if the stream event reports that the output stopped because it reached the output token limit
+    fn is_stream_truncated(&self, _event_data: &str) -> bool {
+        false
+    }
     ///Based on the model type extracts the data portion of the API response
     fn get_data(&self, response_text: &str, function_call: bool) -> Result<String>;
+    ///Extracts the token usage reported in the API response, if available
+    fn get_usage(&self, _response_text: &str) -> Option<TokenUsage> {
+        None
+    }
+    ///Returns the price per million tokens of the model used to estimate the cost of a request, if known
+    fn get_token_pricing(&self) -> Option<TokenPricing> {
+        None
+    }
+    ///Returns `true` if the API response reports that the output stopped because it reached the output token limit
+    fn is_truncated(&self, _response_text: &str) -> bool {
+        false
+    }
+    ///Adds the `seed` used for reproducible sampling to the body of the API call
+    ///Returns `false` if the model does not support setting a seed, in which case the body is left unchanged
+    fn apply_seed(&self, _body: &mut Value, _seed: u64) -> bool {
+        false
+    }
+    ///Attaches files uploaded via the provider's Files API to the user message of the body
+    ///Returns an error if the model does not support file inputs
+    fn apply_files(&self, _body: &mut Value, _files: &[LLMFileRef]) -> Result<()> {
+        Err(anyhow!("{} does not support file inputs.", self.as_str()))
+    }
+    ///Returns `true` if the provider does not resolve `$ref` pointers, in which case definitions are inlined into the output schema
+    fn requires_inlined_schema(&self) -> bool {
+        false
+    }
+    ///Removes the output schema from the prompt, leaving only the `instructions`, when the output is constrained to the schema natively
+    ///Returns `false` if the body does not use native structured output, in which case the body is left unchanged
+    fn remove_inline_schema(&self, _body: &mut Value, _instructions: &str) -> bool {
+        false
+    }
+    ///Attaches the audio clips to the user message of the body
+    ///Returns an error if the model does not support audio inputs
+    fn apply_audio(&self, _body: &mut Value, _audio: &[AudioInput]) -> Result<()> {
+        Err(anyhow!("{} does not support audio inputs.", self.as_str()))
+    }
+    ///Requests the answer to be spoken with the provided `voice` and audio `format`, in addition to text
+    ///Returns `false` if the model does not support audio outputs, in which case the body is left unchanged
+    fn apply_audio_output(&self, _body: &mut Value, _voice: &str, _format: &str) -> bool {
+        false
+    }
+    ///Extracts the spoken answer from the API response, if available
+    fn get_audio_output(&self, _response_text: &str) -> Option<AudioOutput> {
+        None
+    }
+    ///Marks the stable part of the prompt (system prompt, base instructions and attached documents) to be cached by the provider
+    ///Returns `false` if the model does not support prompt caching, in which case the body is left unchanged
+    fn apply_prompt_cache(&self, _body: &mut Value, _ttl: &PromptCacheTtl) -> bool {
+        false
+    }
+    ///Adds the previous turns of the conversation before the instructions of the body
+    ///Returns `false` if the model does not support multi-turn conversations, in which case the body is left unchanged
+    fn apply_message_history(&self, _body: &mut Value, _history: &[LLMMessage]) -> bool {
+        false
+    }
+    ///Extracts the `system_fingerprint` identifying the backend configuration that served the request, if available
+    fn get_system_fingerprint(&self, _response_text: &str) -> Option<String> {
+        None
+    }
+    ///Sets whether the provider stores the response, e.g. to use it for evals or distillation
+    ///Returns `false` if the model does not support it, in which case the body is left unchanged
+    fn apply_store(&self, _body: &mut Value, _store: bool) -> bool {
+        false
+    }
+    ///Adds the key-value pairs used to tag the request, e.g. to filter stored responses
+    ///Returns `false` if the model does not support it, in which case the body is left unchanged
+    fn apply_metadata(&self, _body: &mut Value, _metadata: &HashMap<String, String>) -> bool {
+        false
+    }
+    ///Sets the processing tier used to serve the request
+    ///Returns an error if the model does not support the requested tier
+    fn apply_service_tier(&self, _body: &mut Value, _service_tier: &ServiceTier) -> Result<()> {
+        Err(anyhow!("{} does not support service tiers.", self.as_str()))
+    }
+    ///Extracts the processing tier that actually served the request, if available
+    fn get_service_tier(&self, _response_text: &str) -> Option<String> {
+        None
+    }
+    ///Sets the user-provided limit of output tokens on models whose `get_body` omits it by default
+    ///Models that always include the limit in the body can keep the default no-op implementation
+    fn apply_max_output_tokens(&self, _body: &mut Value, _max_tokens: usize) {}
+    ///Requests the log probabilities of the output tokens to be returned with the response, including `top_logprobs` most likely alternatives per token
+    ///Returns `false` if the model does not support logprobs, in which case the body is left unchanged
+    fn apply_logprobs(&self, _body: &mut Value, _top_logprobs: u8) -> bool {
+        false
+    }
+    ///Extracts the log probabilities of the output tokens from the API response, if available
+    fn get_logprobs(&self, _response_text: &str) -> Option<Vec<TokenLogprob>> {
+        None
+    }
+    ///Enables the provider's built-in web search so the answer is grounded in search results
+    ///Returns `false` if the model does not support web search, in which case the body is left unchanged
+    fn apply_web_search(&self, _body: &mut Value) -> bool {
+        false
+    }
+    ///Extracts the web pages used to ground the answer from the API response, if available
+    fn get_web_sources(&self, _response_text: &str) -> Option<Vec<WebSource>> {
+        None
+    }
+    ///Enables the provider's built-in code execution so the model can generate and run code to answer
+    ///Returns `false` if the model does not support code execution, in which case the body is left unchanged
+    fn apply_code_execution(&self, _body: &mut Value) -> bool {
+        false
+    }
+    ///Extracts the code executed by the model and its output from the API response, if available
+    fn get_code_execution(&self, _response_text: &str) -> Option<Vec<CodeExecution>> {
+        None
+    }
+    ///Declares the functions the model can call instead of answering directly
+    ///Returns `false` if the model does not support function calling, in which case the body is left unchanged
+    fn apply_functions(&self, _body: &mut Value, _functions: &[LLMFunction]) -> bool {
+        false
+    }
+    ///Extracts the function calls requested by the model from the API response, if available
+    fn get_tool_calls(&self, _response_text: &str) -> Option<Vec<ToolCall>> {
+        None
+    }
+    ///Adds the `logit_bias` (token ID to bias) used to suppress or favor tokens to the body of the API call
+    ///Returns `false` if the model does not support logit bias, in which case the body is left unchanged
+    fn apply_logit_bias(&self, _body: &mut Value, _logit_bias: &HashMap<u32, i32>) -> bool {
+        false
+    }
+    ///Sets which tool the model should call
+    ///Returns `false` if the model or the request body does not use tools, in which case the body is left unchanged
+    fn apply_tool_choice(&self, _body: &mut Value, _tool_choice: &ToolChoice) -> bool {
+        false
+    }
+    ///Sets whether the model may return multiple tool calls in one response
+    ///Returns `false` if the model or the request body does not use tools, in which case the body is left unchanged
+    fn apply_parallel_tool_calls(&self, _body: &mut Value, _parallel_tool_calls: bool) -> bool {
+        false
+    }
     ///Returns the rate limit accepted by the API depending on the used model
     ///If not explicitly defined it will assume 1B tokens or 100k transactions a minute
     fn get_rate_limit(&self) -> RateLimit {
@@ -74,12 +328,18 @@ pub trait LLMModel {
     fn get_default_temperature(&self) -> f32 {
         0f32
     }
+    ///Returns the (min, max) range of temperature values accepted by the API for the model
+    fn temperature_range(&self) -> (f32, f32) {
+        (0.0, 1.0)
+    }
+    ///Returns `false` if the model ignores the temperature, in which case it is not sent with the request
+    fn supports_temperature(&self) -> bool {
+        true
+    }
     ///Returns the normalized temperature for the model
     //Input should be a 0-100 number representing the percentage of max temp for the model
     fn get_normalized_temperature(&self, relative_temp: u32) -> f32 {
-        //Assuming 0-1 range for most models. Different ranges require model-specific implementations.
-        let min = 0u32;
-        let max = 1u32;
-        map_to_range(min, max, relative_temp)
+        let (min, max) = self.temperature_range();
+        map_to_range_f32(min, max, relative_temp)
     }
 }
@@ -1,14 +1,16 @@
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use log::info;
-use reqwest::{header, Client};
+use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
-use crate::constants::MISTRAL_API_URL;
-use crate::domain::{MistralAPICompletionsResponse, RateLimit};
+use crate::constants::{MISTRAL_API_URL, MISTRAL_MODELS_API_URL};
+use crate::domain::{
+    LLMFunction, LLMMessage, MistralAPICompletionsResponse, RateLimit, TokenUsage, ToolCall,
+    ToolChoice,
+};
 use crate::llm_models::LLMModel;
-use crate::utils::sanitize_json_response;
+use crate::utils::{get_model_list, insert_message_history, sanitize_json_response};
 
 #[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
 //Mistral docs: https://docs.mistral.ai/platform/endpoints
@@ -24,6 +26,42 @@ pub enum MistralModels {
     MistralMedium,
 }
 
+impl MistralModels {
+    ///
+    /// Lists the IDs of models available for the provided API key, including fine-tuned models
+    /// The IDs can be used with `try_from_str`
+    ///
+    pub async fn list_available(api_key: &str) -> Result<Vec<String>> {
+        //Mistral documentation: https://docs.mistral.ai/api/#tag/models
+        let request = Client::new()
+            .get(&*MISTRAL_MODELS_API_URL)
+            .bearer_auth(api_key);
+        let models = get_model_list("llm_models::mistral", request).await?;
+
+        Ok(models.data.into_iter().map(|model| model.id).collect())
+    }
+
+    ///
+    /// Returns `true` if the model supports Json mode (`response_format: json_object`)
+    ///
+    pub fn supports_json_mode(&self) -> bool {
+        !matches!(
+            self,
+            MistralModels::MistralTiny | MistralModels::MistralMedium
+        )
+    }
+
+    ///
+    /// Returns `true` if the model supports structured outputs, i.e. constraining the response to the provided Json schema
+    ///
+    pub fn supports_structured_outputs(&self) -> bool {
+        matches!(
+            self,
+            MistralModels::MistralLarge | MistralModels::MistralNemo | MistralModels::MistralSmall
+        )
+    }
+}
+
 #[async_trait(?Send)]
 impl LLMModel for MistralModels {
     fn as_str(&self) -> &str {
@@ -81,12 +119,21 @@ impl LLMModel for MistralModels {
         function_call: bool,
         max_tokens: &usize,
         temperature: &f32,
+        system_prompt: Option<&str>,
+        base_instructions: Option<&str>,
     ) -> serde_json::Value {
         //Prepare the 'messages' part of the body
-        let base_instructions = self.get_base_instructions(Some(function_call));
+        let base_instructions = base_instructions
+            .map(str::to_string)
+            .unwrap_or_else(|| self.get_base_instructions(Some(function_call)));
+        //User-provided system prompt is appended to the base instructions
+        let system_instructions = match system_prompt {
+            Some(system_prompt) => format!("{base_instructions}\n\n{system_prompt}"),
+            None => base_instructions,
+        };
         let system_message = json!({
             "role": "system",
-            "content": base_instructions,
+            "content": system_instructions,
         });
         let schema_string = serde_json::to_string(json_schema).unwrap_or_default();
         let user_message = json!({
@@ -97,7 +144,7 @@ impl LLMModel for MistralModels {
                 {instructions}"
             ),
         });
-        json!({
+        let mut body = json!({
             "model": self.as_str(),
             "max_tokens": max_tokens,
             "temperature": temperature,
@@ -105,45 +152,25 @@ impl LLMModel for MistralModels {
                 system_message,
                 user_message,
             ],
-        })
-    }
-    /*
-     * This function leverages Mistral API to perform any query as per the provided body.
-     *
-     * It returns a String the Response object that needs to be parsed based on the self.model.
-     */
-    async fn call_api(
-        &self,
-        api_key: &str,
-        body: &serde_json::Value,
-        debug: bool,
-    ) -> Result<String> {
-        //Get the API url
-        let model_url = self.get_endpoint();
-
-        //Make the API call
-        let client = Client::new();
-
-        //Send request
-        let response = client
-            .post(model_url)
-            .header(header::CONTENT_TYPE, "application/json")
-            .bearer_auth(api_key)
-            .json(&body)
-            .send()
-            .await?;
-
-        let response_status = response.status();
-        let response_text = response.text().await?;
-
-        if debug {
-            info!(
-                "[debug] Mistral API response: [{}] {:#?}",
-                &response_status, &response_text
-            );
-        }
+        });
 
-        Ok(response_text)
+        //https://docs.mistral.ai/capabilities/structured-output/
+        //Models without native Json support rely on the prompt only
+        if self.supports_structured_outputs() {
+            body["response_format"] = json!({
+                "type": "json_schema",
+                "json_schema": {
+                    "name": "response",
+                    "schema": json_schema,
+                    "strict": true,
+                },
+            });
+        } else if self.supports_json_mode() {
+            body["response_format"] = json!({
+                "type": "json_object",
+            });
+        }
+        body
     }
 
     //This method attempts to convert the provided API response text into the expected struct and extracts the data from the response
@@ -167,6 +194,115 @@ impl LLMModel for MistralModels {
             .ok_or_else(|| anyhow!("Assistant role content not found"))
     }
 
+    //This method extracts the token usage from the API response
+    fn get_usage(&self, response_text: &str) -> Option<TokenUsage> {
+        serde_json::from_str::<MistralAPICompletionsResponse>(response_text)
+            .ok()?
+            .usage
+            .map(|usage| TokenUsage {
+                input_tokens: usage.prompt_tokens,
+                output_tokens: usage.completion_tokens,
+                total_tokens: usage.total_tokens,
+                cached_input_tokens: 0,
+                cache_creation_input_tokens: 0,
+            })
+    }
+
+    fn is_truncated(&self, response_text: &str) -> bool {
+        serde_json::from_str::<MistralAPICompletionsResponse>(response_text)
+            .map(|response| {
+                response
+                    .choices
+                    .iter()
+                    .any(|choice| choice.finish_reason == "length")
+            })
+            .unwrap_or(false)
+    }
+
+    //Mistral API calls the seed parameter `random_seed`
+    fn apply_seed(&self, body: &mut Value, seed: u64) -> bool {
+        body["random_seed"] = json!(seed);
+        true
+    }
+
+    //Only strict Json schema output is constrained natively. Json mode still needs the schema in the prompt
+    fn remove_inline_schema(&self, body: &mut Value, instructions: &str) -> bool {
+        if body["response_format"]["type"] != "json_schema" {
+            return false;
+        }
+        body["messages"][1]["content"] = json!(instructions);
+        true
+    }
+
+    fn apply_message_history(&self, body: &mut Value, history: &[LLMMessage]) -> bool {
+        insert_message_history(body, history)
+    }
+
+    //Functions are declared as tools. The model can't call them if the answer is constrained to the output schema, so Json mode is turned off
+    //https://docs.mistral.ai/capabilities/function_calling/
+    fn apply_functions(&self, body: &mut Value, functions: &[LLMFunction]) -> bool {
+        let tools: Vec<Value> = functions
+            .iter()
+            .map(|function| {
+                json!({
+                    "type": "function",
+                    "function": {
+                        "name": function.name,
+                        "description": function.description,
+                        "parameters": function.parameters,
+                    },
+                })
+            })
+            .collect();
+        if let Some(body) = body.as_object_mut() {
+            body.remove("response_format");
+        }
+        body["tools"] = json!(tools);
+        true
+    }
+
+    //This method extracts the function calls from the API response
+    fn get_tool_calls(&self, response_text: &str) -> Option<Vec<ToolCall>> {
+        let completions_response: MistralAPICompletionsResponse =
+            serde_json::from_str(response_text).ok()?;
+        let tool_calls = completions_response
+            .choices
+            .into_iter()
+            .filter_map(|choice| choice.message?.tool_calls)
+            .flatten()
+            .map(|tool_call| ToolCall {
+                name: tool_call.function.name,
+                arguments: serde_json::from_str(&tool_call.function.arguments)
+                    .unwrap_or(Value::String(tool_call.function.arguments)),
+            })
+            .collect();
+        Some(tool_calls)
+    }
+
+    //Tool choice is only applicable when the body defines `tools`
+    //https://docs.mistral.ai/capabilities/function_calling/
+    fn apply_tool_choice(&self, body: &mut Value, tool_choice: &ToolChoice) -> bool {
+        if body.get("tools").is_none() {
+            return false;
+        }
+        body["tool_choice"] = match tool_choice {
+            ToolChoice::Auto => json!("auto"),
+            ToolChoice::Any => json!("any"),
+            ToolChoice::None => json!("none"),
+            ToolChoice::Tool(name) => json!({ "type": "function", "function": { "name": name } }),
+        };
+        true
+    }
+
+    //Parallel tool calls are only applicable when the body defines `tools`
+    fn apply_parallel_tool_calls(&self, body: &mut Value, parallel_tool_calls: bool) -> bool {
+        if body.get("tools").is_none() {
+            return false;
+        }
+        body["parallel_tool_calls"] = json!(parallel_tool_calls);
+        true
+    }
+
     //This function allows to check the rate limits for different models
     fn get_rate_limit(&self) -> RateLimit {
         //Mistral documentation: https://docs.mistral.ai/platform/pricing#rate-limits
@@ -176,3 +312,97 @@ impl LLMModel for MistralModels {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::llm_models::{LLMModel, MistralModels};
+
+    #[test]
+    fn test_get_tool_calls() {
+        let model = MistralModels::MistralLarge;
+        let response = r#"{
+            "id": "cmpl-1",
+            "object": "chat.completion",
+            "created": 1727776800,
+            "model": "mistral-large-latest",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": "",
+                    "tool_calls": [{"id": "call_1", "function": {"name": "get_weather", "arguments": "{\"city\": \"Paris\"}"}}]
+                },
+                "finish_reason": "tool_calls"
+            }]
+        }"#;
+        let tool_calls = model.get_tool_calls(response).unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0].name, "get_weather");
+        assert_eq!(tool_calls[0].arguments, json!({"city": "Paris"}));
+    }
+
+    #[test]
+    fn test_try_from_str_round_trip() {
+        let models = vec![
+            MistralModels::MistralLarge,
+            MistralModels::MistralNemo,
+            MistralModels::Mistral7B,
+            MistralModels::Mixtral8x7B,
+            MistralModels::Mixtral8x22B,
+            MistralModels::MistralTiny,
+            MistralModels::MistralSmall,
+            MistralModels::MistralMedium,
+        ];
+        for model in models {
+            assert_eq!(model.canonical_name(), model.as_str());
+            assert_eq!(
+                MistralModels::try_from_str(model.canonical_name()),
+                Some(model.clone())
+            );
+        }
+    }
+
+    #[test]
+    fn test_max_requests() {
+        let model = MistralModels::MistralLarge;
+        let expected_max = std::cmp::min(120, 2_000_000 / ((128_000_f64 * 0.5).ceil() as usize));
+        assert_eq!(model.get_max_requests(), expected_max);
+
+        let model = MistralModels::Mistral7B;
+        let expected_max = std::cmp::min(120, 2_000_000 / ((32_000_f64 * 0.5).ceil() as usize));
+        assert_eq!(model.get_max_requests(), expected_max);
+    }
+
+    #[test]
+    fn test_default_request() {
+        let model = MistralModels::MistralLarge;
+        assert_eq!(model.request_url(), model.get_endpoint());
+        assert_eq!(
+            model.auth_headers("key").unwrap()["authorization"],
+            "Bearer key"
+        );
+        assert!(model.auth_headers("invalid\nkey").is_err());
+    }
+
+    #[test]
+    fn test_get_body_response_format() {
+        let schema = json!({"type": "object", "properties": {"spanish": {"type": "string"}}});
+
+        let body =
+            MistralModels::MistralLarge.get_body("Say hi", &schema, false, &100, &0.5, None, None);
+        assert_eq!(body["response_format"]["type"], "json_schema");
+        assert_eq!(body["response_format"]["json_schema"]["schema"], schema);
+        assert_eq!(body["response_format"]["json_schema"]["strict"], true);
+
+        let body =
+            MistralModels::Mixtral8x7B.get_body("Say hi", &schema, false, &100, &0.5, None, None);
+        assert_eq!(body["response_format"], json!({"type": "json_object"}));
+
+        //Legacy models fall back to prompt-based Json
+        let body =
+            MistralModels::MistralTiny.get_body("Say hi", &schema, false, &100, &0.5, None, None);
+        assert!(body.get("response_format").is_none());
+    }
+}
@@ -2,11 +2,18 @@ pub mod anthropic;
 pub mod google;
 pub mod llm_model;
 pub mod mistral;
+pub mod ollama;
 pub mod openai;
 
+pub use crate::domain::{
+    AnswerMetadata, AudioInput, AudioOutput, CodeExecution, LLMFileRef, LLMFunction, LLMMessage,
+    LLMMessageRole, LiveRateLimit, PromptCacheTtl, RateLimit, RequestContext, ServiceTier,
+    TokenLogprob, TokenPricing, TokenUsage, ToolCall, ToolChoice, TopLogprob, WebSource,
+};
 pub use anthropic::AnthropicModels;
-pub use google::GoogleModels;
+pub use google::{GoogleModels, GoogleTokenProvider, GoogleVertexAuth};
 pub use llm_model::LLMModel;
 pub use llm_model::LLMModel as LLM;
 pub use mistral::MistralModels;
+pub use ollama::OllamaModels;
 pub use openai::OpenAIModels;
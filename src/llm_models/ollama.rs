@@ -0,0 +1,355 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::constants::OLLAMA_API_URL;
+use crate::domain::{LLMMessage, OllamaChatResponse, OpenAPIChatResponse, TokenUsage};
+use crate::llm_models::LLMModel;
+use crate::utils::{insert_message_history, sanitize_json_response};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+//Ollama docs: https://github.com/ollama/ollama/blob/main/docs/api.md
+//Any model pulled to the local Ollama instance can be used by providing its name, e.g. `llama3.1`
+pub enum OllamaModels {
+    //Native Ollama Chat API (/api/chat)
+    Native { name: String },
+    //OpenAI-compatible Chat Completions API (/v1/chat/completions)
+    OpenAICompatible { name: String },
+}
+
+#[async_trait(?Send)]
+impl LLMModel for OllamaModels {
+    fn as_str(&self) -> &str {
+        match self {
+            OllamaModels::Native { name } | OllamaModels::OpenAICompatible { name } => name,
+        }
+    }
+
+    //Ollama accepts any locally available model so the name is used as is with the native API
+    //`OpenAICompatible` models are therefore not recovered from their name and need to be constructed directly
+    fn try_from_str(name: &str) -> Option<Self> {
+        Some(OllamaModels::Native {
+            name: name.to_string(),
+        })
+    }
+
+    fn default_max_tokens(&self) -> usize {
+        //Context window depends on the model and the local configuration. 8k is supported by most recent models
+        8_192
+    }
+
+    //The native `format` schema is converted to a grammar that does not resolve `$ref` pointers
+    fn requires_inlined_schema(&self) -> bool {
+        matches!(self, OllamaModels::Native { .. })
+    }
+
+    fn get_endpoint(&self) -> String {
+        let trimmed_api_url = (*OLLAMA_API_URL).trim_end_matches('/');
+        match self {
+            OllamaModels::Native { .. } => format!("{trimmed_api_url}/api/chat"),
+            OllamaModels::OpenAICompatible { .. } => {
+                format!("{trimmed_api_url}/v1/chat/completions")
+            }
+        }
+    }
+
+    //Ollama does not require an API key but one can be used if the instance is behind an authenticating proxy
+    fn auth_headers(&self, api_key: &str) -> Result<HeaderMap> {
+        if api_key.is_empty() {
+            return Ok(HeaderMap::new());
+        }
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {api_key}"))?,
+        );
+        Ok(headers)
+    }
+
+    //This method prepares the body of the API call for different models
+    fn get_body(
+        &self,
+        instructions: &str,
+        json_schema: &Value,
+        function_call: bool,
+        max_tokens: &usize,
+        temperature: &f32,
+        system_prompt: Option<&str>,
+        base_instructions: Option<&str>,
+    ) -> serde_json::Value {
+        //Prepare the 'messages' part of the body
+        let base_instructions = base_instructions
+            .map(str::to_string)
+            .unwrap_or_else(|| self.get_base_instructions(Some(function_call)));
+        //User-provided system prompt is appended to the base instructions
+        let system_instructions = match system_prompt {
+            Some(system_prompt) => format!("{base_instructions}\n\n{system_prompt}"),
+            None => base_instructions,
+        };
+        let system_message = json!({
+            "role": "system",
+            "content": system_instructions,
+        });
+        let schema_string = serde_json::to_string(json_schema).unwrap_or_default();
+        let user_message = json!({
+            "role": "user",
+            "content": format!(
+                "Output Json schema:\n
+                {schema_string}\n\n
+                {instructions}"
+            ),
+        });
+
+        match self {
+            //https://github.com/ollama/ollama/blob/main/docs/api.md#generate-a-chat-completion
+            OllamaModels::Native { .. } => json!({
+                "model": self.as_str(),
+                "messages": vec![
+                    system_message,
+                    user_message,
+                ],
+                //Structured outputs: the response is constrained to the provided schema
+                "format": json_schema,
+                "stream": false,
+                "options": {
+                    "temperature": temperature,
+                    "num_predict": max_tokens,
+                },
+            }),
+            //https://github.com/ollama/ollama/blob/main/docs/openai.md
+            OllamaModels::OpenAICompatible { .. } => json!({
+                "model": self.as_str(),
+                "max_tokens": max_tokens,
+                "temperature": temperature,
+                "response_format": {
+                    "type": "json_object",
+                },
+                "messages": vec![
+                    system_message,
+                    user_message,
+                ],
+            }),
+        }
+    }
+
+    //This method attempts to convert the provided API response text into the expected struct and extracts the data from the response
+    fn get_data(&self, response_text: &str, _function_call: bool) -> Result<String> {
+        match self {
+            OllamaModels::Native { .. } => {
+                //Convert API response to struct representing expected response format
+                let chat_response: OllamaChatResponse = serde_json::from_str(response_text)?;
+
+                //Parse the response and return the assistant content
+                chat_response
+                    .message
+                    .filter(|message| message.role == "assistant")
+                    .map(|message| sanitize_json_response(&message.content))
+                    .ok_or_else(|| anyhow!("Assistant role content not found"))
+            }
+            OllamaModels::OpenAICompatible { .. } => {
+                //Convert API response to struct representing expected response format
+                let chat_response: OpenAPIChatResponse = serde_json::from_str(response_text)?;
+
+                //Parse the response and return the assistant content
+                chat_response
+                    .choices
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|choice| choice.message.role == "assistant")
+                    .find_map(|choice| choice.message.content)
+                    .map(|content| sanitize_json_response(&content))
+                    .ok_or_else(|| anyhow!("Assistant role content not found"))
+            }
+        }
+    }
+
+    //This method extracts the token usage from the API response
+    fn get_usage(&self, response_text: &str) -> Option<TokenUsage> {
+        match self {
+            OllamaModels::Native { .. } => {
+                let chat_response: OllamaChatResponse = serde_json::from_str(response_text).ok()?;
+                let input_tokens = chat_response.prompt_eval_count.unwrap_or_default() as usize;
+                let output_tokens = chat_response.eval_count.unwrap_or_default() as usize;
+                Some(TokenUsage {
+                    input_tokens,
+                    output_tokens,
+                    total_tokens: input_tokens + output_tokens,
+                    cached_input_tokens: 0,
+                    cache_creation_input_tokens: 0,
+                })
+            }
+            OllamaModels::OpenAICompatible { .. } => {
+                serde_json::from_str::<OpenAPIChatResponse>(response_text)
+                    .ok()?
+                    .usage
+                    .map(|usage| usage.into())
+            }
+        }
+    }
+
+    fn is_truncated(&self, response_text: &str) -> bool {
+        let finish_reason = match self {
+            OllamaModels::Native { .. } => {
+                serde_json::from_str::<OllamaChatResponse>(response_text)
+                    .ok()
+                    .and_then(|response| response.done_reason)
+            }
+            OllamaModels::OpenAICompatible { .. } => {
+                serde_json::from_str::<OpenAPIChatResponse>(response_text)
+                    .ok()
+                    .and_then(|response| response.choices)
+                    .and_then(|choices| choices.into_iter().next())
+                    .and_then(|choice| choice.finish_reason)
+            }
+        };
+        finish_reason.as_deref() == Some("length")
+    }
+
+    //The native API constrains the output with `format` while the OpenAI-compatible API only supports Json mode
+    fn remove_inline_schema(&self, body: &mut Value, instructions: &str) -> bool {
+        match self {
+            OllamaModels::Native { .. } => {
+                body["messages"][1]["content"] = json!(instructions);
+                true
+            }
+            OllamaModels::OpenAICompatible { .. } => false,
+        }
+    }
+
+    //Both native and OpenAI-compatible APIs take the conversation as a list of messages
+    fn apply_message_history(&self, body: &mut Value, history: &[LLMMessage]) -> bool {
+        insert_message_history(body, history)
+    }
+
+    fn apply_seed(&self, body: &mut Value, seed: u64) -> bool {
+        match self {
+            OllamaModels::Native { .. } => body["options"]["seed"] = json!(seed),
+            OllamaModels::OpenAICompatible { .. } => body["seed"] = json!(seed),
+        }
+        true
+    }
+
+    fn get_system_fingerprint(&self, response_text: &str) -> Option<String> {
+        match self {
+            OllamaModels::Native { .. } => None,
+            OllamaModels::OpenAICompatible { .. } => {
+                serde_json::from_str::<OpenAPIChatResponse>(response_text)
+                    .ok()?
+                    .system_fingerprint
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::llm_models::{LLMModel, OllamaModels};
+
+    #[test]
+    fn test_max_requests() {
+        //Local models are not rate limited by an API so the default rate limit applies
+        let model = OllamaModels::Native {
+            name: "llama3.1".to_string(),
+        };
+        let expected_max =
+            std::cmp::min(100_000, 100_000_000 / ((8_192_f64 * 0.5).ceil() as usize));
+        assert_eq!(model.get_max_requests(), expected_max);
+    }
+
+    #[test]
+    fn test_auth_headers() {
+        let model = OllamaModels::Native {
+            name: "llama3.1".to_string(),
+        };
+        assert!(model.auth_headers("").unwrap().is_empty());
+        assert_eq!(
+            model.auth_headers("proxy-key").unwrap()["authorization"],
+            "Bearer proxy-key"
+        );
+    }
+
+    #[test]
+    fn test_get_endpoint() {
+        let native = OllamaModels::Native {
+            name: "llama3.1".to_string(),
+        };
+        assert_eq!(native.get_endpoint(), "http://localhost:11434/api/chat");
+
+        let compatible = OllamaModels::OpenAICompatible {
+            name: "llama3.1".to_string(),
+        };
+        assert_eq!(
+            compatible.get_endpoint(),
+            "http://localhost:11434/v1/chat/completions"
+        );
+    }
+
+    #[test]
+    fn test_get_body_native() {
+        let model = OllamaModels::try_from_str("llama3.1").unwrap();
+        let schema = json!({"type": "object"});
+        let body = model.get_body("Say hi", &schema, false, &100, &0.5, None, None);
+
+        assert_eq!(body["model"], "llama3.1");
+        assert_eq!(body["stream"], false);
+        assert_eq!(body["format"], schema);
+        assert_eq!(body["options"]["num_predict"], 100);
+        assert_eq!(body["options"]["temperature"], 0.5);
+        assert_eq!(body["messages"][0]["role"], "system");
+        assert_eq!(body["messages"][1]["role"], "user");
+    }
+
+    #[test]
+    fn test_apply_seed() {
+        let model = OllamaModels::try_from_str("llama3.1").unwrap();
+        let mut body = model.get_body("Say hi", &json!({}), false, &100, &0.5, None, None);
+        assert!(model.apply_seed(&mut body, 42));
+        assert_eq!(body["options"]["seed"], 42);
+        assert_eq!(body["options"]["num_predict"], 100);
+    }
+
+    #[test]
+    fn test_get_data_native() {
+        let model = OllamaModels::try_from_str("llama3.1").unwrap();
+        let response = r#"{
+            "model": "llama3.1",
+            "created_at": "2024-10-01T10:00:00.000000Z",
+            "message": {"role": "assistant", "content": "{\"spanish\": \"hola\"}"},
+            "done": true,
+            "prompt_eval_count": 26,
+            "eval_count": 12
+        }"#;
+        assert_eq!(
+            model.get_data(response, false).unwrap(),
+            r#"{"spanish": "hola"}"#
+        );
+
+        let usage = model.get_usage(response).unwrap();
+        assert_eq!(usage.input_tokens, 26);
+        assert_eq!(usage.output_tokens, 12);
+        assert_eq!(usage.total_tokens, 38);
+    }
+
+    #[test]
+    fn test_get_data_openai_compatible() {
+        let model = OllamaModels::OpenAICompatible {
+            name: "llama3.1".to_string(),
+        };
+        let response = r#"{
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1727776800,
+            "model": "llama3.1",
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "{\"spanish\": \"hola\"}"}, "finish_reason": "stop"}],
+            "usage": {"prompt_tokens": 26, "completion_tokens": 12, "total_tokens": 38}
+        }"#;
+        assert_eq!(
+            model.get_data(response, false).unwrap(),
+            r#"{"spanish": "hola"}"#
+        );
+    }
+}
@@ -1,15 +1,29 @@
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use log::info;
-use reqwest::{header, Client};
+use log::{debug, error, info};
+use reqwest::{
+    header::{self, HeaderMap},
+    Client,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::collections::HashMap;
 
 use crate::{
-    constants::{OPENAI_API_URL, OPENAI_BASE_INSTRUCTIONS, OPENAI_FUNCTION_INSTRUCTIONS},
-    domain::{OpenAPIChatResponse, OpenAPICompletionsResponse, RateLimit},
+    constants::{
+        OPENAI_API_URL, OPENAI_BASE_INSTRUCTIONS, OPENAI_FUNCTION_INSTRUCTIONS,
+        OPENAI_OUTPUT_FUNCTION, OPENAI_RATE_LIMIT_HEADERS,
+    },
+    domain::{
+        AllmsRefusalError, AudioInput, AudioOutput, LLMFileRef, LLMFunction, LLMMessage,
+        OpenAPIChatFunctionCall, OpenAPIChatResponse, OpenAPICompletionsResponse, RateLimit,
+        RequestContext, ServiceTier, TokenLogprob, TokenPricing, TokenUsage, ToolCall, ToolChoice,
+    },
     llm_models::LLMModel,
-    utils::{map_to_range, sanitize_json_response},
+    utils::{
+        add_user_content_parts, get_api_error, get_live_rate_limit, get_model_list,
+        insert_message_history, sanitize_json_response,
+    },
 };
 
 #[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
@@ -30,6 +44,22 @@ pub enum OpenAIModels {
     Custom { name: String },
 }
 
+impl OpenAIModels {
+    ///
+    /// Lists the IDs of models available for the provided API key, including fine-tuned models
+    /// The IDs can be used with `try_from_str` or the `Custom` variant
+    ///
+    pub async fn list_available(api_key: &str) -> Result<Vec<String>> {
+        //OpenAI documentation: https://platform.openai.com/docs/api-reference/models/list
+        let models_url = format!("{}/v1/models", (*OPENAI_API_URL).trim_end_matches('/'));
+
+        let request = Client::new().get(models_url).bearer_auth(api_key);
+        let models = get_model_list("llm_models::openai", request).await?;
+
+        Ok(models.data.into_iter().map(|model| model.id).collect())
+    }
+}
+
 #[async_trait(?Send)]
 impl LLMModel for OpenAIModels {
     fn as_str(&self) -> &str {
@@ -131,22 +161,12 @@ impl LLMModel for OpenAIModels {
 
     fn function_call_default(&self) -> bool {
         //OpenAI documentation: https://platform.openai.com/docs/guides/gpt/function-calling
-        match self {
-            OpenAIModels::TextDavinci003
-            | OpenAIModels::Gpt3_5Turbo
-            | OpenAIModels::Gpt4_32k
-            | OpenAIModels::O1Preview
-            | OpenAIModels::O1Mini => false,
-            OpenAIModels::Gpt3_5Turbo0613
-            | OpenAIModels::Gpt3_5Turbo16k
-            | OpenAIModels::Gpt4
-            | OpenAIModels::Gpt4Turbo
-            | OpenAIModels::Gpt4TurboPreview
-            | OpenAIModels::Gpt4o
-            | OpenAIModels::Gpt4o20240806
-            | OpenAIModels::Gpt4oMini
-            | OpenAIModels::Custom { .. } => true,
-        }
+        //Reasoning models don't support the legacy `functions` parameter
+        !self.is_reasoning_model()
+            && !matches!(
+                self,
+                OpenAIModels::TextDavinci003 | OpenAIModels::Gpt3_5Turbo | OpenAIModels::Gpt4_32k
+            )
     }
 
     //This method prepares the body of the API call for different models
@@ -157,40 +177,69 @@ impl LLMModel for OpenAIModels {
         function_call: bool,
         max_tokens: &usize,
         temperature: &f32,
+        system_prompt: Option<&str>,
+        base_instructions: Option<&str>,
     ) -> serde_json::Value {
-        match self {
+        //User-provided system prompt is appended to the base instructions
+        let base_instructions = base_instructions
+            .map(str::to_string)
+            .unwrap_or_else(|| self.get_base_instructions(Some(function_call)));
+        let system_instructions = match system_prompt {
+            Some(system_prompt) => format!("{base_instructions}\n\n{system_prompt}"),
+            None => base_instructions,
+        };
+
+        let mut body = match self {
+            // Review https://platform.openai.com/docs/guides/reasoning for beta limitations:
+            // - Message types: user and assistant messages only, system messages are not supported.
+            // - Tools: tools, function calling, and response format parameters are not supported.
+            // - Other: temperature, top_p and n are fixed at 1, while presence_penalty and frequency_penalty are fixed at 0.
+            // - Assistants and Batch: these models are not supported in the Assistants API or Batch API.
+            _ if self.is_reasoning_model() => {
+                let system_message = json!({
+                    "role": "user",
+                    "content": system_instructions,
+                });
+
+                let schema_string = serde_json::to_string(json_schema).unwrap_or_default();
+
+                let user_message = json!({
+                    "role": "user",
+                    "content": format!(
+                        "Output Json schema:\n
+                        {schema_string}\n\n
+                        {instructions}"
+                    ),
+                });
+                json!({
+                    "model": self.as_str(),
+                    "messages": vec![
+                        system_message,
+                        user_message,
+                    ],
+                })
+            }
             //https://platform.openai.com/docs/api-reference/completions/create
             //For DaVinci model all text goes into the 'prompt' filed of the body
             OpenAIModels::TextDavinci003 => {
                 let schema_string = serde_json::to_string(json_schema).unwrap_or_default();
-                let base_instructions = self.get_base_instructions(Some(function_call));
                 json!({
                     "model": self.as_str(),
                     "max_tokens": max_tokens,
                     "temperature": temperature,
                     "prompt": format!(
-                        "{base_instructions}\n\n
+                        "{system_instructions}\n\n
                         Output Json schema:\n
                         {schema_string}\n\n
                         {instructions}",
                     ),
                 })
             }
-            OpenAIModels::Gpt3_5Turbo
-            | OpenAIModels::Gpt3_5Turbo0613
-            | OpenAIModels::Gpt3_5Turbo16k
-            | OpenAIModels::Gpt4
-            | OpenAIModels::Gpt4Turbo
-            | OpenAIModels::Gpt4TurboPreview
-            | OpenAIModels::Gpt4o
-            | OpenAIModels::Gpt4o20240806
-            | OpenAIModels::Gpt4oMini
-            | OpenAIModels::Gpt4_32k
-            | OpenAIModels::Custom { .. } => {
-                let base_instructions = self.get_base_instructions(Some(function_call));
+            //Chat Completions models, including `Custom` models that are not reasoning models
+            _ => {
                 let system_message = json!({
                     "role": "system",
-                    "content": base_instructions,
+                    "content": system_instructions,
                 });
 
                 match function_call {
@@ -203,13 +252,13 @@ impl LLMModel for OpenAIModels {
                         });
 
                         let function = json!({
-                            "name": "analyze_data",
+                            "name": OPENAI_OUTPUT_FUNCTION,
                             "description": "Use this function to compute the answer based on input data, instructions and your language model. Output should be a fully formed JSON object.",
                             "parameters": json_schema,
                         });
 
                         let function_call = json!({
-                            "name": "analyze_data"
+                            "name": OPENAI_OUTPUT_FUNCTION
                         });
 
                         //For ChatGPT we ignore max_tokens. It will default to 'inf'
@@ -251,37 +300,15 @@ impl LLMModel for OpenAIModels {
                     }
                 }
             }
-            // Review https://platform.openai.com/docs/guides/reasoning for beta limitations:
-            // - Message types: user and assistant messages only, system messages are not supported.
-            // - Tools: tools, function calling, and response format parameters are not supported.
-            // - Other: temperature, top_p and n are fixed at 1, while presence_penalty and frequency_penalty are fixed at 0.
-            // - Assistants and Batch: these models are not supported in the Assistants API or Batch API.
-            OpenAIModels::O1Preview | OpenAIModels::O1Mini => {
-                let base_instructions = self.get_base_instructions(Some(function_call));
-                let system_message = json!({
-                    "role": "user",
-                    "content": base_instructions,
-                });
-
-                let schema_string = serde_json::to_string(json_schema).unwrap_or_default();
+        };
 
-                let user_message = json!({
-                    "role": "user",
-                    "content": format!(
-                        "Output Json schema:\n
-                        {schema_string}\n\n
-                        {instructions}"
-                    ),
-                });
-                json!({
-                    "model": self.as_str(),
-                    "messages": vec![
-                        system_message,
-                        user_message,
-                    ],
-                })
+        //Reasoning models (e.g. `Custom` GPT-5 or o3) reject any temperature other than the default
+        if !self.supports_temperature() {
+            if let Some(body) = body.as_object_mut() {
+                body.remove("temperature");
             }
         }
+        body
     }
     /*
      * This function leverages OpenAI API to perform any query as per the provided body.
@@ -292,6 +319,8 @@ impl LLMModel for OpenAIModels {
         &self,
         api_key: &str,
         body: &serde_json::Value,
+        extra_headers: &HeaderMap,
+        context: &RequestContext,
         debug: bool,
     ) -> Result<String> {
         //Get the API url
@@ -305,11 +334,23 @@ impl LLMModel for OpenAIModels {
             .post(model_url)
             .header(header::CONTENT_TYPE, "application/json")
             .bearer_auth(api_key)
+            .headers(extra_headers.clone())
             .json(&body)
             .send()
             .await?;
 
         let response_status = response.status();
+        debug!(
+            "[allms][{}] OpenAI API response status: {} after {:?}",
+            context.request_id,
+            response_status,
+            context.elapsed()
+        );
+        let response_headers = response.headers().clone();
+        context.set_live_rate_limit(get_live_rate_limit(
+            &response_headers,
+            &OPENAI_RATE_LIMIT_HEADERS,
+        ));
         let response_text = response.text().await?;
 
         if debug {
@@ -319,9 +360,54 @@ impl LLMModel for OpenAIModels {
             );
         }
 
+        if !response_status.is_success() {
+            return Err(get_api_error(
+                "llm_models::openai",
+                response_status,
+                &response_headers,
+                response_text,
+            ));
+        }
+
         Ok(response_text)
     }
 
+    //Only the Chat Completions API is streamed
+    fn apply_stream(&self, body: &mut Value) -> bool {
+        match self {
+            OpenAIModels::TextDavinci003 => false,
+            _ => {
+                body["stream"] = json!(true);
+                true
+            }
+        }
+    }
+
+    //https://platform.openai.com/docs/api-reference/chat-streaming
+    fn get_stream_delta(&self, event_data: &str, function_call: bool) -> Option<String> {
+        let event: Value = serde_json::from_str(event_data).ok()?;
+        let delta = &event["choices"][0]["delta"];
+        //For function_call the response is in arguments, either of the legacy function call or of the tool call, and for regular call in content
+        let text = match function_call {
+            true => [
+                &delta["function_call"]["arguments"],
+                &delta["tool_calls"][0]["function"]["arguments"],
+                &delta["content"],
+            ]
+            .into_iter()
+            .find(|text| text.is_string())?,
+            false => &delta["content"],
+        };
+        text.as_str().map(|text| text.to_string())
+    }
+
+    //The finish reason is reported in the last chunk of the stream
+    fn is_stream_truncated(&self, event_data: &str) -> bool {
+        serde_json::from_str::<Value>(event_data)
+            .map(|event| event["choices"][0]["finish_reason"] == "length")
+            .unwrap_or(false)
+    }
+
     //This method attempts to convert the provided API response text into the expected struct and extracts the data from the response
     fn get_data(&self, response_text: &str, function_call: bool) -> Result<String> {
         match self {
@@ -356,19 +442,42 @@ impl LLMModel for OpenAIModels {
                 //Convert API response to struct representing expected response format
                 let chat_response: OpenAPIChatResponse = serde_json::from_str(response_text)?;
 
+                //If the model refused to answer we return the refusal instead of an empty response
+                if let Some(refusal) = get_chat_refusal(&chat_response) {
+                    let error = AllmsRefusalError {
+                        crate_name: "allms".to_string(),
+                        module: format!("llm_models::openai::{}", self.as_str()),
+                        message: refusal,
+                    };
+                    error!("{}", error);
+                    return Err(anyhow!(error));
+                }
+
                 //Extract data part
                 match chat_response.choices {
                     Some(choices) => Ok(choices
                         .into_iter()
                         .filter_map(|item| {
                             //For function_call the response is in arguments, and for regular call in content
+                            //The model may answer in content if the tool choice allows it not to call the output function
                             match function_call {
-                                true => item.message.function_call.map(|function_call| {
-                                    sanitize_json_response(&function_call.arguments)
-                                }),
+                                true => item
+                                    .message
+                                    .function_call
+                                    .or_else(|| {
+                                        item.message.tool_calls?.into_iter().find_map(|tool_call| {
+                                            (tool_call.function.name == OPENAI_OUTPUT_FUNCTION)
+                                                .then_some(tool_call.function)
+                                        })
+                                    })
+                                    .map(|function_call| function_call.arguments)
+                                    .or(item.message.content)
+                                    .map(|arguments| sanitize_json_response(&arguments)),
+                                //Spoken answers only return the text as the transcript of the audio
                                 false => item
                                     .message
                                     .content
+                                    .or_else(|| item.message.audio.map(|audio| audio.transcript))
                                     .map(|content| sanitize_json_response(&content)),
                             }
                         })
@@ -379,6 +488,342 @@ impl LLMModel for OpenAIModels {
         }
     }
 
+    //This method extracts the token usage from the API response
+    fn get_usage(&self, response_text: &str) -> Option<TokenUsage> {
+        let usage = match self {
+            OpenAIModels::TextDavinci003 => {
+                serde_json::from_str::<OpenAPICompletionsResponse>(response_text)
+                    .ok()?
+                    .usage
+            }
+            _ => {
+                serde_json::from_str::<OpenAPIChatResponse>(response_text)
+                    .ok()?
+                    .usage
+            }
+        };
+        usage.map(|usage| usage.into())
+    }
+
+    //Both Chat Completions and legacy Completions APIs report `length` as the finish reason of truncated outputs
+    fn is_truncated(&self, response_text: &str) -> bool {
+        let finish_reasons: Vec<Option<String>> = match self {
+            OpenAIModels::TextDavinci003 => {
+                serde_json::from_str::<OpenAPICompletionsResponse>(response_text)
+                    .ok()
+                    .and_then(|response| response.choices)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|choice| choice.finish_reason)
+                    .collect()
+            }
+            _ => serde_json::from_str::<OpenAPIChatResponse>(response_text)
+                .ok()
+                .and_then(|response| response.choices)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|choice| choice.finish_reason)
+                .collect(),
+        };
+        finish_reasons
+            .iter()
+            .any(|finish_reason| finish_reason.as_deref() == Some("length"))
+    }
+
+    //Both Chat Completions and legacy Completions APIs accept the `seed` parameter
+    fn apply_seed(&self, body: &mut Value, seed: u64) -> bool {
+        body["seed"] = json!(seed);
+        true
+    }
+
+    //Service tiers are only available in the Chat Completions API and Flex processing only for selected models
+    //https://platform.openai.com/docs/guides/flex-processing
+    fn apply_service_tier(&self, body: &mut Value, service_tier: &ServiceTier) -> Result<()> {
+        if let OpenAIModels::TextDavinci003 = self {
+            return Err(anyhow!("{} does not support service tiers.", self.as_str()));
+        }
+        if *service_tier == ServiceTier::Flex && !self.supports_flex_processing() {
+            return Err(anyhow!(
+                "{} does not support Flex processing.",
+                self.as_str()
+            ));
+        }
+        body["service_tier"] = json!(service_tier.as_str());
+        Ok(())
+    }
+
+    fn get_service_tier(&self, response_text: &str) -> Option<String> {
+        match self {
+            OpenAIModels::TextDavinci003 => None,
+            _ => {
+                serde_json::from_str::<OpenAPIChatResponse>(response_text)
+                    .ok()?
+                    .service_tier
+            }
+        }
+    }
+
+    //Logit bias is accepted by Chat and legacy Completions models but not by reasoning models
+    fn apply_logit_bias(&self, body: &mut Value, logit_bias: &HashMap<u32, i32>) -> bool {
+        if self.is_reasoning_model() {
+            return false;
+        }
+        body["logit_bias"] = json!(logit_bias);
+        true
+    }
+
+    //With function calling the schema is only sent as the function parameters so the prompt already holds only the instructions
+    fn remove_inline_schema(&self, body: &mut Value, _instructions: &str) -> bool {
+        body.get("functions").is_some() || get_tool_names(body).contains(&OPENAI_OUTPUT_FUNCTION)
+    }
+
+    //Previous turns are added to the Chat Completions messages. The legacy Completions API takes a single prompt
+    fn apply_message_history(&self, body: &mut Value, history: &[LLMMessage]) -> bool {
+        match self {
+            OpenAIModels::TextDavinci003 => false,
+            _ => insert_message_history(body, history),
+        }
+    }
+
+    //Functions are sent as `tools`, next to the output function if function calling is used
+    //The output function is no longer forced so that the model can call the provided functions
+    //https://platform.openai.com/docs/guides/function-calling
+    fn apply_functions(&self, body: &mut Value, functions: &[LLMFunction]) -> bool {
+        if !self.supports_tools() {
+            return false;
+        }
+        use_tools(body);
+        if let Some(body) = body.as_object_mut() {
+            body.remove("tool_choice");
+        }
+        let mut tools = body["tools"].as_array().cloned().unwrap_or_default();
+        tools.extend(functions.iter().map(|function| {
+            json!({
+                "type": "function",
+                "function": {
+                    "name": function.name,
+                    "description": function.description,
+                    "parameters": function.parameters,
+                },
+            })
+        }));
+        body["tools"] = json!(tools);
+        true
+    }
+
+    //This method extracts the calls of the provided functions from the API response. Calls of the output function are the answer and are not included
+    fn get_tool_calls(&self, response_text: &str) -> Option<Vec<ToolCall>> {
+        let chat_response: OpenAPIChatResponse = serde_json::from_str(response_text).ok()?;
+        let tool_calls = chat_response
+            .choices?
+            .into_iter()
+            .flat_map(|choice| {
+                let mut function_calls: Vec<OpenAPIChatFunctionCall> = choice
+                    .message
+                    .tool_calls
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|tool_call| tool_call.function)
+                    .collect();
+                function_calls.extend(choice.message.function_call);
+                function_calls
+            })
+            .filter(|function_call| function_call.name != OPENAI_OUTPUT_FUNCTION)
+            .map(|function_call| ToolCall {
+                name: function_call.name,
+                arguments: serde_json::from_str(&function_call.arguments)
+                    .unwrap_or(Value::String(function_call.arguments)),
+            })
+            .collect();
+        Some(tool_calls)
+    }
+
+    //Tool choice is only applicable when the body defines functions. Only functions defined in the body can be forced
+    //https://platform.openai.com/docs/api-reference/chat/create#chat-create-tool_choice
+    fn apply_tool_choice(&self, body: &mut Value, tool_choice: &ToolChoice) -> bool {
+        let tool_names = get_tool_names(body);
+        if !self.supports_tools() || tool_names.is_empty() {
+            return false;
+        }
+        if let ToolChoice::Tool(name) = tool_choice {
+            if !tool_names.contains(&name.as_str()) {
+                return false;
+            }
+        }
+        use_tools(body);
+        body["tool_choice"] = match tool_choice {
+            ToolChoice::Auto => json!("auto"),
+            ToolChoice::None => json!("none"),
+            ToolChoice::Any => json!("required"),
+            ToolChoice::Tool(name) => json!({ "type": "function", "function": { "name": name } }),
+        };
+        true
+    }
+
+    //Parallel tool calls are only accepted alongside `tools`, so legacy `functions` are converted first
+    //https://platform.openai.com/docs/guides/function-calling#parallel-function-calling
+    fn apply_parallel_tool_calls(&self, body: &mut Value, parallel_tool_calls: bool) -> bool {
+        if !self.supports_tools() || get_tool_names(body).is_empty() {
+            return false;
+        }
+        use_tools(body);
+        body["parallel_tool_calls"] = json!(parallel_tool_calls);
+        true
+    }
+
+    //Stored completions can be viewed in the dashboard and used for evals or distillation. Not available in the legacy Completions API
+    //https://platform.openai.com/docs/api-reference/chat/create#chat-create-store
+    fn apply_store(&self, body: &mut Value, store: bool) -> bool {
+        if let OpenAIModels::TextDavinci003 = self {
+            return false;
+        }
+        body["store"] = json!(store);
+        true
+    }
+
+    fn apply_metadata(&self, body: &mut Value, metadata: &HashMap<String, String>) -> bool {
+        if let OpenAIModels::TextDavinci003 = self {
+            return false;
+        }
+        body["metadata"] = json!(metadata);
+        true
+    }
+
+    //The chat body omits the limit so it defaults to 'inf'. If the user provided one it is sent in the field expected by the model family
+    //https://platform.openai.com/docs/guides/reasoning#controlling-costs
+    fn apply_max_output_tokens(&self, body: &mut Value, max_tokens: usize) {
+        match self {
+            //Legacy Completions body always includes `max_tokens`
+            OpenAIModels::TextDavinci003 => {}
+            _ if self.is_reasoning_model() => body["max_completion_tokens"] = json!(max_tokens),
+            _ => body["max_tokens"] = json!(max_tokens),
+        }
+    }
+
+    //Chat Completions API returns per-token logprobs when `logprobs` is set
+    //https://platform.openai.com/docs/api-reference/chat/create#chat-create-logprobs
+    fn apply_logprobs(&self, body: &mut Value, top_logprobs: u8) -> bool {
+        match self {
+            //Legacy Completions API expects the number of alternatives to return besides the sampled token (up to 5)
+            OpenAIModels::TextDavinci003 => {
+                body["logprobs"] = json!(top_logprobs.min(5));
+                true
+            }
+            //Reasoning models reject the logprobs parameter
+            _ if self.is_reasoning_model() => false,
+            //Chat Completions API returns up to 20 alternatives per token
+            _ => {
+                body["logprobs"] = json!(true);
+                if top_logprobs > 0 {
+                    body["top_logprobs"] = json!(top_logprobs.min(20));
+                }
+                true
+            }
+        }
+    }
+
+    //This method extracts the logprobs of the first choice from the API response
+    fn get_logprobs(&self, response_text: &str) -> Option<Vec<TokenLogprob>> {
+        match self {
+            OpenAIModels::TextDavinci003 => {
+                serde_json::from_str::<OpenAPICompletionsResponse>(response_text)
+                    .ok()?
+                    .choices?
+                    .into_iter()
+                    .next()?
+                    .logprobs
+                    .map(|logprobs| logprobs.into())
+            }
+            _ => {
+                serde_json::from_str::<OpenAPIChatResponse>(response_text)
+                    .ok()?
+                    .choices?
+                    .into_iter()
+                    .next()?
+                    .logprobs?
+                    .content
+            }
+        }
+    }
+
+    //Files are attached to the user message as file content parts
+    //https://platform.openai.com/docs/guides/pdf-files
+    fn apply_files(&self, body: &mut Value, files: &[LLMFileRef]) -> Result<()> {
+        match self {
+            OpenAIModels::Gpt4o
+            | OpenAIModels::Gpt4o20240806
+            | OpenAIModels::Gpt4oMini
+            | OpenAIModels::Custom { .. } => {
+                let file_parts = files
+                    .iter()
+                    .map(|file| {
+                        json!({
+                            "type": "file",
+                            "file": { "file_id": file.id },
+                        })
+                    })
+                    .collect();
+                add_user_content_parts(body, file_parts)
+            }
+            _ => Err(anyhow!("{} does not support file inputs.", self.as_str())),
+        }
+    }
+
+    //Audio clips are added as `input_audio` content parts
+    //https://platform.openai.com/docs/guides/audio
+    fn apply_audio(&self, body: &mut Value, audio: &[AudioInput]) -> Result<()> {
+        if !self.supports_audio() {
+            return Err(anyhow!("{} does not support audio inputs.", self.as_str()));
+        }
+        let audio_parts = audio
+            .iter()
+            .map(|audio| {
+                json!({
+                    "type": "input_audio",
+                    "input_audio": { "data": audio.data, "format": audio.format },
+                })
+            })
+            .collect();
+        add_user_content_parts(body, audio_parts)
+    }
+
+    fn apply_audio_output(&self, body: &mut Value, voice: &str, format: &str) -> bool {
+        if !self.supports_audio() {
+            return false;
+        }
+        body["modalities"] = json!(["text", "audio"]);
+        body["audio"] = json!({ "voice": voice, "format": format });
+        true
+    }
+
+    //This method extracts the spoken answer from the API response
+    fn get_audio_output(&self, response_text: &str) -> Option<AudioOutput> {
+        match self {
+            OpenAIModels::TextDavinci003 => None,
+            _ => serde_json::from_str::<OpenAPIChatResponse>(response_text)
+                .ok()?
+                .choices?
+                .into_iter()
+                .find_map(|choice| choice.message.audio),
+        }
+    }
+
+    //This method extracts the fingerprint of the backend configuration from the API response
+    fn get_system_fingerprint(&self, response_text: &str) -> Option<String> {
+        match self {
+            OpenAIModels::TextDavinci003 => {
+                serde_json::from_str::<OpenAPICompletionsResponse>(response_text)
+                    .ok()?
+                    .system_fingerprint
+            }
+            _ => {
+                serde_json::from_str::<OpenAPIChatResponse>(response_text)
+                    .ok()?
+                    .system_fingerprint
+            }
+        }
+    }
+
     /// This function allows to check the rate limits for different models
     /// Rate limit for `Custom` model is assumed based on `GPT-4o` limits
     fn get_rate_limit(&self) -> RateLimit {
@@ -441,16 +886,69 @@ impl LLMModel for OpenAIModels {
         }
     }
 
-    // Accepts a [0-100] percentage range and returns the target temperature based on model ranges
-    fn get_normalized_temperature(&self, relative_temp: u32) -> f32 {
-        // Temperature range documentation: https://platform.openai.com/docs/api-reference/chat/create
-        let min = 0u32;
-        let max = 2u32;
-        map_to_range(min, max, relative_temp)
+    //OpenAI pricing (USD per 1M tokens): https://openai.com/api/pricing/
+    //Cached input tokens are billed at a 50% discount on models that support prompt caching
+    fn get_token_pricing(&self) -> Option<TokenPricing> {
+        match self {
+            OpenAIModels::Gpt3_5Turbo => Some(TokenPricing::new(0.5, 0.5, 1.5)),
+            OpenAIModels::Gpt3_5Turbo0613 => Some(TokenPricing::new(1.5, 1.5, 2.0)),
+            OpenAIModels::Gpt3_5Turbo16k => Some(TokenPricing::new(3.0, 3.0, 4.0)),
+            OpenAIModels::Gpt4 => Some(TokenPricing::new(30.0, 30.0, 60.0)),
+            OpenAIModels::Gpt4Turbo | OpenAIModels::Gpt4TurboPreview => {
+                Some(TokenPricing::new(10.0, 10.0, 30.0))
+            }
+            OpenAIModels::Gpt4_32k => Some(TokenPricing::new(60.0, 60.0, 120.0)),
+            OpenAIModels::Gpt4o | OpenAIModels::Gpt4o20240806 => {
+                Some(TokenPricing::new(2.5, 1.25, 10.0))
+            }
+            OpenAIModels::Gpt4oMini => Some(TokenPricing::new(0.15, 0.075, 0.6)),
+            OpenAIModels::O1Preview => Some(TokenPricing::new(15.0, 7.5, 60.0)),
+            OpenAIModels::O1Mini => Some(TokenPricing::new(3.0, 1.5, 12.0)),
+            OpenAIModels::TextDavinci003 | OpenAIModels::Custom { .. } => None,
+        }
+    }
+
+    // Temperature range documentation: https://platform.openai.com/docs/api-reference/chat/create
+    fn temperature_range(&self) -> (f32, f32) {
+        (0.0, 2.0)
+    }
+
+    // Reasoning models have temperature fixed at 1
+    fn supports_temperature(&self) -> bool {
+        !self.is_reasoning_model()
     }
 }
 
 impl OpenAIModels {
+    // This function checks if a model is a reasoning model (o-series or GPT-5 family)
+    // Reasoning models only accept `max_completion_tokens` to limit the output. `Custom` models are checked by name
+    pub fn is_reasoning_model(&self) -> bool {
+        match self {
+            OpenAIModels::O1Preview | OpenAIModels::O1Mini => true,
+            OpenAIModels::Custom { name } => {
+                let name = name.to_lowercase();
+                ["o1", "o3", "o4", "gpt-5"]
+                    .iter()
+                    .any(|prefix| name == *prefix || name.starts_with(&format!("{prefix}-")))
+            }
+            _ => false,
+        }
+    }
+
+    // This function checks if a model can be served with Flex processing (o3, o4-mini and GPT-5 families)
+    // Only `Custom` models can currently refer to them so they are checked by name
+    fn supports_flex_processing(&self) -> bool {
+        match self {
+            OpenAIModels::Custom { name } => {
+                let name = name.to_lowercase();
+                ["o3", "o4-mini", "gpt-5"]
+                    .iter()
+                    .any(|prefix| name == *prefix || name.starts_with(&format!("{prefix}-")))
+            }
+            _ => false,
+        }
+    }
+
     // This function checks if a model supports tool use in Assistants API (e.g. file_search)
     pub fn tools_support(&self) -> bool {
         matches!(
@@ -465,8 +963,22 @@ impl OpenAIModels {
         )
     }
 
+    // This function checks if a model accepts and returns audio (e.g. `gpt-4o-audio-preview`)
+    // Only `Custom` models can currently refer to them so they are checked by name
+    fn supports_audio(&self) -> bool {
+        match self {
+            OpenAIModels::Custom { name } => name.to_lowercase().contains("audio"),
+            _ => false,
+        }
+    }
+
     // This function checks if a model supports Structured Outputs
     // https://openai.com/index/introducing-structured-outputs-in-the-api/
+    //Reasoning models and the legacy Completions API don't accept tools
+    fn supports_tools(&self) -> bool {
+        !self.is_reasoning_model() && !matches!(self, OpenAIModels::TextDavinci003)
+    }
+
     pub fn structured_output_support(&self) -> bool {
         matches!(
             self,
@@ -478,12 +990,467 @@ impl OpenAIModels {
     }
 }
 
+// Returns the names of the functions defined in the body, either as `tools` or as legacy `functions`
+fn get_tool_names(body: &Value) -> Vec<&str> {
+    let tools = body["tools"].as_array().into_iter().flatten();
+    let functions = body["functions"].as_array().into_iter().flatten();
+    tools
+        .map(|tool| &tool["function"])
+        .chain(functions)
+        .filter_map(|function| function["name"].as_str())
+        .collect()
+}
+
+// Converts legacy function calling (`functions` and `function_call`) into `tools` and `tool_choice`, which also accept provided functions and parallel calls
+fn use_tools(body: &mut Value) {
+    let Some(body) = body.as_object_mut() else {
+        return;
+    };
+    if let Some(Value::Array(functions)) = body.remove("functions") {
+        let tools: Vec<Value> = functions
+            .into_iter()
+            .map(|function| json!({ "type": "function", "function": function }))
+            .collect();
+        body.insert("tools".to_string(), json!(tools));
+    }
+    if let Some(function_call) = body.remove("function_call") {
+        let tool_choice = match function_call["name"].as_str() {
+            Some(name) => json!({ "type": "function", "function": { "name": name } }),
+            None => function_call,
+        };
+        body.insert("tool_choice".to_string(), tool_choice);
+    }
+}
+
+// Returns the refusal message if none of the choices contains an answer but at least one was refused
+fn get_chat_refusal(chat_response: &OpenAPIChatResponse) -> Option<String> {
+    let choices = chat_response.choices.as_ref()?;
+    let has_answer = choices.iter().any(|choice| {
+        choice.message.content.is_some()
+            || choice.message.function_call.is_some()
+            || choice.message.tool_calls.is_some()
+            || choice.message.audio.is_some()
+    });
+    if has_answer {
+        return None;
+    }
+    let refusals: Vec<String> = choices
+        .iter()
+        .filter_map(|choice| choice.message.refusal.clone())
+        .collect();
+    match refusals.is_empty() {
+        true => None,
+        false => Some(refusals.join("\n")),
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    use crate::domain::{AllmsRefusalError, OpenAPICompletionsResponse};
     use crate::llm_models::llm_model::LLMModel;
-    use crate::llm_models::OpenAIModels;
+    use crate::llm_models::{AudioInput, LLMFunction, OpenAIModels, ServiceTier, TokenUsage};
+
+    #[test]
+    fn test_audio_input_and_output() {
+        let model = OpenAIModels::Custom {
+            name: "gpt-4o-audio-preview".to_string(),
+        };
+        let mut body = model.get_body(
+            "Summarize the call",
+            &json!({}),
+            false,
+            &100,
+            &0.0,
+            None,
+            None,
+        );
+        model
+            .apply_audio(&mut body, &[AudioInput::from_bytes(b"RIFF", "wav")])
+            .unwrap();
+        assert!(model.apply_audio_output(&mut body, "alloy", "mp3"));
+        let content = body["messages"][1]["content"].as_array().unwrap();
+        assert_eq!(content[1]["type"], "input_audio");
+        assert_eq!(content[1]["input_audio"]["data"], "UklGRg==");
+        assert_eq!(body["modalities"], json!(["text", "audio"]));
+        assert_eq!(body["audio"]["voice"], "alloy");
+
+        //Models without audio support return an error for inputs and ignore outputs
+        let mut gpt4o_body =
+            OpenAIModels::Gpt4o.get_body("Say hi", &json!({}), false, &100, &0.0, None, None);
+        assert!(OpenAIModels::Gpt4o
+            .apply_audio(&mut gpt4o_body, &[AudioInput::new("UklGRg==", "wav")])
+            .is_err());
+        assert!(!OpenAIModels::Gpt4o.apply_audio_output(&mut gpt4o_body, "alloy", "mp3"));
+
+        //Spoken answers return the text as the transcript
+        let response = r#"{
+            "id": "chatcmpl-123",
+            "object": "chat.completion",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "refusal": null,
+                    "audio": {
+                        "id": "audio_123",
+                        "expires_at": 1729018505,
+                        "data": "aGVsbG8=",
+                        "transcript": "{\"summary\": \"Hello\"}"
+                    }
+                },
+                "finish_reason": "stop"
+            }]
+        }"#;
+        let audio = model.get_audio_output(response).unwrap();
+        assert_eq!(audio.bytes().unwrap(), b"hello".to_vec());
+        assert_eq!(
+            model.get_data(response, false).unwrap(),
+            r#"{"summary": "Hello"}"#
+        );
+    }
 
     // Tests for calculating max requests per model
+    #[test]
+    fn test_seed_and_system_fingerprint() {
+        let model = OpenAIModels::Gpt4o;
+        let mut body = model.get_body("Say hi", &json!({}), false, &100, &0.0, None, None);
+        assert!(model.apply_seed(&mut body, 42));
+        assert_eq!(body["seed"], 42);
+
+        let response = r#"{
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1727776800,
+            "model": "gpt-4o",
+            "system_fingerprint": "fp_3aa7262c27",
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "{}"}, "finish_reason": "stop"}]
+        }"#;
+        assert_eq!(
+            model.get_system_fingerprint(response),
+            Some("fp_3aa7262c27".to_string())
+        );
+    }
+
+    #[test]
+    fn test_logprobs() {
+        let model = OpenAIModels::Gpt4o;
+        let mut body = model.get_body("Say hi", &json!({}), false, &100, &0.0, None, None);
+        assert!(model.apply_logprobs(&mut body, 0));
+        assert_eq!(body["logprobs"], true);
+        assert!(body.get("top_logprobs").is_none());
+        assert!(model.apply_logprobs(&mut body, 3));
+        assert_eq!(body["top_logprobs"], 3);
+
+        //Reasoning models do not support logprobs
+        let mut o1_body =
+            OpenAIModels::O1Mini.get_body("Say hi", &json!({}), false, &100, &0.0, None, None);
+        assert!(!OpenAIModels::O1Mini.apply_logprobs(&mut o1_body, 0));
+        assert!(o1_body.get("logprobs").is_none());
+
+        let response = r#"{
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1727776800,
+            "model": "gpt-4o",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "{\"data\": \"Hi\"}"},
+                "logprobs": {
+                    "content": [
+                        {"token": "Hi", "logprob": -0.0012, "bytes": [72, 105], "top_logprobs": []},
+                        {"token": "!", "logprob": -0.31, "bytes": null, "top_logprobs": [{"token": ".", "logprob": -1.4, "bytes": [46]}]}
+                    ],
+                    "refusal": null
+                },
+                "finish_reason": "stop"
+            }]
+        }"#;
+        let logprobs = model.get_logprobs(response).unwrap();
+        assert_eq!(logprobs.len(), 2);
+        assert_eq!(logprobs[0].token, "Hi");
+        assert_eq!(logprobs[0].bytes, Some(vec![72, 105]));
+        assert_eq!(logprobs[1].top_logprobs[0].token, ".");
+        assert_eq!(logprobs[1].top_logprobs[0].logprob, -1.4);
+    }
+
+    #[test]
+    fn test_is_reasoning_model() {
+        let reasoning_models = vec![
+            OpenAIModels::O1Preview,
+            OpenAIModels::O1Mini,
+            OpenAIModels::Custom {
+                name: "o1".to_string(),
+            },
+            OpenAIModels::Custom {
+                name: "o3-mini".to_string(),
+            },
+            OpenAIModels::Custom {
+                name: "o4-mini".to_string(),
+            },
+            OpenAIModels::Custom {
+                name: "gpt-5-nano".to_string(),
+            },
+        ];
+        for model in reasoning_models {
+            assert!(model.is_reasoning_model(), "{}", model.as_str());
+            assert!(!model.function_call_default(), "{}", model.as_str());
+            let body = model.get_body("Say hi", &json!({}), false, &100, &0.0, None, None);
+            //System instructions are sent as a user message and temperature is omitted
+            assert_eq!(body["messages"][0]["role"], "user", "{}", model.as_str());
+            assert!(body.get("temperature").is_none(), "{}", model.as_str());
+        }
+
+        let chat_models = vec![
+            OpenAIModels::Gpt4o,
+            OpenAIModels::Gpt4oMini,
+            OpenAIModels::Custom {
+                name: "gpt-4.1".to_string(),
+            },
+        ];
+        for model in chat_models {
+            assert!(!model.is_reasoning_model(), "{}", model.as_str());
+            let body = model.get_body("Say hi", &json!({}), false, &100, &0.0, None, None);
+            assert_eq!(body["messages"][0]["role"], "system", "{}", model.as_str());
+        }
+    }
+
+    #[test]
+    fn test_max_output_tokens_field() {
+        let reasoning_models = vec![
+            OpenAIModels::O1Preview,
+            OpenAIModels::O1Mini,
+            OpenAIModels::Custom {
+                name: "o3-mini".to_string(),
+            },
+            OpenAIModels::Custom {
+                name: "gpt-5".to_string(),
+            },
+        ];
+        for model in reasoning_models {
+            let mut body = model.get_body("Say hi", &json!({}), false, &100, &0.0, None, None);
+            model.apply_max_output_tokens(&mut body, 500);
+            assert_eq!(body["max_completion_tokens"], 500, "{}", model.as_str());
+            assert!(body.get("max_tokens").is_none(), "{}", model.as_str());
+        }
+
+        let chat_models = vec![
+            OpenAIModels::Gpt4,
+            OpenAIModels::Gpt4o,
+            OpenAIModels::Custom {
+                name: "gpt-4.1".to_string(),
+            },
+        ];
+        for model in chat_models {
+            let mut body = model.get_body("Say hi", &json!({}), false, &100, &0.0, None, None);
+            model.apply_max_output_tokens(&mut body, 500);
+            assert_eq!(body["max_tokens"], 500, "{}", model.as_str());
+            assert!(
+                body.get("max_completion_tokens").is_none(),
+                "{}",
+                model.as_str()
+            );
+        }
+    }
+
+    #[test]
+    fn test_reasoning_models_drop_temperature() {
+        let gpt5 = OpenAIModels::Custom {
+            name: "gpt-5".to_string(),
+        };
+        assert!(!gpt5.supports_temperature());
+        let body = gpt5.get_body("Say hi", &json!({}), false, &100, &0.7, None, None);
+        assert!(body.get("temperature").is_none());
+
+        assert!(OpenAIModels::Gpt4o.supports_temperature());
+        let body =
+            OpenAIModels::Gpt4o.get_body("Say hi", &json!({}), false, &100, &0.7, None, None);
+        assert_eq!(body["temperature"], 0.7f32);
+    }
+
+    #[test]
+    fn test_service_tier() {
+        let flex_model = OpenAIModels::Custom {
+            name: "o4-mini-2025-04-16".to_string(),
+        };
+        let mut body = flex_model.get_body("Say hi", &json!({}), false, &100, &0.0, None, None);
+        assert!(flex_model
+            .apply_service_tier(&mut body, &ServiceTier::Flex)
+            .is_ok());
+        assert_eq!(body["service_tier"], "flex");
+
+        //Flex processing is rejected for models that do not support it, other tiers are accepted
+        let model = OpenAIModels::Gpt4o;
+        let mut body = model.get_body("Say hi", &json!({}), false, &100, &0.0, None, None);
+        assert!(model
+            .apply_service_tier(&mut body, &ServiceTier::Flex)
+            .is_err());
+        assert!(model
+            .apply_service_tier(&mut body, &ServiceTier::Priority)
+            .is_ok());
+        assert_eq!(body["service_tier"], "priority");
+
+        let response = r#"{"choices": [], "service_tier": "flex"}"#;
+        assert_eq!(
+            flex_model.get_service_tier(response),
+            Some("flex".to_string())
+        );
+    }
+
+    #[test]
+    fn test_logit_bias() {
+        let logit_bias = HashMap::from([(1734, -100)]);
+
+        let model = OpenAIModels::Gpt4o;
+        let mut body = model.get_body("Say hi", &json!({}), false, &100, &0.0, None, None);
+        assert!(model.apply_logit_bias(&mut body, &logit_bias));
+        assert_eq!(body["logit_bias"], json!({"1734": -100}));
+
+        let reasoning_model = OpenAIModels::O1Mini;
+        let mut body =
+            reasoning_model.get_body("Say hi", &json!({}), false, &100, &0.0, None, None);
+        assert!(!reasoning_model.apply_logit_bias(&mut body, &logit_bias));
+        assert!(body.get("logit_bias").is_none());
+    }
+
+    #[test]
+    fn test_parallel_tool_calls() {
+        //Legacy function calling is converted to tools which accept the parameter
+        let model = OpenAIModels::Gpt4o;
+        let mut body = model.get_body("Say hi", &json!({}), true, &100, &0.0, None, None);
+        assert!(model.apply_parallel_tool_calls(&mut body, false));
+        assert_eq!(body["parallel_tool_calls"], false);
+        assert_eq!(body["tools"][0]["type"], "function");
+        assert_eq!(body["tools"][0]["function"]["name"], "analyze_data");
+        assert!(body.get("functions").is_none());
+
+        let mut body = model.get_body("Say hi", &json!({}), false, &100, &0.0, None, None);
+        assert!(!model.apply_parallel_tool_calls(&mut body, false));
+        assert!(body.get("parallel_tool_calls").is_none());
+
+        let reasoning_model = OpenAIModels::O1Mini;
+        let mut body =
+            reasoning_model.get_body("Say hi", &json!({}), false, &100, &0.0, None, None);
+        let functions = vec![LLMFunction::new(
+            "get_weather",
+            "Get the weather",
+            json!({}),
+        )];
+        assert!(!reasoning_model.apply_functions(&mut body, &functions));
+        assert!(body.get("tools").is_none());
+    }
+
+    #[test]
+    fn test_tool_calls_response() {
+        let model = OpenAIModels::Gpt4o;
+        let response = r#"{
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1727776800,
+            "model": "gpt-4o",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [
+                        {"id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": "{\"city\": \"Paris\"}"}},
+                        {"id": "call_2", "type": "function", "function": {"name": "get_weather", "arguments": "{\"city\": \"Rome\"}"}}
+                    ]
+                },
+                "finish_reason": "tool_calls"
+            }]
+        }"#;
+        let tool_calls = model.get_tool_calls(response).unwrap();
+        assert_eq!(tool_calls.len(), 2);
+        assert_eq!(tool_calls[0].name, "get_weather");
+        assert_eq!(tool_calls[1].arguments, json!({"city": "Rome"}));
+
+        //The output function call is the answer and not a tool call
+        let response = r#"{
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{"id": "call_1", "type": "function", "function": {"name": "analyze_data", "arguments": "{\"data\": \"Hi\"}"}}]
+                },
+                "finish_reason": "stop"
+            }]
+        }"#;
+        assert!(model.get_tool_calls(response).unwrap().is_empty());
+        assert_eq!(model.get_data(response, true).unwrap(), r#"{"data": "Hi"}"#);
+
+        //With `auto` or `none` tool choice the model may answer in content
+        let response = r#"{
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "{\"data\": \"Hi\"}"},
+                "finish_reason": "stop"
+            }]
+        }"#;
+        assert_eq!(model.get_data(response, true).unwrap(), r#"{"data": "Hi"}"#);
+
+        let tool_delta = r#"{"choices": [{"index": 0, "delta": {"tool_calls": [{"index": 0, "function": {"arguments": "{\"data\""}}]}}]}"#;
+        assert_eq!(
+            model.get_stream_delta(tool_delta, true),
+            Some("{\"data\"".to_string())
+        );
+    }
+
+    #[test]
+    fn test_legacy_completions_logprobs() {
+        let response = r#"{
+            "id": "cmpl-7QmVI15qgYVllxK0FtxVGG6ywfzaq",
+            "object": "text_completion",
+            "created": 1686617332,
+            "model": "text-davinci-003",
+            "choices": [
+                {
+                    "text": "{\"data\": \"Hi\"}",
+                    "index": 0,
+                    "logprobs": {
+                        "tokens": ["{\"", "data", "\":", " \"", "Hi", "\"}"],
+                        "token_logprobs": [-0.0021, -0.0004, -0.00001, -0.013, -0.48, -0.0002],
+                        "top_logprobs": [
+                            {"{\"": -0.0021, "{": -6.2},
+                            {"data": -0.0004, "answer": -8.1},
+                            {"\":": -0.00001},
+                            {" \"": -0.013},
+                            {"Hi": -0.48, "Hello": -0.97},
+                            {"\"}": -0.0002}
+                        ],
+                        "text_offset": [0, 2, 6, 8, 10, 12]
+                    },
+                    "finish_reason": "stop"
+                }
+            ],
+            "usage": {"prompt_tokens": 5, "completion_tokens": 6, "total_tokens": 11}
+        }"#;
+        let completions_response: OpenAPICompletionsResponse =
+            serde_json::from_str(response).unwrap();
+        let choice_logprobs = completions_response.choices.unwrap()[0]
+            .logprobs
+            .clone()
+            .unwrap();
+        assert_eq!(choice_logprobs.text_offset.unwrap()[4], 10);
+
+        let model = OpenAIModels::TextDavinci003;
+        let mut body = model.get_body("Say hi", &json!({}), false, &100, &0.0, None, None);
+        assert!(model.apply_logprobs(&mut body, 0));
+        assert_eq!(body["logprobs"], 0);
+
+        let logprobs = model.get_logprobs(response).unwrap();
+        assert_eq!(logprobs.len(), 6);
+        assert_eq!(logprobs[4].token, "Hi");
+        assert_eq!(logprobs[4].logprob, -0.48);
+        assert_eq!(logprobs[4].top_logprobs[0].token, "Hi");
+        assert_eq!(logprobs[4].top_logprobs[1].token, "Hello");
+    }
+
     #[test]
     fn test_gpt3_5turbo_max_requests() {
         let model = OpenAIModels::Gpt3_5Turbo;
@@ -565,6 +1532,59 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_stream_delta() {
+        let model = OpenAIModels::Gpt4o;
+        let mut body = json!({});
+        assert!(model.apply_stream(&mut body));
+        assert_eq!(body["stream"], true);
+        assert!(!OpenAIModels::TextDavinci003.apply_stream(&mut json!({})));
+
+        let content_delta = r#"{"id": "chatcmpl-1", "object": "chat.completion.chunk", "choices": [{"index": 0, "delta": {"content": "{\"name\""}, "finish_reason": null}]}"#;
+        assert_eq!(
+            model.get_stream_delta(content_delta, false),
+            Some("{\"name\"".to_string())
+        );
+        let function_delta = r#"{"choices": [{"index": 0, "delta": {"function_call": {"arguments": ": \"Dune\""}}}]}"#;
+        assert_eq!(
+            model.get_stream_delta(function_delta, true),
+            Some(": \"Dune\"".to_string())
+        );
+        let final_chunk = r#"{"choices": [{"index": 0, "delta": {}, "finish_reason": "stop"}]}"#;
+        assert_eq!(model.get_stream_delta(final_chunk, false), None);
+        assert!(!model.is_stream_truncated(final_chunk));
+        assert!(!model.is_stream_truncated(content_delta));
+        let truncated_chunk =
+            r#"{"choices": [{"index": 0, "delta": {}, "finish_reason": "length"}]}"#;
+        assert!(model.is_stream_truncated(truncated_chunk));
+    }
+
+    #[test]
+    fn test_try_from_str_round_trip() {
+        let models = vec![
+            OpenAIModels::Gpt3_5Turbo,
+            OpenAIModels::Gpt3_5Turbo0613,
+            OpenAIModels::Gpt3_5Turbo16k,
+            OpenAIModels::Gpt4,
+            OpenAIModels::Gpt4_32k,
+            OpenAIModels::TextDavinci003,
+            OpenAIModels::Gpt4Turbo,
+            OpenAIModels::Gpt4TurboPreview,
+            OpenAIModels::Gpt4o,
+            OpenAIModels::Gpt4o20240806,
+            OpenAIModels::Gpt4oMini,
+            OpenAIModels::O1Preview,
+            OpenAIModels::O1Mini,
+        ];
+        for model in models {
+            assert_eq!(model.canonical_name(), model.as_str());
+            assert_eq!(
+                OpenAIModels::try_from_str(model.canonical_name()),
+                Some(model.clone())
+            );
+        }
+    }
+
     #[test]
     fn test_try_from_str_case_insensitivity() {
         assert_eq!(
@@ -592,4 +1612,166 @@ mod tests {
             })
         );
     }
+
+    // Tests of body construction
+    #[test]
+    fn test_get_body_with_system_prompt() {
+        let model = OpenAIModels::Gpt4o;
+        let body = model.get_body(
+            "Instructions",
+            &json!({}),
+            false,
+            &1000,
+            &0.0,
+            Some("You are a helpful assistant."),
+            None,
+        );
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages[0]["role"], "system");
+        assert!(messages[0]["content"]
+            .as_str()
+            .unwrap()
+            .ends_with("You are a helpful assistant."));
+        assert_eq!(messages[1]["role"], "user");
+        assert!(!messages[1]["content"]
+            .as_str()
+            .unwrap()
+            .contains("You are a helpful assistant."));
+    }
+
+    #[test]
+    fn test_get_body_without_system_prompt() {
+        let model = OpenAIModels::Gpt4o;
+        let body = model.get_body("Instructions", &json!({}), false, &1000, &0.0, None, None);
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(
+            messages[0]["content"].as_str().unwrap(),
+            model.get_base_instructions(Some(false))
+        );
+    }
+
+    // Refusals
+    #[test]
+    fn test_get_data_refusal() {
+        let model = OpenAIModels::Gpt4o;
+        let response = r#"{
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1723000000,
+            "model": "gpt-4o-2024-08-06",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": null, "refusal": "I'm sorry, I cannot assist with that request."},
+                "finish_reason": "stop"
+            }]
+        }"#;
+        let result = model.get_data(response, false);
+        assert!(result.is_err());
+        let error = result.unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("I'm sorry, I cannot assist with that request."));
+        assert!(error.downcast_ref::<AllmsRefusalError>().is_some());
+    }
+
+    #[test]
+    fn test_get_data_content_with_null_refusal() {
+        let model = OpenAIModels::Gpt4o;
+        let response = r#"{
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "{\"answer\": 42}", "refusal": null},
+                "finish_reason": "stop"
+            }]
+        }"#;
+        assert_eq!(
+            model.get_data(response, false).unwrap(),
+            r#"{"answer": 42}"#
+        );
+    }
+
+    // Token usage
+    #[test]
+    fn test_get_usage() {
+        let model = OpenAIModels::Gpt4o;
+        let response = r#"{
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "{}"},
+                "finish_reason": "stop"
+            }],
+            "usage": {"prompt_tokens": 120, "completion_tokens": 30, "total_tokens": 150}
+        }"#;
+        let usage = model.get_usage(response).unwrap();
+        assert_eq!(usage.input_tokens, 120);
+        assert_eq!(usage.output_tokens, 30);
+        assert_eq!(usage.total_tokens, 150);
+        assert_eq!(usage.cached_input_tokens, 0);
+
+        let cached_response = r#"{
+            "choices": [],
+            "usage": {
+                "prompt_tokens": 2006,
+                "completion_tokens": 300,
+                "total_tokens": 2306,
+                "prompt_tokens_details": {"cached_tokens": 1920, "audio_tokens": 0}
+            }
+        }"#;
+        let usage = model.get_usage(cached_response).unwrap();
+        assert_eq!(usage.input_tokens, 2006);
+        assert_eq!(usage.cached_input_tokens, 1920);
+    }
+
+    #[test]
+    fn test_estimated_cost_with_cached_tokens() {
+        let model = OpenAIModels::Gpt4o;
+        let pricing = model.get_token_pricing().unwrap();
+        let response = r#"{
+            "choices": [],
+            "usage": {
+                "prompt_tokens": 2000,
+                "completion_tokens": 100,
+                "total_tokens": 2100,
+                "prompt_tokens_details": {"cached_tokens": 1920}
+            }
+        }"#;
+        let usage = model.get_usage(response).unwrap();
+        //80 uncached input tokens at $2.50, 1920 cached at $1.25 and 100 output tokens at $10 per 1M
+        let expected = (80.0 * 2.5 + 1920.0 * 1.25 + 100.0 * 10.0) / 1_000_000.0;
+        assert!((usage.estimated_cost(&pricing) - expected).abs() < 1e-12);
+
+        //Without cache hits all input tokens are priced at the regular rate
+        let uncached_usage = TokenUsage {
+            cached_input_tokens: 0,
+            ..usage.clone()
+        };
+        let full_price = (2000.0 * 2.5 + 100.0 * 10.0) / 1_000_000.0;
+        assert!((uncached_usage.estimated_cost(&pricing) - full_price).abs() < 1e-12);
+        assert!(usage.estimated_cost(&pricing) < uncached_usage.estimated_cost(&pricing));
+
+        assert!(OpenAIModels::Custom {
+            name: "my-model".to_string()
+        }
+        .get_token_pricing()
+        .is_none());
+    }
+
+    // Truncated outputs
+    #[test]
+    fn test_is_truncated() {
+        let truncated = r#"{
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "{\"items\": [1, 2"},
+                "finish_reason": "length"
+            }]
+        }"#;
+        assert!(OpenAIModels::Gpt4o.is_truncated(truncated));
+
+        let complete = truncated.replace("length", "stop");
+        assert!(!OpenAIModels::Gpt4o.is_truncated(&complete));
+
+        let legacy = r#"{"choices": [{"text": "{", "index": 0, "finish_reason": "length"}]}"#;
+        assert!(OpenAIModels::TextDavinci003.is_truncated(legacy));
+    }
 }
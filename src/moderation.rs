@@ -0,0 +1,131 @@
+use anyhow::{anyhow, Result};
+use log::error;
+use reqwest::{header, Client};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+
+use crate::constants::{OPENAI_API_URL, OPENAI_MODERATION_MODEL};
+use crate::domain::AllmsError;
+use crate::utils::get_api_error;
+
+/// [OpenAI Docs](https://platform.openai.com/docs/guides/moderation)
+///
+/// The Moderation API can be used to check whether text is potentially harmful before it is sent to a model.
+/// It is free to use and can be combined with any provider using `Completions::with_moderation`.
+pub struct Moderation;
+
+/// Result of a moderation check with the flagged categories and the score of each category
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ModerationResult {
+    pub flagged: bool,
+    pub categories: BTreeMap<String, bool>,
+    pub category_scores: BTreeMap<String, f64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct ModerationResp {
+    results: Vec<ModerationResult>,
+}
+
+impl ModerationResult {
+    ///
+    /// Returns the names of the categories that were flagged
+    ///
+    pub fn flagged_categories(&self) -> Vec<String> {
+        self.categories
+            .iter()
+            .filter(|(_, flagged)| **flagged)
+            .map(|(category, _)| category.to_string())
+            .collect()
+    }
+}
+
+impl Moderation {
+    ///
+    /// This method checks the provided input with the OpenAI Moderation API
+    ///
+    pub async fn check(api_key: &str, input: &str) -> Result<ModerationResult> {
+        let moderation_url = format!("{}/v1/moderations", (*OPENAI_API_URL).trim_end_matches('/'));
+
+        let body = json!({
+            "model": OPENAI_MODERATION_MODEL,
+            "input": input,
+        });
+
+        //Make the API call
+        let client = Client::new();
+
+        let response = client
+            .post(moderation_url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .bearer_auth(api_key)
+            .json(&body)
+            .send()
+            .await?;
+
+        let response_status = response.status();
+        let response_headers = response.headers().clone();
+        let response_text = response.text().await?;
+
+        if !response_status.is_success() {
+            return Err(get_api_error(
+                "moderation",
+                response_status,
+                &response_headers,
+                response_text,
+            ));
+        }
+
+        Self::parse_response(response_text)
+    }
+
+    // This function extracts the result for the single input from the API response
+    fn parse_response(response_text: String) -> Result<ModerationResult> {
+        let response_deser: ModerationResp =
+            serde_json::from_str(&response_text).map_err(|error| {
+                let error = AllmsError {
+                    crate_name: "allms".to_string(),
+                    module: "moderation".to_string(),
+                    error_message: format!(
+                        "Moderation API response serialization error: {}",
+                        error
+                    ),
+                    error_detail: response_text.clone(),
+                };
+                error!("{:?}", error);
+                anyhow!("{:?}", error)
+            })?;
+
+        response_deser
+            .results
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("[allms][Moderation] No moderation result returned."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Moderation;
+
+    #[test]
+    fn test_parse_response() {
+        let response = r#"{
+            "id": "modr-0d9740456c391e43c445bf0f010940c7",
+            "model": "omni-moderation-latest",
+            "results": [
+                {
+                    "flagged": true,
+                    "categories": {"harassment": true, "violence": false, "self-harm": false},
+                    "category_scores": {"harassment": 0.92, "violence": 0.01, "self-harm": 0.0001},
+                    "category_applied_input_types": {"harassment": ["text"], "violence": ["text"], "self-harm": ["text"]}
+                }
+            ]
+        }"#;
+        let result = Moderation::parse_response(response.to_string()).unwrap();
+        assert!(result.flagged);
+        assert_eq!(result.flagged_categories(), vec!["harassment".to_string()]);
+        assert_eq!(result.category_scores["harassment"], 0.92);
+    }
+}
@@ -0,0 +1,150 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use crate::domain::RateLimit;
+use crate::llm_models::LLMModel;
+
+/// Token bucket rate limiter enforcing both the requests-per-minute and tokens-per-minute limits of a model.
+///
+/// It can be used when making calls in custom loops: `acquire` waits until a request with the estimated number of tokens fits within the limits.
+/// The limiter is cheap to clone and all clones share the same buckets, so it can be shared across tasks.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    state: Arc<Mutex<RateLimiterState>>,
+}
+
+#[derive(Debug)]
+struct RateLimiterState {
+    requests: Bucket,
+    tokens: Bucket,
+    last_refill: Instant,
+}
+
+// Buckets start full and refill continuously at `capacity` units per minute
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    available: f64,
+}
+
+impl Bucket {
+    fn new(capacity_per_minute: usize) -> Self {
+        let capacity = capacity_per_minute.max(1) as f64;
+        Bucket {
+            capacity,
+            available: capacity,
+        }
+    }
+
+    fn refill(&mut self, elapsed: Duration) {
+        let refilled = self.capacity * elapsed.as_secs_f64() / 60.0;
+        self.available = (self.available + refilled).min(self.capacity);
+    }
+
+    // Returns how long it takes until the requested amount is available
+    fn wait_time(&self, amount: f64) -> Duration {
+        let missing = amount - self.available;
+        if missing <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(missing * 60.0 / self.capacity)
+    }
+}
+
+impl RateLimiter {
+    /// Constructor
+    pub fn new(rate_limit: RateLimit) -> Self {
+        RateLimiter {
+            state: Arc::new(Mutex::new(RateLimiterState {
+                requests: Bucket::new(rate_limit.rpm),
+                tokens: Bucket::new(rate_limit.tpm),
+                last_refill: Instant::now(),
+            })),
+        }
+    }
+
+    ///
+    /// This method creates a rate limiter based on the rate limit of the provided model
+    ///
+    pub fn for_model<T: LLMModel>(model: &T) -> Self {
+        Self::new(model.get_rate_limit())
+    }
+
+    ///
+    /// This method waits until a request using the estimated number of tokens (prompt + response) can be sent without exceeding the rate limit
+    /// Requests estimated above the tokens-per-minute limit wait for a full bucket instead of waiting forever
+    ///
+    pub async fn acquire(&self, estimated_tokens: usize) {
+        loop {
+            let wait_time = {
+                let mut state = self.state.lock().await;
+                match state.try_acquire(estimated_tokens, Instant::now()) {
+                    Ok(()) => return,
+                    Err(wait_time) => wait_time,
+                }
+            };
+            tokio::time::sleep(wait_time).await;
+        }
+    }
+}
+
+impl RateLimiterState {
+    // Takes one request and the tokens from the buckets or returns how long to wait before trying again
+    fn try_acquire(&mut self, estimated_tokens: usize, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.requests.refill(elapsed);
+        self.tokens.refill(elapsed);
+        self.last_refill = now;
+
+        let tokens = (estimated_tokens as f64).min(self.tokens.capacity);
+        let wait_time = self
+            .requests
+            .wait_time(1.0)
+            .max(self.tokens.wait_time(tokens));
+        if !wait_time.is_zero() {
+            return Err(wait_time);
+        }
+
+        self.requests.available -= 1.0;
+        self.tokens.available -= tokens;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::RateLimiter;
+    use crate::domain::RateLimit;
+
+    #[tokio::test]
+    async fn test_rate_limiter_buckets() {
+        let limiter = RateLimiter::new(RateLimit { tpm: 1000, rpm: 2 });
+        let shared_limiter = limiter.clone();
+        let mut state = shared_limiter.state.lock().await;
+        let start = state.last_refill;
+
+        // Both requests fit in the initial bucket
+        assert!(state.try_acquire(400, start).is_ok());
+        assert!(state.try_acquire(400, start).is_ok());
+
+        // The third request waits for one request to be refilled (30s at 2 rpm)
+        let wait_time = state.try_acquire(100, start).unwrap_err();
+        assert_eq!(wait_time, Duration::from_secs(30));
+
+        // After 30s one request and 700 tokens are available, so 800 tokens need another 6s
+        let later = start + Duration::from_secs(30);
+        let wait_time = state.try_acquire(800, later).unwrap_err();
+        assert_eq!(wait_time.as_millis(), 6000);
+        assert!(state.try_acquire(700, later).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_caps_tokens_at_capacity() {
+        let limiter = RateLimiter::new(RateLimit { tpm: 100, rpm: 100 });
+        let mut state = limiter.state.lock().await;
+        assert!(state.try_acquire(10_000, Instant::now()).is_ok());
+    }
+}
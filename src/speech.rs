@@ -0,0 +1,180 @@
+use anyhow::Result;
+use log::info;
+use reqwest::{header, Client};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::constants::OPENAI_API_URL;
+use crate::utils::get_api_error;
+
+/// [OpenAI Docs](https://platform.openai.com/docs/guides/text-to-speech)
+///
+/// The Speech API converts text into spoken audio using one of the built-in voices (e.g. `alloy`, `echo`, `nova`).
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Speech {
+    model: SpeechModels,
+    debug: bool,
+    api_key: String,
+    voice: String,
+    format: SpeechFormat,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub enum SpeechModels {
+    Tts1,
+    Tts1Hd,
+    Gpt4oMiniTts,
+}
+
+impl SpeechModels {
+    pub fn as_str(&self) -> &str {
+        match self {
+            SpeechModels::Tts1 => "tts-1",
+            SpeechModels::Tts1Hd => "tts-1-hd",
+            SpeechModels::Gpt4oMiniTts => "gpt-4o-mini-tts",
+        }
+    }
+}
+
+/// Audio format of the generated speech
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub enum SpeechFormat {
+    Mp3,
+    Opus,
+    Aac,
+    Flac,
+    Wav,
+    Pcm,
+}
+
+impl SpeechFormat {
+    pub(crate) fn as_str(&self) -> &str {
+        match self {
+            SpeechFormat::Mp3 => "mp3",
+            SpeechFormat::Opus => "opus",
+            SpeechFormat::Aac => "aac",
+            SpeechFormat::Flac => "flac",
+            SpeechFormat::Wav => "wav",
+            SpeechFormat::Pcm => "pcm",
+        }
+    }
+}
+
+impl Speech {
+    /// Constructor
+    pub fn new(model: SpeechModels, api_key: &str) -> Self {
+        Speech {
+            model,
+            debug: false,
+            api_key: api_key.to_string(),
+            voice: "alloy".to_string(),
+            format: SpeechFormat::Mp3,
+        }
+    }
+
+    ///
+    /// This method can be used to turn on debug mode for the Speech struct
+    ///
+    pub fn debug(mut self) -> Self {
+        self.debug = true;
+        self
+    }
+
+    ///
+    /// This method can be used to set the voice used to read the text
+    /// Current default is `alloy`
+    ///
+    pub fn voice(mut self, voice: &str) -> Self {
+        self.voice = voice.to_string();
+        self
+    }
+
+    ///
+    /// This method can be used to set the format of the returned audio
+    /// Current default is Mp3
+    ///
+    pub fn format(mut self, format: SpeechFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    ///
+    /// This function converts the text into speech and returns the audio bytes in the selected format
+    ///
+    pub async fn synthesize(&self, text: &str) -> Result<Vec<u8>> {
+        let speech_url = format!(
+            "{}/v1/audio/speech",
+            (*OPENAI_API_URL).trim_end_matches('/')
+        );
+
+        let body = self.get_body(text);
+
+        //Make the API call
+        let client = Client::new();
+
+        let response = client
+            .post(speech_url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .bearer_auth(&self.api_key)
+            .json(&body)
+            .send()
+            .await?;
+
+        let response_status = response.status();
+        let response_headers = response.headers().clone();
+
+        if !response_status.is_success() {
+            let response_text = response.text().await?;
+            return Err(get_api_error(
+                "speech",
+                response_status,
+                &response_headers,
+                response_text,
+            ));
+        }
+
+        let audio = response.bytes().await?;
+
+        if self.debug {
+            info!(
+                "[debug] OpenAI Speech API response: [{}] {} bytes of {} audio",
+                &response_status,
+                audio.len(),
+                self.format.as_str()
+            );
+        }
+
+        Ok(audio.to_vec())
+    }
+
+    // Builds the body of the Speech API request
+    fn get_body(&self, text: &str) -> serde_json::Value {
+        json!({
+            "model": self.model.as_str(),
+            "input": text,
+            "voice": self.voice,
+            "response_format": self.format.as_str(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Speech, SpeechFormat, SpeechModels};
+
+    #[test]
+    fn test_get_body() {
+        let body = Speech::new(SpeechModels::Tts1Hd, "key")
+            .voice("nova")
+            .format(SpeechFormat::Opus)
+            .get_body("Hello world");
+        assert_eq!(body["model"], "tts-1-hd");
+        assert_eq!(body["input"], "Hello world");
+        assert_eq!(body["voice"], "nova");
+        assert_eq!(body["response_format"], "opus");
+
+        let default_body = Speech::new(SpeechModels::Tts1, "key").get_body("Hi");
+        assert_eq!(default_body["voice"], "alloy");
+        assert_eq!(default_body["response_format"], "mp3");
+    }
+}
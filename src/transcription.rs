@@ -0,0 +1,283 @@
+use anyhow::{anyhow, Result};
+use log::{error, info};
+use reqwest::{multipart, Client};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::constants::OPENAI_API_URL;
+use crate::domain::AllmsError;
+use crate::utils::{get_api_error, get_file_part, get_file_stream_part};
+
+/// [OpenAI Docs](https://platform.openai.com/docs/guides/speech-to-text)
+///
+/// The Transcriptions API converts audio (mp3, mp4, mpeg, mpga, m4a, wav, webm) into text.
+/// The transcript can then be used as input of `Completions` calls.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Transcription {
+    model: TranscriptionModels,
+    debug: bool,
+    api_key: String,
+    language: Option<String>,
+    response_format: TranscriptionFormat,
+    word_timestamps: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub enum TranscriptionModels {
+    Whisper1,
+    Gpt4oTranscribe,
+    Gpt4oMiniTranscribe,
+}
+
+impl TranscriptionModels {
+    pub fn as_str(&self) -> &str {
+        match self {
+            TranscriptionModels::Whisper1 => "whisper-1",
+            TranscriptionModels::Gpt4oTranscribe => "gpt-4o-transcribe",
+            TranscriptionModels::Gpt4oMiniTranscribe => "gpt-4o-mini-transcribe",
+        }
+    }
+}
+
+/// Format of the transcript returned by the API
+/// `VerboseJson` includes the detected language, duration and timestamps and is only supported by `whisper-1`
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub enum TranscriptionFormat {
+    Json,
+    Text,
+    VerboseJson,
+}
+
+impl TranscriptionFormat {
+    pub(crate) fn as_str(&self) -> &str {
+        match self {
+            TranscriptionFormat::Json => "json",
+            TranscriptionFormat::Text => "text",
+            TranscriptionFormat::VerboseJson => "verbose_json",
+        }
+    }
+}
+
+/// Transcript of the audio file. Language, duration and timestamps are only available for `VerboseJson` format
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct TranscriptionResult {
+    pub text: String,
+    pub language: Option<String>,
+    pub duration: Option<f64>,
+    pub words: Option<Vec<TranscriptionWord>>,
+    pub segments: Option<Vec<TranscriptionSegment>>,
+}
+
+/// Segment of the transcript (roughly a sentence) with its start and end time in seconds
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct TranscriptionSegment {
+    pub text: String,
+    pub start: f64,
+    pub end: f64,
+}
+
+/// Word of the transcript with its start and end time in seconds
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct TranscriptionWord {
+    pub word: String,
+    pub start: f64,
+    pub end: f64,
+}
+
+impl Transcription {
+    /// Constructor
+    pub fn new(model: TranscriptionModels, api_key: &str) -> Self {
+        Transcription {
+            model,
+            debug: false,
+            api_key: api_key.to_string(),
+            language: None,
+            response_format: TranscriptionFormat::Json,
+            word_timestamps: false,
+        }
+    }
+
+    ///
+    /// This method can be used to turn on debug mode for the Transcription struct
+    ///
+    pub fn debug(mut self) -> Self {
+        self.debug = true;
+        self
+    }
+
+    ///
+    /// This method can be used to provide the language of the audio in ISO-639-1 format (e.g. `en`) to improve accuracy and latency
+    ///
+    pub fn language(mut self, language: &str) -> Self {
+        self.language = Some(language.to_string());
+        self
+    }
+
+    ///
+    /// This method can be used to set the format of the transcript returned by the API
+    /// Current default is Json
+    ///
+    pub fn response_format(mut self, response_format: TranscriptionFormat) -> Self {
+        self.response_format = response_format;
+        self
+    }
+
+    ///
+    /// This method can be used to request the start and end time of each word. It requires `VerboseJson` format so the format is switched automatically
+    /// Segment timestamps are returned as well
+    ///
+    pub fn word_timestamps(mut self) -> Self {
+        self.word_timestamps = true;
+        self.response_format = TranscriptionFormat::VerboseJson;
+        self
+    }
+
+    ///
+    /// This function uploads the audio bytes and returns the transcript
+    ///
+    pub async fn transcribe(
+        &self,
+        file_name: &str,
+        file_bytes: Vec<u8>,
+    ) -> Result<TranscriptionResult> {
+        let file_part = get_file_part(file_name, file_bytes)?;
+        self.transcribe_part(file_part).await
+    }
+
+    ///
+    /// This function works like `transcribe` but streams the audio file from disk instead of loading it into memory
+    ///
+    pub async fn transcribe_from_path(&self, path: &Path) -> Result<TranscriptionResult> {
+        let file_part = get_file_stream_part(path).await?;
+        self.transcribe_part(file_part).await
+    }
+
+    // Sends the multipart form with the audio file and the parameters to the Transcriptions API
+    async fn transcribe_part(&self, file_part: multipart::Part) -> Result<TranscriptionResult> {
+        let transcriptions_url = format!(
+            "{}/v1/audio/transcriptions",
+            (*OPENAI_API_URL).trim_end_matches('/')
+        );
+
+        let mut form = multipart::Form::new()
+            .text("model", self.model.as_str().to_string())
+            .text("response_format", self.response_format.as_str().to_string())
+            .part("file", file_part);
+        if let Some(language) = &self.language {
+            form = form.text("language", language.to_string());
+        }
+        // Segments are only returned by default if no granularity is requested
+        if self.word_timestamps {
+            form = form
+                .text("timestamp_granularities[]", "word")
+                .text("timestamp_granularities[]", "segment");
+        }
+
+        //Make the API call
+        let client = Client::new();
+
+        // This API sends a form so content type is automatically set by multipart method
+        let response = client
+            .post(transcriptions_url)
+            .bearer_auth(&self.api_key)
+            .multipart(form)
+            .send()
+            .await?;
+
+        let response_status = response.status();
+        let response_headers = response.headers().clone();
+        let response_text = response.text().await?;
+
+        if self.debug {
+            info!(
+                "[debug] OpenAI Transcriptions API response: [{}] {:#?}",
+                &response_status, &response_text
+            );
+        }
+
+        if !response_status.is_success() {
+            return Err(get_api_error(
+                "transcription",
+                response_status,
+                &response_headers,
+                response_text,
+            ));
+        }
+
+        self.parse_response(response_text)
+    }
+
+    // Text format returns the transcript as is, while Json formats are deserialized
+    fn parse_response(&self, response_text: String) -> Result<TranscriptionResult> {
+        if self.response_format == TranscriptionFormat::Text {
+            return Ok(TranscriptionResult {
+                text: response_text.trim().to_string(),
+                language: None,
+                duration: None,
+                words: None,
+                segments: None,
+            });
+        }
+
+        serde_json::from_str(&response_text).map_err(|error| {
+            let error = AllmsError {
+                crate_name: "allms".to_string(),
+                module: "transcription".to_string(),
+                error_message: format!(
+                    "Transcriptions API response serialization error: {}",
+                    error
+                ),
+                error_detail: response_text,
+            };
+            error!("{:?}", error);
+            anyhow!("{:?}", error)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Transcription, TranscriptionFormat, TranscriptionModels};
+
+    #[test]
+    fn test_parse_response() {
+        let transcription = Transcription::new(TranscriptionModels::Whisper1, "key");
+        let result = transcription
+            .parse_response(r#"{"text": "Hello world."}"#.to_string())
+            .unwrap();
+        assert_eq!(result.text, "Hello world.");
+        assert!(result.words.is_none());
+
+        let transcription = transcription.word_timestamps();
+        assert_eq!(
+            transcription.response_format,
+            TranscriptionFormat::VerboseJson
+        );
+        let verbose_response = r#"{
+            "task": "transcribe",
+            "language": "english",
+            "duration": 1.2,
+            "text": "Hello world.",
+            "words": [
+                {"word": "Hello", "start": 0.0, "end": 0.48},
+                {"word": "world", "start": 0.52, "end": 1.1}
+            ],
+            "segments": [
+                {"id": 0, "seek": 0, "start": 0.0, "end": 1.2, "text": " Hello world.", "tokens": [50364, 2425], "temperature": 0.0, "avg_logprob": -0.3, "compression_ratio": 0.8, "no_speech_prob": 0.01}
+            ]
+        }"#;
+        let result = transcription
+            .parse_response(verbose_response.to_string())
+            .unwrap();
+        assert_eq!(result.language, Some("english".to_string()));
+        assert_eq!(result.words.unwrap()[1].start, 0.52);
+        assert_eq!(result.segments.unwrap()[0].end, 1.2);
+
+        let transcription = Transcription::new(TranscriptionModels::Gpt4oTranscribe, "key")
+            .response_format(TranscriptionFormat::Text);
+        let result = transcription
+            .parse_response("Hello world.\n".to_string())
+            .unwrap();
+        assert_eq!(result.text, "Hello world.");
+    }
+}
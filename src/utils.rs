@@ -1,13 +1,24 @@
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
+use futures::stream::StreamExt;
+use jsonschema::JSONSchema;
+use log::error;
+use reqwest::{header::HeaderMap, multipart, Body, RequestBuilder, StatusCode};
 use schemars::{schema_for, JsonSchema};
 use serde::de::DeserializeOwned;
-use serde_json::Value;
-use tiktoken_rs::{cl100k_base, get_bpe_from_model, CoreBPE};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeSet;
+use std::path::Path;
+use tiktoken_rs::{cl100k_base, get_bpe_from_model, o200k_base, CoreBPE};
+use tokio::io::AsyncReadExt;
 
+use crate::domain::{AllmsApiError, AllmsError, LLMMessage, LiveRateLimit, ModelListResp};
 use crate::llm_models::LLMModel;
 #[allow(deprecated)]
 use crate::OpenAIModels;
 
+const ARRAY_WRAPPER_KEY: &str = "items";
+const FILE_STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
 // Get the tokenizer given a model
 #[allow(deprecated)]
 #[deprecated(
@@ -24,9 +35,19 @@ pub(crate) fn get_tokenizer_old(model: &OpenAIModels) -> anyhow::Result<CoreBPE>
     }
 }
 
+// Model name prefixes using the o200k_base encoding, including custom model names not yet known to tiktoken
+const O200K_MODEL_PREFIXES: [&str; 7] = ["gpt-4o", "gpt-4.1", "gpt-4.5", "gpt-5", "o1", "o3", "o4"];
+
 // Get the tokenizer given a model
 pub(crate) fn get_tokenizer<T: LLMModel>(model: &T) -> anyhow::Result<CoreBPE> {
-    let tokenizer = get_bpe_from_model(model.as_str());
+    let model_name = model.as_str().to_lowercase();
+    if O200K_MODEL_PREFIXES
+        .iter()
+        .any(|prefix| model_name.starts_with(prefix))
+    {
+        return o200k_base();
+    }
+    let tokenizer = get_bpe_from_model(&model_name);
     if let Err(_error) = tokenizer {
         // Fallback to the default chat model
         cl100k_base()
@@ -35,11 +56,526 @@ pub(crate) fn get_tokenizer<T: LLMModel>(model: &T) -> anyhow::Result<CoreBPE> {
     }
 }
 
-//OpenAI has a tendency to wrap response Json in ```json{}```
-//TODO: This function might need to become more sophisticated or handled with better prompt eng
+///
+/// This function splits the text into chunks of at most `max_tokens` tokens as counted by the model's tokenizer, e.g. to fit a model's context or for RAG.
+/// Consecutive chunks share up to `overlap` tokens. Chunks are split on token boundaries and never in the middle of a multi-byte character.
+///
+pub fn chunk_by_tokens(
+    text: &str,
+    model: &impl LLMModel,
+    max_tokens: usize,
+    overlap: usize,
+) -> Result<Vec<String>> {
+    if max_tokens == 0 || overlap >= max_tokens {
+        return Err(anyhow!(
+            "max_tokens must be greater than 0 and greater than the overlap."
+        ));
+    }
+    let tokenizer = get_tokenizer(model)?;
+
+    // Group tokens into pieces that decode to valid UTF-8 so characters split across several tokens stay together
+    let mut pieces: Vec<(String, usize)> = Vec::new();
+    let mut pending_tokens = Vec::new();
+    for token in tokenizer.encode_ordinary(text) {
+        pending_tokens.push(token);
+        if let Ok(piece) = tokenizer.decode(pending_tokens.clone()) {
+            pieces.push((piece, pending_tokens.len()));
+            pending_tokens.clear();
+        }
+    }
+
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < pieces.len() {
+        // A single piece larger than the limit is kept whole rather than split mid-character
+        let mut end = start;
+        let mut chunk_tokens = 0;
+        while end < pieces.len() && (end == start || chunk_tokens + pieces[end].1 <= max_tokens) {
+            chunk_tokens += pieces[end].1;
+            end += 1;
+        }
+        chunks.push(
+            pieces[start..end]
+                .iter()
+                .map(|(piece, _)| piece.as_str())
+                .collect(),
+        );
+
+        if end == pieces.len() {
+            break;
+        }
+
+        // Step back to repeat up to `overlap` tokens in the next chunk while still moving forward
+        let mut next_start = end;
+        let mut overlap_tokens = 0;
+        while next_start > start + 1 && overlap_tokens + pieces[next_start - 1].1 <= overlap {
+            next_start -= 1;
+            overlap_tokens += pieces[next_start].1;
+        }
+        start = next_start;
+    }
+
+    Ok(chunks)
+}
+
+// Builds the error returned for a non-success API response, keeping the status code and rate-limit headers needed for backoff
+pub(crate) fn get_api_error(
+    module: &str,
+    status: StatusCode,
+    headers: &HeaderMap,
+    response_text: String,
+) -> anyhow::Error {
+    let headers = headers
+        .iter()
+        .filter(|(name, _)| {
+            let name = name.as_str();
+            name.starts_with("retry-after") || name.contains("ratelimit")
+        })
+        .filter_map(|(name, value)| {
+            value
+                .to_str()
+                .ok()
+                .map(|value| (name.to_string(), value.to_string()))
+        })
+        .collect();
+
+    let error = AllmsApiError {
+        crate_name: "allms".to_string(),
+        module: module.to_string(),
+        status_code: status.as_u16(),
+        headers,
+        message: get_api_error_message(&response_text),
+        error_detail: response_text,
+    };
+    error!("{}", error);
+    anyhow!(error)
+}
+
+// This function extracts the message from the error envelope returned by the providers
+// OpenAI, Anthropic and Gemini use `{"error": {"message": ...}}`, Mistral a top-level `message` and Ollama `{"error": ...}`
+// Vertex AI streams responses so the envelope may be wrapped in an array
+pub(crate) fn get_api_error_message(response_text: &str) -> Option<String> {
+    let value: Value = serde_json::from_str(response_text).ok()?;
+    let envelope = match &value {
+        Value::Array(items) => items.first()?,
+        _ => &value,
+    };
+    match &envelope["error"] {
+        Value::String(message) => Some(message.to_string()),
+        Value::Object(error) => error
+            .get("message")
+            .and_then(|message| message.as_str())
+            .map(|message| message.to_string()),
+        _ => match (&envelope["object"], &envelope["message"]) {
+            (Value::String(object), Value::String(message)) if object == "error" => {
+                Some(message.to_string())
+            }
+            _ => None,
+        },
+    }
+}
+
+// This function checks if the Json data matches the schema and returns the parsed data
+pub(crate) fn get_valid_json(module: &str, schema: &str, value: &str) -> Result<Value> {
+    let schema_value = serde_json::from_str(schema).map_err(|e| {
+        let error = AllmsError {
+            crate_name: "allms".to_string(),
+            module: module.to_string(),
+            error_message: format!("Json Schema parsing error: {:?}", e),
+            error_detail: format!("Schema: {:?}", schema),
+        };
+        anyhow!("{:?}", error)
+    })?;
+
+    let compiled_schema = JSONSchema::compile(&schema_value).map_err(|e| {
+        let error = AllmsError {
+            crate_name: "allms".to_string(),
+            module: module.to_string(),
+            error_message: format!("Json Schema compilation error: {:?}", e),
+            error_detail: format!("Schema: {:?}", schema_value),
+        };
+        anyhow!("{:?}", error)
+    })?;
+
+    let data_value = serde_json::from_str(value).map_err(|e| {
+        let error = AllmsError {
+            crate_name: "allms".to_string(),
+            module: module.to_string(),
+            error_message: format!("Json data parsing error: {:?}", e),
+            error_detail: format!("Data: {:?}", value),
+        };
+        anyhow!("{:?}", error)
+    })?;
+
+    compiled_schema.validate(&data_value).map_err(|_| {
+        let error = AllmsError {
+            crate_name: "allms".to_string(),
+            module: module.to_string(),
+            error_message: "Json Schema validation error".to_string(),
+            error_detail: format!("Data: {:?}\nSchema: {:?}", &data_value, &schema_value),
+        };
+        anyhow!("{:?}", error)
+    })?;
+
+    Ok(data_value)
+}
+
+// Parses the live rate limit from the response headers. Header names are provided in the order: requests limit, remaining, reset and tokens limit, remaining, reset
+// Returns `None` if the provider did not return any of the headers
+pub(crate) fn get_live_rate_limit(
+    headers: &HeaderMap,
+    header_names: &[&str; 6],
+) -> Option<LiveRateLimit> {
+    let get_header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string())
+    };
+    let get_number = |name: &str| get_header(name).and_then(|value| value.parse::<u64>().ok());
+
+    let live_rate_limit = LiveRateLimit {
+        limit_requests: get_number(header_names[0]),
+        remaining_requests: get_number(header_names[1]),
+        reset_requests: get_header(header_names[2]),
+        limit_tokens: get_number(header_names[3]),
+        remaining_tokens: get_number(header_names[4]),
+        reset_tokens: get_header(header_names[5]),
+    };
+
+    match live_rate_limit == LiveRateLimit::default() {
+        true => None,
+        false => Some(live_rate_limit),
+    }
+}
+
+// Sends a request to a Models List API and deserializes a single page of the response
+pub(crate) async fn get_model_list(module: &str, request: RequestBuilder) -> Result<ModelListResp> {
+    let response = request.send().await?;
+
+    let response_status = response.status();
+    let response_headers = response.headers().clone();
+    let response_text = response.text().await?;
+
+    if !response_status.is_success() {
+        return Err(get_api_error(
+            module,
+            response_status,
+            &response_headers,
+            response_text,
+        ));
+    }
+
+    serde_json::from_str(&response_text).map_err(|error| {
+        let error = AllmsError {
+            crate_name: "allms".to_string(),
+            module: module.to_string(),
+            error_message: format!("Models API response serialization error: {}", error),
+            error_detail: response_text,
+        };
+        error!("{:?}", error);
+        anyhow!("{:?}", error)
+    })
+}
+
+// Sends a streaming request and invokes `on_event` with the data of each server-sent event as it arrives
+pub(crate) async fn get_event_stream(
+    module: &str,
+    request: RequestBuilder,
+    on_event: &mut dyn FnMut(&str),
+) -> Result<()> {
+    let response = request.send().await?;
+
+    let response_status = response.status();
+    if !response_status.is_success() {
+        let response_headers = response.headers().clone();
+        let response_text = response.text().await?;
+        return Err(get_api_error(
+            module,
+            response_status,
+            &response_headers,
+            response_text,
+        ));
+    }
+
+    let mut stream = response.bytes_stream();
+    let mut buffer = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        push_event_chunk(&mut buffer, &chunk?, on_event);
+    }
+    if let Some(data) = get_event_data(String::from_utf8_lossy(&buffer).trim()) {
+        on_event(data);
+    }
+    Ok(())
+}
+
+// Adds a chunk of the stream to the buffer and invokes `on_event` for each complete line
+// Events and multi-byte characters can be split across chunks so only complete lines are decoded
+fn push_event_chunk(buffer: &mut Vec<u8>, chunk: &[u8], on_event: &mut dyn FnMut(&str)) {
+    buffer.extend_from_slice(chunk);
+    while let Some(line_end) = buffer.iter().position(|byte| *byte == b'\n') {
+        let line_bytes: Vec<u8> = buffer.drain(..=line_end).collect();
+        let line = String::from_utf8_lossy(&line_bytes);
+        if let Some(data) = get_event_data(line.trim()) {
+            on_event(data);
+        }
+    }
+}
+
+// Returns the payload of a `data:` line of a server-sent event. The `[DONE]` terminator used by OpenAI is skipped
+fn get_event_data(line: &str) -> Option<&str> {
+    let data = line.strip_prefix("data:")?.trim();
+    match data {
+        "" | "[DONE]" => None,
+        _ => Some(data),
+    }
+}
+
+// Incrementally scans a streamed Json object. Each time top-level members are completed the object made of all members completed so far is returned
+#[derive(Debug, Default)]
+pub(crate) struct PartialJsonParser {
+    buffer: String,
+    position: usize,
+    object_start: Option<usize>,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl PartialJsonParser {
+    pub(crate) fn push(&mut self, delta: &str) -> Option<Value> {
+        self.buffer.push_str(delta);
+
+        let mut completed_end = None;
+        for (offset, character) in self.buffer[self.position..].char_indices() {
+            let index = self.position + offset;
+            if self.in_string {
+                match character {
+                    _ if self.escaped => self.escaped = false,
+                    '\\' => self.escaped = true,
+                    '"' => self.in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match character {
+                '"' => self.in_string = true,
+                '{' | '[' => {
+                    //Only Json objects are reported, text before the object (e.g. markdown fences) is skipped
+                    if self.depth == 0 && character == '{' && self.object_start.is_none() {
+                        self.object_start = Some(index);
+                    }
+                    self.depth += 1;
+                }
+                '}' | ']' => {
+                    self.depth = self.depth.saturating_sub(1);
+                    if self.depth == 0 {
+                        completed_end = Some(index);
+                    }
+                }
+                ',' if self.depth == 1 => completed_end = Some(index),
+                _ => {}
+            }
+        }
+        self.position = self.buffer.len();
+
+        let partial = format!("{}}}", &self.buffer[self.object_start?..completed_end?]);
+        serde_json::from_str::<Value>(&partial)
+            .ok()
+            .filter(|value| value.as_object().is_some_and(|object| !object.is_empty()))
+    }
+}
+
+//Models have a tendency to wrap response Json in ```json{}``` fences or add commentary around it
+//The first balanced Json object or array that parses is returned. Otherwise only the fences and language hints are removed
 pub(crate) fn sanitize_json_response(json_response: &str) -> String {
-    let text_no_json = json_response.replace("json\n", "");
-    text_no_json.replace("```", "")
+    if let Some(json) = extract_json(json_response) {
+        return json.to_string();
+    }
+
+    let text_no_fences = json_response.replace("```", "");
+    let trimmed = text_no_fences.trim_start();
+    let hint_length = trimmed
+        .find(char::is_whitespace)
+        .filter(|&length| {
+            ["json", "json5", "jsonc"].contains(&trimmed[..length].to_lowercase().as_str())
+        })
+        .unwrap_or(0);
+    trimmed[hint_length..].trim().to_string()
+}
+
+// Finds the first balanced Json object or array in the text that is valid Json
+fn extract_json(text: &str) -> Option<&str> {
+    text.char_indices()
+        .filter(|(_, c)| *c == '{' || *c == '[')
+        .filter_map(|(start, _)| {
+            let end = find_closing_bracket(&text[start..])?;
+            Some(&text[start..start + end + 1])
+        })
+        .find(|candidate| serde_json::from_str::<Value>(candidate).is_ok())
+}
+
+// Returns the byte offset of the bracket closing the object or array the text starts with. Brackets within strings are ignored
+fn find_closing_bracket(text: &str) -> Option<usize> {
+    let mut expected_closing = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => (),
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => expected_closing.push('}'),
+            '[' => expected_closing.push(']'),
+            '}' | ']' => {
+                if expected_closing.pop() != Some(c) {
+                    return None;
+                }
+                if expected_closing.is_empty() {
+                    return Some(index);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+// Appends content parts (e.g. file references) to the last user message. Text content is converted into a text part first
+pub(crate) fn add_user_content_parts(body: &mut Value, parts: Vec<Value>) -> Result<()> {
+    let user_message = body["messages"]
+        .as_array_mut()
+        .and_then(|messages| {
+            messages
+                .iter_mut()
+                .rev()
+                .find(|message| message["role"] == "user")
+        })
+        .ok_or_else(|| anyhow!("No user message found in the request body."))?;
+
+    if let Value::String(text) = &user_message["content"] {
+        user_message["content"] = json!([{ "type": "text", "text": text }]);
+    }
+    match user_message["content"].as_array_mut() {
+        Some(content) => content.extend(parts),
+        None => return Err(anyhow!("Unsupported user message content.")),
+    }
+    Ok(())
+}
+
+// Inserts the previous turns of the conversation before the last message (the instructions) of a `messages` based body
+pub(crate) fn insert_message_history(body: &mut Value, history: &[LLMMessage]) -> bool {
+    let messages = match body["messages"].as_array_mut() {
+        Some(messages) if !messages.is_empty() => messages,
+        _ => return false,
+    };
+    let position = messages.len() - 1;
+    messages.splice(
+        position..position,
+        history.iter().map(|message| {
+            json!({
+                "role": message.role.as_str(),
+                "content": message.content,
+            })
+        }),
+    );
+    true
+}
+
+// Determines the MIME type of a file uploaded to Files APIs based on its extension
+// OpenAI documentation: https://platform.openai.com/docs/assistants/tools/supported-files
+// Anthropic documentation: https://docs.anthropic.com/en/docs/build-with-claude/files
+pub(crate) fn get_mime_type(file_name: &str) -> Result<&'static str> {
+    let mime_type = match Path::new(file_name)
+        .extension()
+        .and_then(std::ffi::OsStr::to_str)
+    {
+        Some("pdf") => "application/pdf",
+        Some("json") => "application/json",
+        //Used for Batch API input
+        Some("jsonl") => "application/jsonl",
+        Some("txt") => "text/plain",
+        Some("html") => "text/html",
+        Some("c") => "text/x-c",
+        Some("cpp") => "text/x-c++",
+        Some("docx") => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        Some("java") => "text/x-java",
+        Some("md") => "text/markdown",
+        Some("php") => "text/x-php",
+        Some("pptx") => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        Some("py") => "text/x-python",
+        Some("rb") => "text/x-ruby",
+        Some("tex") => "text/x-tex",
+        //The below are currently only supported by OpenAI for Code Interpreter but NOT Retrieval
+        Some("css") => "text/css",
+        Some("jpeg") | Some("jpg") => "image/jpeg",
+        Some("js") => "text/javascript",
+        Some("gif") => "image/gif",
+        Some("png") => "image/png",
+        Some("webp") => "image/webp",
+        Some("tar") => "application/x-tar",
+        Some("ts") => "application/typescript",
+        Some("xlsx") => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        Some("xml") => "application/xml",
+        Some("zip") => "application/zip",
+        //Audio formats accepted by the Transcriptions API
+        Some("mp3") | Some("mpga") | Some("mpeg") => "audio/mpeg",
+        Some("m4a") | Some("mp4") => "audio/mp4",
+        Some("wav") => "audio/wav",
+        Some("webm") => "audio/webm",
+        Some("ogg") => "audio/ogg",
+        Some("flac") => "audio/flac",
+        _ => anyhow::bail!("Unsupported file type"),
+    };
+    Ok(mime_type)
+}
+
+// Builds the multipart part used to upload the file bytes to Files APIs
+pub(crate) fn get_file_part(file_name: &str, file_bytes: Vec<u8>) -> Result<multipart::Part> {
+    // Determine MIME type based on file extension
+    let mime_type = get_mime_type(file_name)?;
+    multipart::Part::bytes(file_bytes)
+        .file_name(file_name.to_string())
+        .mime_str(mime_type)
+        .context("Failed to set MIME type")
+}
+
+// Builds the multipart part used to upload a file from disk to Files APIs
+// The content is streamed in chunks so large files are not loaded into memory
+pub(crate) async fn get_file_stream_part(path: &Path) -> Result<multipart::Part> {
+    let file_name = path
+        .file_name()
+        .and_then(std::ffi::OsStr::to_str)
+        .ok_or_else(|| anyhow!("Invalid file path: {}", path.display()))?;
+    // Determine MIME type based on file extension
+    let mime_type = get_mime_type(file_name)?;
+
+    let file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("Failed to open file: {}", path.display()))?;
+    let file_length = file.metadata().await?.len();
+
+    let file_stream = futures::stream::try_unfold(file, |mut file| async move {
+        let mut buffer = vec![0u8; FILE_STREAM_CHUNK_SIZE];
+        let bytes_read = file.read(&mut buffer).await?;
+        if bytes_read == 0 {
+            return Ok::<_, std::io::Error>(None);
+        }
+        buffer.truncate(bytes_read);
+        Ok(Some((buffer, file)))
+    });
+
+    multipart::Part::stream_with_length(Body::wrap_stream(file_stream), file_length)
+        .file_name(file_name.to_string())
+        .mime_str(mime_type)
+        .context("Failed to set MIME type")
 }
 
 // This function generates a Json schema for the provided type
@@ -60,10 +596,367 @@ pub(crate) fn get_type_schema<T: JsonSchema + DeserializeOwned>() -> Result<Stri
         obj.remove("title");
     }
 
+    // Simplify enums that models often mishandle
+    simplify_enum_schemas(&mut schema_json);
+
     // Convert the modified JSON value back to a pretty-printed JSON string
     Ok(serde_json::to_string_pretty(&schema_json)?)
 }
 
+///
+/// This function returns the Json schema of the provided type exactly as it is added to the prompt of `Completions` calls
+/// It can be used to log, cache or adjust the schema, or to check why a type produces answers the model cannot satisfy
+///
+pub fn schema_for_type<T: JsonSchema + DeserializeOwned>() -> Result<String> {
+    get_type_schema::<T>()
+}
+
+// Models often mishandle enums referenced through `$ref` and `oneOf`, so enum definitions and the schemas of their variants are inlined
+// C-like enums with documented variants (a `oneOf` of single-value string enums) are flattened into a single string `enum`
+fn simplify_enum_schemas(schema_json: &mut Value) {
+    let definitions = schema_json
+        .get("definitions")
+        .and_then(Value::as_object)
+        .cloned()
+        .unwrap_or_default();
+    simplify_enum_schema(schema_json, &definitions, &mut Vec::new());
+    prune_unused_definitions(schema_json);
+}
+
+// Recursively inlines references to enum definitions and flattens C-like enums. References that would create a cycle are left in place
+fn simplify_enum_schema(
+    schema: &mut Value,
+    definitions: &Map<String, Value>,
+    stack: &mut Vec<String>,
+) {
+    match schema {
+        Value::Object(obj) => {
+            let enum_ref_name = obj
+                .get("$ref")
+                .and_then(Value::as_str)
+                .and_then(|reference| reference.strip_prefix("#/definitions/"))
+                .filter(|name| !stack.iter().any(|parent| parent == name))
+                .filter(|name| definitions.get(*name).is_some_and(is_enum_schema))
+                .map(str::to_string);
+            if let Some(name) = enum_ref_name {
+                obj.remove("$ref");
+                if let Some(Value::Object(definition)) = definitions.get(&name) {
+                    for (key, value) in definition {
+                        obj.entry(key.to_string()).or_insert_with(|| value.clone());
+                    }
+                }
+                stack.push(name);
+                simplify_enum_schema(schema, definitions, stack);
+                stack.pop();
+                return;
+            }
+
+            // Schemas of data-carrying variants are inlined
+            for key in ["oneOf", "anyOf"] {
+                if let Some(variants) = obj.get_mut(key) {
+                    let inlined = inline_schema_refs(
+                        variants.take(),
+                        definitions,
+                        &mut stack.clone(),
+                        &mut BTreeSet::new(),
+                    );
+                    *variants = inlined;
+                }
+            }
+            flatten_string_enum(obj);
+
+            for (key, value) in obj.iter_mut() {
+                match (key.as_str(), value) {
+                    // A definition is on the stack while it is simplified so it is not inlined into itself
+                    ("definitions", Value::Object(root_definitions)) => {
+                        for (name, definition) in root_definitions.iter_mut() {
+                            stack.push(name.to_string());
+                            simplify_enum_schema(definition, definitions, stack);
+                            stack.pop();
+                        }
+                    }
+                    (_, value) => simplify_enum_schema(value, definitions, stack),
+                }
+            }
+        }
+        Value::Array(items) => items
+            .iter_mut()
+            .for_each(|item| simplify_enum_schema(item, definitions, stack)),
+        _ => {}
+    }
+}
+
+// Returns `true` for schemas of enums, i.e. a list of values or of variants
+fn is_enum_schema(schema: &Value) -> bool {
+    schema.get("enum").is_some() || schema.get("oneOf").is_some() || schema.get("anyOf").is_some()
+}
+
+// Merges a `oneOf` of single-value string enums into one string `enum`. Variant descriptions are appended to the description
+fn flatten_string_enum(obj: &mut Map<String, Value>) {
+    let variants = match obj.get("oneOf").and_then(Value::as_array) {
+        Some(variants) if !variants.is_empty() => variants,
+        _ => return,
+    };
+    let mut values = Vec::new();
+    let mut descriptions = Vec::new();
+    for variant in variants {
+        let value = match variant.get("enum").and_then(Value::as_array) {
+            Some(enum_values)
+                if variant["type"] == "string"
+                    && enum_values.len() == 1
+                    && variant.as_object().is_some_and(|variant| {
+                        variant
+                            .keys()
+                            .all(|key| ["type", "enum", "description"].contains(&key.as_str()))
+                    }) =>
+            {
+                enum_values[0].clone()
+            }
+            _ => return,
+        };
+        if let Some(description) = variant.get("description").and_then(Value::as_str) {
+            descriptions.push(format!(
+                "- {}: {}",
+                value.as_str().unwrap_or_default(),
+                description
+            ));
+        }
+        values.push(value);
+    }
+
+    obj.remove("oneOf");
+    obj.insert("type".to_string(), json!("string"));
+    obj.insert("enum".to_string(), Value::Array(values));
+    if !descriptions.is_empty() {
+        let description = match obj.get("description").and_then(Value::as_str) {
+            Some(description) => format!("{}\n{}", description, descriptions.join("\n")),
+            None => descriptions.join("\n"),
+        };
+        obj.insert("description".to_string(), json!(description));
+    }
+}
+
+// Removes the definitions that are no longer referenced after inlining
+fn prune_unused_definitions(schema_json: &mut Value) {
+    let definitions = match schema_json
+        .as_object_mut()
+        .and_then(|obj| obj.remove("definitions"))
+    {
+        Some(Value::Object(definitions)) => definitions,
+        _ => return,
+    };
+
+    let mut pending = Vec::new();
+    collect_schema_refs(schema_json, &mut pending);
+    let mut used = BTreeSet::new();
+    while let Some(name) = pending.pop() {
+        if used.insert(name.clone()) {
+            if let Some(definition) = definitions.get(&name) {
+                collect_schema_refs(definition, &mut pending);
+            }
+        }
+    }
+
+    let kept_definitions: Map<String, Value> = definitions
+        .into_iter()
+        .filter(|(name, _)| used.contains(name))
+        .collect();
+    if !kept_definitions.is_empty() {
+        if let Some(obj) = schema_json.as_object_mut() {
+            obj.insert("definitions".to_string(), Value::Object(kept_definitions));
+        }
+    }
+}
+
+// Collects the names of the definitions referenced in the schema
+fn collect_schema_refs(schema: &Value, refs: &mut Vec<String>) {
+    match schema {
+        Value::Object(obj) => {
+            if let Some(name) = obj
+                .get("$ref")
+                .and_then(Value::as_str)
+                .and_then(|reference| reference.strip_prefix("#/definitions/"))
+            {
+                refs.push(name.to_string());
+            }
+            obj.values()
+                .for_each(|value| collect_schema_refs(value, refs));
+        }
+        Value::Array(items) => items
+            .iter()
+            .for_each(|item| collect_schema_refs(item, refs)),
+        _ => {}
+    }
+}
+
+// Function calling and structured outputs require an object at the root of the schema
+// Array schemas (e.g. for `Vec<T>`) are wrapped in an object with a single `items` property. Definitions stay at the root so `$ref`s remain valid
+pub(crate) fn wrap_array_schema(mut json_schema: Value) -> Value {
+    if json_schema.get("type").and_then(Value::as_str) != Some("array") {
+        return json_schema;
+    }
+
+    let definitions = json_schema
+        .as_object_mut()
+        .and_then(|obj| obj.remove("definitions"));
+    let mut wrapper = json!({
+        "type": "object",
+        "properties": { ARRAY_WRAPPER_KEY: json_schema },
+        "required": [ARRAY_WRAPPER_KEY],
+    });
+    if let Some(definitions) = definitions {
+        wrapper["definitions"] = definitions;
+    }
+    wrapper
+}
+
+// This function checks the string values constrained by an `enum` in the schema and returns a description of each value outside of the allowed set
+// With `fuzzy_match` values that match a variant after trimming and ignoring case (e.g. ` active` for `Active`) are replaced with the variant
+// Parts of the value that don't follow the structure of the schema are skipped
+pub(crate) fn check_enum_values(
+    schema: &Value,
+    value: &mut Value,
+    fuzzy_match: bool,
+) -> Vec<String> {
+    let mut violations = Vec::new();
+    check_enum_values_at(schema, schema, value, "", fuzzy_match, &mut violations);
+    violations
+}
+
+fn check_enum_values_at(
+    root: &Value,
+    schema: &Value,
+    value: &mut Value,
+    path: &str,
+    fuzzy_match: bool,
+    violations: &mut Vec<String>,
+) {
+    //Definitions are referenced as `#/definitions/Name`
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        if let Some(definition) = reference
+            .strip_prefix('#')
+            .and_then(|pointer| root.pointer(pointer))
+        {
+            check_enum_values_at(root, definition, value, path, fuzzy_match, violations);
+        }
+        return;
+    }
+
+    if let Some(allowed) = get_enum_variants(schema) {
+        if allowed.contains(value) {
+            return;
+        }
+        let matched = value.as_str().filter(|_| fuzzy_match).and_then(|text| {
+            let mut candidates = allowed.iter().filter(|variant| {
+                variant
+                    .as_str()
+                    .is_some_and(|variant| variant.eq_ignore_ascii_case(text.trim()))
+            });
+            //Ambiguous matches are reported instead of guessing the variant
+            match (candidates.next(), candidates.next()) {
+                (Some(variant), None) => Some(variant.clone()),
+                _ => None,
+            }
+        });
+        match matched {
+            Some(variant) => *value = variant,
+            None => violations.push(format!(
+                "{}: {} is not one of {}",
+                if path.is_empty() { "/" } else { path },
+                value,
+                Value::Array(allowed)
+            )),
+        }
+        return;
+    }
+
+    match value {
+        Value::Object(object) => {
+            if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+                for (key, property_schema) in properties {
+                    if let Some(property_value) = object.get_mut(key) {
+                        let property_path = format!("{path}/{key}");
+                        check_enum_values_at(
+                            root,
+                            property_schema,
+                            property_value,
+                            &property_path,
+                            fuzzy_match,
+                            violations,
+                        );
+                    }
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(items_schema) = schema.get("items").filter(|items| items.is_object()) {
+                for (index, item) in items.iter_mut().enumerate() {
+                    let item_path = format!("{path}/{index}");
+                    check_enum_values_at(
+                        root,
+                        items_schema,
+                        item,
+                        &item_path,
+                        fuzzy_match,
+                        violations,
+                    );
+                }
+            }
+        }
+        _ => {}
+    }
+
+    //Optional fields are wrapped in `anyOf` with `null` and documented references in `allOf` with a single schema
+    //Other combinations are ambiguous and skipped
+    if value.is_null() {
+        return;
+    }
+    for combinator in ["allOf", "anyOf", "oneOf"] {
+        let Some(schemas) = schema.get(combinator).and_then(Value::as_array) else {
+            continue;
+        };
+        let non_null: Vec<&Value> = schemas
+            .iter()
+            .filter(|schema| schema.get("type").and_then(Value::as_str) != Some("null"))
+            .collect();
+        if let [single_schema] = non_null.as_slice() {
+            check_enum_values_at(root, single_schema, value, path, fuzzy_match, violations);
+        }
+    }
+}
+
+// Returns the allowed values of an enum schema. Enums with documented variants are generated as a `oneOf` of single value enums
+fn get_enum_variants(schema: &Value) -> Option<Vec<Value>> {
+    if let Some(variants) = schema.get("enum").and_then(Value::as_array) {
+        return Some(variants.clone());
+    }
+    let branches = schema.get("oneOf").and_then(Value::as_array)?;
+    branches
+        .iter()
+        .map(|branch| {
+            branch
+                .get("enum")
+                .and_then(Value::as_array)
+                .cloned()
+                .or_else(|| branch.get("const").map(|value| vec![value.clone()]))
+        })
+        .collect::<Option<Vec<Vec<Value>>>>()
+        .map(|variants| variants.into_iter().flatten().collect())
+}
+
+// Returns the array from a response that wraps it in an object with a single property (e.g. `{"items": [...]}`)
+pub(crate) fn unwrap_array_response(json_response: &str) -> Option<String> {
+    let response: Value = serde_json::from_str(json_response).ok()?;
+    let obj = response.as_object()?;
+    if obj.len() != 1 {
+        return None;
+    }
+    obj.values()
+        .next()
+        .filter(|value| value.is_array())
+        .map(Value::to_string)
+}
+
 // The Schemars crate uses `Bool(true)` for `Value`, which essentially means "accept anything". We need to replace it with actual `Object` type
 fn fix_value_schema(schema: &mut schemars::schema::RootSchema) {
     if let Some(object) = &mut schema.schema.object {
@@ -81,15 +974,444 @@ fn fix_value_schema(schema: &mut schemars::schema::RootSchema) {
     }
 }
 
+///
+/// This function generates a Json schema for the provided type that meets the requirements of strict structured outputs (e.g. OpenAI `strict: true`):
+/// - `$ref` definitions are inlined (recursive types keep their references)
+/// - every object has `additionalProperties: false`
+/// - all properties are required, and optional ones are made nullable instead
+/// - `oneOf` is replaced with `anyOf`
+///
+/// Strict mode does not allow free-form objects, so `serde_json::Value` and map fields can only be returned as empty objects.
+///
+pub fn strict_schema_for<T: JsonSchema>() -> Value {
+    let schema = schema_for!(T);
+    let mut schema_json = serde_json::to_value(&schema).unwrap_or_default();
+
+    let mut root_name = None;
+    let definitions = match schema_json.as_object_mut() {
+        Some(obj) => {
+            // Remove '$schema' and 'title' elements that are added by schema_for macro but are not needed
+            obj.remove("$schema");
+            root_name = obj
+                .remove("title")
+                .and_then(|title| title.as_str().map(str::to_string));
+            obj.remove("definitions")
+                .and_then(|definitions| match definitions {
+                    Value::Object(definitions) => Some(definitions),
+                    _ => None,
+                })
+                .unwrap_or_default()
+        }
+        None => Map::new(),
+    };
+
+    // Inline all definitions and collect the ones that can't be inlined because they are recursive
+    // A recursive root type is also listed in definitions and references to it should not be inlined
+    let mut stack: Vec<String> = root_name
+        .filter(|name| definitions.contains_key(name))
+        .into_iter()
+        .collect();
+    let mut recursive = BTreeSet::new();
+    let mut schema_json = inline_schema_refs(schema_json, &definitions, &mut stack, &mut recursive);
+    keep_recursive_definitions(&mut schema_json, &definitions, &mut recursive);
+
+    make_schema_strict(&mut schema_json);
+    schema_json
+}
+
+// Inlines `$ref` pointers to `#/definitions/...` so the schema is self-contained, for providers that don't resolve references
+// Definitions of recursive types can't be inlined and are kept. Keys are sorted so the output is deterministic
+pub(crate) fn inline_schema_definitions(json_schema: &Value) -> Value {
+    let mut schema_json = json_schema.clone();
+    let definitions = schema_json
+        .as_object_mut()
+        .and_then(|obj| obj.remove("definitions"))
+        .and_then(|definitions| match definitions {
+            Value::Object(definitions) => Some(definitions),
+            _ => None,
+        })
+        .unwrap_or_default();
+
+    let mut recursive = BTreeSet::new();
+    let mut schema_json =
+        inline_schema_refs(schema_json, &definitions, &mut Vec::new(), &mut recursive);
+    keep_recursive_definitions(&mut schema_json, &definitions, &mut recursive);
+    schema_json
+}
+
+// Adds the definitions of recursive types, with their other references inlined, back to the root of the schema
+fn keep_recursive_definitions(
+    schema_json: &mut Value,
+    definitions: &Map<String, Value>,
+    recursive: &mut BTreeSet<String>,
+) {
+    let mut kept_definitions = Map::new();
+    while let Some(name) = recursive
+        .iter()
+        .find(|name| !kept_definitions.contains_key(*name))
+        .cloned()
+    {
+        let definition = definitions.get(&name).cloned().unwrap_or(json!({}));
+        let definition =
+            inline_schema_refs(definition, definitions, &mut vec![name.clone()], recursive);
+        kept_definitions.insert(name, definition);
+    }
+    if !kept_definitions.is_empty() {
+        if let Some(obj) = schema_json.as_object_mut() {
+            obj.insert("definitions".to_string(), Value::Object(kept_definitions));
+        }
+    }
+}
+
+// Replaces `$ref` pointers to definitions with the referenced schema. References that would create a cycle are left in place
+fn inline_schema_refs(
+    schema: Value,
+    definitions: &Map<String, Value>,
+    stack: &mut Vec<String>,
+    recursive: &mut BTreeSet<String>,
+) -> Value {
+    match schema {
+        Value::Object(mut obj) => {
+            let ref_name = obj
+                .get("$ref")
+                .and_then(Value::as_str)
+                .and_then(|reference| reference.strip_prefix("#/definitions/"))
+                .map(str::to_string);
+
+            if let Some(name) = ref_name {
+                if stack.contains(&name) {
+                    recursive.insert(name);
+                    return Value::Object(obj);
+                }
+                if let Some(definition) = definitions.get(&name) {
+                    stack.push(name);
+                    let inlined =
+                        inline_schema_refs(definition.clone(), definitions, stack, recursive);
+                    stack.pop();
+
+                    // Keep any sibling keywords of the reference, e.g. description
+                    obj.remove("$ref");
+                    return match inlined {
+                        Value::Object(mut inlined) => {
+                            for (key, value) in obj {
+                                let value =
+                                    inline_schema_refs(value, definitions, stack, recursive);
+                                inlined.insert(key, value);
+                            }
+                            Value::Object(inlined)
+                        }
+                        other => other,
+                    };
+                }
+            }
+
+            Value::Object(
+                obj.into_iter()
+                    .map(|(key, value)| {
+                        (
+                            key,
+                            inline_schema_refs(value, definitions, stack, recursive),
+                        )
+                    })
+                    .collect(),
+            )
+        }
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(|item| inline_schema_refs(item, definitions, stack, recursive))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+// Recursively applies strict mode requirements to the schema
+fn make_schema_strict(schema: &mut Value) {
+    // `serde_json::Value` is represented with `true` ("accept anything") which needs to be replaced with an object
+    if *schema == Value::Bool(true) {
+        *schema = json!({ "type": "object" });
+    }
+    let obj = if let Some(obj) = schema.as_object_mut() {
+        obj
+    } else {
+        return;
+    };
+
+    // Strict mode doesn't support `oneOf`. For schemas generated from Rust types `anyOf` is equivalent
+    if let Some(one_of) = obj.remove("oneOf") {
+        obj.insert("anyOf".to_string(), one_of);
+    }
+
+    // A single-element `allOf` (used by schemars for documented references) is merged into the parent
+    let single_all_of = matches!(obj.get("allOf"), Some(Value::Array(items)) if items.len() == 1);
+    if single_all_of {
+        if let Some(Value::Array(mut items)) = obj.remove("allOf") {
+            if let Some(Value::Object(inner)) = items.pop() {
+                for (key, value) in inner {
+                    obj.entry(key).or_insert(value);
+                }
+            }
+        }
+    }
+
+    for key in ["anyOf", "allOf"] {
+        if let Some(Value::Array(items)) = obj.get_mut(key) {
+            items.iter_mut().for_each(make_schema_strict);
+        }
+    }
+    match obj.get_mut("items") {
+        Some(Value::Array(items)) => items.iter_mut().for_each(make_schema_strict),
+        Some(items) => make_schema_strict(items),
+        None => (),
+    }
+    if let Some(Value::Object(definitions)) = obj.get_mut("definitions") {
+        definitions.values_mut().for_each(make_schema_strict);
+    }
+
+    let is_object = match obj.get("type") {
+        Some(Value::String(schema_type)) => schema_type == "object",
+        Some(Value::Array(types)) => types.iter().any(|schema_type| schema_type == "object"),
+        _ => obj.contains_key("properties"),
+    };
+    if !is_object {
+        return;
+    }
+
+    let required: BTreeSet<String> = obj
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|required| {
+            required
+                .iter()
+                .filter_map(|name| name.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut property_names = Vec::new();
+    if let Some(Value::Object(properties)) = obj.get_mut("properties") {
+        for (name, property) in properties.iter_mut() {
+            make_schema_strict(property);
+            // Strict mode requires all properties so optional ones are expressed as nullable
+            if !required.contains(name) {
+                make_schema_nullable(property);
+            }
+            property_names.push(json!(name));
+        }
+    } else {
+        obj.insert("properties".to_string(), json!({}));
+    }
+
+    obj.insert("required".to_string(), Value::Array(property_names));
+    obj.insert("additionalProperties".to_string(), Value::Bool(false));
+}
+
+// Allows `null` as a valid value of the schema
+fn make_schema_nullable(schema: &mut Value) {
+    let null_schema = json!({ "type": "null" });
+    if let Some(obj) = schema.as_object_mut() {
+        if let Some(schema_type) = obj.get_mut("type") {
+            match schema_type {
+                Value::String(single_type) if single_type != "null" => {
+                    *schema_type = json!([single_type, "null"]);
+                }
+                Value::Array(types) if !types.contains(&json!("null")) => {
+                    types.push(json!("null"));
+                }
+                _ => (),
+            }
+            // Enum values need to include null as well
+            if let Some(Value::Array(values)) = obj.get_mut("enum") {
+                if !values.contains(&Value::Null) {
+                    values.push(Value::Null);
+                }
+            }
+            return;
+        }
+        if let Some(Value::Array(variants)) = obj.get_mut("anyOf") {
+            if !variants.contains(&null_schema) {
+                variants.push(null_schema);
+            }
+            return;
+        }
+    }
+    let schema_value = schema.take();
+    *schema = json!({ "anyOf": [schema_value, null_schema] });
+}
+
+// Converts a Json schema into the OpenAPI subset accepted by Gemini `responseSchema`
+// Gemini docs: https://ai.google.dev/api/generate-content#generationconfig
+// Returns `None` if the schema can't be expressed, e.g. recursive types or free-form objects (`serde_json::Value`)
+pub(crate) fn gemini_response_schema(json_schema: &Value) -> Option<Value> {
+    let mut schema_json = json_schema.clone();
+    let definitions = schema_json
+        .as_object_mut()
+        .and_then(|obj| obj.remove("definitions"))
+        .and_then(|definitions| match definitions {
+            Value::Object(definitions) => Some(definitions),
+            _ => None,
+        })
+        .unwrap_or_default();
+
+    let mut recursive = BTreeSet::new();
+    let schema_json =
+        inline_schema_refs(schema_json, &definitions, &mut Vec::new(), &mut recursive);
+    if !recursive.is_empty() {
+        return None;
+    }
+    to_gemini_schema(&schema_json)
+}
+
+// Recursively keeps only the keywords supported by Gemini. Nullable types and `anyOf` with null are expressed with `nullable`
+fn to_gemini_schema(schema: &Value) -> Option<Value> {
+    let obj = schema.as_object()?;
+
+    // A single-element `allOf` (used by schemars for documented references) is merged into the parent
+    if let Some(Value::Array(items)) = obj.get("allOf") {
+        let mut merged = match items.as_slice() {
+            [Value::Object(inner)] => inner.clone(),
+            _ => return None,
+        };
+        for (key, value) in obj.iter().filter(|(key, _)| *key != "allOf") {
+            merged.insert(key.to_string(), value.clone());
+        }
+        return to_gemini_schema(&Value::Object(merged));
+    }
+
+    let null_schema = json!({ "type": "null" });
+    if let Some(Value::Array(variants)) = obj.get("anyOf").or_else(|| obj.get("oneOf")) {
+        let nullable = variants.contains(&null_schema);
+        let variants: Vec<Value> = variants
+            .iter()
+            .filter(|variant| **variant != null_schema)
+            .map(to_gemini_schema)
+            .collect::<Option<_>>()?;
+
+        let mut gemini_schema = match variants.as_slice() {
+            [variant] => variant.clone(),
+            // Unit enum variants with descriptions are generated as separate string enums
+            _ if variants
+                .iter()
+                .all(|variant| variant.get("format") == Some(&json!("enum"))) =>
+            {
+                let values: Vec<Value> = variants
+                    .iter()
+                    .filter_map(|variant| variant.get("enum").and_then(Value::as_array))
+                    .flatten()
+                    .cloned()
+                    .collect();
+                json!({ "type": "string", "format": "enum", "enum": values })
+            }
+            _ => return None,
+        };
+        if nullable {
+            gemini_schema["nullable"] = json!(true);
+        }
+        if let Some(description) = obj.get("description") {
+            gemini_schema["description"] = description.clone();
+        }
+        return Some(gemini_schema);
+    }
+
+    let mut gemini_schema = Map::new();
+    let schema_type = match obj.get("type") {
+        Some(Value::String(schema_type)) => schema_type.to_string(),
+        Some(Value::Array(types)) => {
+            let non_null: Vec<&str> = types
+                .iter()
+                .filter_map(Value::as_str)
+                .filter(|schema_type| *schema_type != "null")
+                .collect();
+            if non_null.len() != 1 {
+                return None;
+            }
+            if non_null.len() < types.len() {
+                gemini_schema.insert("nullable".to_string(), json!(true));
+            }
+            non_null[0].to_string()
+        }
+        // Constant values (e.g. enum tags) are expressed as single-value enums
+        None if obj.get("const").map(Value::is_string).unwrap_or(false) => "string".to_string(),
+        None if obj.contains_key("properties") => "object".to_string(),
+        _ => return None,
+    };
+    gemini_schema.insert("type".to_string(), json!(schema_type));
+
+    if let Some(description) = obj.get("description") {
+        gemini_schema.insert("description".to_string(), description.clone());
+    }
+
+    match schema_type.as_str() {
+        "string" => {
+            let values = match (obj.get("enum"), obj.get("const")) {
+                (Some(Value::Array(values)), _) => Some(
+                    values
+                        .iter()
+                        .filter(|value| value.is_string())
+                        .cloned()
+                        .collect::<Vec<_>>(),
+                ),
+                (_, Some(value)) => Some(vec![value.clone()]),
+                _ => None,
+            };
+            if let Some(values) = values {
+                gemini_schema.insert("format".to_string(), json!("enum"));
+                gemini_schema.insert("enum".to_string(), json!(values));
+            } else if obj.get("format") == Some(&json!("date-time")) {
+                gemini_schema.insert("format".to_string(), json!("date-time"));
+            }
+        }
+        "integer" | "number" => {
+            if let Some(format) = obj.get("format").filter(|format| {
+                ["int32", "int64", "float", "double"].contains(&format.as_str().unwrap_or_default())
+            }) {
+                gemini_schema.insert("format".to_string(), format.clone());
+            }
+        }
+        "array" => {
+            let items = match obj.get("items") {
+                Some(items) if items.is_object() => to_gemini_schema(items)?,
+                _ => return None,
+            };
+            gemini_schema.insert("items".to_string(), items);
+            for key in ["minItems", "maxItems"] {
+                if let Some(value) = obj.get(key) {
+                    gemini_schema.insert(key.to_string(), value.clone());
+                }
+            }
+        }
+        "object" => {
+            // Gemini requires objects to define their properties so free-form objects are not supported
+            let properties = match obj.get("properties") {
+                Some(Value::Object(properties)) if !properties.is_empty() => properties,
+                _ => return None,
+            };
+            let properties: Map<String, Value> = properties
+                .iter()
+                .map(|(name, property)| Some((name.to_string(), to_gemini_schema(property)?)))
+                .collect::<Option<_>>()?;
+            gemini_schema.insert("properties".to_string(), Value::Object(properties));
+            if let Some(required) = obj.get("required") {
+                gemini_schema.insert("required".to_string(), required.clone());
+            }
+        }
+        "boolean" => (),
+        _ => return None,
+    }
+
+    Some(Value::Object(gemini_schema))
+}
+
 //Used internally to pick a number from range based on its % representation
-pub(crate) fn map_to_range(min: u32, max: u32, target: u32) -> f32 {
+pub(crate) fn map_to_range_f32(min: f32, max: f32, target: u32) -> f32 {
     // Cap the target to the percentage range [0, 100]
     let capped_target = target.min(100);
 
     // Calculate the target value in the range [min, max]
-    let range = max as f32 - min as f32;
+    let range = max - min;
     let percentage = capped_target as f32 / 100.0;
-    min as f32 + (range * percentage)
+    min + (range * percentage)
 }
 
 #[cfg(test)]
@@ -97,10 +1419,22 @@ mod tests {
     use schemars::schema::{InstanceType, ObjectValidation, RootSchema, Schema, SchemaObject};
     use schemars::JsonSchema;
     use serde::{Deserialize, Serialize};
-    use serde_json::Value;
+    use serde_json::{json, Value};
 
+    use reqwest::{header::HeaderMap, StatusCode};
+    use tiktoken_rs::{cl100k_base, o200k_base};
+
+    use crate::constants::{ANTHROPIC_RATE_LIMIT_HEADERS, OPENAI_RATE_LIMIT_HEADERS};
+    use crate::domain::AllmsApiError;
     use crate::llm_models::OpenAIModels;
-    use crate::utils::{fix_value_schema, get_tokenizer, get_type_schema, map_to_range};
+    use crate::utils::{
+        check_enum_values, chunk_by_tokens, fix_value_schema, gemini_response_schema,
+        get_api_error, get_api_error_message, get_event_data, get_file_stream_part,
+        get_live_rate_limit, get_mime_type, get_tokenizer, get_type_schema, get_valid_json,
+        inline_schema_definitions, map_to_range_f32, push_event_chunk, sanitize_json_response,
+        schema_for_type, strict_schema_for, unwrap_array_response, wrap_array_schema,
+        PartialJsonParser,
+    };
 
     #[derive(JsonSchema, Serialize, Deserialize)]
     struct SimpleStruct {
@@ -119,7 +1453,73 @@ mod tests {
         optional_field: Option<String>,
     }
 
+    #[derive(JsonSchema, Serialize, Deserialize)]
+    enum Status {
+        Active,
+        Inactive,
+    }
+
+    #[derive(JsonSchema, Serialize, Deserialize)]
+    struct StrictStruct {
+        /// Nested struct with a description
+        info: SimpleStruct,
+        nested: NestedStruct,
+        optional_info: Option<SimpleStruct>,
+        status: Status,
+        optional_status: Option<Status>,
+        optional_number: Option<i32>,
+        data: serde_json::Value,
+    }
+
+    #[derive(JsonSchema, Serialize, Deserialize)]
+    struct GeminiStruct {
+        /// Nested struct with a description
+        info: SimpleStruct,
+        nested: NestedStruct,
+        optional_info: Option<SimpleStruct>,
+        status: Status,
+    }
+
+    #[derive(JsonSchema, Serialize, Deserialize)]
+    struct RecursiveStruct {
+        name: String,
+        children: Vec<RecursiveStruct>,
+    }
+
     // Tokenizer tests
+    #[test]
+    fn it_computes_o200k_tokenization() {
+        let text = "Tokenizers for GPT-4o count tokens differently: 今日は良い天気ですね。";
+        let o200k_tokens = o200k_base().unwrap().encode_ordinary(text);
+        let cl100k_tokens = cl100k_base().unwrap().encode_ordinary(text);
+        assert_ne!(o200k_tokens.len(), cl100k_tokens.len());
+
+        for model in [
+            OpenAIModels::Gpt4o,
+            OpenAIModels::Gpt4oMini,
+            OpenAIModels::O1Mini,
+            OpenAIModels::Custom {
+                name: "gpt-4.1-mini".to_string(),
+            },
+            OpenAIModels::Custom {
+                name: "gpt-5".to_string(),
+            },
+            OpenAIModels::Custom {
+                name: "o3".to_string(),
+            },
+            OpenAIModels::Custom {
+                name: "o4-mini".to_string(),
+            },
+        ] {
+            let bpe = get_tokenizer(&model).unwrap();
+            assert_eq!(bpe.encode_ordinary(text), o200k_tokens, "{:?}", model);
+        }
+
+        // Older models keep using cl100k_base
+        let bpe = get_tokenizer(&OpenAIModels::Gpt4Turbo).unwrap();
+        assert_eq!(bpe.encode_ordinary(text), cl100k_tokens);
+    }
+
     #[test]
     fn it_computes_gpt3_5_tokenization() {
         let bpe = get_tokenizer(&OpenAIModels::Gpt4_32k).unwrap();
@@ -271,6 +1671,164 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_get_event_data() {
+        assert_eq!(
+            get_event_data(r#"data: {"type": "ping"}"#),
+            Some(r#"{"type": "ping"}"#)
+        );
+        assert_eq!(get_event_data("data:[DONE]"), None);
+        assert_eq!(get_event_data("event: message_start"), None);
+        assert_eq!(get_event_data(""), None);
+    }
+
+    #[test]
+    fn test_push_event_chunk() {
+        let stream = "data: {\"text\": \"caf\u{e9} \u{1f600}\"}\n\ndata: [DONE]\n".as_bytes();
+        //Split inside the two-byte `\u{e9}` and the four-byte `\u{1f600}`
+        let first_split = stream.iter().position(|byte| *byte == 0xC3).unwrap() + 1;
+        let second_split = stream.iter().position(|byte| *byte == 0xF0).unwrap() + 2;
+
+        let mut events = Vec::new();
+        let mut buffer = Vec::new();
+        for chunk in [
+            &stream[..first_split],
+            &stream[first_split..second_split],
+            &stream[second_split..],
+        ] {
+            push_event_chunk(&mut buffer, chunk, &mut |data| {
+                events.push(data.to_string())
+            });
+        }
+        assert_eq!(events, vec!["{\"text\": \"caf\u{e9} \u{1f600}\"}"]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_partial_json_parser() {
+        let mut parser = PartialJsonParser::default();
+        assert_eq!(parser.push("```json\n{\"title\": \"Dune"), None);
+        assert_eq!(
+            parser.push(", part 1\", \"ta"),
+            Some(json!({"title": "Dune, part 1"}))
+        );
+        assert_eq!(parser.push("gs\": [\"sci-fi\", \"{\\\"classic\"]"), None);
+        assert_eq!(
+            parser.push(", \"rating\": {\"score\": 5, \"max\": 5}}\n```"),
+            Some(json!({
+                "title": "Dune, part 1",
+                "tags": ["sci-fi", "{\"classic"],
+                "rating": {"score": 5, "max": 5}
+            }))
+        );
+
+        // Arrays and empty objects are not reported
+        assert_eq!(PartialJsonParser::default().push("[1, 2]"), None);
+        assert_eq!(PartialJsonParser::default().push("{}"), None);
+    }
+
+    #[test]
+    fn test_schema_for_type_matches_prompt_schema() {
+        let schema = schema_for_type::<SimpleStruct>().unwrap();
+        assert_eq!(schema, get_type_schema::<SimpleStruct>().unwrap());
+        assert!(!schema.contains("$schema"));
+    }
+
+    // Strict schema generation
+    #[test]
+    fn test_strict_schema_for_inlines_definitions() {
+        let schema = strict_schema_for::<StrictStruct>();
+        let schema_string = serde_json::to_string(&schema).unwrap();
+
+        assert!(
+            !schema_string.contains("$ref"),
+            "Schema should not contain '$ref'"
+        );
+        assert!(schema.get("definitions").is_none());
+        assert!(schema.get("$schema").is_none());
+        assert!(schema.get("title").is_none());
+        assert!(!schema_string.contains("oneOf"));
+        assert!(!schema_string.contains("allOf"));
+
+        // Nested struct is inlined and keeps its description
+        let info = &schema["properties"]["info"];
+        assert_eq!(info["type"], "object");
+        assert_eq!(info["description"], "Nested struct with a description");
+        assert_eq!(info["additionalProperties"], false);
+        assert_eq!(info["required"], serde_json::json!(["id", "name"]));
+
+        // Deeper nesting is inlined as well
+        let nested_info = &schema["properties"]["nested"]["properties"]["info"];
+        assert_eq!(nested_info["additionalProperties"], false);
+    }
+
+    #[test]
+    fn test_strict_schema_for_requires_all_properties() {
+        let schema = strict_schema_for::<StrictStruct>();
+
+        assert_eq!(schema["additionalProperties"], false);
+        let properties = schema["properties"].as_object().unwrap();
+        let required = schema["required"].as_array().unwrap();
+        assert_eq!(properties.len(), required.len());
+        for name in properties.keys() {
+            assert!(required.contains(&serde_json::json!(name)));
+        }
+
+        // Optional fields are nullable
+        assert_eq!(
+            schema["properties"]["optional_number"]["type"],
+            serde_json::json!(["integer", "null"])
+        );
+        let optional_info = schema["properties"]["optional_info"]["anyOf"]
+            .as_array()
+            .unwrap();
+        assert!(optional_info.contains(&serde_json::json!({"type": "null"})));
+        assert_eq!(optional_info[0]["additionalProperties"], false);
+
+        let nested_optional = &schema["properties"]["nested"]["properties"]["optional_field"];
+        assert_eq!(
+            nested_optional["type"],
+            serde_json::json!(["string", "null"])
+        );
+        assert_eq!(
+            schema["properties"]["nested"]["required"],
+            serde_json::json!(["info", "optional_field"])
+        );
+    }
+
+    #[test]
+    fn test_strict_schema_for_enums_and_values() {
+        let schema = strict_schema_for::<StrictStruct>();
+
+        let status = &schema["properties"]["status"];
+        assert_eq!(status["type"], "string");
+        assert_eq!(status["enum"], serde_json::json!(["Active", "Inactive"]));
+
+        let optional_status = schema["properties"]["optional_status"]["anyOf"]
+            .as_array()
+            .unwrap();
+        assert!(optional_status.contains(&serde_json::json!({"type": "null"})));
+
+        // `serde_json::Value` is represented as an object
+        let data = &schema["properties"]["data"];
+        assert_eq!(data["type"], "object");
+        assert_eq!(data["additionalProperties"], false);
+    }
+
+    #[test]
+    fn test_strict_schema_for_recursive_struct() {
+        let schema = strict_schema_for::<RecursiveStruct>();
+
+        // Recursive reference is kept together with its definition
+        assert_eq!(
+            schema["properties"]["children"]["items"]["$ref"],
+            "#/definitions/RecursiveStruct"
+        );
+        let definition = &schema["definitions"]["RecursiveStruct"];
+        assert_eq!(definition["additionalProperties"], false);
+        assert_eq!(schema["additionalProperties"], false);
+    }
+
     // Fixing how Value is represented in schema
     #[test]
     fn test_fix_value_schema_replaces_bool_true() {
@@ -382,42 +1940,490 @@ mod tests {
         assert!(schema.schema.object.is_none());
     }
 
+    // API errors
+    #[test]
+    fn test_get_api_error_keeps_rate_limit_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", "2".parse().unwrap());
+        headers.insert("x-ratelimit-remaining-requests", "0".parse().unwrap());
+        headers.insert(
+            "anthropic-ratelimit-tokens-remaining",
+            "100".parse().unwrap(),
+        );
+        headers.insert("content-type", "application/json".parse().unwrap());
+
+        let error = get_api_error(
+            "llm_models::openai",
+            StatusCode::TOO_MANY_REQUESTS,
+            &headers,
+            "Rate limit reached".to_string(),
+        );
+        let api_error = error.downcast_ref::<AllmsApiError>().unwrap();
+
+        assert_eq!(api_error.status_code, 429);
+        assert!(api_error.is_rate_limited());
+        assert_eq!(api_error.headers.len(), 3);
+        assert!(!api_error.headers.contains_key("content-type"));
+        assert_eq!(
+            api_error.retry_after(),
+            Some(std::time::Duration::from_secs(2))
+        );
+        assert_eq!(api_error.error_detail, "Rate limit reached");
+        assert!(api_error.message.is_none());
+    }
+
+    #[test]
+    fn test_check_enum_values() {
+        //Enums with documented variants are generated as `oneOf` single value enums
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "status": {"$ref": "#/definitions/Status"},
+                "tags": {"type": "array", "items": {"type": "string"}}
+            },
+            "definitions": {
+                "Status": {"oneOf": [
+                    {"description": "Open", "type": "string", "enum": ["Open"]},
+                    {"description": "Closed", "type": "string", "enum": ["Closed"]}
+                ]}
+            }
+        });
+
+        let mut value = json!({"status": "closed ", "tags": ["closed"]});
+        assert!(check_enum_values(&schema, &mut value, true).is_empty());
+        assert_eq!(value, json!({"status": "Closed", "tags": ["closed"]}));
+
+        let mut value = json!({"status": "Archived"});
+        assert_eq!(
+            check_enum_values(&schema, &mut value, true),
+            vec![r#"/status: "Archived" is not one of ["Open","Closed"]"#.to_string()]
+        );
+
+        //Ambiguous variants are not guessed
+        let schema = json!({"type": "string", "enum": ["on", "ON"]});
+        let mut value = json!("On");
+        assert_eq!(check_enum_values(&schema, &mut value, true).len(), 1);
+    }
+
+    #[test]
+    fn test_get_api_error_message() {
+        let openai = r#"{"error": {"message": "Incorrect API key provided", "type": "invalid_request_error", "param": null, "code": "invalid_api_key"}}"#;
+        assert_eq!(
+            get_api_error_message(openai),
+            Some("Incorrect API key provided".to_string())
+        );
+
+        let anthropic = r#"{"type": "error", "error": {"type": "authentication_error", "message": "invalid x-api-key"}}"#;
+        assert_eq!(
+            get_api_error_message(anthropic),
+            Some("invalid x-api-key".to_string())
+        );
+
+        let vertex = r#"[{"error": {"code": 403, "message": "Permission denied", "status": "PERMISSION_DENIED"}}]"#;
+        assert_eq!(
+            get_api_error_message(vertex),
+            Some("Permission denied".to_string())
+        );
+
+        let mistral =
+            r#"{"object": "error", "message": "Unauthorized", "type": "invalid_request_error"}"#;
+        assert_eq!(
+            get_api_error_message(mistral),
+            Some("Unauthorized".to_string())
+        );
+
+        let ollama = r#"{"error": "model 'llama9' not found"}"#;
+        assert_eq!(
+            get_api_error_message(ollama),
+            Some("model 'llama9' not found".to_string())
+        );
+
+        assert!(get_api_error_message(r#"{"choices": []}"#).is_none());
+        assert!(get_api_error_message("Bad Gateway").is_none());
+    }
+
+    #[test]
+    fn test_get_valid_json() {
+        let schema = r#"{"type": "object", "properties": {"name": {"type": "string"}}, "required": ["name"]}"#;
+        assert_eq!(
+            get_valid_json("utils", schema, r#"{"name": "Ada"}"#).unwrap(),
+            json!({"name": "Ada"})
+        );
+        assert!(get_valid_json("utils", schema, r#"{"age": 36}"#).is_err());
+        assert!(get_valid_json("utils", schema, "not json").is_err());
+        assert!(get_valid_json("utils", "not a schema", r#"{"name": "Ada"}"#).is_err());
+    }
+
+    #[test]
+    fn test_get_live_rate_limit() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-limit-tokens", "150000".parse().unwrap());
+        headers.insert("x-ratelimit-remaining-tokens", "149984".parse().unwrap());
+        headers.insert("x-ratelimit-reset-tokens", "6ms".parse().unwrap());
+        headers.insert("x-ratelimit-remaining-requests", "59".parse().unwrap());
+
+        let live_rate_limit = get_live_rate_limit(&headers, &OPENAI_RATE_LIMIT_HEADERS).unwrap();
+        assert_eq!(live_rate_limit.limit_tokens, Some(150000));
+        assert_eq!(live_rate_limit.remaining_tokens, Some(149984));
+        assert_eq!(live_rate_limit.reset_tokens, Some("6ms".to_string()));
+        assert_eq!(live_rate_limit.remaining_requests, Some(59));
+        assert_eq!(live_rate_limit.limit_requests, None);
+
+        // Headers of other providers are ignored
+        assert!(get_live_rate_limit(&headers, &ANTHROPIC_RATE_LIMIT_HEADERS).is_none());
+    }
+
     // Mapping % target to temperature range
     #[test]
     fn test_target_at_min() {
-        assert_eq!(map_to_range(0, 100, 0), 0.0);
-        assert_eq!(map_to_range(10, 20, 0), 10.0);
+        assert_eq!(map_to_range_f32(0.0, 100.0, 0), 0.0);
+        assert_eq!(map_to_range_f32(10.0, 20.0, 0), 10.0);
     }
 
     #[test]
     fn test_target_at_max() {
-        assert_eq!(map_to_range(0, 100, 100), 100.0);
-        assert_eq!(map_to_range(10, 20, 100), 20.0);
+        assert_eq!(map_to_range_f32(0.0, 100.0, 100), 100.0);
+        assert_eq!(map_to_range_f32(10.0, 20.0, 100), 20.0);
     }
 
     #[test]
     fn test_target_in_middle() {
-        assert_eq!(map_to_range(0, 100, 50), 50.0);
-        assert_eq!(map_to_range(10, 20, 50), 15.0);
-        assert_eq!(map_to_range(0, 1, 50), 0.5);
+        assert_eq!(map_to_range_f32(0.0, 100.0, 50), 50.0);
+        assert_eq!(map_to_range_f32(10.0, 20.0, 50), 15.0);
+        assert_eq!(map_to_range_f32(0.0, 1.0, 50), 0.5);
     }
 
     #[test]
     fn test_target_out_of_bounds() {
-        assert_eq!(map_to_range(0, 100, 3000), 100.0); // Cap to 100
-        assert_eq!(map_to_range(0, 100, 200), 100.0); // Cap to 100
-        assert_eq!(map_to_range(10, 20, 200), 20.0); // Cap to 100
+        assert_eq!(map_to_range_f32(0.0, 100.0, 3000), 100.0); // Cap to 100
+        assert_eq!(map_to_range_f32(0.0, 100.0, 200), 100.0); // Cap to 100
+        assert_eq!(map_to_range_f32(10.0, 20.0, 200), 20.0); // Cap to 100
     }
 
     #[test]
     fn test_zero_range() {
-        assert_eq!(map_to_range(10, 10, 50), 10.0); // Always return min if min == max
-        assert_eq!(map_to_range(5, 5, 100), 5.0); // Even at max target
+        assert_eq!(map_to_range_f32(10.0, 10.0, 50), 10.0); // Always return min if min == max
+        assert_eq!(map_to_range_f32(5.0, 5.0, 100), 5.0); // Even at max target
+    }
+
+    #[test]
+    fn test_fractional_range() {
+        assert_eq!(map_to_range_f32(0.0, 1.5, 50), 0.75);
+        assert_eq!(map_to_range_f32(0.5, 1.0, 100), 1.0);
     }
 
     #[test]
     fn test_negative_behavior_not_applicable() {
         // Not applicable for unsigned inputs but could test edge cases:
-        assert_eq!(map_to_range(0, 100, 0), 0.0);
+        assert_eq!(map_to_range_f32(0.0, 100.0, 0), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_get_file_stream_part() {
+        let path = std::env::temp_dir().join("allms_test_get_file_stream_part.txt");
+        std::fs::write(&path, "Hello from disk").unwrap();
+        assert!(get_file_stream_part(&path).await.is_ok());
+        std::fs::remove_file(&path).unwrap();
+
+        //Missing files and unsupported extensions are rejected before uploading
+        assert!(get_file_stream_part(&path).await.is_err());
+        let unsupported_path = std::env::temp_dir().join("allms_test.unsupported");
+        assert!(get_file_stream_part(&unsupported_path).await.is_err());
+    }
+
+    #[test]
+    fn test_get_mime_type() {
+        assert_eq!(get_mime_type("report.pdf").unwrap(), "application/pdf");
+        assert_eq!(
+            get_mime_type("requests.jsonl").unwrap(),
+            "application/jsonl"
+        );
+        assert_eq!(get_mime_type("photo.webp").unwrap(), "image/webp");
+        assert_eq!(get_mime_type("meeting.mp3").unwrap(), "audio/mpeg");
+        assert!(get_mime_type("archive.rar").is_err());
+        assert!(get_mime_type("no_extension").is_err());
+    }
+
+    #[test]
+    fn test_gemini_response_schema() {
+        let schema: Value =
+            serde_json::from_str(&get_type_schema::<GeminiStruct>().unwrap()).unwrap();
+        let gemini_schema = gemini_response_schema(&schema).unwrap();
+        let gemini_schema_string = gemini_schema.to_string();
+
+        // References are inlined and unsupported keywords are stripped
+        assert!(!gemini_schema_string.contains("$ref"));
+        assert!(!gemini_schema_string.contains("definitions"));
+        assert!(!gemini_schema_string.contains("additionalProperties"));
+        assert_eq!(
+            gemini_schema["properties"]["info"]["properties"]["id"],
+            serde_json::json!({"type": "integer", "format": "int32"})
+        );
+        assert_eq!(
+            gemini_schema["properties"]["info"]["description"],
+            "Nested struct with a description"
+        );
+        // Optional fields are nullable
+        assert_eq!(
+            gemini_schema["properties"]["nested"]["properties"]["optional_field"],
+            serde_json::json!({"type": "string", "nullable": true})
+        );
+        assert_eq!(
+            gemini_schema["properties"]["optional_info"]["nullable"],
+            true
+        );
+        // Enums use the enum format
+        assert_eq!(
+            gemini_schema["properties"]["status"],
+            serde_json::json!({"type": "string", "format": "enum", "enum": ["Active", "Inactive"]})
+        );
+    }
+
+    #[test]
+    fn test_gemini_response_schema_unsupported() {
+        // Unsigned formats are not supported and are removed
+        let schema = serde_json::json!({"type": "integer", "format": "uint32", "minimum": 0.0});
+        assert_eq!(
+            gemini_response_schema(&schema).unwrap(),
+            serde_json::json!({"type": "integer"})
+        );
+
+        // Free-form objects and recursive types can't be expressed
+        let schema: Value =
+            serde_json::from_str(&get_type_schema::<StructWithValue>().unwrap()).unwrap();
+        assert!(gemini_response_schema(&schema).is_none());
+        let schema: Value =
+            serde_json::from_str(&get_type_schema::<RecursiveStruct>().unwrap()).unwrap();
+        assert!(gemini_response_schema(&schema).is_none());
+    }
+
+    #[derive(JsonSchema, Deserialize, Debug, PartialEq)]
+    enum Priority {
+        /// Needs attention today
+        High,
+        /// Can wait
+        Low,
+    }
+
+    #[derive(JsonSchema, Serialize, Deserialize)]
+    enum Assignee {
+        Unassigned,
+        Person(SimpleStruct),
+        Team { name: String },
+    }
+
+    #[derive(JsonSchema, Deserialize)]
+    struct Ticket {
+        priority: Priority,
+        assignee: Assignee,
+    }
+
+    #[test]
+    fn test_get_type_schema_simplifies_enums() {
+        let schema: Value = serde_json::from_str(&get_type_schema::<Ticket>().unwrap()).unwrap();
+
+        //C-like enums are a single string enum with the variant descriptions
+        let priority = &schema["properties"]["priority"];
+        assert_eq!(priority["type"], "string");
+        assert_eq!(priority["enum"], json!(["High", "Low"]));
+        assert!(priority.get("oneOf").is_none());
+        assert!(priority["description"]
+            .as_str()
+            .unwrap()
+            .contains("- High: Needs attention today"));
+
+        //Data-carrying enums are inlined with the schemas of their variants
+        let assignee = &schema["properties"]["assignee"];
+        let variants = assignee["oneOf"].as_array().unwrap();
+        assert_eq!(variants.len(), 3);
+        assert_eq!(
+            variants[1]["properties"]["Person"]["properties"]["name"]["type"],
+            "string"
+        );
+        assert!(!schema.to_string().contains("$ref"));
+        assert!(schema.get("definitions").is_none());
+
+        //The simplified schema still validates and deserializes the expected Json
+        let json = r#"{"priority": "High", "assignee": {"Team": {"name": "Core"}}}"#;
+        let ticket: Ticket = serde_json::from_str(json).unwrap();
+        assert_eq!(ticket.priority, Priority::High);
+        assert!(matches!(ticket.assignee, Assignee::Team { .. }));
+        assert!(get_valid_json("utils", &schema.to_string(), json).is_ok());
+    }
+
+    #[derive(JsonSchema, Serialize, Deserialize, Debug, PartialEq)]
+    #[serde(rename_all = "camelCase")]
+    struct Address {
+        street_name: String,
+        #[serde(rename = "zip")]
+        postal_code: String,
+    }
+
+    #[derive(JsonSchema, Serialize, Deserialize, Debug, PartialEq)]
+    struct Customer {
+        #[serde(rename = "fullName")]
+        name: String,
+        #[serde(flatten)]
+        address: Address,
+    }
+
+    #[test]
+    fn test_get_type_schema_respects_serde_attributes() {
+        let schema_string = get_type_schema::<Customer>().unwrap();
+        let schema: Value = serde_json::from_str(&schema_string).unwrap();
+
+        //Property names match the deserialization keys and flattened fields are at the same level
+        let properties = schema["properties"].as_object().unwrap();
+        let mut keys: Vec<&str> = properties.keys().map(String::as_str).collect();
+        keys.sort();
+        assert_eq!(keys, vec!["fullName", "streetName", "zip"]);
+        assert_eq!(schema["required"], json!(["fullName", "streetName", "zip"]));
+
+        //Json matching the schema round-trips through serde
+        let json =
+            r#"{"fullName": "Ada Lovelace", "streetName": "St James's Square", "zip": "SW1Y"}"#;
+        assert!(get_valid_json("utils", &schema_string, json).is_ok());
+        let customer: Customer = serde_json::from_str(json).unwrap();
+        assert_eq!(customer.address.postal_code, "SW1Y");
+        assert_eq!(
+            serde_json::to_value(&customer).unwrap(),
+            serde_json::from_str::<Value>(json).unwrap()
+        );
+
+        //Strict schemas use the same keys
+        let strict_schema = strict_schema_for::<Customer>();
+        assert_eq!(
+            strict_schema["required"],
+            json!(["fullName", "streetName", "zip"])
+        );
+    }
+
+    #[test]
+    fn test_inline_schema_definitions() {
+        let schema: Value =
+            serde_json::from_str(&get_type_schema::<NestedStruct>().unwrap()).unwrap();
+        let inlined_schema = inline_schema_definitions(&schema);
+        assert!(inlined_schema.get("definitions").is_none());
+        assert!(!inlined_schema.to_string().contains("$ref"));
+        //Inlining is deterministic
+        assert_eq!(
+            inlined_schema.to_string(),
+            inline_schema_definitions(&schema).to_string()
+        );
+
+        //Recursive types keep their definitions so references stay valid
+        let schema: Value =
+            serde_json::from_str(&get_type_schema::<RecursiveStruct>().unwrap()).unwrap();
+        let inlined_schema = inline_schema_definitions(&schema);
+        let references = inlined_schema.to_string();
+        assert!(references.contains("#/definitions/"));
+        assert!(inlined_schema["definitions"].as_object().unwrap().len() == 1);
+    }
+
+    #[test]
+    fn test_chunk_by_tokens() {
+        let model = OpenAIModels::Gpt4o;
+        let tokenizer = get_tokenizer(&model).unwrap();
+        let text = "The quick brown fox jumps over the lazy dog. ".repeat(20);
+
+        let chunks = chunk_by_tokens(&text, &model, 16, 0).unwrap();
+        assert!(chunks.len() > 1);
+        assert!(chunks
+            .iter()
+            .all(|chunk| tokenizer.encode_ordinary(chunk).len() <= 16));
+        assert_eq!(chunks.concat(), text);
+
+        //Consecutive chunks repeat the overlapping tokens
+        let overlapping_chunks = chunk_by_tokens(&text, &model, 16, 4).unwrap();
+        assert!(overlapping_chunks.len() > chunks.len());
+        let first_tokens = tokenizer.encode_ordinary(&overlapping_chunks[0]);
+        let second_tokens = tokenizer.encode_ordinary(&overlapping_chunks[1]);
+        assert_eq!(first_tokens[first_tokens.len() - 4..], second_tokens[..4]);
+
+        assert!(chunk_by_tokens(&text, &model, 4, 4).is_err());
+        assert!(chunk_by_tokens("", &model, 4, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_chunk_by_tokens_multi_byte() {
+        //Emojis and some CJK characters are encoded with several tokens
+        let model = OpenAIModels::Gpt4o;
+        let text = "🦀 Rust 語言 🚀 こんにちは 👩‍💻";
+
+        let chunks = chunk_by_tokens(text, &model, 1, 0).unwrap();
+        assert_eq!(chunks.concat(), text);
+        assert!(chunks.iter().all(|chunk| !chunk.contains('\u{FFFD}')));
+    }
+
+    #[test]
+    fn test_wrap_and_unwrap_array() {
+        let schema = json!({
+            "type": "array",
+            "items": { "$ref": "#/definitions/Item" },
+            "definitions": { "Item": { "type": "object" } }
+        });
+        let wrapped = wrap_array_schema(schema);
+        assert_eq!(wrapped["type"], "object");
+        assert_eq!(wrapped["required"], json!(["items"]));
+        assert_eq!(
+            wrapped["properties"]["items"]["items"]["$ref"],
+            "#/definitions/Item"
+        );
+        assert!(wrapped["properties"]["items"].get("definitions").is_none());
+        assert!(wrapped["definitions"]["Item"].is_object());
+
+        //Object schemas are left unchanged
+        let object_schema = json!({ "type": "object", "properties": {} });
+        assert_eq!(wrap_array_schema(object_schema.clone()), object_schema);
+
+        assert_eq!(
+            unwrap_array_response(r#"{"items": [1, 2]}"#),
+            Some("[1,2]".to_string())
+        );
+        assert_eq!(
+            unwrap_array_response(r#"{"items": [1], "more": true}"#),
+            None
+        );
+        assert_eq!(unwrap_array_response(r#"{"items": 1}"#), None);
+        assert_eq!(unwrap_array_response("[1, 2]"), None);
+    }
+
+    #[test]
+    fn test_sanitize_json_response_fenced() {
+        assert_eq!(
+            sanitize_json_response("```json\n{\"a\": 1}\n```"),
+            r#"{"a": 1}"#
+        );
+        assert_eq!(
+            sanitize_json_response("```JSON\n{\"a\": 1}\n```"),
+            r#"{"a": 1}"#
+        );
+        assert_eq!(
+            sanitize_json_response("```json5\n[1, 2, 3]\n```"),
+            "[1, 2, 3]"
+        );
+        // Double-fenced responses
+        assert_eq!(
+            sanitize_json_response("```json\n```json\n{\"a\": 1}\n```\n```"),
+            r#"{"a": 1}"#
+        );
+    }
+
+    #[test]
+    fn test_sanitize_json_response_with_prose() {
+        assert_eq!(
+            sanitize_json_response(
+                "Here is the JSON:\n{\"a\": \"}{\"} \n\nLet me know if you need more."
+            ),
+            r#"{"a": "}{"}"#
+        );
+        // Brackets in commentary that are not valid Json are skipped
+        assert_eq!(
+            sanitize_json_response("Using {placeholders} as [requested]: {\"a\": [1, {\"b\": 2}]}"),
+            r#"{"a": [1, {"b": 2}]}"#
+        );
+        // Scalars are returned without the fences
+        assert_eq!(sanitize_json_response("```json\n42\n```"), "42");
+        assert_eq!(sanitize_json_response("\"hola\""), "\"hola\"");
     }
 }